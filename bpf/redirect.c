@@ -1,21 +1,342 @@
 #include <linux/bpf.h>
+#include <linux/if_ether.h>
+#include <linux/in.h>
+#include <linux/ip.h>
+#include <linux/ipv6.h>
+#include <bpf/bpf_endian.h>
 #include <bpf/bpf_helpers.h>
 
+#define FILTER_ETHERTYPE   (1 << 0)
+#define FILTER_IP_PROTOCOL (1 << 1)
+#define FILTER_PORT        (1 << 2)
+#define FILTER_VLAN        (1 << 3)
+
+// frames not matching the enabled criteria go to the kernel stack, must match BPFRedirectManager::set_filter
+struct redirect_filter {
+    __u32 flags;
+    __u16 ethertype;
+    __u8 ip_protocol;
+    __u8 _pad;
+    __u16 port;
+    __u16 vlan_id;
+};
+
+// frames matching filter are forwarded by the kernel to the tx_ports entry egress, must match BPFRedirectManager::set_forwarding
+struct forward_rule {
+    struct redirect_filter filter;
+    __u32 enabled;
+    __u32 egress;
+};
+
+#define MAX_STEERING_RULES 16
+
+#define STEER_REDIRECT    1
+#define STEER_REDIRECT_TO 2
+#define STEER_PASS        3
+#define STEER_DROP        4
+
+// frames matching filter get action, the first rule without one ends the list, must match BPFRedirectManager::set_steering_rules
+struct steering_rule {
+    struct redirect_filter filter;
+    __u32 action;
+    __u32 entry;
+};
+
+// 1-in-rate frames have their first snap_len bytes copied to the samples ringbuf, must match BPFRedirectManager::set_sampling
+struct sample_config {
+    __u32 rate;
+    __u32 snap_len;
+};
+
+#define SAMPLE_MAX_BYTES 256
+
+// must match PacketSample::from_record
+struct sample {
+    __u64 timestamp_ns;
+    __u32 queue_id;
+    __u32 len;
+    __u32 captured_len;
+    __u32 _pad;
+    __u8 data[SAMPLE_MAX_BYTES];
+};
+
+// frames tagged vlan_id arriving on queue_id of if_index go to the vlan_xsks_map entry of the value, must match BPFRedirectManager::add_interface_vlan_redirect
+struct vlan_key {
+    __u32 if_index;
+    __u32 queue_id;
+    __u16 vlan_id;
+    __u16 _pad;
+};
+
+// frames to mac arriving on queue_id of if_index go to the mac_xsks_map entry of the value, must match BPFRedirectManager::add_interface_mac_redirect
+struct mac_key {
+    __u32 if_index;
+    __u32 queue_id;
+    __u8 mac[ETH_ALEN];
+    __u16 _pad;
+};
+
+struct vlan_hdr {
+    __be16 tci;
+    __be16 encapsulated_proto;
+};
+
+// the sockets of an interface by queue, must match BPFRedirectManager::add_interface_redirect
+struct xsks_map {
+    __uint(type, BPF_MAP_TYPE_XSKMAP);
+    __type(key, __u32);
+    __type(value, __u32);
+    __uint(max_entries, 64);
+};
+
+// the xsks_map of every interface the program is attached to, by ifindex
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH_OF_MAPS);
+    __type(key, __u32);
+    __uint(max_entries, 64);
+    __array(values, struct xsks_map);
+} xsks_maps SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __type(key, struct vlan_key);
+    __type(value, __u32);
+    __uint(max_entries, 1024);
+} vlan_map SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_XSKMAP);
+    __type(key, __u32);
+    __type(value, __u32);
+    __uint(max_entries, 1024);
+} vlan_xsks_map SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __type(key, struct mac_key);
+    __type(value, __u32);
+    __uint(max_entries, 1024);
+} mac_map SEC(".maps");
+
 struct {
     __uint(type, BPF_MAP_TYPE_XSKMAP);
     __type(key, __u32);
     __type(value, __u32);
+    __uint(max_entries, 1024);
+} mac_xsks_map SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __type(key, __u32);
+    __type(value, struct redirect_filter);
+    __uint(max_entries, 1);
+} filter_map SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_DEVMAP);
+    __type(key, __u32);
+    __type(value, __u32);
     __uint(max_entries, 64);
-} xsks_map SEC(".maps");
+} tx_ports SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __type(key, __u32);
+    __type(value, struct forward_rule);
+    __uint(max_entries, 1);
+} forward_map SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __type(key, __u32);
+    __type(value, struct steering_rule);
+    __uint(max_entries, MAX_STEERING_RULES);
+} steering_map SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_ARRAY);
+    __type(key, __u32);
+    __type(value, struct sample_config);
+    __uint(max_entries, 1);
+} sample_config_map SEC(".maps");
+
+struct {
+    __uint(type, BPF_MAP_TYPE_RINGBUF);
+    __uint(max_entries, 256 * 1024);
+} samples SEC(".maps");
+
+static __always_inline void maybe_sample(struct xdp_md *ctx)
+{
+    __u32 key = 0;
+    struct sample_config *config = bpf_map_lookup_elem(&sample_config_map, &key);
+    if (!config || !config->rate || bpf_get_prandom_u32() % config->rate)
+        return;
+
+    // when the ringbuf is full the sample is lost
+    struct sample *sample = bpf_ringbuf_reserve(&samples, sizeof(*sample), 0);
+    if (!sample)
+        return;
+    __u32 len = ctx->data_end - ctx->data;
+    __u32 captured_len = len < config->snap_len ? len : config->snap_len;
+    if (captured_len > SAMPLE_MAX_BYTES)
+        captured_len = SAMPLE_MAX_BYTES;
+    if (captured_len == 0 || bpf_xdp_load_bytes(ctx, 0, sample->data, captured_len)) {
+        bpf_ringbuf_discard(sample, 0);
+        return;
+    }
+    sample->timestamp_ns = bpf_ktime_get_ns();
+    sample->queue_id = ctx->rx_queue_index;
+    sample->len = len;
+    sample->captured_len = captured_len;
+    bpf_ringbuf_submit(sample, 0);
+}
+
+// to the socket of queue_id on the interface the frame arrived on
+static __always_inline int redirect_to_queue(struct xdp_md *ctx, __u32 queue_id)
+{
+    __u32 if_index = ctx->ingress_ifindex;
+    void *xsks_map = bpf_map_lookup_elem(&xsks_maps, &if_index);
+    if (!xsks_map)
+        return XDP_DROP;
+    return bpf_redirect_map(xsks_map, queue_id, XDP_DROP);
+}
+
+// the vlan id of the outer tag, -1 when untagged
+static __always_inline int vlan_id(struct xdp_md *ctx)
+{
+    void *data = (void *)(long)ctx->data;
+    void *data_end = (void *)(long)ctx->data_end;
+
+    struct ethhdr *eth = data;
+    if ((void *)(eth + 1) > data_end)
+        return -1;
+    if (eth->h_proto != bpf_htons(ETH_P_8021Q) && eth->h_proto != bpf_htons(ETH_P_8021AD))
+        return -1;
+    struct vlan_hdr *vlan = (void *)(eth + 1);
+    if ((void *)(vlan + 1) > data_end)
+        return -1;
+    return bpf_ntohs(vlan->tci) & 0x0fff;
+}
+
+static __always_inline int filter_matches(struct xdp_md *ctx, const struct redirect_filter *filter)
+{
+    void *data = (void *)(long)ctx->data;
+    void *data_end = (void *)(long)ctx->data_end;
+
+    // ethernet, skipping one vlan tag
+    struct ethhdr *eth = data;
+    if ((void *)(eth + 1) > data_end)
+        return 0;
+    __be16 proto = eth->h_proto;
+    void *cursor = eth + 1;
+    int vid = -1;
+    if (proto == bpf_htons(ETH_P_8021Q) || proto == bpf_htons(ETH_P_8021AD)) {
+        struct vlan_hdr *vlan = cursor;
+        if ((void *)(vlan + 1) > data_end)
+            return 0;
+        vid = bpf_ntohs(vlan->tci) & 0x0fff;
+        proto = vlan->encapsulated_proto;
+        cursor = vlan + 1;
+    }
+    if ((filter->flags & FILTER_VLAN) && vid != filter->vlan_id)
+        return 0;
+    if ((filter->flags & FILTER_ETHERTYPE) && proto != bpf_htons(filter->ethertype))
+        return 0;
+    if (!(filter->flags & (FILTER_IP_PROTOCOL | FILTER_PORT)))
+        return 1;
+
+    // ip
+    __u8 ip_protocol;
+    if (proto == bpf_htons(ETH_P_IP)) {
+        struct iphdr *ip = cursor;
+        if ((void *)(ip + 1) > data_end)
+            return 0;
+        ip_protocol = ip->protocol;
+        cursor = (void *)ip + ip->ihl * 4;
+    } else if (proto == bpf_htons(ETH_P_IPV6)) {
+        struct ipv6hdr *ip6 = cursor;
+        if ((void *)(ip6 + 1) > data_end)
+            return 0;
+        ip_protocol = ip6->nexthdr;
+        cursor = ip6 + 1;
+    } else {
+        return 0;
+    }
+    if ((filter->flags & FILTER_IP_PROTOCOL) && ip_protocol != filter->ip_protocol)
+        return 0;
+    if (!(filter->flags & FILTER_PORT))
+        return 1;
+
+    // tcp and udp ports
+    if (ip_protocol != IPPROTO_TCP && ip_protocol != IPPROTO_UDP)
+        return 0;
+    __be16 *ports = cursor;
+    if ((void *)(ports + 2) > data_end)
+        return 0;
+    return ports[0] == bpf_htons(filter->port) || ports[1] == bpf_htons(filter->port);
+}
 
 SEC("xdp")
 int xdp_sock_redir(struct xdp_md *ctx)
 {
+    maybe_sample(ctx);
+
+    // apply the first steering rule matching
+    for (__u32 i = 0; i < MAX_STEERING_RULES; i++) {
+        struct steering_rule *rule = bpf_map_lookup_elem(&steering_map, &i);
+        if (!rule || !rule->action)
+            break;
+        if (!filter_matches(ctx, &rule->filter))
+            continue;
+        switch (rule->action) {
+        case STEER_REDIRECT:
+            return redirect_to_queue(ctx, ctx->rx_queue_index);
+        case STEER_REDIRECT_TO:
+            return redirect_to_queue(ctx, rule->entry);
+        case STEER_PASS:
+            return XDP_PASS;
+        default:
+            return XDP_DROP;
+        }
+    }
+
+    // forward in the kernel what the rule selects, to the stack if the port is missing
+    __u32 key = 0;
+    struct forward_rule *forward = bpf_map_lookup_elem(&forward_map, &key);
+    if (forward && forward->enabled && filter_matches(ctx, &forward->filter))
+        return bpf_redirect_map(&tx_ports, forward->egress, XDP_PASS);
+
+    // let through what is filtered out
+    struct redirect_filter *filter = bpf_map_lookup_elem(&filter_map, &key);
+    if (filter && filter->flags && !filter_matches(ctx, filter))
+        return XDP_PASS;
+
     // we will redirect according to the queue id
     __u32 queue_id = ctx->rx_queue_index;
 
+    // unless the destination has a socket of its own on this queue of this interface
+    void *data = (void *)(long)ctx->data;
+    void *data_end = (void *)(long)ctx->data_end;
+    struct ethhdr *eth = data;
+    if ((void *)(eth + 1) <= data_end) {
+        struct mac_key mac_key = { .if_index = ctx->ingress_ifindex, .queue_id = queue_id };
+        __builtin_memcpy(mac_key.mac, eth->h_dest, ETH_ALEN);
+        __u32 *slot = bpf_map_lookup_elem(&mac_map, &mac_key);
+        if (slot)
+            return bpf_redirect_map(&mac_xsks_map, *slot, XDP_DROP);
+    }
+
+    // or the vlan does
+    int vid = vlan_id(ctx);
+    if (vid >= 0) {
+        struct vlan_key vlan_key = { .if_index = ctx->ingress_ifindex, .queue_id = queue_id, .vlan_id = vid };
+        __u32 *slot = bpf_map_lookup_elem(&vlan_map, &vlan_key);
+        if (slot)
+            return bpf_redirect_map(&vlan_xsks_map, *slot, XDP_DROP);
+    }
+
     // lookup and send
-    return bpf_redirect_map(&xsks_map, queue_id, XDP_DROP);
+    return redirect_to_queue(ctx, queue_id);
 }
 
 char _license[] SEC("license") = "GPL";
@@ -1,12 +1,20 @@
 fn main() {
     println!("cargo::rerun-if-changed=bpf/redirect.c");
-    if ! std::process::Command::new("clang")
+    println!("cargo::rerun-if-env-changed=CLANG");
+    let output = format!("{}/redirect.o", std::env::var("OUT_DIR").unwrap());
+    let compiled = std::process::Command::new(std::env::var_os("CLANG").unwrap_or("clang".into()))
         .arg("-O2").arg("-g")
         .arg("-target").arg("bpf")
         .arg("-c").arg("bpf/redirect.c")
-        .arg("-o").arg(format!("{}/redirect.o", std::env::var("OUT_DIR").unwrap()))
-        .status().unwrap()
-        .success() {
+        .arg("-o").arg(&output)
+        .status()
+        .is_ok_and(|status| status.success());
+    if ! compiled {
+        // with runtime-bpf the program is compiled when attaching instead
+        if std::env::var_os("CARGO_FEATURE_RUNTIME_BPF").is_none() {
             panic!("Failed compiling BPF program")
         }
+        println!("cargo::warning=Failed compiling BPF program, it will be compiled at runtime");
+        std::fs::write(&output, []).unwrap();
+    }
 }
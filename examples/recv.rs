@@ -1,4 +1,4 @@
-use xdrippi::{utils::interface_name_to_index, BPFRedirectManager, DefaultAllocator, Umem, UmemAllocator, XDPSocket};
+use xdrippi::{utils::interface_name_to_index, BPFRedirectManager, DefaultAllocator, Umem, UmemAllocator, UmemAllocatorFactory, XDPSocket};
 
 use std::{os::fd::AsRawFd, sync::Arc};
 
@@ -13,7 +13,7 @@ fn main() {
 
     // bpf
     let mut bpf_manager = BPFRedirectManager::attach(if_index);
-    bpf_manager.add_redirect(0, sock.as_raw_fd());
+    bpf_manager.add_redirect(0, sock.as_raw_fd()).unwrap();
 
     // umem allocator
     let umem_allocator = DefaultAllocator::for_umem(sock.umem.clone());
@@ -21,14 +21,7 @@ fn main() {
     // fill the fill ring
     sock.debug_print_status();
 
-    while let Some(chunk_index) = umem_allocator.try_allocate() {
-        if sock.fill_ring.can_produce() {
-            sock.fill_ring.produce_umem_offset(umem.chunk_start_offset_for_index(chunk_index));
-        } else {
-            umem_allocator.release(chunk_index);
-            break;
-        }
-    }
+    sock.refill_fill_ring(&umem_allocator, usize::MAX);
     sock.debug_print_status();
 
     // receive
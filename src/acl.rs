@@ -0,0 +1,467 @@
+//! Packet classification: prioritized 5-tuple [`AclRule`]s and the [`Classifier`] pipeline stage applying them
+
+use std::{collections::HashMap, net::IpAddr, ops::RangeInclusive};
+
+use crate::{packet::FiveTuple, pipeline::{FrameCtx, Stage, Verdict}};
+
+/// What to do with packets matching a rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclAction {
+    /// Let the packet continue down the pipeline
+    Permit,
+    /// Drop the packet
+    Deny,
+    /// Forward the packet to a port, skipping the rest of the pipeline
+    Redirect(usize),
+}
+
+/// A rule matching packets by prefixes of their addresses, their protocol and ranges of their ports
+///
+/// Built from [`Self::new`], matching everything, narrowed down by the other methods
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AclRule {
+    /// Among the rules matching a packet the one with the lowest priority wins, the oldest one on ties
+    pub priority: u32,
+    pub source: Option<(IpAddr, u8)>,
+    pub destination: Option<(IpAddr, u8)>,
+    pub protocol: Option<u8>,
+    pub source_ports: RangeInclusive<u16>,
+    pub destination_ports: RangeInclusive<u16>,
+    pub action: AclAction,
+}
+impl AclRule {
+    /// A rule matching every IP packet
+    pub fn new(priority: u32, action: AclAction) -> Self {
+        Self {
+            priority,
+            source: None,
+            destination: None,
+            protocol: None,
+            source_ports: 0..=u16::MAX,
+            destination_ports: 0..=u16::MAX,
+            action,
+        }
+    }
+
+    /// Match source addresses within `prefix/length`
+    pub fn source(mut self, prefix: IpAddr, length: u8) -> Self {
+        self.source = Some((prefix, length));
+        self
+    }
+
+    /// Match destination addresses within `prefix/length`
+    pub fn destination(mut self, prefix: IpAddr, length: u8) -> Self {
+        self.destination = Some((prefix, length));
+        self
+    }
+
+    /// Match an IP protocol, see [`crate::packet::ip_protocol`]
+    pub fn protocol(mut self, protocol: u8) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    /// Match source ports within `ports`
+    pub fn source_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.source_ports = ports;
+        self
+    }
+
+    /// Match destination ports within `ports`
+    pub fn destination_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.destination_ports = ports;
+        self
+    }
+
+    /// Whether the rule only matches IPv6, `None` if it matches both families
+    fn is_ipv6(&self) -> Option<bool> {
+        self.source.or(self.destination).map(|(prefix, _)| prefix.is_ipv6())
+    }
+
+    /// Check that the prefixes belong to the same family and that their lengths fit their addresses
+    fn validate(&self) -> Result<(), crate::Error> {
+        for (prefix, length) in self.source.iter().chain(&self.destination) {
+            let bits = if prefix.is_ipv6() { 128 } else { 32 };
+            if *length > bits {
+                return Err(crate::Error::InvalidConfiguration { reason: format!("prefix {prefix}/{length} is longer than its address") });
+            }
+        }
+        if let (Some((source, _)), Some((destination, _))) = (self.source, self.destination) && source.is_ipv6() != destination.is_ipv6() {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("source {source} and destination {destination} belong to different families") });
+        }
+        Ok(())
+    }
+}
+
+/// Identifies a rule inserted into a [`Classifier`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RuleId(u64);
+
+/// Rules are ranked by priority first, then by age
+type Rank = (u32, RuleId);
+
+/// The prefix lengths and protocol wildcard shared by the rules of a [`Tuple`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Shape {
+    source_length: u8,
+    destination_length: u8,
+    protocol: bool,
+}
+
+/// The masked addresses and protocol of a packet or rule
+type Key = (u128, u128, u8);
+
+/// The part of a rule checked after its [`Key`] matched
+#[derive(Debug, Clone)]
+struct Entry {
+    rank: Rank,
+    source_ports: RangeInclusive<u16>,
+    destination_ports: RangeInclusive<u16>,
+    action: AclAction,
+}
+
+/// The rules sharing a [`Shape`], found with a single hash lookup
+#[derive(Debug, Clone)]
+struct Tuple {
+    shape: Shape,
+    /// The best rank of the rules within
+    best: Rank,
+    /// Rules with the same key, sorted by rank
+    buckets: HashMap<Key, Vec<Entry>>,
+}
+
+/// The rules of one address family, searched one tuple at a time from the one holding the best ranked rule
+///
+/// A packet costs one hash lookup per distinct shape rather than one comparison per rule, and the search stops as soon as
+/// no remaining tuple can hold a rule ranking better than the match found so far
+#[derive(Debug, Clone)]
+struct TupleSpace {
+    bits: u8,
+    /// Sorted by best rank
+    tuples: Vec<Tuple>,
+}
+impl TupleSpace {
+    fn new(bits: u8) -> Self {
+        Self { bits, tuples: Vec::new() }
+    }
+
+    fn mask(&self, address: u128, length: u8) -> u128 {
+        assert!(length <= self.bits, "Prefix length exceeds the address size");
+        let address_bits = u128::MAX >> (128 - self.bits);
+        address & address_bits & ! address_bits.checked_shr(length as u32).unwrap_or(0)
+    }
+
+    fn key(&self, shape: Shape, source: u128, destination: u128, protocol: u8) -> Key {
+        (
+            self.mask(source, shape.source_length),
+            self.mask(destination, shape.destination_length),
+            if shape.protocol { protocol } else { 0 },
+        )
+    }
+
+    /// The shape and key of `rule`
+    fn place(&self, rule: &AclRule) -> (Shape, Key) {
+        let (source, source_length) = rule.source.map_or((0, 0), |(prefix, length)| (address_bits(prefix), length));
+        let (destination, destination_length) = rule.destination.map_or((0, 0), |(prefix, length)| (address_bits(prefix), length));
+        let shape = Shape { source_length, destination_length, protocol: rule.protocol.is_some() };
+        (shape, self.key(shape, source, destination, rule.protocol.unwrap_or(0)))
+    }
+
+    fn sort(&mut self) {
+        self.tuples.sort_by_key(|tuple| tuple.best);
+    }
+
+    fn insert(&mut self, rule: &AclRule, rank: Rank) {
+        let (shape, key) = self.place(rule);
+        let entry = Entry { rank, source_ports: rule.source_ports.clone(), destination_ports: rule.destination_ports.clone(), action: rule.action };
+
+        // find the tuple
+        let tuple = match self.tuples.iter().position(|tuple| tuple.shape == shape) {
+            Some(position) => &mut self.tuples[position],
+            None => {
+                self.tuples.push(Tuple { shape, best: rank, buckets: HashMap::new() });
+                self.tuples.last_mut().unwrap()
+            },
+        };
+
+        // add the entry
+        let bucket = tuple.buckets.entry(key).or_default();
+        let position = bucket.partition_point(|probe| probe.rank < rank);
+        bucket.insert(position, entry);
+        tuple.best = tuple.best.min(rank);
+        self.sort();
+    }
+
+    fn remove(&mut self, rule: &AclRule, rank: Rank) {
+        let (shape, key) = self.place(rule);
+        let Some(position) = self.tuples.iter().position(|tuple| tuple.shape == shape) else {
+            return;
+        };
+        let tuple = &mut self.tuples[position];
+        if let Some(bucket) = tuple.buckets.get_mut(&key) {
+            bucket.retain(|entry| entry.rank != rank);
+            if bucket.is_empty() {
+                tuple.buckets.remove(&key);
+            }
+        }
+
+        // update the ranking of the tuples
+        match tuple.buckets.values().map(|bucket| bucket[0].rank).min() {
+            Some(best) => tuple.best = best,
+            None => {
+                self.tuples.remove(position);
+            },
+        }
+        self.sort();
+    }
+
+    fn lookup(&self, flow: &FiveTuple) -> Option<&Entry> {
+        let (source, destination) = (address_bits(flow.source), address_bits(flow.destination));
+        let mut best: Option<&Entry> = None;
+        for tuple in &self.tuples {
+            if best.is_some_and(|entry| entry.rank < tuple.best) {
+                break;
+            }
+            let key = self.key(tuple.shape, source, destination, flow.protocol);
+            let found = tuple.buckets.get(&key).and_then(|bucket| bucket.iter().find(|entry| {
+                entry.source_ports.contains(&flow.source_port) && entry.destination_ports.contains(&flow.destination_port)
+            }));
+            if let Some(entry) = found && best.is_none_or(|best| entry.rank < best.rank) {
+                best = Some(entry);
+            }
+        }
+        best
+    }
+}
+
+fn address_bits(address: IpAddr) -> u128 {
+    match address {
+        IpAddr::V4(address) => u32::from(address) as u128,
+        IpAddr::V6(address) => u128::from(address),
+    }
+}
+
+/// Counters kept by a [`Classifier`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassifierStats {
+    pub permitted: u64,
+    pub denied: u64,
+    pub redirected: u64,
+    /// Packets no rule matched, handled by the default action
+    pub unmatched: u64,
+}
+
+/// A set of [`AclRule`]s applied to IP packets, usable as a pipeline stage
+///
+/// Rules are grouped by the lengths of their prefixes and whether they match a protocol, a tuple space search then
+/// needs a hash lookup per group, keeping classification fast with tens of thousands of rules as long as they share
+/// a few shapes. Frames that are not IP continue down the pipeline unclassified
+#[derive(Debug, Clone)]
+pub struct Classifier {
+    v4: TupleSpace,
+    v6: TupleSpace,
+    rules: HashMap<RuleId, AclRule>,
+    next_id: u64,
+    default_action: AclAction,
+    stats: ClassifierStats,
+}
+impl Default for Classifier {
+    fn default() -> Self {
+        Self::new(AclAction::Permit)
+    }
+}
+impl Classifier {
+    /// An empty classifier applying `default_action` to packets no rule matches
+    pub fn new(default_action: AclAction) -> Self {
+        Self {
+            v4: TupleSpace::new(32),
+            v6: TupleSpace::new(128),
+            rules: HashMap::new(),
+            next_id: 0,
+            default_action,
+            stats: ClassifierStats::default(),
+        }
+    }
+
+    /// Add `rule`, returning the identifier to remove it with
+    ///
+    /// Host bits of the prefixes are ignored, rules without prefixes match both address families. Fails with
+    /// [`crate::Error::InvalidConfiguration`] if the prefixes belong to different families or are longer than their address
+    pub fn insert(&mut self, rule: AclRule) -> Result<RuleId, crate::Error> {
+        rule.validate()?;
+        let id = RuleId(self.next_id);
+        self.next_id += 1;
+        let is_ipv6 = rule.is_ipv6();
+        if is_ipv6 != Some(true) {
+            self.v4.insert(&rule, (rule.priority, id));
+        }
+        if is_ipv6 != Some(false) {
+            self.v6.insert(&rule, (rule.priority, id));
+        }
+        self.rules.insert(id, rule);
+        Ok(id)
+    }
+
+    /// Remove the rule identified by `id`, returning it
+    pub fn remove(&mut self, id: RuleId) -> Option<AclRule> {
+        let rule = self.rules.remove(&id)?;
+        let is_ipv6 = rule.is_ipv6();
+        if is_ipv6 != Some(true) {
+            self.v4.remove(&rule, (rule.priority, id));
+        }
+        if is_ipv6 != Some(false) {
+            self.v6.remove(&rule, (rule.priority, id));
+        }
+        Some(rule)
+    }
+
+    pub fn rule(&self, id: RuleId) -> Option<&AclRule> {
+        self.rules.get(&id)
+    }
+
+    /// The number of rules
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Whether there are no rules
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub const fn default_action(&self) -> AclAction {
+        self.default_action
+    }
+
+    pub fn set_default_action(&mut self, action: AclAction) {
+        self.default_action = action;
+    }
+
+    pub const fn stats(&self) -> &ClassifierStats {
+        &self.stats
+    }
+
+    /// The best ranked rule matching `flow` along with its action
+    pub fn classify(&self, flow: &FiveTuple) -> Option<(RuleId, AclAction)> {
+        let space = if flow.destination.is_ipv6() { &self.v6 } else { &self.v4 };
+        space.lookup(flow).map(|entry| (entry.rank.1, entry.action))
+    }
+
+    /// Classify `frame`, see [`Stage::process`]
+    pub fn filter(&mut self, frame: &[u8]) -> Verdict {
+        let Some(flow) = FiveTuple::from_frame(frame) else {
+            return Verdict::Continue;
+        };
+        let action = match self.classify(&flow) {
+            Some((_, action)) => action,
+            None => {
+                self.stats.unmatched += 1;
+                self.default_action
+            },
+        };
+        match action {
+            AclAction::Permit => {
+                self.stats.permitted += 1;
+                Verdict::Continue
+            },
+            AclAction::Deny => {
+                self.stats.denied += 1;
+                Verdict::Drop
+            },
+            AclAction::Redirect(port) => {
+                self.stats.redirected += 1;
+                Verdict::Forward(port)
+            },
+        }
+    }
+}
+impl Stage for Classifier {
+    fn process(&mut self, ctx: &mut FrameCtx) -> Verdict {
+        self.filter(ctx.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::{AclAction, AclRule, Classifier, ClassifierStats};
+    use crate::{packet::{ip_protocol, FiveTuple, FrameBuilder, MacAddress}, pipeline::Verdict};
+
+    fn flow(source: &str, destination: &str, protocol: u8, source_port: u16, destination_port: u16) -> FiveTuple {
+        FiveTuple { source: source.parse().unwrap(), destination: destination.parse().unwrap(), protocol, source_port, destination_port }
+    }
+
+    #[test]
+    fn test_classify_by_priority() {
+        let mut classifier = Classifier::new(AclAction::Deny);
+        let web = classifier.insert(AclRule::new(10, AclAction::Permit).destination("10.0.0.0".parse().unwrap(), 8).protocol(ip_protocol::TCP).destination_ports(80..=443)).unwrap();
+        let banned = classifier.insert(AclRule::new(5, AclAction::Deny).source("192.168.66.0".parse().unwrap(), 24)).unwrap();
+        let dns = classifier.insert(AclRule::new(20, AclAction::Redirect(3)).protocol(ip_protocol::UDP).destination_ports(53..=53)).unwrap();
+        assert_eq!(classifier.len(), 3);
+
+        let action = |classifier: &Classifier, flow: FiveTuple| classifier.classify(&flow).map(|(_, action)| action);
+        assert_eq!(action(&classifier, flow("192.168.1.1", "10.1.1.1", ip_protocol::TCP, 5000, 443)), Some(AclAction::Permit));
+        assert_eq!(action(&classifier, flow("192.168.66.1", "10.1.1.1", ip_protocol::TCP, 5000, 443)), Some(AclAction::Deny));
+        assert_eq!(action(&classifier, flow("192.168.1.1", "10.1.1.1", ip_protocol::TCP, 5000, 22)), None);
+        assert_eq!(action(&classifier, flow("192.168.1.1", "10.1.1.1", ip_protocol::UDP, 5000, 443)), None);
+
+        // rules without prefixes match both families
+        assert_eq!(classifier.classify(&flow("192.168.1.1", "8.8.8.8", ip_protocol::UDP, 5000, 53)), Some((dns, AclAction::Redirect(3))));
+        assert_eq!(action(&classifier, flow("2001:db8::1", "2001:db8::53", ip_protocol::UDP, 5000, 53)), Some(AclAction::Redirect(3)));
+
+        // removal reveals lower priority rules
+        let overlap = classifier.insert(AclRule::new(15, AclAction::Redirect(1)).destination("10.1.0.0".parse().unwrap(), 16)).unwrap();
+        assert_eq!(action(&classifier, flow("192.168.66.1", "10.1.1.1", ip_protocol::TCP, 5000, 443)), Some(AclAction::Deny));
+        assert_eq!(classifier.remove(banned).unwrap().action, AclAction::Deny);
+        assert_eq!(classifier.remove(banned), None);
+        assert_eq!(classifier.classify(&flow("192.168.66.1", "10.1.1.1", ip_protocol::TCP, 5000, 443)), Some((web, AclAction::Permit)));
+        assert_eq!(classifier.remove(web).unwrap().priority, 10);
+        assert_eq!(classifier.classify(&flow("192.168.66.1", "10.1.1.1", ip_protocol::TCP, 5000, 443)), Some((overlap, AclAction::Redirect(1))));
+
+        // ties go to the oldest rule
+        let first = classifier.insert(AclRule::new(1, AclAction::Permit).source("172.16.0.0".parse().unwrap(), 12)).unwrap();
+        classifier.insert(AclRule::new(1, AclAction::Deny).source("172.16.0.1".parse().unwrap(), 32)).unwrap();
+        assert_eq!(classifier.classify(&flow("172.16.0.1", "1.1.1.1", ip_protocol::ICMP, 0, 0)), Some((first, AclAction::Permit)));
+
+        // mixed families and overlong prefixes are refused
+        let mixed = AclRule::new(1, AclAction::Deny).source("10.0.0.0".parse().unwrap(), 8).destination("2001:db8::".parse().unwrap(), 32);
+        assert!(matches!(classifier.insert(mixed), Err(crate::Error::InvalidConfiguration { .. })));
+        let overlong = AclRule::new(1, AclAction::Deny).destination("10.0.0.0".parse().unwrap(), 33);
+        assert!(matches!(classifier.insert(overlong), Err(crate::Error::InvalidConfiguration { .. })));
+    }
+
+    #[test]
+    fn test_classify_many_rules() {
+        let mut classifier = Classifier::default();
+        for n in 0..20_000_u32 {
+            let host = Ipv4Addr::from(0x0A00_0000 | n);
+            classifier.insert(AclRule::new(100 + n, AclAction::Redirect(n as usize)).destination(IpAddr::V4(host), 32).protocol(ip_protocol::UDP)).unwrap();
+        }
+        classifier.insert(AclRule::new(50, AclAction::Deny).destination("10.0.16.0".parse().unwrap(), 20)).unwrap();
+
+        assert_eq!(classifier.classify(&flow("1.1.1.1", "10.0.3.7", ip_protocol::UDP, 1, 1)).map(|(_, action)| action), Some(AclAction::Redirect(0x307)));
+        assert_eq!(classifier.classify(&flow("1.1.1.1", "10.0.16.1", ip_protocol::UDP, 1, 1)).map(|(_, action)| action), Some(AclAction::Deny));
+        assert_eq!(classifier.classify(&flow("1.1.1.1", "10.0.3.7", ip_protocol::TCP, 1, 1)), None);
+        assert_eq!(classifier.len(), 20_001);
+    }
+
+    #[test]
+    fn test_classifier_stage() {
+        let mut classifier = Classifier::new(AclAction::Permit);
+        classifier.insert(AclRule::new(0, AclAction::Deny).source(IpAddr::V6(Ipv6Addr::LOCALHOST), 128)).unwrap();
+        classifier.insert(AclRule::new(1, AclAction::Redirect(2)).protocol(ip_protocol::UDP).source_ports(1000..=1999)).unwrap();
+        let builder = FrameBuilder::ethernet(MacAddress([6; 6]), MacAddress([8; 6]));
+
+        let mut frame = [0_u8; 128];
+        let len = builder.clone().ipv6(Ipv6Addr::LOCALHOST, Ipv6Addr::LOCALHOST).udp(1000, 2000).write_with_payload(&mut frame, b"denied").unwrap();
+        assert_eq!(classifier.filter(&frame[..len]), Verdict::Drop);
+        let len = builder.clone().ipv4(Ipv4Addr::LOCALHOST, Ipv4Addr::LOCALHOST).udp(1500, 2000).write_with_payload(&mut frame, b"redirected").unwrap();
+        assert_eq!(classifier.filter(&frame[..len]), Verdict::Forward(2));
+        let len = builder.clone().ipv4(Ipv4Addr::LOCALHOST, Ipv4Addr::LOCALHOST).udp(2500, 2000).write_with_payload(&mut frame, b"permitted").unwrap();
+        assert_eq!(classifier.filter(&frame[..len]), Verdict::Continue);
+        let len = builder.ethertype(crate::packet::ethertype::ARP).write_with_payload(&mut frame, &[0; 28]).unwrap();
+        assert_eq!(classifier.filter(&frame[..len]), Verdict::Continue);
+        assert_eq!(*classifier.stats(), ClassifierStats { permitted: 1, denied: 1, redirected: 1, unmatched: 1 });
+    }
+}
@@ -0,0 +1,75 @@
+//! Readiness-based integration with async-io, the reactor smol is built on, behind the `async-io` feature
+//!
+//! The socket stays a plain [`XDPSocket`]: rings are checked first and the task only parks on the reactor when there is
+//! nothing to do, so a busy socket never goes through it
+
+use std::{os::fd::{FromRawFd, OwnedFd}, time::Duration};
+
+use async_io::{Async, Timer};
+
+use crate::{UmemAllocator, XDPSocket};
+
+/// How long [`AsyncXdpSocket::send`] backs off when the TX ring has room but the frame was not taken, e.g. for lack of chunks
+const SEND_BACKOFF: Duration = Duration::from_micros(50);
+
+/// An [`XDPSocket`] registered with the async-io reactor
+///
+/// The reactor watches a duplicate of the descriptor of the socket, which the socket itself knows nothing about, so
+/// every [`XDPSocket`] method remains available through [`Self::get_mut`]
+pub struct AsyncXdpSocket<'a> {
+    socket: XDPSocket<'a>,
+    readiness: Async<OwnedFd>,
+}
+impl<'a> AsyncXdpSocket<'a> {
+    /// Register `socket` with the reactor, which makes its descriptor non-blocking
+    pub fn new(socket: XDPSocket<'a>) -> Result<Self, crate::Error> {
+        let fd = unsafe { libc::fcntl(socket.fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(crate::Error::PollFailure { error: std::io::Error::last_os_error() });
+        }
+        let readiness = Async::new(unsafe { OwnedFd::from_raw_fd(fd) }).map_err(|error| crate::Error::PollFailure { error })?;
+        Ok(Self { socket, readiness })
+    }
+
+    pub const fn get_ref(&self) -> &XDPSocket<'a> {
+        &self.socket
+    }
+
+    pub fn get_mut(&mut self) -> &mut XDPSocket<'a> {
+        &mut self.socket
+    }
+
+    /// Unregister the socket from the reactor
+    pub fn into_inner(self) -> XDPSocket<'a> {
+        self.socket
+    }
+
+    /// Wait for frames, then hand them to `handler` like [`XDPSocket::recv`] does, returning how many there were
+    pub async fn recv(&mut self, allocator: &(impl UmemAllocator + ?Sized), mut handler: impl FnMut(&[u8])) -> Result<usize, crate::Error> {
+        loop {
+            let count = self.socket.recv(allocator, &mut handler);
+            if count > 0 {
+                return Ok(count);
+            }
+            self.readiness.readable().await.map_err(|error| crate::Error::PollFailure { error })?;
+        }
+    }
+
+    /// Copy `frame` into a chunk from `allocator` and enqueue it like [`XDPSocket::send`] does, waiting for room
+    ///
+    /// Completions are reaped while waiting. The kernel signals room on the TX ring, the lack of chunks or a rate limit
+    /// are waited out by backing off
+    pub async fn send(&mut self, allocator: &(impl UmemAllocator + ?Sized), frame: &[u8]) -> Result<(), crate::Error> {
+        loop {
+            self.socket.reap_completions(allocator);
+            if self.socket.send(allocator, frame)? {
+                return Ok(());
+            }
+            if self.socket.tx_ring.can_produce() {
+                Timer::after(SEND_BACKOFF).await;
+            } else {
+                self.readiness.writable().await.map_err(|error| crate::Error::PollFailure { error })?;
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+/// How loaded the transmission path of a socket is, see [`crate::XDPSocket::tx_backlog`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TxBacklog {
+    /// Descriptors that can still be produced on the TX ring
+    pub tx_free: u32,
+    /// Descriptors on the TX ring not yet taken by the kernel
+    pub tx_queued: u32,
+    /// Transmitted chunks waiting on the completion ring to be reaped
+    pub completions: u32,
+}
+
+/// Hysteresis over the free space of a TX ring, telling applications when to hold back traffic upstream
+///
+/// The ring becomes congested when its free space drops below `low` and stops being so once it is back to `high`,
+/// see [`crate::XDPSocket::tx_watermarks`]
+#[derive(Debug, Clone)]
+pub struct TxWatermarks {
+    low: u32,
+    high: u32,
+    congested: bool,
+    transitions: u64,
+}
+impl TxWatermarks {
+    /// Become congested under `low` free descriptors, uncongested from `high` onwards
+    pub fn new(low: u32, high: u32) -> Self {
+        assert!(low <= high, "Low watermark must not exceed the high one");
+        Self { low, high, congested: false, transitions: 0 }
+    }
+
+    /// Whether the ring was congested when last updated
+    pub const fn is_congested(&self) -> bool {
+        self.congested
+    }
+
+    /// How many times the state changed
+    pub const fn transitions(&self) -> u64 {
+        self.transitions
+    }
+
+    /// Update the state from the current free space, returning the new state if it changed
+    pub fn update(&mut self, tx_free: u32) -> Option<bool> {
+        let congested = if self.congested { tx_free < self.high } else { tx_free < self.low };
+        if congested == self.congested {
+            return None;
+        }
+        self.congested = congested;
+        self.transitions += 1;
+        Some(congested)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::TxWatermarks;
+    use crate::{testing::MockXDP, DefaultAllocator, Umem, UmemAllocatorFactory};
+
+    #[test]
+    fn test_watermarks_hysteresis() {
+        let mut watermarks = TxWatermarks::new(4, 12);
+        assert_eq!(watermarks.update(8), None);
+        assert_eq!(watermarks.update(3), Some(true));
+        assert_eq!(watermarks.update(8), None);
+        assert!(watermarks.is_congested());
+        assert_eq!(watermarks.update(12), Some(false));
+        assert_eq!(watermarks.update(4), None);
+        assert_eq!(watermarks.transitions(), 2);
+    }
+
+    #[test]
+    fn test_socket_backpressure() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let allocator = DefaultAllocator::for_umem(umem.clone());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+        socket.tx_watermarks = Some(TxWatermarks::new(4, 12));
+
+        // filling the ring raises the flag
+        let mut batch = socket.tx_batch();
+        for _ in 0..12 {
+            assert!(batch.send(&allocator, b"frame").unwrap());
+        }
+        batch.commit().unwrap();
+        assert!(socket.is_tx_congested());
+        assert_eq!(socket.tx_backlog().tx_queued, 12);
+
+        // the kernel catching up lowers it, completions pile up until reaped
+        mock.transmit(|_| {});
+        assert!(! socket.is_tx_congested());
+        assert_eq!(socket.tx_backlog(), super::TxBacklog { tx_free: 15, tx_queued: 0, completions: 12 });
+        assert_eq!(socket.reap_completions(&allocator), 12);
+        assert_eq!(socket.tx_backlog().completions, 0);
+    }
+}
@@ -0,0 +1,167 @@
+//! Capture the traffic of an interface queue through an AF_XDP socket, printing a summary of every frame or writing a pcap file
+//!
+//! Captured frames are taken away from the kernel stack, use a filter expression or the filter options to only capture
+//! what is needed. The part of an expression the redirect program cannot apply is checked here, frames it rejects are
+//! still taken away from the kernel stack.
+
+use std::{os::fd::AsRawFd, process::ExitCode};
+
+use xdrippi::{
+    capture::{CaptureFilter, PcapWriter},
+    config::SocketConfig,
+    packet::{ip_protocol, parse, NetworkHeader, TransportHeader},
+    BPFRedirectManager, DefaultAllocator, RedirectFilter, UmemAllocatorFactory,
+};
+
+const USAGE: &str = "\
+usage: xdrippi-dump <interface> [options] [expression]
+
+expression:
+  a pcap-filter(7) expression, e.g. \"udp port 53\" or \"vlan 10 and not arp\"
+
+options:
+  -q, --queue <id>         queue to capture from (default 0)
+  -w, --write <file>       write a pcap file instead of printing frames
+  -c, --count <n>          stop after n frames
+  --ethertype <type>       only capture this ethertype (e.g. 0x0800)
+  --proto <tcp|udp|icmp|n> only capture this IP protocol
+  --port <port>            only capture TCP/UDP traffic with this source or destination port
+  -h, --help               show this help";
+
+#[derive(Debug, Default)]
+struct Args {
+    interface: String,
+    queue_id: u32,
+    output: Option<String>,
+    count: Option<u64>,
+    filter: RedirectFilter,
+    expression: Option<CaptureFilter>,
+}
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut interface = None;
+        let mut expression = Vec::new();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("missing value for {name}"));
+            match arg.as_str() {
+                "-q" | "--queue" => parsed.queue_id = parse_number(&value(&arg)?)?,
+                "-w" | "--write" => parsed.output = Some(value(&arg)?),
+                "-c" | "--count" => parsed.count = Some(parse_number(&value(&arg)?)?),
+                "--ethertype" => parsed.filter.ethertype = Some(parse_number(&value(&arg)?)?),
+                "--proto" => parsed.filter.ip_protocol = Some(match value(&arg)?.as_str() {
+                    "tcp" => ip_protocol::TCP,
+                    "udp" => ip_protocol::UDP,
+                    "icmp" => ip_protocol::ICMP,
+                    "icmpv6" => ip_protocol::ICMPV6,
+                    other => parse_number(other)?,
+                }),
+                "--port" => parsed.filter.port = Some(parse_number(&value(&arg)?)?),
+                "-h" | "--help" => return Err(String::new()),
+                other if other.starts_with('-') => return Err(format!("unknown option {other}")),
+                other if interface.is_none() => interface = Some(other.to_string()),
+                other => expression.push(other.to_string()),
+            }
+        }
+        parsed.interface = interface.ok_or("missing interface")?;
+        if !expression.is_empty() {
+            if parsed.filter != RedirectFilter::default() {
+                return Err("filter options cannot be combined with an expression".to_string());
+            }
+            let filter: CaptureFilter = expression.join(" ").parse().map_err(|error| format!("{error}"))?;
+            parsed.filter = filter.kernel_filter();
+            parsed.expression = Some(filter).filter(|filter| !filter.is_kernel_exact());
+        }
+        Ok(parsed)
+    }
+}
+
+fn parse_number<T: TryFrom<u64>>(value: &str) -> Result<T, String> {
+    let number = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    number.ok()
+        .and_then(|number| T::try_from(number).ok())
+        .ok_or_else(|| format!("invalid number {value}"))
+}
+
+fn describe(frame: &[u8]) -> String {
+    let Some(parsed) = parse(frame) else {
+        return format!("truncated frame, {} bytes", frame.len());
+    };
+    let ethernet = format!("{} > {}", parsed.ethernet.source(), parsed.ethernet.destination());
+    let ports = match parsed.transport {
+        Some(TransportHeader::Tcp(tcp)) => Some(("TCP", tcp.source_port(), tcp.destination_port())),
+        Some(TransportHeader::Udp(udp)) => Some(("UDP", udp.source_port(), udp.destination_port())),
+        None => None,
+    };
+    let network = match (parsed.network, ports) {
+        (Some(NetworkHeader::Ipv4(ip)), Some((proto, sp, dp))) => format!("{proto} {}:{sp} > {}:{dp}", ip.source(), ip.destination()),
+        (Some(NetworkHeader::Ipv6(ip)), Some((proto, sp, dp))) => format!("{proto} [{}]:{sp} > [{}]:{dp}", ip.source(), ip.destination()),
+        (Some(NetworkHeader::Ipv4(ip)), None) => format!("IPv4 {} > {} proto {}", ip.source(), ip.destination(), ip.protocol()),
+        (Some(NetworkHeader::Ipv6(ip)), None) => format!("IPv6 {} > {} next header {}", ip.source(), ip.destination(), ip.next_header()),
+        (None, _) => format!("ethertype {:#06x}", parsed.ethernet.inner_ethertype()),
+    };
+    format!("{ethernet}, {network}, {} bytes", frame.len())
+}
+
+fn run(args: Args) -> Result<(), xdrippi::Error> {
+    // socket
+    let mut sock = SocketConfig::new(&args.interface, args.queue_id).build()?;
+    let mut bpf_manager = BPFRedirectManager::attach(sock.if_index);
+    bpf_manager.set_filter(args.filter)?;
+    bpf_manager.add_redirect(args.queue_id, sock.as_raw_fd())?;
+
+    // fill ring
+    let allocator = DefaultAllocator::for_umem(sock.umem.clone());
+    sock.refill_fill_ring(&allocator, usize::MAX);
+
+    // capture
+    let mut writer = args.output.as_ref().map(PcapWriter::create).transpose()?;
+    let epoch = std::time::Instant::now();
+    let mut captured = 0;
+    let mut result = Ok(());
+    while result.is_ok() && args.count.is_none_or(|count| captured < count) {
+        sock.poll_for_reception()?;
+        sock.recv(&allocator, |frame| {
+            if result.is_err() || args.count.is_some_and(|count| captured >= count) {
+                return;
+            }
+            if args.expression.as_ref().is_some_and(|filter| !filter.matches(frame)) {
+                return;
+            }
+            captured += 1;
+            match writer.as_mut() {
+                Some(writer) => result = writer.write_frame(frame),
+                None => println!("{:>12.6} {}", epoch.elapsed().as_secs_f64(), describe(frame)),
+            }
+        });
+        // keep the file usable when interrupted
+        if let Some(writer) = writer.as_mut() && result.is_ok() {
+            result = writer.flush();
+        }
+    }
+    result
+}
+
+fn main() -> ExitCode {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) if message.is_empty() => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        },
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        },
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("xdrippi-dump: {error}");
+            ExitCode::FAILURE
+        },
+    }
+}
@@ -1,43 +1,918 @@
-use std::os::fd::AsRawFd;
+use std::{collections::HashMap, ffi::OsString, os::{fd::{AsFd, AsRawFd, RawFd}, unix::ffi::OsStringExt}, path::{Path, PathBuf}, time::Duration};
 
 use libbpf_rs::MapCore;
 
+use crate::{packet::MacAddress, BindFlags, BindMode, RingSizes, Umem, XDPSocket};
+
+/// Restricts which frames the redirect program sends to sockets, the others continue to the kernel stack
+///
+/// Every criterion set must match; a single VLAN tag is skipped, IPv6 extension headers are not
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RedirectFilter {
+    /// The ethertype, after the VLAN tag if any
+    pub ethertype: Option<u16>,
+    /// The id of the VLAN tag, untagged frames never match
+    pub vlan_id: Option<u16>,
+    /// The IPv4 protocol or IPv6 next header
+    pub ip_protocol: Option<u8>,
+    /// The TCP or UDP source or destination port
+    pub port: Option<u16>,
+}
+impl RedirectFilter {
+    // must match struct redirect_filter in redirect.c
+    const FILTER_ETHERTYPE: u32 = 1 << 0;
+    const FILTER_IP_PROTOCOL: u32 = 1 << 1;
+    const FILTER_PORT: u32 = 1 << 2;
+    const FILTER_VLAN: u32 = 1 << 3;
+
+    fn to_map_value(self) -> [u8; 12] {
+        let flags = self.ethertype.map_or(0, |_| Self::FILTER_ETHERTYPE)
+            | self.ip_protocol.map_or(0, |_| Self::FILTER_IP_PROTOCOL)
+            | self.port.map_or(0, |_| Self::FILTER_PORT)
+            | self.vlan_id.map_or(0, |_| Self::FILTER_VLAN);
+        let mut value = [0_u8; 12];
+        value[0..4].copy_from_slice(&flags.to_ne_bytes());
+        value[4..6].copy_from_slice(&self.ethertype.unwrap_or_default().to_ne_bytes());
+        value[6] = self.ip_protocol.unwrap_or_default();
+        value[8..10].copy_from_slice(&self.port.unwrap_or_default().to_ne_bytes());
+        value[10..12].copy_from_slice(&self.vlan_id.unwrap_or_default().to_ne_bytes());
+        value
+    }
+}
+
+/// What the redirect program does with the frames a [`SteeringRule`] matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SteeringAction {
+    /// To the socket of the queue the frame arrived on
+    Redirect,
+    /// To the socket added for `queue_id` with [`BPFRedirectManager::add_redirect`]
+    ///
+    /// The kernel only delivers to sockets bound to the queue the frame arrived on, so this targets sockets sharing the
+    /// queue, e.g. a [`crate::SharedXDPSocket`] added under an id past the number of queues of the NIC. Ids go up to
+    /// [`BPFRedirectManager::MAX_QUEUES`], 64, excluded
+    RedirectTo(u32),
+    /// To the kernel stack
+    Pass,
+    Drop,
+}
+
+/// Frames the redirect program treats alike, see [`BPFRedirectManager::set_steering_rules`]
+///
+/// ```ignore
+/// manager.set_steering_rules(&[
+///     SteeringRule::new(SteeringAction::Pass).ip_protocol(ip_protocol::TCP).port(22),
+///     SteeringRule::new(SteeringAction::Drop).vlan(666),
+///     SteeringRule::new(SteeringAction::Redirect).ethertype(ethertype::IPV4),
+/// ])?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SteeringRule {
+    pub filter: RedirectFilter,
+    pub action: SteeringAction,
+}
+impl SteeringRule {
+    // must match STEER_* in redirect.c
+    const STEER_REDIRECT: u32 = 1;
+    const STEER_REDIRECT_TO: u32 = 2;
+    const STEER_PASS: u32 = 3;
+    const STEER_DROP: u32 = 4;
+
+    /// A rule applying `action` to every frame, until narrowed down
+    pub fn new(action: SteeringAction) -> Self {
+        Self { filter: RedirectFilter::default(), action }
+    }
+
+    pub const fn ethertype(mut self, ethertype: u16) -> Self {
+        self.filter.ethertype = Some(ethertype);
+        self
+    }
+
+    pub const fn vlan(mut self, vlan_id: u16) -> Self {
+        self.filter.vlan_id = Some(vlan_id);
+        self
+    }
+
+    pub const fn ip_protocol(mut self, ip_protocol: u8) -> Self {
+        self.filter.ip_protocol = Some(ip_protocol);
+        self
+    }
+
+    pub const fn port(mut self, port: u16) -> Self {
+        self.filter.port = Some(port);
+        self
+    }
+
+    // must match struct steering_rule in redirect.c
+    fn to_map_value(self) -> [u8; 20] {
+        let (action, entry) = match self.action {
+            SteeringAction::Redirect => (Self::STEER_REDIRECT, 0),
+            SteeringAction::RedirectTo(queue_id) => (Self::STEER_REDIRECT_TO, queue_id),
+            SteeringAction::Pass => (Self::STEER_PASS, 0),
+            SteeringAction::Drop => (Self::STEER_DROP, 0),
+        };
+        let mut value = [0_u8; 20];
+        value[0..12].copy_from_slice(&self.filter.to_map_value());
+        value[12..16].copy_from_slice(&action.to_ne_bytes());
+        value[16..20].copy_from_slice(&entry.to_ne_bytes());
+        value
+    }
+}
+
+/// The first bytes of a frame sampled by the redirect program, see [`BPFRedirectManager::set_sampling`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketSample<'a> {
+    /// When the frame was seen, in nanoseconds of `CLOCK_MONOTONIC`
+    pub timestamp_ns: u64,
+    pub queue_id: u32,
+    /// The length of the whole frame
+    pub len: u32,
+    pub headers: &'a [u8],
+}
+impl<'a> PacketSample<'a> {
+    /// The most bytes copied from a frame, must match SAMPLE_MAX_BYTES in redirect.c
+    pub const MAX_LEN: u32 = 256;
+
+    // must match struct sample in redirect.c
+    const HEADER_SIZE: usize = 24;
+
+    fn from_record(record: &'a [u8]) -> Option<Self> {
+        let header = record.get(..Self::HEADER_SIZE)?;
+        let captured_len = u32::from_ne_bytes(header[16..20].try_into().unwrap()) as usize;
+        Some(Self {
+            timestamp_ns: u64::from_ne_bytes(header[0..8].try_into().unwrap()),
+            queue_id: u32::from_ne_bytes(header[8..12].try_into().unwrap()),
+            len: u32::from_ne_bytes(header[12..16].try_into().unwrap()),
+            headers: record.get(Self::HEADER_SIZE..Self::HEADER_SIZE + captured_len)?,
+        })
+    }
+}
+
+/// Reads the frames sampled by the redirect program, see [`BPFRedirectManager::sampler`]
+///
+/// Its file descriptor becomes readable when samples are available
+pub struct PacketSampler {
+    ring: libbpf_rs::RingBuffer<'static>,
+    _map: libbpf_rs::MapHandle,
+}
+impl PacketSampler {
+    /// Wait up to `timeout` for samples, passing the available ones to the callback
+    pub fn poll(&self, timeout: Duration) -> Result<(), crate::Error> {
+        self.ring.poll(timeout).map_err(|error| crate::Error::BpfFailure { error })
+    }
+
+    /// Pass the available samples to the callback without waiting
+    pub fn consume(&self) -> Result<(), crate::Error> {
+        self.ring.consume().map_err(|error| crate::Error::BpfFailure { error })
+    }
+}
+impl AsRawFd for PacketSampler {
+    fn as_raw_fd(&self) -> std::os::unix::prelude::RawFd {
+        self.ring.epoll_fd()
+    }
+}
+
+/// How the redirect program is attached, see [`BPFRedirectManager::attach_mode`]
+///
+/// The kernel attaches it natively when the driver supports XDP, generically otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XdpAttachMode {
+    /// Run by the driver
+    Native,
+    /// Run by the kernel on socket buffers, for drivers without XDP support
+    Generic,
+    /// Run by the NIC
+    Offloaded,
+}
+
+/// The path frames take to a socket, from the fastest, see [`BPFRedirectManager::bind_with_fallback`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XdpMode {
+    /// Native program and zero-copy socket
+    ZeroCopy,
+    /// Native program and a socket frames are copied to
+    Copy,
+    /// Generic program, which always copies
+    Generic,
+}
+
+/// Where [`BPFRedirectManager::attach_with`] takes the redirect program from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgramSource {
+    /// The object compiled along with the crate, empty if it was built with the `runtime-bpf` feature and no clang
+    Embedded,
+    /// The `redirect.c` shipped with the crate, compiled at runtime, see [`compile_program`]
+    BundledSource,
+    /// A compiled object, e.g. of a tweaked `redirect.c`
+    Object(PathBuf),
+    /// A variant of `redirect.c`, compiled at runtime, see [`compile_program`]
+    Source(PathBuf),
+}
+impl ProgramSource {
+    fn open(&self) -> Result<libbpf_rs::OpenObject, crate::Error> {
+        let object = match self {
+            Self::Embedded if EMBEDDED_OBJECT.is_empty() => {
+                return Err(crate::Error::InvalidConfiguration { reason: "the crate was built without a BPF object".to_string() });
+            },
+            Self::Embedded => return open_memory(EMBEDDED_OBJECT),
+            Self::Object(path) => {
+                return libbpf_rs::ObjectBuilder::default().open_file(path).map_err(|error| crate::Error::BpfFailure { error });
+            },
+            Self::BundledSource => {
+                let directory = TemporaryDirectory::new()?;
+                let source = directory.create_file("redirect.c", BUNDLED_SOURCE.as_bytes())?;
+                compile_program(source)?
+            },
+            Self::Source(path) => compile_program(path)?,
+        };
+        open_memory(&object)
+    }
+}
+
+/// The object built along with the crate
+const EMBEDDED_OBJECT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/redirect.o"));
+
+/// The source of the redirect program
+const BUNDLED_SOURCE: &str = include_str!("../bpf/redirect.c");
+
+fn open_memory(object: &[u8]) -> Result<libbpf_rs::OpenObject, crate::Error> {
+    libbpf_rs::ObjectBuilder::default().open_memory(object).map_err(|error| crate::Error::BpfFailure { error })
+}
+
+/// Compile the BPF program at `source` like the build of the crate does, returning the object
+///
+/// The `CLANG` environment variable selects the compiler, `clang` from `PATH` otherwise. Pass the object to
+/// [`BPFRedirectManager::reload`] to swap a tweaked program in without rebuilding
+pub fn compile_program(source: impl AsRef<Path>) -> Result<Vec<u8>, crate::Error> {
+    let directory = TemporaryDirectory::new()?;
+    let output = directory.0.join("redirect.o");
+    let mut command = std::process::Command::new(std::env::var_os("CLANG").unwrap_or("clang".into()));
+    command.arg("-O2").arg("-g").arg("-target").arg("bpf").arg("-c").arg(source.as_ref()).arg("-o").arg(&output);
+    let description = format!("{command:?}");
+    let result = command.output().map_err(|error| crate::Error::CommandFailure { command: description.clone(), reason: error.to_string() })?;
+    if ! result.status.success() {
+        let reason = String::from_utf8_lossy(&result.stderr).trim().to_string();
+        return Err(crate::Error::CommandFailure { command: description, reason });
+    }
+    std::fs::read(&output).map_err(|error| crate::Error::CommandFailure { command: description, reason: error.to_string() })
+}
+
+/// A directory only the current user can access in the temporary directory, removed along with its contents on drop
+///
+/// The program may run privileged: predictable paths in a shared directory would let other users plant symlinks there
+struct TemporaryDirectory(PathBuf);
+impl TemporaryDirectory {
+    fn new() -> Result<Self, crate::Error> {
+        let failure = |error: std::io::Error| crate::Error::CommandFailure { command: "mkdtemp".to_string(), reason: error.to_string() };
+        let mut template = std::env::temp_dir().join("xdrippi-redirect-XXXXXX").into_os_string().into_vec();
+        if template.contains(&0) {
+            return Err(failure(std::io::ErrorKind::InvalidInput.into()));
+        }
+        template.push(0);
+        if unsafe { libc::mkdtemp(template.as_mut_ptr().cast()) }.is_null() {
+            return Err(failure(std::io::Error::last_os_error()));
+        }
+        template.pop();
+        Ok(Self(OsString::from_vec(template).into()))
+    }
+
+    /// Create the file `name` holding `contents`, failing if it exists already
+    fn create_file(&self, name: &str, contents: &[u8]) -> Result<PathBuf, crate::Error> {
+        use std::io::Write;
+
+        let path = self.0.join(name);
+        std::fs::OpenOptions::new().write(true).create_new(true).open(&path)
+            .and_then(|mut file| file.write_all(contents))
+            .map_err(|error| crate::Error::CommandFailure { command: format!("write {}", path.display()), reason: error.to_string() })?;
+        Ok(path)
+    }
+}
+impl Drop for TemporaryDirectory {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// must match struct vlan_key in redirect.c
+fn vlan_key(if_index: libc::c_uint, vlan_id: u16, queue_id: u32) -> [u8; 12] {
+    let mut key = [0_u8; 12];
+    key[0..4].copy_from_slice(&if_index.to_ne_bytes());
+    key[4..8].copy_from_slice(&queue_id.to_ne_bytes());
+    key[8..10].copy_from_slice(&vlan_id.to_ne_bytes());
+    key
+}
+
+// must match struct mac_key in redirect.c
+fn mac_key(if_index: libc::c_uint, mac: MacAddress, queue_id: u32) -> [u8; 16] {
+    let mut key = [0_u8; 16];
+    key[0..4].copy_from_slice(&if_index.to_ne_bytes());
+    key[4..8].copy_from_slice(&queue_id.to_ne_bytes());
+    key[8..14].copy_from_slice(&mac.0);
+    key
+}
+
+/// An XSKMAP entry taken by a redirect, along with the socket it points to
+#[derive(Debug, Clone, Copy)]
+struct RedirectSlot {
+    entry: u32,
+    socket_fd: RawFd,
+}
+
+/// The lowest map entry below `max` none of `slots` takes
+fn free_slot<K>(slots: &HashMap<K, RedirectSlot>, max: u32) -> Option<u32> {
+    (0..max).find(|&entry| ! slots.values().any(|slot| slot.entry == entry))
+}
+
+/// How the program is attached to an interface
+enum Attachment {
+    /// Through a link, in the mode the kernel picks, detached when dropped
+    Link(libbpf_rs::Link),
+    /// In generic mode, detached by the manager, see [`BPFRedirectManager::attach_interface_generic`]
+    Generic,
+}
+
 /// The BPF redirect manager is tasked with loading a BPF XDP program allowing the redirection of frames to userspace AF_XDP sockets.
+///
+/// A single program can serve several interfaces, see [`Self::attach_interface`]
 pub struct BPFRedirectManager {
+    if_index: libc::c_uint,
     bpf_object: libbpf_rs::Object,
-    _bpf_link: libbpf_rs::Link,
+    /// The attachment of the program to each interface, the first one included
+    attachments: HashMap<libc::c_uint, Attachment>,
+    /// The xsks_map of each interface, inserted into xsks_maps
+    xsks_maps: HashMap<libc::c_uint, libbpf_rs::MapHandle>,
+    /// The socket each interface and queue pair is redirected to
+    queue_sockets: HashMap<(libc::c_uint, u32), RawFd>,
+    /// The vlan_xsks_map entry taken by each interface, VLAN and queue
+    vlan_slots: HashMap<(libc::c_uint, u16, u32), RedirectSlot>,
+    /// The mac_xsks_map entry taken by each interface, MAC address and queue
+    mac_slots: HashMap<(libc::c_uint, MacAddress, u32), RedirectSlot>,
 }
 impl BPFRedirectManager {
+    /// How many interfaces can have sockets, must match the size of xsks_maps in redirect.c
+    pub const MAX_INTERFACES: usize = 64;
+    /// How many queues of an interface can have a socket, must match the size of struct xsks_map in redirect.c
+    pub const MAX_QUEUES: u32 = 64;
+    /// How many rules [`Self::set_steering_rules`] takes, must match MAX_STEERING_RULES in redirect.c
+    pub const MAX_STEERING_RULES: usize = 16;
+    /// How many VLANs of a queue can have a socket, over all interfaces, must match the size of vlan_xsks_map in redirect.c
+    pub const MAX_VLAN_REDIRECTS: u32 = 1024;
+    /// How many MAC addresses of a queue can have a socket, over all interfaces, must match the size of mac_xsks_map in redirect.c
+    pub const MAX_MAC_REDIRECTS: u32 = 1024;
 
     /// Attach the XDP program to a given network interface
+    ///
+    /// The object compiled along with the crate is used, or the bundled `redirect.c` is compiled with clang when that one
+    /// is missing or the kernel rejects it, see [`Self::attach_with`]
     pub fn attach(if_index: libc::c_uint) -> Self {
-        // open object
-        let bpf_object = libbpf_rs::ObjectBuilder::default()
-            .open_memory(include_bytes!(concat!(env!("OUT_DIR"), "/redirect.o"))).unwrap()
-            .load().unwrap();
+        Self::attach_with(if_index, &[ProgramSource::Embedded, ProgramSource::BundledSource]).unwrap()
+    }
+
+    /// Attach the XDP program taken from the first of `sources` which loads, returning the error of the last one otherwise
+    pub fn attach_with(if_index: libc::c_uint, sources: &[ProgramSource]) -> Result<Self, crate::Error> {
+        // open object, the errors of all sources but the last are ignored
+        let load = |source: &ProgramSource| source.open()?.load().map_err(|error| crate::Error::BpfFailure { error });
+        let Some((last, others)) = sources.split_last() else {
+            return Err(crate::Error::InvalidConfiguration { reason: "at least one program source is needed".to_string() });
+        };
+        let bpf_object = others.iter().find_map(|source| load(source).ok()).map_or_else(|| load(last), Ok)?;
 
         // attach
-        let bpf_link = if let Some(prog) = bpf_object.progs_mut().find(|x| x.name() == "xdp_sock_redir") {
-            prog.attach_xdp(if_index as _).unwrap()
-        } else {
-            panic!()
+        let bpf_link = match bpf_object.progs_mut().find(|x| x.name() == "xdp_sock_redir") {
+            Some(prog) => prog.attach_xdp(if_index as _).map_err(|error| crate::Error::BpfFailure { error })?,
+            None => return Err(crate::Error::InvalidConfiguration { reason: "the object has no xdp_sock_redir program".to_string() }),
         };
 
-        Self { bpf_object, _bpf_link: bpf_link }
+        Ok(Self {
+            if_index,
+            bpf_object,
+            attachments: HashMap::from([(if_index, Attachment::Link(bpf_link))]),
+            xsks_maps: HashMap::new(),
+            queue_sockets: HashMap::new(),
+            vlan_slots: HashMap::new(),
+            mac_slots: HashMap::new(),
+        })
     }
 
-    /// Add an AF_XDP socket for all packets incoming from the NIC queue `queue_id`
-    pub fn add_redirect(&mut self, queue_id: u32, socket_fd: impl AsRawFd) {
-        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "xsks_map") {
-            map.update(&queue_id.to_ne_bytes(), &socket_fd.as_raw_fd().to_ne_bytes(), libbpf_rs::MapFlags::ANY).unwrap();
+    /// Attach the program to `if_index` as well, so that the sockets of that interface get their frames too
+    ///
+    /// Sockets are looked up by the interface frames arrive on, then by queue, see [`Self::add_interface_redirect`], and
+    /// so are VLAN and MAC redirects. The filter, steering rules, forwarding and sampling apply to every interface
+    pub fn attach_interface(&mut self, if_index: libc::c_uint) -> Result<(), crate::Error> {
+        if self.attachments.contains_key(&if_index) {
+            return Ok(());
         }
+        let prog = self.bpf_object.progs_mut().find(|x| x.name() == "xdp_sock_redir").expect("the program is loaded");
+        let bpf_link = prog.attach_xdp(if_index as _).map_err(|error| crate::Error::BpfFailure { error })?;
+        self.attachments.insert(if_index, Attachment::Link(bpf_link));
+        Ok(())
+    }
+
+    /// Attach the program to `if_index` in generic mode, replacing its current attachment, if any
+    ///
+    /// Generic mode runs the program on socket buffers, past the driver: it works with any driver, only copies frames to
+    /// sockets, and is the slowest. The kernel does not run a native and a generic program at once, so the interface goes
+    /// without a program between the detachment of the native one and the attachment, and stays without one if the
+    /// attachment fails
+    pub fn attach_interface_generic(&mut self, if_index: libc::c_uint) -> Result<(), crate::Error> {
+        if matches!(self.attachments.get(&if_index), Some(Attachment::Generic)) {
+            return Ok(());
+        }
+        self.attachments.remove(&if_index);
+        let prog = self.bpf_object.progs().find(|x| x.name() == "xdp_sock_redir").expect("the program is loaded");
+        libbpf_rs::Xdp::new(prog.as_fd()).attach(if_index as _, libbpf_rs::XdpFlags::SKB_MODE)
+            .map_err(|error| crate::Error::BpfFailure { error })?;
+        self.attachments.insert(if_index, Attachment::Generic);
+        Ok(())
+    }
+
+    /// Detach the program from `if_index`, dropping the redirects to its sockets
+    ///
+    /// Fails with [`crate::Error::InvalidConfiguration`] for the interface the manager was created with, which stays attached
+    /// as long as the manager: drop the manager instead
+    pub fn detach_interface(&mut self, if_index: libc::c_uint) -> Result<(), crate::Error> {
+        if if_index == self.if_index {
+            return Err(crate::Error::InvalidConfiguration { reason: "the first interface stays attached as long as the manager".to_string() });
+        }
+        self.detach(if_index)?;
+        self.queue_sockets.retain(|&(interface, _), _| interface != if_index);
+        let vlans: Vec<_> = self.vlan_slots.keys().filter(|&&(interface, _, _)| interface == if_index).copied().collect();
+        for (_, vlan_id, queue_id) in vlans {
+            self.del_interface_vlan_redirect(if_index, vlan_id, queue_id)?;
+        }
+        let macs: Vec<_> = self.mac_slots.keys().filter(|&&(interface, _, _)| interface == if_index).copied().collect();
+        for (_, mac, queue_id) in macs {
+            self.del_interface_mac_redirect(if_index, mac, queue_id)?;
+        }
+        if self.xsks_maps.remove(&if_index).is_some()
+            && let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "xsks_maps") {
+            map.delete(&if_index.to_ne_bytes()).map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        Ok(())
+    }
+
+    /// The interfaces the program is attached to
+    pub fn interfaces(&self) -> impl Iterator<Item = libc::c_uint> + '_ {
+        self.attachments.keys().copied()
+    }
+
+    /// Drop the attachment of the program to `if_index`
+    fn detach(&mut self, if_index: libc::c_uint) -> Result<(), crate::Error> {
+        if let Some(Attachment::Generic) = self.attachments.remove(&if_index) {
+            let prog = self.bpf_object.progs().find(|x| x.name() == "xdp_sock_redir").expect("the program is loaded");
+            libbpf_rs::Xdp::new(prog.as_fd()).detach(if_index as _, libbpf_rs::XdpFlags::SKB_MODE)
+                .map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        Ok(())
+    }
+
+    /// How the program is attached to the interface
+    pub fn attach_mode(&self) -> Result<XdpAttachMode, crate::Error> {
+        // from enum in linux/if_link.h
+        const XDP_ATTACHED_DRV: u8 = 1;
+        const XDP_ATTACHED_SKB: u8 = 2;
+        const XDP_ATTACHED_HW: u8 = 3;
+
+        let prog = self.bpf_object.progs().find(|x| x.name() == "xdp_sock_redir").expect("the program is loaded");
+        let query = libbpf_rs::Xdp::new(prog.as_fd()).query(self.if_index as _, libbpf_rs::XdpFlags::NONE)
+            .map_err(|error| crate::Error::BpfFailure { error })?;
+        match query.attach_mode {
+            XDP_ATTACHED_DRV => Ok(XdpAttachMode::Native),
+            XDP_ATTACHED_SKB => Ok(XdpAttachMode::Generic),
+            XDP_ATTACHED_HW => Ok(XdpAttachMode::Offloaded),
+            mode => Err(crate::Error::InvalidConfiguration { reason: format!("unexpected XDP attach mode {mode}") }),
+        }
+    }
+
+    /// Create a socket for the queue `queue_id` of the interface and redirect its frames to it, taking the fastest path available
+    ///
+    /// The socket is bound with [`XDPSocket::bind_with_fallback`], trying zero-copy then copy mode. When the driver takes
+    /// neither, the program is attached again in generic mode, see [`Self::attach_interface_generic`], and the socket bound
+    /// in copy mode. So one binary works on NICs with and without zero-copy or even XDP support, and the path obtained is
+    /// returned along with the socket
+    pub fn bind_with_fallback<'a>(
+        &mut self,
+        queue_id: libc::c_uint,
+        umem: std::sync::Arc<Umem>,
+        ring_sizes: RingSizes,
+        bind_flags: BindFlags,
+    ) -> Result<(XDPSocket<'a>, XdpMode), crate::Error> {
+        let (socket, bind_mode) = match XDPSocket::bind_with_fallback(self.if_index, queue_id, umem.clone(), ring_sizes, bind_flags) {
+            Ok(bound) => bound,
+            Err(error) if matches!(error.root_cause(), crate::Error::SocketBindFailure { .. }) => {
+                self.attach_interface_generic(self.if_index)?;
+                let socket = XDPSocket::with_bind_flags(self.if_index, queue_id, umem, ring_sizes, BindFlags { mode: BindMode::Copy, ..bind_flags })?;
+                (socket, BindMode::Copy)
+            },
+            Err(error) => return Err(error),
+        };
+        self.add_redirect(queue_id, socket.as_raw_fd())?;
+        let mode = match (self.attach_mode()?, bind_mode) {
+            (XdpAttachMode::Generic, _) => XdpMode::Generic,
+            (_, BindMode::ZeroCopy) => XdpMode::ZeroCopy,
+            (_, _) => XdpMode::Copy,
+        };
+        Ok((socket, mode))
+    }
+
+    /// Atomically replace the attached program with the one compiled in `object`, e.g. an updated `redirect.c`
+    ///
+    /// The new object reuses the maps of the current one having the same name, so sockets, filter and sampling stay in place
+    /// and no frame is missed. Its program must be named `xdp_sock_redir` and its shared maps must have the same definitions,
+    /// on failure the current program stays attached. With several interfaces, those swapped before a failure keep the new one
+    pub fn reload(&mut self, object: &[u8]) -> Result<(), crate::Error> {
+        let open_object = libbpf_rs::ObjectBuilder::default().open_memory(object).map_err(|error| crate::Error::BpfFailure { error })?;
+        self.replace_program(open_object)
+    }
+
+    /// Like [`Self::reload`], with the object read from `path`
+    pub fn reload_from_file(&mut self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let open_object = libbpf_rs::ObjectBuilder::default().open_file(path).map_err(|error| crate::Error::BpfFailure { error })?;
+        self.replace_program(open_object)
+    }
+
+    fn replace_program(&mut self, mut open_object: libbpf_rs::OpenObject) -> Result<(), crate::Error> {
+        // share maps
+        for mut map in open_object.maps_mut() {
+            if let Some(current) = self.bpf_object.maps().find(|x| x.name() == map.name()) {
+                map.reuse_fd(current.as_fd()).map_err(|error| crate::Error::BpfFailure { error })?;
+            }
+        }
+        let bpf_object = open_object.load().map_err(|error| crate::Error::BpfFailure { error })?;
+
+        // swap programs
+        match bpf_object.progs().find(|x| x.name() == "xdp_sock_redir") {
+            Some(prog) => {
+                for (&if_index, attachment) in &mut self.attachments {
+                    match attachment {
+                        Attachment::Link(bpf_link) => bpf_link.update_prog(&prog),
+                        Attachment::Generic => libbpf_rs::Xdp::new(prog.as_fd()).attach(if_index as _, libbpf_rs::XdpFlags::SKB_MODE),
+                    }.map_err(|error| crate::Error::BpfFailure { error })?;
+                }
+            },
+            None => return Err(crate::Error::InvalidConfiguration { reason: "the object has no xdp_sock_redir program".to_string() }),
+        }
+        self.bpf_object = bpf_object;
+        Ok(())
+    }
+
+    /// Add an AF_XDP socket for all packets incoming from the NIC queue `queue_id`, see [`Self::add_interface_redirect`]
+    pub fn add_redirect(&mut self, queue_id: u32, socket_fd: impl AsRawFd) -> Result<(), crate::Error> {
+        self.add_interface_redirect(self.if_index, queue_id, socket_fd)
     }
 
     /// Remove an AF_XDP socket for all packets incoming from the NIC queue `queue_id`
-    pub fn del_redirect(&mut self, queue_id: u32) {
-        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "xsks_map") {
-            map.delete(&(queue_id as i32).to_ne_bytes()).unwrap();
+    pub fn del_redirect(&mut self, queue_id: u32) -> Result<(), crate::Error> {
+        self.del_interface_redirect(self.if_index, queue_id)
+    }
+
+    /// Add an AF_XDP socket for all packets incoming from the NIC queue `queue_id` of `if_index`
+    ///
+    /// The program must be attached to `if_index` for the socket to get frames, see [`Self::attach_interface`]. The xsks_map
+    /// of the interface is created along with its first socket. Fails with [`crate::Error::InvalidConfiguration`] for queues
+    /// from [`Self::MAX_QUEUES`] on, or when [`Self::MAX_INTERFACES`] interfaces already have sockets
+    pub fn add_interface_redirect(&mut self, if_index: libc::c_uint, queue_id: u32, socket_fd: impl AsRawFd) -> Result<(), crate::Error> {
+        if queue_id >= Self::MAX_QUEUES {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("queue {queue_id} is beyond the {} queues of an interface", Self::MAX_QUEUES) });
+        }
+        let socket_fd = socket_fd.as_raw_fd();
+        let entry = (queue_id.to_ne_bytes(), socket_fd.to_ne_bytes());
+        if let Some(xsks_map) = self.xsks_maps.get(&if_index) {
+            xsks_map.update(&entry.0, &entry.1, libbpf_rs::MapFlags::ANY).map_err(|error| crate::Error::BpfFailure { error })?;
+            self.queue_sockets.insert((if_index, queue_id), socket_fd);
+            return Ok(());
+        }
+        if self.xsks_maps.len() >= Self::MAX_INTERFACES {
+            return Err(crate::Error::InvalidConfiguration { reason: "too many interfaces with sockets".to_string() });
+        }
+
+        // socket first, so that the interface never points to an empty map; must match struct xsks_map in redirect.c
+        let options = libbpf_rs::libbpf_sys::bpf_map_create_opts { sz: size_of::<libbpf_rs::libbpf_sys::bpf_map_create_opts>() as _, ..Default::default() };
+        let xsks_map = libbpf_rs::MapHandle::create(libbpf_rs::MapType::Xskmap, Some("xsks_map"), 4, 4, Self::MAX_QUEUES, &options)
+            .map_err(|error| crate::Error::BpfFailure { error })?;
+        xsks_map.update(&entry.0, &entry.1, libbpf_rs::MapFlags::ANY).map_err(|error| crate::Error::BpfFailure { error })?;
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "xsks_maps") {
+            map.update(&if_index.to_ne_bytes(), &xsks_map.as_fd().as_raw_fd().to_ne_bytes(), libbpf_rs::MapFlags::ANY)
+                .map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        self.xsks_maps.insert(if_index, xsks_map);
+        self.queue_sockets.insert((if_index, queue_id), socket_fd);
+        Ok(())
+    }
+
+    /// Remove an AF_XDP socket for all packets incoming from the NIC queue `queue_id` of `if_index`
+    pub fn del_interface_redirect(&mut self, if_index: libc::c_uint, queue_id: u32) -> Result<(), crate::Error> {
+        self.queue_sockets.remove(&(if_index, queue_id));
+        match self.xsks_maps.get(&if_index) {
+            Some(xsks_map) => xsks_map.delete(&queue_id.to_ne_bytes()).map_err(|error| crate::Error::BpfFailure { error }),
+            None => Ok(()),
+        }
+    }
+
+    /// Redirect the frames tagged `vlan_id` which arrive on the NIC queue `queue_id` to an AF_XDP socket of their own, see
+    /// [`Self::add_interface_vlan_redirect`]
+    pub fn add_vlan_redirect(&mut self, vlan_id: u16, queue_id: u32, socket_fd: impl AsRawFd) -> Result<(), crate::Error> {
+        self.add_interface_vlan_redirect(self.if_index, vlan_id, queue_id, socket_fd)
+    }
+
+    /// Stop redirecting the frames tagged `vlan_id` which arrive on the NIC queue `queue_id` to their own socket
+    pub fn del_vlan_redirect(&mut self, vlan_id: u16, queue_id: u32) -> Result<(), crate::Error> {
+        self.del_interface_vlan_redirect(self.if_index, vlan_id, queue_id)
+    }
+
+    /// Redirect the frames tagged `vlan_id` which arrive on the NIC queue `queue_id` of `if_index` to an AF_XDP socket of
+    /// their own
+    ///
+    /// The socket must be bound to `queue_id`, e.g. a [`crate::SharedXDPSocket`] alongside the one of the queue, which keeps
+    /// the untagged frames and the other VLANs. Only the outer tag counts, and only if the NIC leaves it in the frame: turn
+    /// VLAN stripping off with `ethtool -K <interface> rxvlan off`. Fails with [`crate::Error::InvalidConfiguration`] for
+    /// ids beyond 12 bits or when [`Self::MAX_VLAN_REDIRECTS`] VLANs already have a socket
+    pub fn add_interface_vlan_redirect(&mut self, if_index: libc::c_uint, vlan_id: u16, queue_id: u32, socket_fd: impl AsRawFd) -> Result<(), crate::Error> {
+        if vlan_id >= 4096 {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("VLAN id {vlan_id} is beyond 12 bits") });
+        }
+        let entry = match self.vlan_slots.get(&(if_index, vlan_id, queue_id)) {
+            Some(slot) => slot.entry,
+            None => free_slot(&self.vlan_slots, Self::MAX_VLAN_REDIRECTS).ok_or(crate::Error::InvalidConfiguration { reason: "too many VLAN redirects".to_string() })?,
+        };
+        let slot = RedirectSlot { entry, socket_fd: socket_fd.as_raw_fd() };
+
+        // socket first, so that the key never points to an empty slot
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "vlan_xsks_map") {
+            map.update(&entry.to_ne_bytes(), &slot.socket_fd.to_ne_bytes(), libbpf_rs::MapFlags::ANY)
+                .map_err(|error| crate::Error::BpfFailure { error })?;
         }
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "vlan_map") {
+            map.update(&vlan_key(if_index, vlan_id, queue_id), &entry.to_ne_bytes(), libbpf_rs::MapFlags::ANY)
+                .map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        self.vlan_slots.insert((if_index, vlan_id, queue_id), slot);
+        Ok(())
     }
 
+    /// Stop redirecting the frames tagged `vlan_id` which arrive on the NIC queue `queue_id` of `if_index` to their own socket
+    ///
+    /// The key goes first, so that the socket gets no frames even when its entry cannot be removed
+    pub fn del_interface_vlan_redirect(&mut self, if_index: libc::c_uint, vlan_id: u16, queue_id: u32) -> Result<(), crate::Error> {
+        let Some(slot) = self.vlan_slots.remove(&(if_index, vlan_id, queue_id)) else {
+            return Ok(());
+        };
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "vlan_map") {
+            map.delete(&vlan_key(if_index, vlan_id, queue_id)).map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "vlan_xsks_map") {
+            map.delete(&slot.entry.to_ne_bytes()).map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        Ok(())
+    }
+
+    /// Remove the redirects to `socket`, VLAN and MAC ones included, then [`XDPSocket::close`] it
+    ///
+    /// Redirects of the same queue to other sockets, e.g. [`crate::SharedXDPSocket`]s, stay in place. The socket is closed
+    /// even if its redirects could not all be removed, the first failure is returned
+    pub fn close_socket(&mut self, socket: XDPSocket) -> Result<(), crate::Error> {
+        let socket_fd = socket.as_raw_fd();
+        let mut removed = Ok(());
+        let vlans: Vec<_> = self.vlan_slots.iter().filter(|(_, slot)| slot.socket_fd == socket_fd).map(|(&key, _)| key).collect();
+        for (if_index, vlan_id, queue_id) in vlans {
+            removed = removed.and(self.del_interface_vlan_redirect(if_index, vlan_id, queue_id));
+        }
+        let macs: Vec<_> = self.mac_slots.iter().filter(|(_, slot)| slot.socket_fd == socket_fd).map(|(&key, _)| key).collect();
+        for (if_index, mac, queue_id) in macs {
+            removed = removed.and(self.del_interface_mac_redirect(if_index, mac, queue_id));
+        }
+        if self.queue_sockets.get(&(socket.if_index, socket.if_queue)) == Some(&socket_fd) {
+            removed = removed.and(self.del_interface_redirect(socket.if_index, socket.if_queue));
+        }
+        let closed = socket.close();
+        removed.and(closed)
+    }
+
+    /// The interface, VLAN and queue of every VLAN redirected to its own socket
+    pub fn vlan_redirects(&self) -> impl Iterator<Item = (libc::c_uint, u16, u32)> + '_ {
+        self.vlan_slots.keys().copied()
+    }
+
+    /// Redirect the frames to `mac` which arrive on the NIC queue `queue_id` to an AF_XDP socket of their own, see
+    /// [`Self::add_interface_mac_redirect`]
+    pub fn add_mac_redirect(&mut self, mac: MacAddress, queue_id: u32, socket_fd: impl AsRawFd) -> Result<(), crate::Error> {
+        self.add_interface_mac_redirect(self.if_index, mac, queue_id, socket_fd)
+    }
+
+    /// Stop redirecting the frames to `mac` which arrive on the NIC queue `queue_id` to their own socket
+    pub fn del_mac_redirect(&mut self, mac: MacAddress, queue_id: u32) -> Result<(), crate::Error> {
+        self.del_interface_mac_redirect(self.if_index, mac, queue_id)
+    }
+
+    /// Redirect the frames to `mac` which arrive on the NIC queue `queue_id` of `if_index` to an AF_XDP socket of their own
+    ///
+    /// This is how a virtual switch hands the traffic of each VM to its socket without looking at it. The socket must be
+    /// bound to `queue_id`, like for [`Self::add_interface_vlan_redirect`], which this takes precedence over. Fails with
+    /// [`crate::Error::InvalidConfiguration`] when [`Self::MAX_MAC_REDIRECTS`] MAC addresses already have a socket
+    pub fn add_interface_mac_redirect(&mut self, if_index: libc::c_uint, mac: MacAddress, queue_id: u32, socket_fd: impl AsRawFd) -> Result<(), crate::Error> {
+        let entry = match self.mac_slots.get(&(if_index, mac, queue_id)) {
+            Some(slot) => slot.entry,
+            None => free_slot(&self.mac_slots, Self::MAX_MAC_REDIRECTS).ok_or(crate::Error::InvalidConfiguration { reason: "too many MAC redirects".to_string() })?,
+        };
+        let slot = RedirectSlot { entry, socket_fd: socket_fd.as_raw_fd() };
+
+        // socket first, so that the key never points to an empty slot
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "mac_xsks_map") {
+            map.update(&entry.to_ne_bytes(), &slot.socket_fd.to_ne_bytes(), libbpf_rs::MapFlags::ANY)
+                .map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "mac_map") {
+            map.update(&mac_key(if_index, mac, queue_id), &entry.to_ne_bytes(), libbpf_rs::MapFlags::ANY)
+                .map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        self.mac_slots.insert((if_index, mac, queue_id), slot);
+        Ok(())
+    }
+
+    /// Stop redirecting the frames to `mac` which arrive on the NIC queue `queue_id` of `if_index` to their own socket
+    ///
+    /// The key goes first, like for [`Self::del_interface_vlan_redirect`]
+    pub fn del_interface_mac_redirect(&mut self, if_index: libc::c_uint, mac: MacAddress, queue_id: u32) -> Result<(), crate::Error> {
+        let Some(slot) = self.mac_slots.remove(&(if_index, mac, queue_id)) else {
+            return Ok(());
+        };
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "mac_map") {
+            map.delete(&mac_key(if_index, mac, queue_id)).map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "mac_xsks_map") {
+            map.delete(&slot.entry.to_ne_bytes()).map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        Ok(())
+    }
+
+    /// The interface, MAC address and queue of every MAC address redirected to its own socket
+    pub fn mac_redirects(&self) -> impl Iterator<Item = (libc::c_uint, MacAddress, u32)> + '_ {
+        self.mac_slots.keys().copied()
+    }
+
+    /// Apply the action of the first of `rules` matching to each frame, for all queues, replacing the previous rules
+    ///
+    /// Rules come before anything else the program does: forwarding, the filter and the redirection of MAC addresses,
+    /// VLANs and queues only see the frames no rule matched. An empty list removes every rule. While the rules are
+    /// replaced, frames briefly see none of them rather than a mix of old and new. Fails with
+    /// [`crate::Error::InvalidConfiguration`] beyond [`Self::MAX_STEERING_RULES`] rules or for queues from
+    /// [`Self::MAX_QUEUES`] on
+    pub fn set_steering_rules(&mut self, rules: &[SteeringRule]) -> Result<(), crate::Error> {
+        if rules.len() > Self::MAX_STEERING_RULES {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("at most {} steering rules are supported", Self::MAX_STEERING_RULES) });
+        }
+        if let Some(queue_id) = rules.iter().find_map(|rule| match rule.action {
+            SteeringAction::RedirectTo(queue_id) if queue_id >= Self::MAX_QUEUES => Some(queue_id),
+            _ => None,
+        }) {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("queue {queue_id} is beyond the {} queues of an interface", Self::MAX_QUEUES) });
+        }
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "steering_map") {
+            // end the list at its start while the rest is rewritten, the first rule goes last
+            let update = |index: usize, value: &[u8]| map.update(&(index as u32).to_ne_bytes(), value, libbpf_rs::MapFlags::ANY)
+                .map_err(|error| crate::Error::BpfFailure { error });
+            update(0, &[0_u8; 20])?;
+            if rules.len() < Self::MAX_STEERING_RULES {
+                update(rules.len(), &[0_u8; 20])?;
+            }
+            for (index, rule) in rules.iter().enumerate().skip(1) {
+                update(index, &rule.to_map_value())?;
+            }
+            if let Some(rule) = rules.first() {
+                update(0, &rule.to_map_value())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Only redirect frames matching `filter`, for all queues
+    pub fn set_filter(&mut self, filter: RedirectFilter) -> Result<(), crate::Error> {
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "filter_map") {
+            map.update(&0_u32.to_ne_bytes(), &filter.to_map_value(), libbpf_rs::MapFlags::ANY).map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        Ok(())
+    }
+
+    /// Redirect every frame again
+    pub fn clear_filter(&mut self) -> Result<(), crate::Error> {
+        self.set_filter(RedirectFilter::default())
+    }
+
+    /// Make `if_index` the port `index` frames can be forwarded to by [`Self::set_forwarding`]
+    ///
+    /// Some drivers only transmit redirected frames when an XDP program is attached to the egress interface too
+    pub fn set_forward_port(&mut self, index: u32, if_index: libc::c_uint) -> Result<(), crate::Error> {
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "tx_ports") {
+            map.update(&index.to_ne_bytes(), &if_index.to_ne_bytes(), libbpf_rs::MapFlags::ANY).map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        Ok(())
+    }
+
+    /// Remove the port `index`, frames forwarded to it go to the kernel stack instead
+    pub fn del_forward_port(&mut self, index: u32) -> Result<(), crate::Error> {
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "tx_ports") {
+            map.delete(&index.to_ne_bytes()).map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        Ok(())
+    }
+
+    /// Forward the frames matching `filter` to the port `egress` from within the kernel, for all queues
+    ///
+    /// This is the fast path of hybrid designs: forwarded frames never reach sockets, which only get the exceptions
+    /// still passing the redirect filter. An empty filter forwards everything
+    pub fn set_forwarding(&mut self, filter: RedirectFilter, egress: u32) -> Result<(), crate::Error> {
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "forward_map") {
+            // must match struct forward_rule in redirect.c
+            let mut value = [0_u8; 20];
+            value[0..12].copy_from_slice(&filter.to_map_value());
+            value[12..16].copy_from_slice(&1_u32.to_ne_bytes());
+            value[16..20].copy_from_slice(&egress.to_ne_bytes());
+            map.update(&0_u32.to_ne_bytes(), &value, libbpf_rs::MapFlags::ANY).map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        Ok(())
+    }
+
+    /// Stop forwarding from within the kernel
+    pub fn clear_forwarding(&mut self) -> Result<(), crate::Error> {
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "forward_map") {
+            map.update(&0_u32.to_ne_bytes(), &[0_u8; 20], libbpf_rs::MapFlags::ANY).map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        Ok(())
+    }
+
+    /// Copy the first `snap_len` bytes, up to [`PacketSample::MAX_LEN`], of 1 in `rate` frames to the samples ring buffer
+    ///
+    /// Frames are sampled before filtering, on every queue, whether they are redirected or not. A zero `rate` stops sampling
+    pub fn set_sampling(&mut self, rate: u32, snap_len: u32) -> Result<(), crate::Error> {
+        if let Some(map) = self.bpf_object.maps_mut().find(|x| x.name() == "sample_config_map") {
+            let mut value = [0_u8; 8];
+            value[0..4].copy_from_slice(&rate.to_ne_bytes());
+            value[4..8].copy_from_slice(&snap_len.min(PacketSample::MAX_LEN).to_ne_bytes());
+            map.update(&0_u32.to_ne_bytes(), &value, libbpf_rs::MapFlags::ANY).map_err(|error| crate::Error::BpfFailure { error })?;
+        }
+        Ok(())
+    }
+
+    /// A reader passing sampled frames to `callback`
+    ///
+    /// Samples are lost while the ring buffer is full, read it often enough for the sampling rate. Fails with
+    /// [`crate::Error::InvalidConfiguration`] if the program has no samples map
+    pub fn sampler(&self, mut callback: impl FnMut(PacketSample) + 'static) -> Result<PacketSampler, crate::Error> {
+        let map = self.bpf_object.maps().find(|x| x.name() == "samples")
+            .ok_or(crate::Error::InvalidConfiguration { reason: "the program has no samples map".to_string() })?;
+        let map = libbpf_rs::MapHandle::try_from(&map).map_err(|error| crate::Error::BpfFailure { error })?;
+        let mut builder = libbpf_rs::RingBufferBuilder::new();
+        builder.add(&map, move |record| {
+            if let Some(sample) = PacketSample::from_record(record) {
+                callback(sample);
+            }
+            0
+        }).map_err(|error| crate::Error::BpfFailure { error })?;
+        let ring = builder.build().map_err(|error| crate::Error::BpfFailure { error })?;
+        Ok(PacketSampler { ring, _map: map })
+    }
+
+}
+
+impl Drop for BPFRedirectManager {
+    fn drop(&mut self) {
+        // links detach themselves
+        for if_index in self.interfaces().collect::<Vec<_>>() {
+            let _ = self.detach(if_index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::{SteeringAction, SteeringRule, TemporaryDirectory};
+
+    #[test]
+    fn test_steering_rule_layout() {
+        let rule = SteeringRule::new(SteeringAction::RedirectTo(40)).vlan(10).port(53);
+        let value = rule.to_map_value();
+        assert_eq!(u32::from_ne_bytes(value[0..4].try_into().unwrap()), (1 << 2) | (1 << 3));
+        assert_eq!(u16::from_ne_bytes(value[8..10].try_into().unwrap()), 53);
+        assert_eq!(u16::from_ne_bytes(value[10..12].try_into().unwrap()), 10);
+        assert_eq!(u32::from_ne_bytes(value[12..16].try_into().unwrap()), 2);
+        assert_eq!(u32::from_ne_bytes(value[16..20].try_into().unwrap()), 40);
+
+        // a rule without criteria matches everything
+        assert_eq!(SteeringRule::new(SteeringAction::Drop).to_map_value()[0..12], [0; 12]);
+    }
+
+    #[test]
+    fn test_temporary_directory() {
+        let directory = TemporaryDirectory::new().unwrap();
+        let path = directory.0.clone();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o700);
+
+        // files are never reused
+        let file = directory.create_file("redirect.c", b"source").unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"source");
+        assert!(directory.create_file("redirect.c", b"other").is_err());
+        drop(directory);
+        assert!(! path.exists());
+    }
 }
@@ -0,0 +1,478 @@
+use std::{net::IpAddr, str::FromStr};
+
+use crate::{
+    packet::{ethertype, ip_protocol, parse, read_u16, FiveTuple, MacAddress, ParsedFrame},
+    RedirectFilter,
+};
+
+/// Which side of a frame an address or port criterion looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Either,
+    Source,
+    Destination,
+}
+impl Direction {
+    fn matches<T: PartialEq>(self, value: T, source: T, destination: T) -> bool {
+        match self {
+            Self::Either => value == source || value == destination,
+            Self::Source => value == source,
+            Self::Destination => value == destination,
+        }
+    }
+}
+
+/// A frame as the redirect program looks at it, see `filter_matches` in redirect.c
+///
+/// The criteria a [`RedirectFilter`] can hold are checked against this view, so that the kernel stage never drops a frame
+/// [`CaptureFilter::matches`] selects
+#[derive(Debug, Clone, Copy)]
+struct KernelView {
+    /// The ethertype after the outer VLAN tag if any
+    ethertype: u16,
+    /// The IPv4 protocol or the IPv6 next header, extension headers are not skipped
+    protocol: Option<u8>,
+    /// The source and destination ports right after the IP header, for TCP and UDP
+    ports: Option<(u16, u16)>,
+}
+impl KernelView {
+    fn new(frame: &[u8]) -> Option<Self> {
+        let read = |offset: usize| (offset + 2 <= frame.len()).then(|| read_u16(frame, offset));
+
+        // skipping one VLAN tag
+        let (mut ethertype, mut offset) = (read(12)?, 14);
+        if matches!(ethertype, ethertype::VLAN | ethertype::QINQ) {
+            (ethertype, offset) = (read(16)?, 18);
+        }
+        let (protocol, transport_offset) = match ethertype {
+            ethertype::IPV4 if frame.len() >= offset + 20 => (frame[offset + 9], offset + (frame[offset] & 0x0f) as usize * 4),
+            ethertype::IPV6 if frame.len() >= offset + 40 => (frame[offset + 6], offset + 40),
+            _ => return Some(Self { ethertype, protocol: None, ports: None }),
+        };
+        let ports = match protocol {
+            ip_protocol::TCP | ip_protocol::UDP => read(transport_offset).zip(read(transport_offset + 2)),
+            _ => None,
+        };
+        Some(Self { ethertype, protocol: Some(protocol), ports })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Primitive {
+    /// The ethertype, after the outer VLAN tag if any
+    Ethertype(u16),
+    /// The id of the outer VLAN tag, any tag when `None`
+    Vlan(Option<u16>),
+    /// The IPv4 protocol or IPv6 next header, extension headers are not followed like in pcap
+    Protocol(u8),
+    /// A TCP or UDP port, right after the IP header
+    Port(Direction, u16),
+    Host(Direction, IpAddr),
+    EtherHost(Direction, MacAddress),
+}
+impl Primitive {
+    fn matches(self, frame: &ParsedFrame, flow: Option<&FiveTuple>, view: &KernelView) -> bool {
+        match self {
+            Self::Ethertype(value) => view.ethertype == value,
+            Self::Vlan(id) => frame.ethernet.vlan_tags().next().is_some_and(|tag| id.is_none_or(|id| tag.vid == id)),
+            Self::Protocol(value) => view.protocol == Some(value),
+            Self::Port(direction, port) => view.ports.is_some_and(|(source, destination)| direction.matches(port, source, destination)),
+            Self::Host(direction, address) => flow.is_some_and(|flow| direction.matches(address, flow.source, flow.destination)),
+            Self::EtherHost(direction, address) => direction.matches(address, frame.ethernet.source(), frame.ethernet.destination()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Primitive(Primitive),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+}
+impl Node {
+    fn and(left: Self, right: Self) -> Self {
+        Self::And(Box::new(left), Box::new(right))
+    }
+
+    fn matches(&self, frame: &ParsedFrame, flow: Option<&FiveTuple>, view: &KernelView) -> bool {
+        match self {
+            Self::Primitive(primitive) => primitive.matches(frame, flow, view),
+            Self::Not(node) => !node.matches(frame, flow, view),
+            Self::And(left, right) => left.matches(frame, flow, view) && right.matches(frame, flow, view),
+            Self::Or(left, right) => left.matches(frame, flow, view) || right.matches(frame, flow, view),
+        }
+    }
+
+    /// Narrow `filter` with the criteria this node requires, returning whether it now selects exactly the same frames
+    fn narrow(&self, filter: &mut RedirectFilter) -> bool {
+        fn set<T>(field: &mut Option<T>, value: T) -> bool {
+            // a conflicting criterion means nothing matches, any filter is a superset then
+            field.is_none() && field.replace(value).is_none()
+        }
+        match self {
+            Self::And(left, right) => left.narrow(filter) & right.narrow(filter),
+            Self::Primitive(Primitive::Ethertype(value)) => set(&mut filter.ethertype, *value),
+            Self::Primitive(Primitive::Vlan(Some(id))) => set(&mut filter.vlan_id, *id),
+            Self::Primitive(Primitive::Protocol(value)) => set(&mut filter.ip_protocol, *value),
+            Self::Primitive(Primitive::Port(direction, port)) => set(&mut filter.port, *port) && *direction == Direction::Either,
+            _ => false,
+        }
+    }
+}
+
+/// A capture filter in the syntax of pcap-filter(7), e.g. `udp port 53` or `vlan 10 and not (arp or icmp)`
+///
+/// Supported primitives are `ip`, `ip6`, `arp`, `tcp`, `udp`, `icmp`, `icmp6`, `ether proto <ethertype>`,
+/// `[ip|ip6] proto <protocol>`, `vlan [id]`, `[tcp|udp] [src|dst] port <port>`, `[src|dst] host <address>` and
+/// `ether [src|dst] host <mac>`, combined with `and`/`&&`, `or`/`||`, `not`/`!` and parentheses. As in pcap, `and`
+/// and `or` have the same precedence and associate to the left. Like in the redirect program, only the outer VLAN tag
+/// is skipped to find the ethertype, IP protocols and ports, and IPv6 extension headers are not followed.
+///
+/// The redirect program only knows a single [`RedirectFilter`], so a filter is applied in two stages: the criteria all
+/// matching frames share go to the kernel with [`Self::kernel_filter`], then [`Self::matches`] checks the rest unless
+/// [`Self::is_kernel_exact`]. Frames the first stage lets through are taken away from the kernel stack either way.
+///
+/// ```ignore
+/// let filter: CaptureFilter = "udp port 53".parse()?;
+/// bpf_manager.set_filter(filter.kernel_filter())?;
+/// socket.recv(&allocator, |frame| if filter.is_kernel_exact() || filter.matches(frame) { /* capture */ });
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CaptureFilter {
+    root: Option<Node>,
+}
+impl CaptureFilter {
+    /// Whether `frame` is selected, the empty filter selects everything
+    pub fn matches(&self, frame: &[u8]) -> bool {
+        let Some(root) = &self.root else {
+            return true;
+        };
+        let (Some(parsed), Some(view)) = (parse(frame), KernelView::new(frame)) else {
+            return false;
+        };
+        root.matches(&parsed, parsed.five_tuple().as_ref(), &view)
+    }
+
+    /// The filter for the redirect program, which lets through at least the frames this one selects
+    pub fn kernel_filter(&self) -> RedirectFilter {
+        let mut filter = RedirectFilter::default();
+        if let Some(root) = &self.root {
+            root.narrow(&mut filter);
+        }
+        filter
+    }
+
+    /// Whether [`Self::kernel_filter`] selects the same frames, so that [`Self::matches`] needs not be checked
+    pub fn is_kernel_exact(&self) -> bool {
+        self.root.as_ref().is_none_or(|root| root.narrow(&mut RedirectFilter::default()))
+    }
+}
+impl FromStr for CaptureFilter {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| crate::Error::InvalidFilterExpression { expression: s.to_string(), reason };
+        let tokens = tokenize(s);
+        let mut parser = Parser { tokens: &tokens, position: 0 };
+        if tokens.is_empty() {
+            return Ok(Self::default());
+        }
+        let root = parser.expression().map_err(invalid)?;
+        if let Some(token) = parser.peek() {
+            return Err(invalid(format!("unexpected {token:?}")));
+        }
+        Ok(Self { root: Some(root) })
+    }
+}
+
+/// Split an expression into words, parentheses and operators
+fn tokenize(expression: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+    while !rest.is_empty() {
+        let len = if rest.starts_with("&&") || rest.starts_with("||") {
+            2
+        } else if rest.starts_with(['(', ')', '!']) {
+            1
+        } else {
+            rest.find(|c: char| c.is_whitespace() || "()!&|".contains(c)).unwrap_or(rest.len()).max(1)
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: &'a [&'a str],
+    position: usize,
+}
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn next(&mut self, expected: &str) -> Result<&'a str, String> {
+        let token = self.peek().ok_or_else(|| format!("missing {expected}"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn accept(&mut self, token: &str) -> bool {
+        let accepted = self.peek() == Some(token);
+        self.position += accepted as usize;
+        accepted
+    }
+
+    fn expression(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+        loop {
+            if self.accept("and") || self.accept("&&") {
+                node = Node::and(node, self.unary()?);
+            } else if self.accept("or") || self.accept("||") {
+                node = Node::Or(Box::new(node), Box::new(self.unary()?));
+            } else {
+                return Ok(node);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.accept("not") || self.accept("!") {
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+        if self.accept("(") {
+            let node = self.expression()?;
+            return match self.next("\")\"")? {
+                ")" => Ok(node),
+                other => Err(format!("expected \")\", found {other:?}")),
+            };
+        }
+        self.primitive()
+    }
+
+    fn primitive(&mut self) -> Result<Node, String> {
+        let primitive = |primitive| Ok(Node::Primitive(primitive));
+        match self.next("primitive")? {
+            "ether" => match self.next("ether qualifier")? {
+                "proto" => primitive(Primitive::Ethertype(self.ethertype()?)),
+                "host" => primitive(Primitive::EtherHost(Direction::Either, self.mac()?)),
+                "src" => self.ether_host(Direction::Source),
+                "dst" => self.ether_host(Direction::Destination),
+                other => Err(format!("unknown ether qualifier {other:?}")),
+            },
+            "ip" => self.protocol_of(ethertype::IPV4),
+            "ip6" => self.protocol_of(ethertype::IPV6),
+            "arp" => primitive(Primitive::Ethertype(ethertype::ARP)),
+            "proto" => primitive(Primitive::Protocol(self.protocol()?)),
+            "tcp" => self.port_of(ip_protocol::TCP),
+            "udp" => self.port_of(ip_protocol::UDP),
+            "icmp" => primitive(Primitive::Protocol(ip_protocol::ICMP)),
+            "icmp6" => primitive(Primitive::Protocol(ip_protocol::ICMPV6)),
+            "vlan" => {
+                let id = self.peek().and_then(|token| parse_number::<u16>(token).ok()).filter(|&id| id < 4096);
+                self.position += id.is_some() as usize;
+                primitive(Primitive::Vlan(id))
+            },
+            "src" => self.directed(Direction::Source),
+            "dst" => self.directed(Direction::Destination),
+            "port" => primitive(Primitive::Port(Direction::Either, self.port()?)),
+            "host" => primitive(Primitive::Host(Direction::Either, self.address()?)),
+            other => Err(format!("unknown primitive {other:?}")),
+        }
+    }
+
+    /// `ip` or `ip6`, possibly followed by `proto <protocol>`
+    fn protocol_of(&mut self, ethertype: u16) -> Result<Node, String> {
+        let node = Node::Primitive(Primitive::Ethertype(ethertype));
+        if !self.accept("proto") {
+            return Ok(node);
+        }
+        Ok(Node::and(node, Node::Primitive(Primitive::Protocol(self.protocol()?))))
+    }
+
+    /// `tcp` or `udp`, possibly followed by `[src|dst] port <port>`
+    fn port_of(&mut self, protocol: u8) -> Result<Node, String> {
+        let node = Node::Primitive(Primitive::Protocol(protocol));
+        let direction = match self.peek() {
+            Some("port") => Direction::Either,
+            Some("src") if self.tokens.get(self.position + 1) == Some(&"port") => Direction::Source,
+            Some("dst") if self.tokens.get(self.position + 1) == Some(&"port") => Direction::Destination,
+            _ => return Ok(node),
+        };
+        self.position += if direction == Direction::Either { 1 } else { 2 };
+        Ok(Node::and(node, Node::Primitive(Primitive::Port(direction, self.port()?))))
+    }
+
+    /// What follows `src` or `dst`
+    fn directed(&mut self, direction: Direction) -> Result<Node, String> {
+        let primitive = match self.next("\"port\" or \"host\"")? {
+            "port" => Primitive::Port(direction, self.port()?),
+            "host" => Primitive::Host(direction, self.address()?),
+            other => return Err(format!("expected \"port\" or \"host\", found {other:?}")),
+        };
+        Ok(Node::Primitive(primitive))
+    }
+
+    fn ether_host(&mut self, direction: Direction) -> Result<Node, String> {
+        self.accept("host");
+        Ok(Node::Primitive(Primitive::EtherHost(direction, self.mac()?)))
+    }
+
+    fn ethertype(&mut self) -> Result<u16, String> {
+        match self.next("ethertype")? {
+            "ip" => Ok(ethertype::IPV4),
+            "ip6" => Ok(ethertype::IPV6),
+            "arp" => Ok(ethertype::ARP),
+            other => parse_number(other),
+        }
+    }
+
+    fn protocol(&mut self) -> Result<u8, String> {
+        match self.next("protocol")? {
+            "tcp" => Ok(ip_protocol::TCP),
+            "udp" => Ok(ip_protocol::UDP),
+            "icmp" => Ok(ip_protocol::ICMP),
+            "icmp6" => Ok(ip_protocol::ICMPV6),
+            other => parse_number(other),
+        }
+    }
+
+    fn port(&mut self) -> Result<u16, String> {
+        parse_number(self.next("port")?)
+    }
+
+    fn address(&mut self) -> Result<IpAddr, String> {
+        let token = self.next("address")?;
+        token.parse().map_err(|_| format!("invalid address {token:?}"))
+    }
+
+    fn mac(&mut self) -> Result<MacAddress, String> {
+        let token = self.next("MAC address")?;
+        token.parse().map_err(|_| format!("invalid MAC address {token:?}"))
+    }
+}
+
+fn parse_number<T: TryFrom<u64>>(token: &str) -> Result<T, String> {
+    let number = match token.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => token.parse(),
+    };
+    number.ok()
+        .and_then(|number| T::try_from(number).ok())
+        .ok_or_else(|| format!("invalid number {token:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::CaptureFilter;
+    use crate::{
+        packet::{ethertype, ip_protocol, FrameBuilder, MacAddress, VlanTag},
+        RedirectFilter,
+    };
+
+    const SOURCE: MacAddress = MacAddress([2, 0, 0, 0, 0, 1]);
+
+    fn frame(builder: FrameBuilder) -> Vec<u8> {
+        let mut buffer = vec![0_u8; 256];
+        let len = builder.write_with_payload(&mut buffer, b"payload").unwrap();
+        buffer.truncate(len);
+        buffer
+    }
+
+    fn filter(expression: &str) -> CaptureFilter {
+        expression.parse().unwrap()
+    }
+
+    #[test]
+    fn test_matches() {
+        let dns = frame(FrameBuilder::ethernet(SOURCE, MacAddress::BROADCAST)
+            .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 53))
+            .udp(40000, 53));
+        let tagged = frame(FrameBuilder::ethernet(SOURCE, MacAddress::BROADCAST)
+            .vlan(VlanTag { tpid: ethertype::VLAN, pcp: 0, dei: false, vid: 10 })
+            .ipv6(Ipv6Addr::LOCALHOST, Ipv6Addr::LOCALHOST)
+            .udp(1234, 5678));
+        let arp = frame(FrameBuilder::ethernet(SOURCE, MacAddress::BROADCAST).ethertype(ethertype::ARP));
+
+        for (expression, expected) in [
+            ("", [true, true, true]),
+            ("udp port 53", [true, false, false]),
+            ("udp dst port 53", [true, false, false]),
+            ("src port 53", [false, false, false]),
+            ("ip or arp", [true, false, true]),
+            ("ip6 && vlan 10", [false, true, false]),
+            ("vlan", [false, true, false]),
+            ("vlan 11", [false, false, false]),
+            ("not (arp or icmp)", [true, true, false]),
+            ("!arp", [true, true, false]),
+            ("host 10.0.0.53", [true, false, false]),
+            ("src host ::1 and udp", [false, true, false]),
+            ("ether src 02:00:00:00:00:01 and ether proto 0x0806", [false, false, true]),
+            ("ip proto 17", [true, false, false]),
+            // and, or share precedence
+            ("arp or ip and udp port 53", [true, false, false]),
+        ] {
+            let filter = filter(expression);
+            assert_eq!([&dns, &tagged, &arp].map(|frame| filter.matches(frame)), expected, "{expression}");
+        }
+        assert!(!filter("ip").matches(&[0; 6]));
+    }
+
+    #[test]
+    fn test_matches_like_kernel() {
+        // the ethertype after the outer tag
+        let double_tagged = frame(FrameBuilder::ethernet(SOURCE, MacAddress::BROADCAST)
+            .vlan(VlanTag { tpid: ethertype::QINQ, pcp: 0, dei: false, vid: 10 })
+            .vlan(VlanTag { tpid: ethertype::VLAN, pcp: 0, dei: false, vid: 20 })
+            .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 53))
+            .udp(40000, 53));
+
+        // a hop-by-hop options header in front of UDP
+        let mut extended = frame(FrameBuilder::ethernet(SOURCE, MacAddress::BROADCAST)
+            .ipv6(Ipv6Addr::LOCALHOST, Ipv6Addr::LOCALHOST)
+            .udp(1234, 53));
+        extended.splice(54..54, [ip_protocol::UDP, 0, 0, 0, 0, 0, 0, 0]);
+        extended[20] = 0;
+        extended[19] += 8;
+
+        for (expression, expected) in [
+            ("vlan 10", [true, false]),
+            ("ip", [false, false]),
+            ("ether proto 0x8100", [true, false]),
+            ("ip6 proto udp", [false, false]),
+            ("ip6 proto 0", [false, true]),
+            ("udp port 53", [false, false]),
+            ("host ::1", [false, true]),
+        ] {
+            let filter = filter(expression);
+            assert_eq!([&double_tagged, &extended].map(|frame| filter.matches(frame)), expected, "{expression}");
+        }
+    }
+
+    #[test]
+    fn test_kernel_filter() {
+        let dns = filter("udp port 53");
+        assert_eq!(dns.kernel_filter(), RedirectFilter { ip_protocol: Some(ip_protocol::UDP), port: Some(53), ..RedirectFilter::default() });
+        assert!(dns.is_kernel_exact());
+
+        let tagged = filter("vlan 10 and ip and (tcp or udp)");
+        assert_eq!(tagged.kernel_filter(), RedirectFilter { vlan_id: Some(10), ethertype: Some(ethertype::IPV4), ..RedirectFilter::default() });
+        assert!(!tagged.is_kernel_exact());
+
+        assert!(!filter("udp src port 53").is_kernel_exact());
+        assert!(!filter("tcp and udp").is_kernel_exact());
+        assert_eq!(filter("arp or ip").kernel_filter(), RedirectFilter::default());
+        assert!(CaptureFilter::default().is_kernel_exact());
+    }
+
+    #[test]
+    fn test_invalid() {
+        for expression in ["udp port", "port 65536", "(ip", "ip)", "vlan and", "foo", "host 10.0.0", "ether src 02:00"] {
+            assert!(matches!(expression.parse::<CaptureFilter>(), Err(crate::Error::InvalidFilterExpression { .. })), "{expression}");
+        }
+    }
+}
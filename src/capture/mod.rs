@@ -0,0 +1,17 @@
+mod filter; pub use filter::CaptureFilter;
+mod pcap; pub use pcap::PcapWriter;
+mod pcapng; pub use pcapng::PcapNgWriter;
+mod reader; pub use reader::{CapturedFrame, PcapReader};
+
+/// The link type of the captured frames, AF_XDP sockets always see ethernet frames
+pub(crate) const LINKTYPE_ETHERNET: u16 = 1;
+
+/// The default maximum number of bytes of a frame which are stored in a capture
+pub const DEFAULT_SNAPLEN: u32 = 65535;
+
+/// The current wallclock time, expressed as time elapsed since the UNIX epoch
+pub(crate) fn now() -> std::time::Duration {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+}
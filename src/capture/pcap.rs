@@ -0,0 +1,113 @@
+use std::io::Write;
+
+use crate::XDPSocket;
+
+/// A writer producing classic pcap files (nanosecond resolution) out of ethernet frames
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    snaplen: u32,
+}
+impl<W: Write> PcapWriter<W> {
+    // constants
+    const MAGIC_NANOSECONDS: u32 = 0xa1b2_3c4d;
+    const VERSION_MAJOR: u16 = 2;
+    const VERSION_MINOR: u16 = 4;
+
+    /// Create a new writer, emitting the pcap file header to `writer`
+    pub fn new(writer: W) -> Result<Self, crate::Error> {
+        Self::with_snaplen(writer, super::DEFAULT_SNAPLEN)
+    }
+
+    /// Create a new writer storing at most `snaplen` bytes of each frame
+    pub fn with_snaplen(mut writer: W, snaplen: u32) -> Result<Self, crate::Error> {
+        // assemble file header
+        let mut header = [0_u8; 24];
+        header[0..4].copy_from_slice(&Self::MAGIC_NANOSECONDS.to_ne_bytes());
+        header[4..6].copy_from_slice(&Self::VERSION_MAJOR.to_ne_bytes());
+        header[6..8].copy_from_slice(&Self::VERSION_MINOR.to_ne_bytes());
+        // thiszone and sigfigs are always zero
+        header[16..20].copy_from_slice(&snaplen.to_ne_bytes());
+        header[20..24].copy_from_slice(&(super::LINKTYPE_ETHERNET as u32).to_ne_bytes());
+
+        // write it
+        writer.write_all(&header).map_err(|error| crate::Error::CaptureIOFailure { error })?;
+
+        Ok(Self { writer, snaplen })
+    }
+
+    /// Write a frame, timestamping it with the current time
+    pub fn write_frame(&mut self, frame: &[u8]) -> Result<(), crate::Error> {
+        self.write_frame_at(frame, super::now())
+    }
+
+    /// Write a frame, with `timestamp` being the time elapsed since the UNIX epoch
+    pub fn write_frame_at(&mut self, frame: &[u8], timestamp: std::time::Duration) -> Result<(), crate::Error> {
+        // truncate to snaplen
+        let captured_len = frame.len().min(self.snaplen as usize);
+
+        // assemble record header
+        let mut header = [0_u8; 16];
+        header[0..4].copy_from_slice(&(timestamp.as_secs() as u32).to_ne_bytes());
+        header[4..8].copy_from_slice(&timestamp.subsec_nanos().to_ne_bytes());
+        header[8..12].copy_from_slice(&(captured_len as u32).to_ne_bytes());
+        header[12..16].copy_from_slice(&(frame.len() as u32).to_ne_bytes());
+
+        // write record
+        self.writer.write_all(&header)
+            .and_then(|_| self.writer.write_all(&frame[..captured_len]))
+            .map_err(|error| crate::Error::CaptureIOFailure { error })
+    }
+
+    /// Write all the frames currently waiting in the RX ring of `socket`, without consuming them
+    /// 
+    /// Returns the number of frames written
+    pub fn write_pending_rx(&mut self, socket: &XDPSocket) -> Result<usize, crate::Error> {
+        let timestamp = super::now();
+        let mut index = socket.rx_ring.get_consumer_index() as usize;
+        let mut count = 0;
+        while index != socket.rx_ring.get_producer_index() as usize {
+            self.write_frame_at(&socket.rx_ring.get_nth_slice(index, &socket.umem), timestamp)?;
+            index = (index + 1) % socket.rx_ring.num_elements();
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Flush the underlying writer
+    pub fn flush(&mut self) -> Result<(), crate::Error> {
+        self.writer.flush().map_err(|error| crate::Error::CaptureIOFailure { error })
+    }
+
+    /// Get back the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+impl PcapWriter<std::io::BufWriter<std::fs::File>> {
+    /// Create a new pcap file at `path`, truncating it if it exists
+    pub fn create(path: impl AsRef<std::path::Path>) -> Result<Self, crate::Error> {
+        let file = std::fs::File::create(path).map_err(|error| crate::Error::CaptureIOFailure { error })?;
+        Self::new(std::io::BufWriter::new(file))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PcapWriter;
+
+    #[test]
+    fn test_pcap_layout() {
+        let frame = [0xAA_u8; 100];
+        let mut writer = PcapWriter::with_snaplen(Vec::new(), 64).unwrap();
+        writer.write_frame_at(&frame, std::time::Duration::new(10, 20)).unwrap();
+        let bytes = writer.into_inner();
+
+        // file header + record header + truncated frame
+        assert_eq!(bytes.len(), 24 + 16 + 64);
+        assert_eq!(u32::from_ne_bytes(bytes[0..4].try_into().unwrap()), 0xa1b2_3c4d);
+        assert_eq!(u32::from_ne_bytes(bytes[24..28].try_into().unwrap()), 10);
+        assert_eq!(u32::from_ne_bytes(bytes[28..32].try_into().unwrap()), 20);
+        assert_eq!(u32::from_ne_bytes(bytes[32..36].try_into().unwrap()), 64);
+        assert_eq!(u32::from_ne_bytes(bytes[36..40].try_into().unwrap()), 100);
+    }
+}
@@ -0,0 +1,245 @@
+use std::io::Write;
+
+use crate::{phc::{PhcCorrelation, SystemClock}, utils, XDPSocket};
+
+/// A writer producing pcapng files out of ethernet frames
+/// 
+/// Every interface (usually an [`XDPSocket`]) must be registered with [`Self::add_interface`] before writing its frames,
+/// timestamps are always stored with nanosecond resolution
+pub struct PcapNgWriter<W: Write> {
+    writer: W,
+    // the snaplen of every registered interface, by id
+    snaplens: Vec<u32>,
+}
+impl<W: Write> PcapNgWriter<W> {
+    // block types
+    const BLOCK_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+    const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+    const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+
+    // option codes
+    const OPT_ENDOFOPT: u16 = 0;
+    const OPT_IF_NAME: u16 = 2;
+    const OPT_IF_DESCRIPTION: u16 = 3;
+    const OPT_IF_TSRESOL: u16 = 9;
+
+    /// Create a new writer, emitting the section header block to `writer`
+    pub fn new(writer: W) -> Result<Self, crate::Error> {
+        let mut this = Self { writer, snaplens: Vec::new() };
+
+        // byte order magic, version 1.0, unspecified section length
+        let mut body = Vec::with_capacity(16);
+        body.extend_from_slice(&0x1A2B_3C4D_u32.to_ne_bytes());
+        body.extend_from_slice(&1_u16.to_ne_bytes());
+        body.extend_from_slice(&0_u16.to_ne_bytes());
+        body.extend_from_slice(&(-1_i64).to_ne_bytes());
+        this.write_block(Self::BLOCK_SECTION_HEADER, &body)?;
+
+        Ok(this)
+    }
+
+    /// Register the interface and queue `socket` is bound to, returning the interface id to use when writing its frames
+    pub fn add_interface(&mut self, socket: &XDPSocket) -> Result<u32, crate::Error> {
+        let if_name = utils::interface_index_to_name(socket.if_index)
+            .unwrap_or_else(|_| format!("if{}", socket.if_index));
+        self.add_interface_named(&if_name, &format!("AF_XDP queue {}", socket.if_queue), super::DEFAULT_SNAPLEN)
+    }
+
+    /// Register an interface by name and description, returning the interface id to use when writing its frames
+    ///
+    /// Frames written on it are truncated to `snaplen` bytes, 0 meaning no limit
+    pub fn add_interface_named(&mut self, name: &str, description: &str, snaplen: u32) -> Result<u32, crate::Error> {
+        // link type, reserved, snaplen
+        let mut body = Vec::with_capacity(64);
+        body.extend_from_slice(&super::LINKTYPE_ETHERNET.to_ne_bytes());
+        body.extend_from_slice(&0_u16.to_ne_bytes());
+        body.extend_from_slice(&snaplen.to_ne_bytes());
+
+        // options
+        push_option(&mut body, Self::OPT_IF_NAME, name.as_bytes());
+        push_option(&mut body, Self::OPT_IF_DESCRIPTION, description.as_bytes());
+        push_option(&mut body, Self::OPT_IF_TSRESOL, &[9]);
+        push_option(&mut body, Self::OPT_ENDOFOPT, &[]);
+        self.write_block(Self::BLOCK_INTERFACE_DESCRIPTION, &body)?;
+
+        // assign id
+        self.snaplens.push(snaplen);
+        Ok(self.snaplens.len() as u32 - 1)
+    }
+
+    /// Write a frame seen on `interface_id`, timestamping it with the current time
+    pub fn write_frame(&mut self, interface_id: u32, frame: &[u8]) -> Result<(), crate::Error> {
+        self.write_frame_at(interface_id, frame, super::now())
+    }
+
+    /// Write a frame seen on `interface_id`, with `timestamp` being the time elapsed since the UNIX epoch
+    /// 
+    /// When the NIC provides hardware RX timestamps, pass them here to preserve their precision, see
+    /// [`Self::write_pending_rx_hw`]
+    pub fn write_frame_at(&mut self, interface_id: u32, frame: &[u8], timestamp: std::time::Duration) -> Result<(), crate::Error> {
+        let Some(&snaplen) = self.snaplens.get(interface_id as usize) else {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("interface {interface_id} was not registered") });
+        };
+
+        // truncate to snaplen
+        let captured_len = match snaplen {
+            0 => frame.len(),
+            snaplen => frame.len().min(snaplen as usize),
+        };
+
+        // timestamp in nanoseconds, stored as high and low halves
+        let timestamp = timestamp.as_nanos() as u64;
+
+        // interface, timestamp, lengths, data
+        let mut body = Vec::with_capacity(20 + captured_len.next_multiple_of(4));
+        body.extend_from_slice(&interface_id.to_ne_bytes());
+        body.extend_from_slice(&((timestamp >> 32) as u32).to_ne_bytes());
+        body.extend_from_slice(&(timestamp as u32).to_ne_bytes());
+        body.extend_from_slice(&(captured_len as u32).to_ne_bytes());
+        body.extend_from_slice(&(frame.len() as u32).to_ne_bytes());
+        body.extend_from_slice(&frame[..captured_len]);
+        body.resize(body.len().next_multiple_of(4), 0);
+        self.write_block(Self::BLOCK_ENHANCED_PACKET, &body)
+    }
+
+    /// Write all the frames currently waiting in the RX ring of `socket`, without consuming them
+    /// 
+    /// Returns the number of frames written
+    pub fn write_pending_rx(&mut self, interface_id: u32, socket: &XDPSocket) -> Result<usize, crate::Error> {
+        let timestamp = super::now();
+        let mut index = socket.rx_ring.get_consumer_index() as usize;
+        let mut count = 0;
+        while index != socket.rx_ring.get_producer_index() as usize {
+            self.write_frame_at(interface_id, &socket.rx_ring.get_nth_slice(index, &socket.umem), timestamp)?;
+            index = (index + 1) % socket.rx_ring.num_elements();
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Like [`Self::write_pending_rx`], timestamping frames with the hardware RX timestamp in the XDP metadata in front of them
+    ///
+    /// The redirect program does not store them, a program of choice has to: e.g. reserving 8 bytes with
+    /// `bpf_xdp_adjust_meta` and filling them with `bpf_xdp_metadata_rx_timestamp`. Those count nanoseconds of the PHC,
+    /// `correlation` must convert them to [`SystemClock::Realtime`]. Frames without a timestamp, i.e. lacking the room for
+    /// one or carrying zero, get the current time
+    pub fn write_pending_rx_hw(&mut self, interface_id: u32, socket: &XDPSocket, correlation: &PhcCorrelation) -> Result<usize, crate::Error> {
+        if correlation.clock != SystemClock::Realtime {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("timestamps are converted to {:?} instead of the realtime clock", correlation.clock) });
+        }
+        let mut index = socket.rx_ring.get_consumer_index() as usize;
+        let mut count = 0;
+        while index != socket.rx_ring.get_producer_index() as usize {
+            let descriptor = *socket.rx_ring.get_nth_descriptor(index);
+            let chunk_index = socket.umem.chunk_index_for_offset(descriptor.addr);
+            let headroom = (descriptor.addr - socket.umem.chunk_start_offset_for_index(chunk_index)) as usize;
+            let chunk = socket.umem.chunk(chunk_index);
+            let timestamp = rx_timestamp(&chunk, headroom)
+                .map(|phc_ns| std::time::Duration::from_nanos(correlation.convert(phc_ns)))
+                .unwrap_or_else(super::now);
+            self.write_frame_at(interface_id, &chunk[headroom..headroom + descriptor.len as usize], timestamp)?;
+            index = (index + 1) % socket.rx_ring.num_elements();
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Flush the underlying writer
+    pub fn flush(&mut self) -> Result<(), crate::Error> {
+        self.writer.flush().map_err(|error| crate::Error::CaptureIOFailure { error })
+    }
+
+    /// Get back the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> Result<(), crate::Error> {
+        // type, length, body, length
+        let total_length = (12 + body.len()) as u32;
+        self.writer.write_all(&block_type.to_ne_bytes())
+            .and_then(|_| self.writer.write_all(&total_length.to_ne_bytes()))
+            .and_then(|_| self.writer.write_all(body))
+            .and_then(|_| self.writer.write_all(&total_length.to_ne_bytes()))
+            .map_err(|error| crate::Error::CaptureIOFailure { error })
+    }
+}
+impl PcapNgWriter<std::io::BufWriter<std::fs::File>> {
+    /// Create a new pcapng file at `path`, truncating it if it exists
+    pub fn create(path: impl AsRef<std::path::Path>) -> Result<Self, crate::Error> {
+        let file = std::fs::File::create(path).map_err(|error| crate::Error::CaptureIOFailure { error })?;
+        Self::new(std::io::BufWriter::new(file))
+    }
+}
+
+/// The hardware RX timestamp stored in the 8 bytes of metadata in front of the frame at `headroom` in `chunk`, if any
+fn rx_timestamp(chunk: &[u8], headroom: usize) -> Option<u64> {
+    let metadata = chunk.get(headroom.checked_sub(8)?..headroom)?;
+    Some(u64::from_ne_bytes(metadata.try_into().unwrap())).filter(|timestamp| *timestamp != 0)
+}
+
+/// Append an option to a block body, padding its value to 32 bits
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend_from_slice(&code.to_ne_bytes());
+    body.extend_from_slice(&(value.len() as u16).to_ne_bytes());
+    body.extend_from_slice(value);
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rx_timestamp, PcapNgWriter};
+
+    #[test]
+    fn test_pcapng_blocks() {
+        let mut writer = PcapNgWriter::new(Vec::new()).unwrap();
+        let if0 = writer.add_interface_named("test1", "AF_XDP queue 0", 65535).unwrap();
+        let if1 = writer.add_interface_named("test2", "AF_XDP queue 0", 65535).unwrap();
+        assert_eq!((if0, if1), (0, 1));
+        writer.write_frame_at(if1, &[0x55; 61], std::time::Duration::from_nanos(0x1_0000_0002)).unwrap();
+        let bytes = writer.into_inner();
+
+        // walk the blocks, checking lengths match at both ends
+        let mut offset = 0;
+        let mut block_types = Vec::new();
+        while offset < bytes.len() {
+            let block_type = u32::from_ne_bytes(bytes[offset..offset+4].try_into().unwrap());
+            let length = u32::from_ne_bytes(bytes[offset+4..offset+8].try_into().unwrap()) as usize;
+            assert_eq!(length % 4, 0);
+            assert_eq!(u32::from_ne_bytes(bytes[offset+length-4..offset+length].try_into().unwrap()) as usize, length);
+            block_types.push(block_type);
+            if block_type == 6 {
+                assert_eq!(u32::from_ne_bytes(bytes[offset+8..offset+12].try_into().unwrap()), 1);
+                assert_eq!(u32::from_ne_bytes(bytes[offset+12..offset+16].try_into().unwrap()), 1);
+                assert_eq!(u32::from_ne_bytes(bytes[offset+16..offset+20].try_into().unwrap()), 2);
+                assert_eq!(u32::from_ne_bytes(bytes[offset+20..offset+24].try_into().unwrap()), 61);
+            }
+            offset += length;
+        }
+        assert_eq!(block_types, [0x0A0D_0D0A, 1, 1, 6]);
+    }
+
+    #[test]
+    fn test_pcapng_snaplen() {
+        let mut writer = PcapNgWriter::new(Vec::new()).unwrap();
+        let interface_id = writer.add_interface_named("test1", "", 16).unwrap();
+        assert!(matches!(writer.write_frame(interface_id + 1, &[0; 60]), Err(crate::Error::InvalidConfiguration { .. })));
+
+        // only the snaplen is stored, along with the original length
+        writer.write_frame(interface_id, &[0x55; 60]).unwrap();
+        let bytes = writer.into_inner();
+        let block = &bytes[bytes.len() - 48..];
+        assert_eq!(u32::from_ne_bytes(block[4..8].try_into().unwrap()), 48);
+        assert_eq!(u32::from_ne_bytes(block[20..24].try_into().unwrap()), 16);
+        assert_eq!(u32::from_ne_bytes(block[24..28].try_into().unwrap()), 60);
+    }
+
+    #[test]
+    fn test_rx_timestamp() {
+        let mut chunk = [0_u8; 2048];
+        chunk[248..256].copy_from_slice(&1234_u64.to_ne_bytes());
+        assert_eq!(rx_timestamp(&chunk, 256), Some(1234));
+        assert_eq!(rx_timestamp(&chunk, 128), None);
+        assert_eq!(rx_timestamp(&chunk, 4), None);
+    }
+}
@@ -0,0 +1,334 @@
+use std::io::Read;
+
+/// A frame read back from a capture file
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    /// Time elapsed since the UNIX epoch when the frame was captured
+    pub timestamp: std::time::Duration,
+    /// The captured bytes, possibly truncated by the capture snaplen
+    pub data: Vec<u8>,
+}
+
+/// The largest frame a reader accepts, the snaplen of files with none like in libpcap
+const MAX_SNAPLEN: usize = 256 * 1024;
+
+/// The largest pcapng block a reader accepts, a frame of [`MAX_SNAPLEN`] bytes with room for options
+const MAX_BLOCK_LEN: usize = MAX_SNAPLEN + 64 * 1024;
+
+/// A pcapng interface frames can reference
+struct Interface {
+    units_per_second: u64,
+    snaplen: usize,
+}
+
+enum Format {
+    Pcap { swapped: bool, units_per_second: u64, snaplen: usize },
+    PcapNg { swapped: bool, interfaces: Vec<Interface> },
+}
+
+/// A reader for both classic pcap and pcapng files, the format is detected from the file header
+pub struct PcapReader<R: Read> {
+    reader: R,
+    format: Format,
+}
+impl<R: Read> PcapReader<R> {
+    // magic numbers
+    const PCAP_MAGIC_MICROSECONDS: u32 = 0xa1b2_c3d4;
+    const PCAP_MAGIC_NANOSECONDS: u32 = 0xa1b2_3c4d;
+    const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+
+    // pcapng block types
+    const BLOCK_SECTION_HEADER: u32 = 0x0A0D_0D0A;
+    const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+    const BLOCK_SIMPLE_PACKET: u32 = 0x0000_0003;
+    const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+
+    /// Create a new reader, consuming the file header from `reader`
+    pub fn new(mut reader: R) -> Result<Self, crate::Error> {
+        // read magic
+        let mut magic = [0_u8; 4];
+        reader.read_exact(&mut magic).map_err(|error| crate::Error::CaptureIOFailure { error })?;
+        let magic = u32::from_ne_bytes(magic);
+
+        // pcapng
+        if magic == Self::BLOCK_SECTION_HEADER {
+            let mut this = Self { reader, format: Format::PcapNg { swapped: false, interfaces: Vec::new() } };
+            this.read_section_header()?;
+            return Ok(this);
+        }
+
+        // pcap
+        let (swapped, units_per_second) = match magic {
+            Self::PCAP_MAGIC_MICROSECONDS => (false, 1_000_000),
+            Self::PCAP_MAGIC_NANOSECONDS => (false, 1_000_000_000),
+            x if x.swap_bytes() == Self::PCAP_MAGIC_MICROSECONDS => (true, 1_000_000),
+            x if x.swap_bytes() == Self::PCAP_MAGIC_NANOSECONDS => (true, 1_000_000_000),
+            _ => return Err(crate::Error::CaptureFormatFailure { reason: "unknown file magic" }),
+        };
+        let mut header = [0_u8; 20];
+        reader.read_exact(&mut header).map_err(|error| crate::Error::CaptureIOFailure { error })?;
+        let snaplen = to_snaplen(read_u32(&header, 12, swapped));
+        let link_type = read_u32(&header, 16, swapped) & 0xFFFF;
+        if link_type != super::LINKTYPE_ETHERNET as u32 {
+            return Err(crate::Error::CaptureFormatFailure { reason: "link type is not ethernet" });
+        }
+        Ok(Self { reader, format: Format::Pcap { swapped, units_per_second, snaplen } })
+    }
+
+    /// Read the next frame, returning `None` once the file is over
+    pub fn next_frame(&mut self) -> Result<Option<CapturedFrame>, crate::Error> {
+        match self.format {
+            Format::Pcap { swapped, units_per_second, snaplen } => {
+                // record header
+                let mut header = [0_u8; 16];
+                if ! self.read_or_eof(&mut header)? {
+                    return Ok(None);
+                }
+                let seconds = read_u32(&header, 0, swapped) as u64;
+                let fraction = read_u32(&header, 4, swapped) as u64;
+                let captured_len = read_u32(&header, 8, swapped) as usize;
+                if captured_len > snaplen {
+                    return Err(crate::Error::CaptureFormatFailure { reason: "frame larger than the snaplen" });
+                }
+
+                // data
+                let mut data = vec![0_u8; captured_len];
+                self.reader.read_exact(&mut data).map_err(|error| crate::Error::CaptureIOFailure { error })?;
+                Ok(Some(CapturedFrame { timestamp: to_duration(seconds * units_per_second + fraction, units_per_second), data }))
+            },
+            Format::PcapNg { .. } => self.next_pcapng_frame(),
+        }
+    }
+
+    fn next_pcapng_frame(&mut self) -> Result<Option<CapturedFrame>, crate::Error> {
+        loop {
+            // block type
+            let mut block_type = [0_u8; 4];
+            if ! self.read_or_eof(&mut block_type)? {
+                return Ok(None);
+            }
+            let block_type = u32::from_ne_bytes(block_type);
+
+            // a new section may change the byte order
+            if block_type == Self::BLOCK_SECTION_HEADER {
+                self.read_section_header()?;
+                continue;
+            }
+
+            // read block body
+            let Format::PcapNg { swapped, .. } = self.format else { unreachable!() };
+            let block_type = if swapped { block_type.swap_bytes() } else { block_type };
+            let body = self.read_block_body()?;
+
+            // decode
+            match block_type {
+                Self::BLOCK_INTERFACE_DESCRIPTION => {
+                    let units_per_second = parse_interface_resolution(&body, swapped)?;
+                    let snaplen = to_snaplen(read_u32(&body, 4, swapped));
+                    if let Format::PcapNg { interfaces, .. } = &mut self.format {
+                        interfaces.push(Interface { units_per_second, snaplen });
+                    }
+                },
+                Self::BLOCK_ENHANCED_PACKET => {
+                    if body.len() < 20 {
+                        return Err(crate::Error::CaptureFormatFailure { reason: "truncated enhanced packet block" });
+                    }
+                    let interface_id = read_u32(&body, 0, swapped) as usize;
+                    let timestamp = ((read_u32(&body, 4, swapped) as u64) << 32) | read_u32(&body, 8, swapped) as u64;
+                    let captured_len = read_u32(&body, 12, swapped) as usize;
+                    let Format::PcapNg { interfaces, .. } = &self.format else { unreachable!() };
+                    let Some(interface) = interfaces.get(interface_id) else {
+                        return Err(crate::Error::CaptureFormatFailure { reason: "packet references an unknown interface" });
+                    };
+                    if captured_len > interface.snaplen {
+                        return Err(crate::Error::CaptureFormatFailure { reason: "frame larger than the snaplen" });
+                    }
+                    let Some(data) = body.get(20..20 + captured_len) else {
+                        return Err(crate::Error::CaptureFormatFailure { reason: "truncated enhanced packet block" });
+                    };
+                    return Ok(Some(CapturedFrame { timestamp: to_duration(timestamp, interface.units_per_second), data: data.to_vec() }));
+                },
+                Self::BLOCK_SIMPLE_PACKET => {
+                    if body.len() < 4 {
+                        return Err(crate::Error::CaptureFormatFailure { reason: "truncated simple packet block" });
+                    }
+                    let original_len = read_u32(&body, 0, swapped) as usize;
+                    let data = &body[4..body.len().min(4 + original_len)];
+                    return Ok(Some(CapturedFrame { timestamp: std::time::Duration::ZERO, data: data.to_vec() }));
+                },
+                _ => {},
+            }
+        }
+    }
+
+    /// Read a section header block, whose block type has already been consumed
+    fn read_section_header(&mut self) -> Result<(), crate::Error> {
+        // length and byte order magic
+        let mut header = [0_u8; 8];
+        self.reader.read_exact(&mut header).map_err(|error| crate::Error::CaptureIOFailure { error })?;
+        let swapped = match u32::from_ne_bytes(header[4..8].try_into().unwrap()) {
+            Self::PCAPNG_BYTE_ORDER_MAGIC => false,
+            x if x.swap_bytes() == Self::PCAPNG_BYTE_ORDER_MAGIC => true,
+            _ => return Err(crate::Error::CaptureFormatFailure { reason: "bad byte order magic" }),
+        };
+
+        // skip the rest of the block
+        let length = read_u32(&header, 0, swapped) as usize;
+        if ! (28..=MAX_BLOCK_LEN).contains(&length) || ! length.is_multiple_of(4) {
+            return Err(crate::Error::CaptureFormatFailure { reason: "bad section header length" });
+        }
+        let mut rest = vec![0_u8; length - 12];
+        self.reader.read_exact(&mut rest).map_err(|error| crate::Error::CaptureIOFailure { error })?;
+
+        // interfaces are scoped to their section
+        self.format = Format::PcapNg { swapped, interfaces: Vec::new() };
+        Ok(())
+    }
+
+    /// Read a block body, whose block type has already been consumed, discarding the trailing length
+    fn read_block_body(&mut self) -> Result<Vec<u8>, crate::Error> {
+        let Format::PcapNg { swapped, .. } = self.format else { unreachable!() };
+        let mut length = [0_u8; 4];
+        self.reader.read_exact(&mut length).map_err(|error| crate::Error::CaptureIOFailure { error })?;
+        let length = read_u32(&length, 0, swapped) as usize;
+        if ! (12..=MAX_BLOCK_LEN).contains(&length) || ! length.is_multiple_of(4) {
+            return Err(crate::Error::CaptureFormatFailure { reason: "bad block length" });
+        }
+        let mut body = vec![0_u8; length - 8];
+        self.reader.read_exact(&mut body).map_err(|error| crate::Error::CaptureIOFailure { error })?;
+        body.truncate(length - 12);
+        Ok(body)
+    }
+
+    /// Fill `buffer`, returning `false` if the reader was already at its end
+    fn read_or_eof(&mut self, buffer: &mut [u8]) -> Result<bool, crate::Error> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match self.reader.read(&mut buffer[filled..]) {
+                Ok(0) if filled == 0 => return Ok(false),
+                Ok(0) => return Err(crate::Error::CaptureFormatFailure { reason: "truncated file" }),
+                Ok(n) => filled += n,
+                Err(error) if error.kind() == std::io::ErrorKind::Interrupted => {},
+                Err(error) => return Err(crate::Error::CaptureIOFailure { error }),
+            }
+        }
+        Ok(true)
+    }
+}
+impl PcapReader<std::io::BufReader<std::fs::File>> {
+    /// Open the pcap or pcapng file at `path`
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self, crate::Error> {
+        let file = std::fs::File::open(path).map_err(|error| crate::Error::CaptureIOFailure { error })?;
+        Self::new(std::io::BufReader::new(file))
+    }
+}
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = Result<CapturedFrame, crate::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize, swapped: bool) -> u32 {
+    let value = u32::from_ne_bytes(bytes[offset..offset+4].try_into().unwrap());
+    if swapped { value.swap_bytes() } else { value }
+}
+
+/// The snaplen of a file header or interface, no snaplen or a larger one meaning [`MAX_SNAPLEN`]
+fn to_snaplen(snaplen: u32) -> usize {
+    match snaplen as usize {
+        0 => MAX_SNAPLEN,
+        snaplen => snaplen.min(MAX_SNAPLEN),
+    }
+}
+
+fn to_duration(timestamp: u64, units_per_second: u64) -> std::time::Duration {
+    let seconds = timestamp / units_per_second;
+    let nanos = (timestamp % units_per_second) as u128 * 1_000_000_000 / units_per_second as u128;
+    std::time::Duration::new(seconds, nanos as u32)
+}
+
+/// Extract the timestamp resolution from an interface description block body, defaulting to microseconds
+fn parse_interface_resolution(body: &[u8], swapped: bool) -> Result<u64, crate::Error> {
+    if body.len() < 8 {
+        return Err(crate::Error::CaptureFormatFailure { reason: "truncated interface description block" });
+    }
+    let mut offset = 8;
+    while offset + 4 <= body.len() {
+        let code = read_u32(body, offset, swapped);
+        let (code, length) = if swapped {
+            ((code as u16).swap_bytes(), ((code >> 16) as u16).swap_bytes())
+        } else {
+            (code as u16, (code >> 16) as u16)
+        };
+        let value = body.get(offset + 4..offset + 4 + length as usize)
+            .ok_or(crate::Error::CaptureFormatFailure { reason: "truncated interface option" })?;
+        match code {
+            0 => break,
+            9 if length == 1 => {
+                let resolution = value[0];
+                let units_per_second = if resolution & 0x80 == 0 {
+                    10_u64.checked_pow(resolution as u32)
+                } else {
+                    1_u64.checked_shl((resolution & 0x7F) as u32)
+                };
+                return units_per_second.ok_or(crate::Error::CaptureFormatFailure { reason: "unsupported timestamp resolution" });
+            },
+            _ => {},
+        }
+        offset += 4 + (length as usize).next_multiple_of(4);
+    }
+    Ok(1_000_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capture::{PcapNgWriter, PcapReader, PcapWriter};
+
+    #[test]
+    fn test_round_trip() {
+        let frames: Vec<Vec<u8>> = (1..=5_u8).map(|i| vec![i; 60 + i as usize]).collect();
+        let timestamp = |i: usize| std::time::Duration::new(1_700_000_000 + i as u64, 123_456_789);
+
+        // pcap
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for (i, frame) in frames.iter().enumerate() {
+            writer.write_frame_at(frame, timestamp(i)).unwrap();
+        }
+        let read = PcapReader::new(writer.into_inner().as_slice()).unwrap()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(read.iter().map(|f| &f.data).collect::<Vec<_>>(), frames.iter().collect::<Vec<_>>());
+        assert!(read.iter().enumerate().all(|(i, f)| f.timestamp == timestamp(i)));
+
+        // pcapng
+        let mut writer = PcapNgWriter::new(Vec::new()).unwrap();
+        let interface_id = writer.add_interface_named("test1", "", 65535).unwrap();
+        for (i, frame) in frames.iter().enumerate() {
+            writer.write_frame_at(interface_id, frame, timestamp(i)).unwrap();
+        }
+        let read = PcapReader::new(writer.into_inner().as_slice()).unwrap()
+            .collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(read.iter().map(|f| &f.data).collect::<Vec<_>>(), frames.iter().collect::<Vec<_>>());
+        assert!(read.iter().enumerate().all(|(i, f)| f.timestamp == timestamp(i)));
+    }
+
+    #[test]
+    fn test_oversized() {
+        // a record larger than the snaplen
+        let mut writer = PcapWriter::with_snaplen(Vec::new(), 64).unwrap();
+        writer.write_frame_at(&[0; 60], std::time::Duration::ZERO).unwrap();
+        let mut file = writer.into_inner();
+        let record = file.len() - 60 - 16;
+        file[record + 8..record + 12].copy_from_slice(&u32::MAX.to_ne_bytes());
+        let mut reader = PcapReader::new(file.as_slice()).unwrap();
+        assert!(matches!(reader.next_frame(), Err(crate::Error::CaptureFormatFailure { .. })));
+
+        // a block larger than any frame
+        let mut file = PcapNgWriter::new(Vec::new()).unwrap().into_inner();
+        file.extend_from_slice(&6_u32.to_ne_bytes());
+        file.extend_from_slice(&0xFFFF_FFF0_u32.to_ne_bytes());
+        let mut reader = PcapReader::new(file.as_slice()).unwrap();
+        assert!(matches!(reader.next_frame(), Err(crate::Error::CaptureFormatFailure { .. })));
+    }
+}
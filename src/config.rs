@@ -0,0 +1,328 @@
+//! Plain descriptions of an AF_XDP setup, which can be loaded from configuration files with the `serde` feature
+//!
+//! ```toml
+//! [[sockets]]
+//! interface = "eth0"
+//! queue_id = 0
+//! rings_size = 2048
+//! fill_ring_size = 4096
+//! umem = { chunk_size = 2048, num_chunks = 4096 }
+//! ```
+
+use std::{os::fd::AsRawFd, sync::Arc};
+
+use crate::{ethtool::{self, Coalescing}, BPFRedirectManager, BindFlags, BindMode, RingSizes, Umem, WaitStrategy, XDPSocket};
+
+/// How to allocate a [`Umem`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct UmemConfig {
+    /// Either 2048 or 4096
+    pub chunk_size: usize,
+    pub num_chunks: usize,
+    /// See [`Umem::with_tx_metadata_len`]
+    pub tx_metadata_len: u32,
+    /// See [`Umem::with_tx_sw_csum`]
+    pub tx_sw_csum: bool,
+    /// Fault in and warm up the umem once allocated, see [`Umem::prefault`]
+    pub prefault: bool,
+}
+impl Default for UmemConfig {
+    fn default() -> Self {
+        Self { chunk_size: 2048, num_chunks: 4096, tx_metadata_len: 0, tx_sw_csum: false, prefault: false }
+    }
+}
+impl UmemConfig {
+    pub fn build(&self) -> Result<Arc<Umem>, crate::Error> {
+        let mut umem = match self.chunk_size {
+            2048 => Umem::new_2k(self.num_chunks)?,
+            4096 => Umem::new_4k(self.num_chunks)?,
+            chunk_size => return Err(crate::Error::InvalidConfiguration { reason: format!("unsupported chunk size {chunk_size}") }),
+        };
+        if ! self.tx_metadata_len.is_multiple_of(8) {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("TX metadata length {} is not a multiple of 8", self.tx_metadata_len) });
+        }
+        umem = umem.with_tx_metadata_len(self.tx_metadata_len);
+        if self.tx_sw_csum {
+            umem = umem.with_tx_sw_csum();
+        }
+        if self.prefault {
+            umem.prefault(true)?;
+        }
+        Ok(Arc::new(umem))
+    }
+}
+
+/// How to create an [`XDPSocket`], along with its own umem
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(deny_unknown_fields))]
+pub struct SocketConfig {
+    /// The name of the interface to bind to
+    pub interface: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub queue_id: u32,
+    /// The size of all rings, a power of two
+    #[cfg_attr(feature = "serde", serde(default = "SocketConfig::default_rings_size"))]
+    pub rings_size: usize,
+    /// Overrides [`Self::rings_size`] for the RX ring
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub rx_ring_size: Option<usize>,
+    /// Overrides [`Self::rings_size`] for the TX ring
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub tx_ring_size: Option<usize>,
+    /// Overrides [`Self::rings_size`] for the fill ring
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub fill_ring_size: Option<usize>,
+    /// Overrides [`Self::rings_size`] for the completion ring
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub completion_ring_size: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub umem: UmemConfig,
+    /// Whether packets may span several chunks, see [`XDPSocket::with_multi_buffer`]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub multi_buffer: bool,
+    /// Whether the driver only gets syscalls when it asks for them, see [`BindFlags::need_wakeup`]
+    #[cfg_attr(feature = "serde", serde(default = "SocketConfig::default_need_wakeup"))]
+    pub need_wakeup: bool,
+    /// Whether to require or refuse zero-copy, `auto`, `zero_copy` or `copy`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub bind_mode: BindMode,
+    /// Whether frames received on the queue are redirected to the socket, see [`RedirectConfig`]
+    #[cfg_attr(feature = "serde", serde(default = "SocketConfig::default_redirect"))]
+    pub redirect: bool,
+    /// How to wait for frames, see [`crate::XdpDevice::rx_burst_wait`]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub wait: WaitStrategy,
+}
+impl SocketConfig {
+    /// A socket on `interface` and `queue_id`, with default ring and umem sizes
+    pub fn new(interface: impl Into<String>, queue_id: u32) -> Self {
+        Self {
+            interface: interface.into(),
+            queue_id,
+            rings_size: Self::default_rings_size(),
+            rx_ring_size: None,
+            tx_ring_size: None,
+            fill_ring_size: None,
+            completion_ring_size: None,
+            umem: UmemConfig::default(),
+            multi_buffer: false,
+            need_wakeup: Self::default_need_wakeup(),
+            bind_mode: BindMode::default(),
+            redirect: Self::default_redirect(),
+            wait: WaitStrategy::default(),
+        }
+    }
+
+    /// The index of [`Self::interface`]
+    pub fn interface_index(&self) -> Result<libc::c_uint, crate::Error> {
+        crate::utils::interface_name_to_index(&self.interface)
+    }
+
+    /// The size of every ring, checked to be powers of two
+    pub fn ring_sizes(&self) -> Result<RingSizes, crate::Error> {
+        let ring_sizes = RingSizes {
+            rx: self.rx_ring_size.unwrap_or(self.rings_size),
+            tx: self.tx_ring_size.unwrap_or(self.rings_size),
+            fill: self.fill_ring_size.unwrap_or(self.rings_size),
+            completion: self.completion_ring_size.unwrap_or(self.rings_size),
+        };
+        if ! ring_sizes.are_valid() {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("ring sizes {ring_sizes:?} are not all powers of two") });
+        }
+        Ok(ring_sizes)
+    }
+
+    /// The flags to bind the socket with
+    pub const fn bind_flags(&self) -> BindFlags {
+        BindFlags { need_wakeup: self.need_wakeup, multi_buffer: self.multi_buffer, mode: self.bind_mode }
+    }
+
+    /// Allocate the umem and create the socket
+    pub fn build<'a>(&self) -> Result<XDPSocket<'a>, crate::Error> {
+        self.build_over(self.interface_index()?, self.umem.build()?)
+    }
+
+    /// Create the socket on the interface with index `interface_index`, over an existing `umem`
+    pub(crate) fn build_over<'a>(&self, interface_index: libc::c_uint, umem: Arc<Umem>) -> Result<XDPSocket<'a>, crate::Error> {
+        XDPSocket::with_bind_flags(interface_index, self.queue_id, umem, self.ring_sizes()?, self.bind_flags())
+    }
+
+    fn default_rings_size() -> usize {
+        2048
+    }
+
+    fn default_need_wakeup() -> bool {
+        true
+    }
+
+    fn default_redirect() -> bool {
+        true
+    }
+}
+
+/// A queue whose frames are redirected to a socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(deny_unknown_fields))]
+pub struct QueueRedirect {
+    pub queue_id: u32,
+    /// The index of the socket within [`XdpConfig::sockets`]
+    pub socket: usize,
+}
+
+/// Which queues of an interface have their frames redirected to which socket
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(deny_unknown_fields))]
+pub struct RedirectConfig {
+    pub interface: String,
+    pub queues: Vec<QueueRedirect>,
+}
+
+/// Interrupt coalescing to apply to an interface, settings left out keep their current value
+///
+/// ```toml
+/// [[coalescing]]
+/// interface = "eth0"
+/// rx_usecs = 0
+/// adaptive_rx = false
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(deny_unknown_fields))]
+pub struct CoalescingConfig {
+    pub interface: String,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub rx_usecs: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub rx_max_frames: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub tx_usecs: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub tx_max_frames: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub adaptive_rx: Option<bool>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub adaptive_tx: Option<bool>,
+}
+impl CoalescingConfig {
+    /// `current` with the settings of this configuration applied
+    pub fn merge(&self, current: Coalescing) -> Coalescing {
+        Coalescing {
+            rx_usecs: self.rx_usecs.unwrap_or(current.rx_usecs),
+            rx_max_frames: self.rx_max_frames.unwrap_or(current.rx_max_frames),
+            tx_usecs: self.tx_usecs.unwrap_or(current.tx_usecs),
+            tx_max_frames: self.tx_max_frames.unwrap_or(current.tx_max_frames),
+            adaptive_rx: self.adaptive_rx.unwrap_or(current.adaptive_rx),
+            adaptive_tx: self.adaptive_tx.unwrap_or(current.adaptive_tx),
+        }
+    }
+
+    /// Change the coalescing of the interface, returning the previous one
+    pub fn apply(&self) -> Result<Coalescing, crate::Error> {
+        let current = ethtool::coalescing(&self.interface)?;
+        let wanted = self.merge(current);
+        if wanted == current {
+            return Ok(current);
+        }
+        ethtool::set_coalescing(&self.interface, &wanted)
+    }
+}
+
+/// A whole AF_XDP setup
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default, deny_unknown_fields))]
+pub struct XdpConfig {
+    pub sockets: Vec<SocketConfig>,
+    /// Applied before the sockets are created
+    pub coalescing: Vec<CoalescingConfig>,
+}
+impl XdpConfig {
+    /// The redirections implied by [`SocketConfig::redirect`], one per interface
+    pub fn redirects(&self) -> Vec<RedirectConfig> {
+        let mut redirects: Vec<RedirectConfig> = Vec::new();
+        for (index, socket) in self.sockets.iter().enumerate().filter(|(_, socket)| socket.redirect) {
+            let redirect = match redirects.iter_mut().position(|redirect| redirect.interface == socket.interface) {
+                Some(position) => &mut redirects[position],
+                None => {
+                    redirects.push(RedirectConfig { interface: socket.interface.clone(), queues: Vec::new() });
+                    redirects.last_mut().unwrap()
+                },
+            };
+            redirect.queues.push(QueueRedirect { queue_id: socket.queue_id, socket: index });
+        }
+        redirects
+    }
+
+    /// Apply the coalescing settings, create every socket and attach the redirect program where needed
+    pub fn build<'a>(&self) -> Result<XdpSetup<'a>, crate::Error> {
+        for coalescing in &self.coalescing {
+            coalescing.apply()?;
+        }
+        let sockets = self.sockets.iter().map(SocketConfig::build).collect::<Result<Vec<_>, _>>()?;
+        let mut redirect_managers = Vec::new();
+        for redirect in self.redirects() {
+            let mut manager = BPFRedirectManager::attach(sockets[redirect.queues[0].socket].if_index);
+            for queue in &redirect.queues {
+                manager.add_redirect(queue.queue_id, sockets[queue.socket].as_raw_fd())?;
+            }
+            redirect_managers.push(manager);
+        }
+        Ok(XdpSetup { sockets, redirect_managers })
+    }
+}
+
+/// What [`XdpConfig::build`] created, redirections last as long as this is alive
+pub struct XdpSetup<'a> {
+    /// In the same order as [`XdpConfig::sockets`]
+    pub sockets: Vec<XDPSocket<'a>>,
+    pub redirect_managers: Vec<BPFRedirectManager>,
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::{CoalescingConfig, RedirectConfig, SocketConfig, UmemConfig, XdpConfig};
+    use crate::{ethtool::Coalescing, BindFlags, BindMode, RingSizes, WaitStrategy};
+
+    #[test]
+    fn test_load_toml() {
+        let config: XdpConfig = toml::from_str(r#"
+            [[sockets]]
+            interface = "eth0"
+            queue_id = 0
+
+            [[sockets]]
+            interface = "eth0"
+            queue_id = 1
+            rings_size = 4096
+            fill_ring_size = 8192
+            umem = { chunk_size = 4096, num_chunks = 1024 }
+
+            [[sockets]]
+            interface = "eth1"
+            redirect = false
+            wait = { spin_iterations = 1000, poll_timeout_ms = 10 }
+            need_wakeup = false
+            bind_mode = "copy"
+
+            [[coalescing]]
+            interface = "eth0"
+            rx_usecs = 0
+            adaptive_rx = false
+        "#).unwrap();
+
+        assert_eq!(config.sockets[0], SocketConfig::new("eth0", 0));
+        assert_eq!(config.sockets[1].umem, UmemConfig { chunk_size: 4096, num_chunks: 1024, ..UmemConfig::default() });
+        assert_eq!(config.sockets[1].ring_sizes().unwrap(), RingSizes { rx: 4096, tx: 4096, fill: 8192, completion: 4096 });
+        assert_eq!(config.sockets[2].rings_size, 2048);
+        assert_eq!(config.sockets[2].wait, WaitStrategy::hybrid(1000, Some(10)));
+        assert_eq!(config.sockets[2].bind_flags(), BindFlags { need_wakeup: false, multi_buffer: false, mode: BindMode::Copy });
+        assert_eq!(config.sockets[2].bind_flags().bits(), libc::XDP_COPY);
+        let current = Coalescing { rx_usecs: 50, tx_usecs: 50, adaptive_rx: true, ..Coalescing::default() };
+        assert_eq!(config.coalescing[0].merge(current), Coalescing { rx_usecs: 0, tx_usecs: 50, ..Coalescing::default() });
+        assert_eq!(config.coalescing[0], CoalescingConfig { interface: "eth0".to_string(), rx_usecs: Some(0), adaptive_rx: Some(false), ..CoalescingConfig::default() });
+        let redirects = config.redirects();
+        assert_eq!(redirects.len(), 1);
+        assert_eq!(redirects[0].queues.len(), 2);
+        assert_eq!(toml::from_str::<RedirectConfig>(&toml::to_string(&redirects[0]).unwrap()).unwrap(), redirects[0]);
+        assert_eq!(toml::from_str::<XdpConfig>(&toml::to_string(&config).unwrap()).unwrap(), config);
+    }
+}
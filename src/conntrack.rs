@@ -0,0 +1,302 @@
+//! Lightweight connection tracking on top of a [`FlowTable`], following TCP handshakes and teardowns and pairing UDP
+//! datagrams with their replies, so that stateful network functions can tell new, established and invalid packets apart
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    flow::FlowTable,
+    packet::{self, ip_protocol, FiveTuple, TcpSegment, TransportHeader},
+    pipeline::{FrameCtx, Stage, Verdict},
+};
+
+/// The direction of a packet relative to the one that opened its connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Original = 0,
+    Reply = 1,
+}
+
+/// The state of a tracked connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnState {
+    /// A TCP SYN was seen
+    SynSent,
+    /// The TCP SYN was answered by a SYN ACK
+    SynReceived,
+    /// The TCP handshake completed
+    Established,
+    /// A TCP FIN was seen in at least one direction
+    Closing,
+    /// FINs were seen in both directions or the connection was reset, a SYN may reopen it
+    Closed,
+    /// Datagrams of a connectionless protocol were only seen in the original direction
+    Unreplied,
+    /// Datagrams of a connectionless protocol were seen in both directions
+    Replied,
+}
+
+/// A connection, keyed by the 5-tuple of the packet that opened it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connection {
+    pub state: ConnState,
+    /// Packets seen, indexed by [`Direction`]
+    pub packets: [u64; 2],
+    /// Bytes seen, indexed by [`Direction`]
+    pub bytes: [u64; 2],
+    fin_seen: [bool; 2],
+}
+impl Connection {
+    fn new(state: ConnState) -> Self {
+        Self { state, packets: [0; 2], bytes: [0; 2], fin_seen: [false; 2] }
+    }
+
+    /// Move along the TCP state machine after a segment with `tcp` flags went in `direction`
+    fn update_tcp(&mut self, direction: Direction, tcp: &TcpSegment<&[u8]>) {
+        self.state = match (self.state, direction) {
+            (_, _) if tcp.rst() => ConnState::Closed,
+            (ConnState::Closed, Direction::Original) if tcp.syn() && ! tcp.ack() => {
+                self.fin_seen = [false; 2];
+                ConnState::SynSent
+            },
+            (ConnState::SynSent, Direction::Reply) if tcp.syn() && tcp.ack() => ConnState::SynReceived,
+            (ConnState::SynReceived, Direction::Original) if tcp.ack() && ! tcp.syn() => ConnState::Established,
+            (state, _) => state,
+        };
+        if tcp.fin() && matches!(self.state, ConnState::SynReceived | ConnState::Established | ConnState::Closing) {
+            self.fin_seen[direction as usize] = true;
+            self.state = if self.fin_seen == [true; 2] { ConnState::Closed } else { ConnState::Closing };
+        }
+    }
+}
+
+/// How a packet relates to the tracked connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CtStatus {
+    /// The packet opened a connection, or reopened a closed one
+    New,
+    /// The packet belongs to a known connection
+    Established(Direction),
+    /// The packet belongs to a closed connection, e.g. the last ACK of a teardown or a retransmission
+    Closed(Direction),
+    /// The packet neither belongs to a connection nor can open one, e.g. a TCP segment without SYN
+    Invalid,
+}
+
+/// Counters kept by a [`ConnTracker`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnTrackStats {
+    /// Connections opened
+    pub new: u64,
+    pub invalid: u64,
+}
+
+/// Tracks the connections of the IP packets going through it, usable as a pipeline stage acting as a stateful firewall
+///
+/// TCP connections are opened by a SYN and follow the handshake and teardown, other protocols are opened by any packet and
+/// become replied once one is seen in the other direction. Connections are forgotten once idle for the timeout of the
+/// table or evicted to make room, after which their packets are invalid if TCP and open a new connection otherwise
+pub struct ConnTracker {
+    table: FlowTable<Connection>,
+    trusted_ports: Option<Vec<usize>>,
+    stats: ConnTrackStats,
+}
+impl ConnTracker {
+    /// A tracker for up to `capacity` connections, forgetting them after `idle_timeout` without packets
+    pub fn new(capacity: usize, idle_timeout: Duration) -> Self {
+        Self { table: FlowTable::new(capacity, idle_timeout), trusted_ports: None, stats: ConnTrackStats::default() }
+    }
+
+    /// As a pipeline stage, only let frames received on `ports` open connections
+    ///
+    /// By default frames from any port can
+    pub fn with_trusted_ports(mut self, ports: impl IntoIterator<Item = usize>) -> Self {
+        self.trusted_ports = Some(ports.into_iter().collect());
+        self
+    }
+
+    /// The tracked connections
+    pub const fn connections(&self) -> &FlowTable<Connection> {
+        &self.table
+    }
+
+    /// The connection `flow` belongs to, in either direction
+    pub fn connection(&self, flow: &FiveTuple) -> Option<(Direction, &Connection)> {
+        self.table.get(flow).map(|connection| (Direction::Original, connection))
+            .or_else(|| self.table.get(&flow.reversed()).map(|connection| (Direction::Reply, connection)))
+    }
+
+    pub const fn stats(&self) -> &ConnTrackStats {
+        &self.stats
+    }
+
+    /// Account for `frame`, returning how it relates to the connections or `None` if it is not IP
+    pub fn track(&mut self, frame: &[u8]) -> Option<CtStatus> {
+        self.track_if(frame, true)
+    }
+
+    /// Account for `frame`, opening a connection only if `may_open`
+    fn track_if(&mut self, frame: &[u8], may_open: bool) -> Option<CtStatus> {
+        let parsed = packet::parse(frame)?;
+        let flow = parsed.five_tuple()?;
+        let tcp = match parsed.transport {
+            Some(TransportHeader::Tcp(tcp)) => Some(tcp),
+            _ if flow.protocol == ip_protocol::TCP => {
+                self.stats.invalid += 1;
+                return Some(CtStatus::Invalid);
+            },
+            _ => None,
+        };
+
+        // find the connection, closed ones are only reopened by a SYN in the original direction
+        let opening = tcp.is_none_or(|tcp| tcp.syn() && ! tcp.ack());
+        let found = self.connection(&flow).map(|(direction, connection)| (direction, connection.state == ConnState::Closed));
+        let (status, key) = match found {
+            Some((Direction::Original, true)) if opening && may_open => {
+                self.stats.new += 1;
+                (CtStatus::New, flow)
+            },
+            Some((Direction::Original, true)) if opening => {
+                self.stats.invalid += 1;
+                return Some(CtStatus::Invalid);
+            },
+            Some((direction, closed)) => {
+                let status = if closed { CtStatus::Closed(direction) } else { CtStatus::Established(direction) };
+                (status, if direction == Direction::Original { flow } else { flow.reversed() })
+            },
+            None if may_open && opening => {
+                self.stats.new += 1;
+                self.table.insert(flow, Connection::new(if tcp.is_some() { ConnState::SynSent } else { ConnState::Unreplied }));
+                (CtStatus::New, flow)
+            },
+            None => {
+                self.stats.invalid += 1;
+                return Some(CtStatus::Invalid);
+            },
+        };
+
+        // update it
+        let connection = self.table.lookup(&key).expect("the connection was just found");
+        let direction = match status {
+            CtStatus::Established(direction) | CtStatus::Closed(direction) => direction,
+            CtStatus::New | CtStatus::Invalid => Direction::Original,
+        };
+        connection.packets[direction as usize] += 1;
+        connection.bytes[direction as usize] += frame.len() as u64;
+        match tcp {
+            Some(tcp) => connection.update_tcp(direction, &tcp),
+            None if direction == Direction::Reply => connection.state = ConnState::Replied,
+            None => {},
+        }
+        Some(status)
+    }
+
+    /// Forget the connections idle for longer than the timeout, returning how many there were
+    pub fn expire(&mut self, now: Instant) -> usize {
+        self.table.expire(now, |_, _| {})
+    }
+}
+impl Stage for ConnTracker {
+    /// Drop invalid frames and frames opening connections from untrusted ports, let the others continue
+    fn process(&mut self, ctx: &mut FrameCtx) -> Verdict {
+        let may_open = self.trusted_ports.as_ref().is_none_or(|ports| ports.contains(&ctx.ingress));
+        match self.track_if(ctx.data, may_open) {
+            Some(CtStatus::Invalid) => Verdict::Drop,
+            _ => Verdict::Continue,
+        }
+    }
+
+    fn tick(&mut self, now: Instant) {
+        self.expire(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, time::Duration};
+
+    use super::{ConnState, ConnTracker, CtStatus, Direction};
+    use crate::{packet::{FiveTuple, FrameBuilder, MacAddress}, pipeline::{FrameCtx, Stage, Verdict}};
+
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const SERVER: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+    /// A TCP segment with `flags` between the client and the server
+    fn segment(to_server: bool, flags: u8) -> Vec<u8> {
+        let mut frame = vec![0_u8; 14 + 20 + 20];
+        let (source, destination, ports) = if to_server { (CLIENT, SERVER, [40000_u16, 80]) } else { (SERVER, CLIENT, [80, 40000]) };
+        frame[12..14].copy_from_slice(&0x0800_u16.to_be_bytes());
+        frame[14] = 0x45;
+        frame[16..18].copy_from_slice(&40_u16.to_be_bytes());
+        frame[23] = 6;
+        frame[26..30].copy_from_slice(&source.octets());
+        frame[30..34].copy_from_slice(&destination.octets());
+        frame[34..36].copy_from_slice(&ports[0].to_be_bytes());
+        frame[36..38].copy_from_slice(&ports[1].to_be_bytes());
+        frame[46] = 5 << 4;
+        frame[47] = flags;
+        frame
+    }
+
+    const FIN: u8 = 0x01;
+    const SYN: u8 = 0x02;
+    const RST: u8 = 0x04;
+    const ACK: u8 = 0x10;
+
+    #[test]
+    fn test_track_tcp() {
+        let mut tracker = ConnTracker::new(16, Duration::from_secs(60));
+        let flow = FiveTuple::from_frame(&segment(true, SYN)).unwrap();
+        let state = |tracker: &ConnTracker| tracker.connection(&flow).map(|(_, connection)| connection.state);
+
+        // without a handshake nothing is tracked
+        assert_eq!(tracker.track(&segment(true, ACK)), Some(CtStatus::Invalid));
+        assert_eq!(tracker.track(&segment(false, SYN | ACK)), Some(CtStatus::Invalid));
+        assert_eq!(state(&tracker), None);
+
+        // handshake
+        assert_eq!(tracker.track(&segment(true, SYN)), Some(CtStatus::New));
+        assert_eq!(state(&tracker), Some(ConnState::SynSent));
+        assert_eq!(tracker.track(&segment(false, SYN | ACK)), Some(CtStatus::Established(Direction::Reply)));
+        assert_eq!(state(&tracker), Some(ConnState::SynReceived));
+        assert_eq!(tracker.track(&segment(true, ACK)), Some(CtStatus::Established(Direction::Original)));
+        assert_eq!(state(&tracker), Some(ConnState::Established));
+
+        // teardown
+        tracker.track(&segment(false, FIN | ACK));
+        assert_eq!(state(&tracker), Some(ConnState::Closing));
+        tracker.track(&segment(true, FIN | ACK));
+        assert_eq!(state(&tracker), Some(ConnState::Closed));
+        assert_eq!(tracker.track(&segment(false, ACK)), Some(CtStatus::Closed(Direction::Reply)));
+        assert_eq!(state(&tracker), Some(ConnState::Closed));
+
+        // reopening, then resetting
+        assert_eq!(tracker.track(&segment(true, SYN)), Some(CtStatus::New));
+        assert_eq!(state(&tracker), Some(ConnState::SynSent));
+        tracker.track(&segment(false, RST));
+        assert_eq!(state(&tracker), Some(ConnState::Closed));
+
+        let (_, connection) = tracker.connection(&flow).unwrap();
+        assert_eq!(connection.packets, [4, 4]);
+        assert_eq!(connection.bytes, [4 * 54, 4 * 54]);
+        assert_eq!((tracker.stats().new, tracker.stats().invalid), (2, 2));
+    }
+
+    #[test]
+    fn test_stateful_firewall() {
+        // port 0 is inside, port 1 is outside
+        let mut firewall = ConnTracker::new(16, Duration::from_secs(60)).with_trusted_ports([0]);
+        let builder = FrameBuilder::ethernet(MacAddress([6; 6]), MacAddress([8; 6]));
+        let mut process = |ingress: usize, source: Ipv4Addr, destination: Ipv4Addr, ports: (u16, u16)| {
+            let mut frame = [0_u8; 128];
+            let len = builder.clone().ipv4(source, destination).udp(ports.0, ports.1).write_with_payload(&mut frame, b"query").unwrap();
+            firewall.process(&mut FrameCtx { ingress, data: &mut frame[..len] })
+        };
+
+        assert_eq!(process(1, SERVER, CLIENT, (53, 5353)), Verdict::Drop);
+        assert_eq!(process(0, CLIENT, SERVER, (5353, 53)), Verdict::Continue);
+        assert_eq!(process(1, SERVER, CLIENT, (53, 5353)), Verdict::Continue);
+        assert_eq!(process(1, SERVER, CLIENT, (53, 5354)), Verdict::Drop);
+        let flow = FiveTuple { source: CLIENT.into(), destination: SERVER.into(), protocol: 17, source_port: 5353, destination_port: 53 };
+        assert_eq!(firewall.connection(&flow.reversed()).map(|(direction, connection)| (direction, connection.state)), Some((Direction::Reply, ConnState::Replied)));
+    }
+}
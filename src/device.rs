@@ -0,0 +1,349 @@
+use std::{os::fd::AsRawFd, sync::Arc};
+
+use crate::{config::SocketConfig, packet::Segmenter, BPFRedirectManager, DefaultAllocator, FrameGuard, Umem, UmemAllocator, UmemAllocatorFactory, WaitStrategy, XDPSocket};
+
+/// A frame held in a chunk of the umem of an [`XdpDevice`], the counterpart of a DPDK mbuf
+///
+/// Frames are plain handles: whoever holds one owns its chunk until it is given to [`XdpDevice::tx_burst`] or
+/// [`XdpDevice::free`], forgetting one leaks the chunk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Frame {
+    /// Offset of the first byte of the frame within the umem
+    pub addr: u64,
+    pub len: u32,
+}
+impl Frame {
+    /// The bytes available in front of the frame within its chunk of `umem`
+    ///
+    /// Received frames start past the headroom reserved by the kernel (256 bytes), room for encapsulation headers
+    pub const fn headroom(&self, umem: &Umem) -> usize {
+        (self.addr - umem.chunk_start_offset_for_index(umem.chunk_index_for_offset(self.addr))) as usize
+    }
+
+    /// Grow the frame by `n` bytes at its front, e.g. to prepend a header in place
+    ///
+    /// Returns `false`, leaving the frame untouched, if the headroom within its chunk of `umem` is smaller than `n`
+    pub const fn push_front(&mut self, umem: &Umem, n: usize) -> bool {
+        if n > self.headroom(umem) {
+            return false;
+        }
+        self.addr -= n as u64;
+        self.len += n as u32;
+        true
+    }
+
+    /// Shrink the frame by `n` bytes at its front, e.g. to strip a header in place
+    ///
+    /// Returns `false`, leaving the frame untouched, if it is shorter than `n`
+    pub const fn pull_front(&mut self, n: usize) -> bool {
+        if n > self.len as usize {
+            return false;
+        }
+        self.addr += n as u64;
+        self.len -= n as u32;
+        true
+    }
+}
+
+/// A single queue of an interface driven through batched receive and transmit calls, the way DPDK poll mode drivers are
+///
+/// The fill ring is topped up after every [`Self::rx_burst`] and completions are reaped before every [`Self::tx_burst`],
+/// so the only buffer handling left to applications is freeing the frames they do not transmit
+pub struct XdpDevice<'a, A: UmemAllocator + ?Sized = DefaultAllocator> {
+    socket: XDPSocket<'a>,
+    allocator: Arc<A>,
+    wait_strategy: WaitStrategy,
+    _bpf_manager: Option<BPFRedirectManager>,
+}
+impl<'a, A: UmemAllocatorFactory> XdpDevice<'a, A> {
+    /// Create the socket described by `config` along with its allocator, attaching the redirect program if requested
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "debug"))]
+    pub fn open(config: &SocketConfig) -> Result<Self, crate::Error> {
+        let socket = config.build()?;
+        let allocator = Arc::new(A::for_umem(socket.umem.clone()));
+        let mut bpf_manager = None;
+        if config.redirect {
+            bpf_manager.insert(BPFRedirectManager::attach(socket.if_index)).add_redirect(socket.if_queue, socket.as_raw_fd())?;
+        }
+        let mut device = Self::new(socket, allocator).with_wait_strategy(config.wait);
+        device._bpf_manager = bpf_manager;
+        Ok(device)
+    }
+}
+impl<'a, A: UmemAllocator + ?Sized> XdpDevice<'a, A> {
+    /// Drive `socket`, whose chunks come from `allocator`, filling its fill ring right away
+    pub fn new(mut socket: XDPSocket<'a>, allocator: Arc<A>) -> Self {
+        assert!(std::ptr::eq(allocator.umem_reference(), socket.umem.as_ref()), "allocator does not belong to the umem of the socket");
+        socket.refill_fill_ring(allocator.as_ref(), usize::MAX);
+        Self { socket, allocator, wait_strategy: WaitStrategy::default(), _bpf_manager: None }
+    }
+
+    /// Wait for frames following `wait_strategy` in [`Self::rx_burst_wait`], instead of blocking
+    pub fn with_wait_strategy(mut self, wait_strategy: WaitStrategy) -> Self {
+        self.wait_strategy = wait_strategy;
+        self
+    }
+
+    /// The underlying socket, e.g. to poll it or gather its status
+    pub fn socket(&mut self) -> &mut XDPSocket<'a> {
+        &mut self.socket
+    }
+
+    /// The allocator of the chunks of this device
+    pub const fn allocator(&self) -> &Arc<A> {
+        &self.allocator
+    }
+
+    /// Receive up to `frames.len()` frames into `frames`, returning how many were received
+    ///
+    /// The received frames belong to the caller, which transmits or frees them
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.socket.fd)))]
+    pub fn rx_burst(&mut self, frames: &mut [Frame]) -> usize {
+        let ring = &mut self.socket.rx_ring;
+        let count = frames.len().min(ring.num_consumable() as usize);
+        let consumer_index = ring.get_consumer_index() as usize;
+        for (n, frame) in frames[..count].iter_mut().enumerate() {
+            let descriptor = ring.get_nth_descriptor((consumer_index + n) & (ring.num_elements() - 1));
+            *frame = Frame { addr: descriptor.addr, len: descriptor.len };
+            self.socket.traffic.record_rx(descriptor.len as usize);
+        }
+        ring.advance_consumer_index_by(count as u32);
+
+        // replace the chunks handed out
+        self.socket.refill_fill_ring(self.allocator.as_ref(), usize::MAX);
+        count
+    }
+
+    /// Wait for frames following the wait strategy of this device, then receive them like [`Self::rx_burst`]
+    ///
+    /// Returns 0 if the wait timed out
+    pub fn rx_burst_wait(&mut self, frames: &mut [Frame]) -> Result<usize, crate::Error> {
+        if ! self.socket.wait_for_reception(&self.wait_strategy)? {
+            return Ok(0);
+        }
+        Ok(self.rx_burst(frames))
+    }
+
+    /// Enqueue up to `frames.len()` frames for transmission, returning how many were enqueued
+    ///
+    /// Enqueued frames are given back to the allocator once transmitted, the others still belong to the caller.
+    /// [`XDPSocket::tx_rate_limiter`] does not apply
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.socket.fd)))]
+    pub fn tx_burst(&mut self, frames: &[Frame]) -> Result<usize, crate::Error> {
+        // make room
+        self.socket.reap_completions(self.allocator.as_ref());
+
+        // enqueue, waking up once
+        let mut batch = self.socket.tx_batch();
+        let count = frames.iter().take_while(|frame| batch.push(**frame)).count();
+        batch.commit()?;
+        Ok(count)
+    }
+
+    /// Enqueue a single packet made of `segments` for transmission, see [`XDPSocket::send_multi_buffer`]
+    ///
+    /// The device must have been opened with [`SocketConfig::multi_buffer`]
+    pub fn tx_chain(&mut self, segments: &[Frame]) -> Result<bool, crate::Error> {
+        self.socket.reap_completions(self.allocator.as_ref());
+        self.socket.send_multi_buffer(segments)
+    }
+
+    /// Enqueue `payload` for transmission split into as many frames as `segmenter` needs, returning how many were enqueued
+    ///
+    /// Every segment is copied into its own chunk behind its own headers. Frames are enqueued in order, so when the TX
+    /// ring or the allocator runs short the first `n` segments went out and the payload can be resumed from
+    /// `n * segmenter.segment_size()`
+    pub fn tx_segmented(&mut self, segmenter: &Segmenter, payload: &[u8]) -> Result<usize, crate::Error> {
+        let mut frames = vec![Frame::default(); segmenter.segment_count(payload.len())];
+        let allocated = self.alloc_burst(&mut frames);
+        let chunk_size = self.socket.umem.chunk_size();
+        for (index, frame) in frames[..allocated].iter_mut().enumerate() {
+            let written = segmenter.write_segment(&mut self.data_mut(frame, chunk_size), payload, index);
+            match written {
+                Ok(len) => frame.len = len as u32,
+                Err(error) => {
+                    self.free_burst(&frames[..allocated]);
+                    return Err(error);
+                },
+            }
+        }
+        let count = self.tx_burst(&frames[..allocated])?;
+        self.free_burst(&frames[count..allocated]);
+        Ok(count)
+    }
+
+    /// Allocate an empty frame, to be written with [`Self::data_mut`]
+    pub fn alloc(&self) -> Option<Frame> {
+        let chunk_index = self.allocator.try_allocate()?;
+        Some(Frame { addr: self.socket.umem.chunk_start_offset_for_index(chunk_index), len: 0 })
+    }
+
+    /// Allocate up to `frames.len()` empty frames into `frames`, returning how many were allocated
+    pub fn alloc_burst(&self, frames: &mut [Frame]) -> usize {
+        for (count, frame) in frames.iter_mut().enumerate() {
+            let Some(allocated) = self.alloc() else {
+                return count;
+            };
+            *frame = allocated;
+        }
+        frames.len()
+    }
+
+    /// Give the chunk of `frame` back to the allocator
+    pub fn free(&self, frame: Frame) {
+        self.allocator.release_offset(frame.addr);
+    }
+
+    /// Give the chunks of all of `frames` back to the allocator
+    pub fn free_burst(&self, frames: &[Frame]) {
+        for frame in frames {
+            self.free(*frame);
+        }
+    }
+
+    /// Borrow the contents of `frame`
+    ///
+    /// Panics if `frame` does not lie within a single chunk of the umem or its chunk is already borrowed
+    pub fn data(&self, frame: &Frame) -> FrameGuard<'_> {
+        self.socket.umem.try_frame(frame.addr, frame.len as _).expect("Frame lies outside of its chunk or the chunk is already borrowed")
+    }
+
+    /// Resize `frame` to `len` bytes and borrow its contents for writing
+    ///
+    /// Panics if the frame would not fit within its chunk or its chunk is already borrowed
+    pub fn data_mut(&mut self, frame: &mut Frame, len: usize) -> FrameGuard<'_> {
+        let data = self.socket.umem.try_frame(frame.addr, len).expect("Frame lies outside of its chunk or the chunk is already borrowed");
+        frame.len = len as _;
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, sync::Arc};
+
+    use super::{Frame, XdpDevice};
+    use crate::{packet::{FrameBuilder, MacAddress, Segmenter}, testing::MockXDP, DefaultAllocator, Umem, UmemAllocatorFactory};
+
+    #[test]
+    fn test_device_bursts() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let (socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+        let mut device = XdpDevice::new(socket, Arc::new(DefaultAllocator::for_umem(umem.clone())));
+        assert_eq!(mock.fill_ring_len(), 15);
+
+        // bursts are bounded by the array
+        for frame in [b"one", b"two", b"six"] {
+            assert!(mock.inject(frame));
+        }
+        let mut frames = [Frame::default(); 2];
+        assert_eq!(device.rx_burst(&mut frames), 2);
+        assert_eq!([&*device.data(&frames[0]), &*device.data(&frames[1])], [b"one", b"two"]);
+        let data = device.data(&frames[0]);
+        assert!(umem.try_frame(frames[0].addr, frames[0].len as _).is_err());
+        drop(data);
+        assert_eq!(mock.fill_ring_len(), 15);
+
+        // received frames are transmitted as they are, or freed
+        assert_eq!(device.tx_burst(&frames).unwrap(), 2);
+        assert_eq!(device.rx_burst(&mut frames), 1);
+        device.free(frames[0]);
+        assert_eq!(mock.transmitted_frames(), [b"one", b"two"]);
+
+        // new frames are written in place
+        let mut frame = device.alloc().unwrap();
+        device.data_mut(&mut frame, 5).copy_from_slice(b"fresh");
+        assert_eq!(device.tx_burst(&[frame]).unwrap(), 1);
+        assert_eq!(mock.transmitted_frames(), [b"fresh"]);
+        assert_eq!(device.socket().traffic.rx_packets, 3);
+        assert_eq!(device.socket().traffic.tx_packets, 3);
+
+        // a full ring takes what fits
+        let mut frames = [Frame::default(); 20];
+        assert_eq!(device.alloc_burst(&mut frames), 20);
+        assert_eq!(device.tx_burst(&frames).unwrap(), 15);
+        device.free_burst(&frames[15..]);
+    }
+
+    #[test]
+    fn test_device_tx_chain() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let (socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+        let mut device = XdpDevice::new(socket, Arc::new(DefaultAllocator::for_umem(umem)));
+
+        // a new header in front of a received payload
+        assert!(mock.inject(b"payload"));
+        let mut payload = [Frame::default()];
+        assert_eq!(device.rx_burst(&mut payload), 1);
+        let mut header = device.alloc().unwrap();
+        device.data_mut(&mut header, 6).copy_from_slice(b"header");
+        assert!(device.tx_chain(&[header, payload[0]]).unwrap());
+        let options = [0, 1].map(|index| device.socket().tx_ring.get_nth_descriptor(index).options);
+        assert_eq!(options, [libc::XDP_PKT_CONTD, 0]);
+        assert_eq!(mock.transmitted_frames(), [&b"header"[..], b"payload"]);
+        assert_eq!((device.socket().traffic.tx_packets, device.socket().traffic.tx_bytes), (1, 13));
+
+        // all segments or none
+        let mut segments = [Frame::default(); 16];
+        assert_eq!(device.alloc_burst(&mut segments), 16);
+        assert!(! device.tx_chain(&segments).unwrap());
+        assert!(device.tx_chain(&segments[..15]).unwrap());
+        device.free(segments[15]);
+
+        // segments must stay within their chunk
+        let corrupt = Frame { addr: 2048 - 4, len: 8 };
+        assert!(matches!(device.tx_chain(&[corrupt]), Err(crate::Error::CorruptDescriptor { .. })));
+    }
+
+    #[test]
+    fn test_device_tx_segmented() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let (socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+        let mut device = XdpDevice::new(socket, Arc::new(DefaultAllocator::for_umem(umem)));
+        let builder = FrameBuilder::ethernet(MacAddress([2, 0, 0, 0, 0, 1]), MacAddress([2, 0, 0, 0, 0, 2]))
+            .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2))
+            .udp(1000, 2000);
+
+        // 100 bytes per segment
+        let segmenter = Segmenter::new(builder, 128);
+        let payload = [7_u8; 250];
+        assert_eq!(device.tx_segmented(&segmenter, &payload).unwrap(), 3);
+        let lengths = mock.transmitted_frames().iter().map(Vec::len).collect::<Vec<_>>();
+        assert_eq!(lengths, [142, 142, 92]);
+
+        // a full ring takes the first segments
+        assert_eq!(device.tx_segmented(&segmenter, &[7_u8; 2000]).unwrap(), 15);
+        assert_eq!(mock.transmitted_frames().len(), 15);
+
+        // segments must fit within chunks
+        let segmenter = Segmenter::new(segmenter.builder().clone(), 4000);
+        assert!(matches!(device.tx_segmented(&segmenter, &[7_u8; 4000]), Err(crate::Error::BufferTooSmall { .. })));
+    }
+
+    #[test]
+    fn test_frame_push_pull() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let (socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+        let mut device = XdpDevice::new(socket, Arc::new(DefaultAllocator::for_umem(umem.clone())));
+        assert!(mock.inject(b"\xaa\xbbinner"));
+        let mut frames = [Frame::default()];
+        assert_eq!(device.rx_burst(&mut frames), 1);
+        let [mut frame] = frames;
+        assert_eq!(frame.headroom(&umem), MockXDP::DEFAULT_HEADROOM);
+
+        // decapsulate
+        assert!(frame.pull_front(2));
+        assert_eq!(*device.data(&frame), *b"inner");
+        assert!(! frame.pull_front(6));
+
+        // encapsulate, within the headroom only
+        assert!(frame.push_front(&umem, 4));
+        device.data_mut(&mut frame, 9)[..4].copy_from_slice(b"outr");
+        assert_eq!(*device.data(&frame), *b"outrinner");
+        assert_eq!(frame.headroom(&umem), MockXDP::DEFAULT_HEADROOM - 2);
+        assert!(! frame.push_front(&umem, MockXDP::DEFAULT_HEADROOM - 1));
+        assert!(frame.push_front(&umem, MockXDP::DEFAULT_HEADROOM - 2));
+        assert_eq!(frame.headroom(&umem), 0);
+        device.free(frame);
+    }
+}
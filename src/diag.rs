@@ -0,0 +1,277 @@
+//! Enumerate the AF_XDP sockets of the network namespace through the kernel socket diag interface (xsk_diag)
+//!
+//! Useful to monitor sockets from outside of the application owning them, or to find out who is bound to a queue.
+//! The kernel needs `CONFIG_XDP_SOCKETS_DIAG`
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+// netlink layout
+const NLMSG_HEADER_SIZE: usize = 16;
+const NLATTR_HEADER_SIZE: usize = 4;
+const SOCK_DIAG_BY_FAMILY: u16 = 20;
+const XDP_DIAG_REQ_SIZE: usize = 20;
+const XDP_DIAG_MSG_SIZE: usize = 16;
+
+// what to show, see linux/xdp_diag.h
+const XDP_SHOW_INFO: u32 = 1 << 0;
+const XDP_SHOW_RING_CFG: u32 = 1 << 1;
+const XDP_SHOW_UMEM: u32 = 1 << 2;
+const XDP_SHOW_STATS: u32 = 1 << 4;
+
+// attributes
+const XDP_DIAG_INFO: u16 = 1;
+const XDP_DIAG_UID: u16 = 2;
+const XDP_DIAG_RX_RING: u16 = 3;
+const XDP_DIAG_TX_RING: u16 = 4;
+const XDP_DIAG_UMEM: u16 = 5;
+const XDP_DIAG_UMEM_FILL_RING: u16 = 6;
+const XDP_DIAG_UMEM_COMPLETION_RING: u16 = 7;
+const XDP_DIAG_STATS: u16 = 9;
+
+/// An AF_XDP socket as reported by the kernel, rings which were not set up have no size
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XskDiagInfo {
+    /// The inode of the socket, to be matched against `/proc/<pid>/fd/*` links of the form `socket:[<inode>]`
+    pub inode: u32,
+    pub cookie: u64,
+    /// The interface and queue the socket is bound to, 0 and 0 while unbound
+    pub if_index: u32,
+    pub if_queue: u32,
+    pub uid: Option<u32>,
+    pub rx_ring: Option<u32>,
+    pub tx_ring: Option<u32>,
+    pub fill_ring: Option<u32>,
+    pub completion_ring: Option<u32>,
+    pub umem: Option<XskDiagUmem>,
+    pub stats: Option<XskDiagStats>,
+}
+
+/// The umem registered with, or shared by, an AF_XDP socket
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XskDiagUmem {
+    /// Sockets sharing a umem report the same id
+    pub id: u32,
+    pub size: u64,
+    pub num_pages: u32,
+    pub chunk_size: u32,
+    pub headroom: u32,
+    pub if_index: u32,
+    pub if_queue: u32,
+    pub flags: u32,
+    pub refs: u32,
+}
+
+/// The drop counters of an AF_XDP socket
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct XskDiagStats {
+    pub rx_dropped: u64,
+    pub rx_invalid: u64,
+    pub rx_full: u64,
+    pub fill_ring_empty: u64,
+    pub tx_invalid: u64,
+    pub tx_ring_empty: u64,
+}
+
+/// List every AF_XDP socket of the current network namespace
+///
+/// Fails with [`crate::Error::SocketReceiveFailure`] carrying `ENOENT` when the kernel lacks xsk_diag
+#[cfg_attr(feature = "instrumentation", tracing::instrument(level = "debug"))]
+pub fn list_sockets() -> Result<Vec<XskDiagInfo>, crate::Error> {
+    // create netlink socket
+    let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_SOCK_DIAG) };
+    if fd < 0 {
+        return Err(crate::Error::SocketCreationFailure);
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // ask for a dump
+    let mut request = [0_u8; NLMSG_HEADER_SIZE + XDP_DIAG_REQ_SIZE];
+    let request_len = request.len() as u32;
+    request[0..4].copy_from_slice(&request_len.to_ne_bytes());
+    request[4..6].copy_from_slice(&SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+    request[6..8].copy_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+    request[NLMSG_HEADER_SIZE] = libc::AF_XDP as u8;
+    let show = XDP_SHOW_INFO | XDP_SHOW_RING_CFG | XDP_SHOW_UMEM | XDP_SHOW_STATS;
+    request[NLMSG_HEADER_SIZE + 8..NLMSG_HEADER_SIZE + 12].copy_from_slice(&show.to_ne_bytes());
+    if unsafe { libc::send(fd.as_raw_fd(), request.as_ptr().cast(), request.len(), 0) } < 0 {
+        return Err(crate::Error::SocketSendFailure { error: std::io::Error::last_os_error() });
+    }
+
+    // collect answers
+    let mut sockets = Vec::new();
+    let mut buffer = vec![0_u8; 32 * 1024];
+    loop {
+        let received = unsafe { libc::recv(fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len(), 0) };
+        if received < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(crate::Error::SocketReceiveFailure { error });
+        }
+        if parse(&buffer[..received as usize], &mut sockets)? {
+            return Ok(sockets);
+        }
+    }
+}
+
+/// Find the sockets bound to queue `queue_id` of the interface with index `interface_index`
+pub fn sockets_on_queue(interface_index: u32, queue_id: u32) -> Result<Vec<XskDiagInfo>, crate::Error> {
+    let mut sockets = list_sockets()?;
+    sockets.retain(|socket| socket.if_index == interface_index && socket.if_queue == queue_id);
+    Ok(sockets)
+}
+
+/// Push the sockets described by the netlink messages in `buffer`, returning whether the dump is over
+fn parse(buffer: &[u8], sockets: &mut Vec<XskDiagInfo>) -> Result<bool, crate::Error> {
+    let u16_at = |bytes: &[u8], at: usize| u16::from_ne_bytes([bytes[at], bytes[at + 1]]);
+    let u32_at = |bytes: &[u8], at: usize| u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap());
+    let u64_at = |bytes: &[u8], at: usize| u64::from_ne_bytes(bytes[at..at + 8].try_into().unwrap());
+
+    let mut messages = buffer;
+    while messages.len() >= NLMSG_HEADER_SIZE {
+        let message_len = u32_at(messages, 0) as usize;
+        if message_len < NLMSG_HEADER_SIZE || message_len > messages.len() {
+            break;
+        }
+        let message = &messages[..message_len];
+        messages = &messages[message_len.next_multiple_of(4).min(messages.len())..];
+
+        // control messages
+        match u16_at(message, 4) as libc::c_int {
+            libc::NLMSG_DONE => return Ok(true),
+            libc::NLMSG_ERROR if message.len() >= NLMSG_HEADER_SIZE + 4 => {
+                let errno = -(u32_at(message, NLMSG_HEADER_SIZE) as i32);
+                if errno == 0 {
+                    continue;
+                }
+                return Err(crate::Error::SocketReceiveFailure { error: std::io::Error::from_raw_os_error(errno) });
+            },
+            message_type if message_type == SOCK_DIAG_BY_FAMILY as libc::c_int && message.len() >= NLMSG_HEADER_SIZE + XDP_DIAG_MSG_SIZE => {},
+            _ => continue,
+        }
+
+        // socket
+        let diag = &message[NLMSG_HEADER_SIZE..];
+        let mut socket = XskDiagInfo {
+            inode: u32_at(diag, 4),
+            cookie: u32_at(diag, 8) as u64 | (u32_at(diag, 12) as u64) << 32,
+            ..Default::default()
+        };
+
+        // attributes
+        let mut attributes = &diag[XDP_DIAG_MSG_SIZE..];
+        while attributes.len() >= NLATTR_HEADER_SIZE {
+            let attribute_len = u16_at(attributes, 0) as usize;
+            if attribute_len < NLATTR_HEADER_SIZE || attribute_len > attributes.len() {
+                break;
+            }
+            let payload = &attributes[NLATTR_HEADER_SIZE..attribute_len];
+            match u16_at(attributes, 2) {
+                XDP_DIAG_INFO if payload.len() >= 8 => {
+                    socket.if_index = u32_at(payload, 0);
+                    socket.if_queue = u32_at(payload, 4);
+                },
+                XDP_DIAG_UID if payload.len() >= 4 => socket.uid = Some(u32_at(payload, 0)),
+                XDP_DIAG_RX_RING if payload.len() >= 4 => socket.rx_ring = Some(u32_at(payload, 0)),
+                XDP_DIAG_TX_RING if payload.len() >= 4 => socket.tx_ring = Some(u32_at(payload, 0)),
+                XDP_DIAG_UMEM_FILL_RING if payload.len() >= 4 => socket.fill_ring = Some(u32_at(payload, 0)),
+                XDP_DIAG_UMEM_COMPLETION_RING if payload.len() >= 4 => socket.completion_ring = Some(u32_at(payload, 0)),
+                XDP_DIAG_UMEM if payload.len() >= 40 => socket.umem = Some(XskDiagUmem {
+                    size: u64_at(payload, 0),
+                    id: u32_at(payload, 8),
+                    num_pages: u32_at(payload, 12),
+                    chunk_size: u32_at(payload, 16),
+                    headroom: u32_at(payload, 20),
+                    if_index: u32_at(payload, 24),
+                    if_queue: u32_at(payload, 28),
+                    flags: u32_at(payload, 32),
+                    refs: u32_at(payload, 36),
+                }),
+                XDP_DIAG_STATS if payload.len() >= 48 => socket.stats = Some(XskDiagStats {
+                    rx_dropped: u64_at(payload, 0),
+                    rx_invalid: u64_at(payload, 8),
+                    rx_full: u64_at(payload, 16),
+                    fill_ring_empty: u64_at(payload, 24),
+                    tx_invalid: u64_at(payload, 32),
+                    tx_ring_empty: u64_at(payload, 40),
+                }),
+                _ => {},
+            }
+            attributes = &attributes[attribute_len.next_multiple_of(4).min(attributes.len())..];
+        }
+        sockets.push(socket);
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    use super::{list_sockets, parse, XskDiagInfo, XskDiagUmem, SOCK_DIAG_BY_FAMILY, XDP_DIAG_INFO, XDP_DIAG_RX_RING, XDP_DIAG_UMEM};
+    use crate::Umem;
+
+    fn attribute(kind: u16, payload: &[u8]) -> Vec<u8> {
+        let mut attribute = Vec::new();
+        attribute.extend_from_slice(&(4 + payload.len() as u16).to_ne_bytes());
+        attribute.extend_from_slice(&kind.to_ne_bytes());
+        attribute.extend_from_slice(payload);
+        attribute
+    }
+
+    fn message(kind: u16, payload: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&(16 + payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        message.extend_from_slice(payload);
+        message
+    }
+
+    #[test]
+    fn test_parse_diag_messages() {
+        // socket 7 bound to queue 2 of interface 3
+        let mut diag = vec![libc::AF_XDP as u8, libc::SOCK_RAW as u8, 0, 0];
+        diag.extend_from_slice(&7_u32.to_ne_bytes());
+        diag.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+        diag.extend(attribute(XDP_DIAG_INFO, &[3_u32.to_ne_bytes(), 2_u32.to_ne_bytes()].concat()));
+        diag.extend(attribute(XDP_DIAG_RX_RING, &2048_u32.to_ne_bytes()));
+        let umem: Vec<u8> = [&(1_u64 << 20).to_ne_bytes()[..], &[5_u32, 256, 2048, 0, 3, 2, 0, 1].map(u32::to_ne_bytes).concat()].concat();
+        diag.extend(attribute(XDP_DIAG_UMEM, &umem));
+
+        let mut buffer = message(SOCK_DIAG_BY_FAMILY, &diag);
+        buffer.extend(message(libc::NLMSG_DONE as u16, &0_u32.to_ne_bytes()));
+        let mut sockets = Vec::new();
+        assert!(parse(&buffer, &mut sockets).unwrap());
+        assert_eq!(sockets, [XskDiagInfo {
+            inode: 7,
+            cookie: 1,
+            if_index: 3,
+            if_queue: 2,
+            rx_ring: Some(2048),
+            umem: Some(XskDiagUmem { id: 5, size: 1 << 20, num_pages: 256, chunk_size: 2048, headroom: 0, if_index: 3, if_queue: 2, flags: 0, refs: 1 }),
+            ..Default::default()
+        }]);
+
+        // errors are reported
+        let error = message(libc::NLMSG_ERROR as u16, &(-libc::ENOENT).to_ne_bytes());
+        assert!(parse(&error, &mut sockets).is_err());
+    }
+
+    #[test]
+    #[ignore = "requires root"]
+    fn test_list_sockets() {
+        // an unbound socket with a registered umem
+        let umem = Umem::new_2k(16).unwrap();
+        let fd = unsafe { OwnedFd::from_raw_fd(libc::socket(libc::AF_XDP, libc::SOCK_RAW, 0)) };
+        umem.register(fd.as_raw_fd()).unwrap();
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        assert_eq!(unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) }, 0);
+
+        let sockets = list_sockets().unwrap();
+        let socket = sockets.iter().find(|socket| socket.inode as u64 == stat.st_ino).unwrap();
+        assert_eq!(socket.umem.unwrap().chunk_size, 2048);
+        assert_eq!(socket.umem.unwrap().size, 16 * 2048);
+    }
+}
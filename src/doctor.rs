@@ -0,0 +1,396 @@
+use std::{fmt, os::fd::{AsRawFd, FromRawFd, OwnedFd}};
+
+use crate::genl::{attributes, NLMSG_HEADER_SIZE};
+
+/// How much a [`Finding`] of [`doctor`] matters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Nothing to act upon, e.g. the index of the interface
+    Info,
+    /// Things will work, slower or with caveats
+    Warning,
+    /// Binding sockets or attaching the redirect program will fail
+    Blocker,
+}
+
+/// The outcome of one of the checks run by [`doctor`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// How much this finding matters
+    pub severity: Severity,
+    /// What was checked, e.g. `kernel` or `capabilities`
+    pub check: &'static str,
+    /// What was found, in plain words
+    pub message: String,
+}
+
+/// Everything [`doctor`] found out about an interface and the current process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DoctorReport {
+    pub interface: String,
+    pub findings: Vec<Finding>,
+}
+impl DoctorReport {
+    /// The findings preventing AF_XDP from working
+    pub fn blockers(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(|finding| finding.severity == Severity::Blocker)
+    }
+
+    /// Whether nothing prevents AF_XDP from working
+    pub fn is_ok(&self) -> bool {
+        self.blockers().next().is_none()
+    }
+
+    fn push(&mut self, severity: Severity, check: &'static str, message: impl Into<String>) {
+        self.findings.push(Finding { severity, check, message: message.into() });
+    }
+}
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in &self.findings {
+            let severity = match finding.severity {
+                Severity::Info => "ok",
+                Severity::Warning => "warning",
+                Severity::Blocker => "BLOCKER",
+            };
+            writeln!(f, "[{severity}] {}: {}", finding.check, finding.message)?;
+        }
+        Ok(())
+    }
+}
+
+// capability numbers, see linux/capability.h
+const CAP_NET_ADMIN: u32 = 12;
+const CAP_NET_RAW: u32 = 13;
+const CAP_IPC_LOCK: u32 = 14;
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_BPF: u32 = 39;
+
+/// Drivers known to support zero-copy AF_XDP sockets
+const ZERO_COPY_DRIVERS: &[&str] = &["i40e", "ice", "igb", "igc", "ixgbe", "mlx5_core", "stmmac", "virtio_net", "idpf", "bnxt_en"];
+
+/// Below this much lockable memory umems of a few thousand chunks cannot be registered without CAP_IPC_LOCK
+const MIN_MEMLOCK: u64 = 64 << 20;
+
+/// The largest MTU a frame fits a single 4k chunk with, past the headroom reserved by the kernel
+const MAX_SINGLE_BUFFER_MTU: u32 = 3498;
+
+/// Check whether the interface named `if_name` and the current process can run AF_XDP sockets
+///
+/// Looks at the kernel version, the driver, queues and MTU of the interface, the XDP programs and sockets already on it,
+/// the memlock limit and the capabilities of the process. Nothing is changed, and checks which cannot be run are reported
+/// as warnings rather than failing
+pub fn doctor(if_name: &str) -> DoctorReport {
+    let mut report = DoctorReport { interface: if_name.to_string(), findings: Vec::new() };
+
+    // process
+    check_kernel(&mut report, &kernel_release());
+    let capabilities = std::fs::read_to_string("/proc/self/status").ok().and_then(|status| effective_capabilities(&status));
+    check_capabilities(&mut report, capabilities);
+    check_memlock(&mut report, capabilities);
+
+    // interface
+    let if_index = match crate::utils::interface_name_to_index(if_name) {
+        Ok(if_index) => if_index,
+        Err(crate::Error::InterfaceNotFound { .. }) => {
+            report.push(Severity::Blocker, "interface", format!("no interface named {if_name} in this network namespace"));
+            return report;
+        },
+        Err(error) => {
+            report.push(Severity::Blocker, "interface", error.to_string());
+            return report;
+        },
+    };
+    let sysfs = |file: &str| std::fs::read_to_string(format!("/sys/class/net/{if_name}/{file}")).ok().map(|value| value.trim().to_string());
+    report.push(Severity::Info, "interface", format!("{if_name} has index {if_index}"));
+    if sysfs("operstate").as_deref() == Some("down") {
+        report.push(Severity::Warning, "interface", "the interface is down, sockets bind but receive nothing");
+    }
+    check_driver(&mut report, if_name);
+    check_queues(&mut report, if_name);
+    if let Some(mtu) = sysfs("mtu").and_then(|mtu| mtu.parse::<u32>().ok()) && mtu > MAX_SINGLE_BUFFER_MTU {
+        report.push(Severity::Warning, "mtu", format!("an MTU of {mtu} exceeds {MAX_SINGLE_BUFFER_MTU}, native XDP needs multi-buffer sockets (XDP_USE_SG) or a smaller MTU"));
+    }
+    check_xdp_program(&mut report, if_index);
+    check_sockets(&mut report, if_index);
+    report
+}
+
+fn kernel_release() -> String {
+    let mut uname: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uname) } < 0 {
+        return String::new();
+    }
+    unsafe { std::ffi::CStr::from_ptr(uname.release.as_ptr()) }.to_string_lossy().into_owned()
+}
+
+/// The major and minor version of a kernel release string such as `6.8.0-45-generic`
+fn kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut numbers = release.split(|c: char| ! c.is_ascii_digit());
+    Some((numbers.next()?.parse().ok()?, numbers.next()?.parse().ok()?))
+}
+
+fn check_kernel(report: &mut DoctorReport, release: &str) {
+    let Some(version) = kernel_version(release) else {
+        report.push(Severity::Warning, "kernel", format!("cannot make sense of kernel release {release:?}"));
+        return;
+    };
+    let (severity, message) = match version {
+        version if version < (5, 4) => (Severity::Blocker, "sockets are bound with XDP_USE_NEED_WAKEUP, which needs 5.4"),
+        version if version < (5, 18) => (Severity::Warning, "frame sampling needs 5.18, multi-buffer sockets 6.6"),
+        version if version < (6, 6) => (Severity::Warning, "multi-buffer sockets (XDP_USE_SG) need 6.6"),
+        _ => (Severity::Info, "every feature is supported"),
+    };
+    report.push(severity, "kernel", format!("{release}: {message}"));
+}
+
+/// The effective capabilities in the contents of `/proc/<pid>/status`
+fn effective_capabilities(status: &str) -> Option<u64> {
+    let line = status.lines().find_map(|line| line.strip_prefix("CapEff:"))?;
+    u64::from_str_radix(line.trim(), 16).ok()
+}
+
+fn check_capabilities(report: &mut DoctorReport, capabilities: Option<u64>) {
+    let Some(capabilities) = capabilities else {
+        report.push(Severity::Warning, "capabilities", "cannot read the capabilities of the process");
+        return;
+    };
+    let has = |capability: u32| capabilities & 1 << capability != 0;
+    let mut missing = false;
+    if ! has(CAP_NET_RAW) {
+        report.push(Severity::Blocker, "capabilities", "CAP_NET_RAW is needed to create AF_XDP sockets, unless they are handed over (see handoff)");
+        missing = true;
+    }
+    if ! has(CAP_NET_ADMIN) {
+        report.push(Severity::Blocker, "capabilities", "CAP_NET_ADMIN is needed to attach the redirect program");
+        missing = true;
+    }
+    if ! has(CAP_BPF) && ! has(CAP_SYS_ADMIN) {
+        report.push(Severity::Blocker, "capabilities", "CAP_BPF or CAP_SYS_ADMIN is needed to load the redirect program");
+        missing = true;
+    }
+    if ! missing {
+        report.push(Severity::Info, "capabilities", "the process may create sockets and load programs");
+    }
+}
+
+fn check_memlock(report: &mut DoctorReport, capabilities: Option<u64>) {
+    if capabilities.is_some_and(|capabilities| capabilities & 1 << CAP_IPC_LOCK != 0) {
+        report.push(Severity::Info, "memlock", "CAP_IPC_LOCK lifts the memlock limit");
+        return;
+    }
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) } < 0 {
+        report.push(Severity::Warning, "memlock", "cannot read the memlock limit");
+    } else if limit.rlim_cur != libc::RLIM_INFINITY && limit.rlim_cur < MIN_MEMLOCK {
+        report.push(Severity::Warning, "memlock", format!(
+            "only {} KiB can be locked, registering larger umems fails with ENOBUFS: raise it (ulimit -l) or grant CAP_IPC_LOCK",
+            limit.rlim_cur >> 10,
+        ));
+    } else {
+        report.push(Severity::Info, "memlock", "the memlock limit leaves room for large umems");
+    }
+}
+
+fn check_driver(report: &mut DoctorReport, if_name: &str) {
+    let driver = std::fs::read_link(format!("/sys/class/net/{if_name}/device/driver")).ok()
+        .and_then(|path| path.file_name().map(|name| name.to_string_lossy().into_owned()));
+    match driver {
+        Some(driver) if ZERO_COPY_DRIVERS.contains(&driver.as_str()) => {
+            report.push(Severity::Info, "driver", format!("{driver} supports native XDP and zero-copy sockets"));
+        },
+        Some(driver) => {
+            report.push(Severity::Warning, "driver", format!("{driver} is not known to support zero-copy, expect copy mode or a generic program"));
+        },
+        None => {
+            report.push(Severity::Warning, "driver", "the interface is virtual (e.g. veth, bridge), sockets run in copy mode");
+        },
+    }
+}
+
+fn check_queues(report: &mut DoctorReport, if_name: &str) {
+    let rx_queues = std::fs::read_dir(format!("/sys/class/net/{if_name}/queues")).map(|entries| {
+        entries.filter_map(Result::ok).filter(|entry| entry.file_name().to_string_lossy().starts_with("rx-")).count()
+    });
+    match rx_queues {
+        Ok(1) => report.push(Severity::Info, "queues", "1 RX queue, a single socket receives everything"),
+        Ok(count) => report.push(Severity::Info, "queues", format!(
+            "{count} RX queues, frames only reach the sockets of the queues they land on: bind one per queue or steer traffic with ethtool",
+        )),
+        Err(_) => report.push(Severity::Warning, "queues", "cannot count the queues of the interface"),
+    }
+}
+
+fn check_xdp_program(report: &mut DoctorReport, if_index: libc::c_uint) {
+    match query_xdp_program(if_index) {
+        Ok(None) => report.push(Severity::Info, "xdp", "no XDP program is attached"),
+        Ok(Some((mode, program_id))) => {
+            let mode = match mode {
+                1 => "native",
+                2 => "generic",
+                3 => "offloaded",
+                _ => "multiple",
+            };
+            report.push(Severity::Warning, "xdp", format!(
+                "program {program_id} is attached ({mode}), attaching the redirect program fails until it is removed (ip link set dev <if> xdp off)",
+            ));
+        },
+        Err(error) => report.push(Severity::Warning, "xdp", format!("cannot query XDP programs ({error})")),
+    }
+}
+
+fn check_sockets(report: &mut DoctorReport, if_index: libc::c_uint) {
+    match crate::diag::list_sockets() {
+        Ok(sockets) => {
+            let mut queues = sockets.iter().filter(|socket| socket.if_index == if_index).map(|socket| socket.if_queue).collect::<Vec<_>>();
+            queues.sort_unstable();
+            queues.dedup();
+            if queues.is_empty() {
+                report.push(Severity::Info, "sockets", "no AF_XDP socket is bound to the interface");
+            } else {
+                report.push(Severity::Warning, "sockets", format!("queues {queues:?} already have sockets bound, binding others to them fails with EBUSY"));
+            }
+        },
+        Err(error) => report.push(Severity::Warning, "sockets", format!("cannot list AF_XDP sockets ({error})")),
+    }
+}
+
+// netlink layout
+const IFINFOMSG_SIZE: usize = 16;
+const IFLA_XDP_ATTACHED: u16 = 2;
+const IFLA_XDP_PROG_ID: u16 = 4;
+
+/// The attach mode and id of the XDP program of the interface with index `if_index`, if any
+fn query_xdp_program(if_index: libc::c_uint) -> Result<Option<(u8, u32)>, crate::Error> {
+    // create netlink socket
+    let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) };
+    if fd < 0 {
+        return Err(crate::Error::SocketCreationFailure);
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // ask for the link
+    let mut request = [0_u8; NLMSG_HEADER_SIZE + IFINFOMSG_SIZE];
+    let request_len = request.len() as u32;
+    request[0..4].copy_from_slice(&request_len.to_ne_bytes());
+    request[4..6].copy_from_slice(&libc::RTM_GETLINK.to_ne_bytes());
+    request[6..8].copy_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+    request[NLMSG_HEADER_SIZE] = libc::AF_UNSPEC as u8;
+    request[NLMSG_HEADER_SIZE + 4..NLMSG_HEADER_SIZE + 8].copy_from_slice(&(if_index as i32).to_ne_bytes());
+    if unsafe { libc::send(fd.as_raw_fd(), request.as_ptr().cast(), request.len(), 0) } < 0 {
+        return Err(crate::Error::SocketSendFailure { error: std::io::Error::last_os_error() });
+    }
+
+    // read the answer
+    let mut buffer = vec![0_u8; 32 * 1024];
+    let received = loop {
+        let received = unsafe { libc::recv(fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len(), 0) };
+        if received >= 0 {
+            break received as usize;
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(crate::Error::SocketReceiveFailure { error });
+        }
+    };
+    parse_xdp_attachment(&buffer[..received])
+}
+
+/// The attach mode and program id found in the IFLA_XDP attribute of the link message in `message`
+fn parse_xdp_attachment(message: &[u8]) -> Result<Option<(u8, u32)>, crate::Error> {
+    let u16_at = |bytes: &[u8], at: usize| u16::from_ne_bytes([bytes[at], bytes[at + 1]]);
+    let u32_at = |bytes: &[u8], at: usize| u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap());
+
+    // header
+    if message.len() < NLMSG_HEADER_SIZE + 4 {
+        return Ok(None);
+    }
+    if u16_at(message, 4) as libc::c_int == libc::NLMSG_ERROR {
+        let errno = -(u32_at(message, NLMSG_HEADER_SIZE) as i32);
+        return Err(crate::Error::SocketReceiveFailure { error: std::io::Error::from_raw_os_error(errno) });
+    }
+    let message_len = (u32_at(message, 0) as usize).min(message.len());
+    if u16_at(message, 4) != libc::RTM_NEWLINK || message_len < NLMSG_HEADER_SIZE + IFINFOMSG_SIZE {
+        return Ok(None);
+    }
+
+    // attributes, IFLA_XDP nests the mode and program
+    let Some((_, xdp)) = attributes(&message[NLMSG_HEADER_SIZE + IFINFOMSG_SIZE..message_len]).find(|(kind, _)| *kind == libc::IFLA_XDP) else {
+        return Ok(None);
+    };
+    let mut mode = 0;
+    let mut program_id = 0;
+    for (kind, payload) in attributes(xdp) {
+        match kind {
+            IFLA_XDP_ATTACHED if ! payload.is_empty() => mode = payload[0],
+            IFLA_XDP_PROG_ID if payload.len() >= 4 => program_id = u32_at(payload, 0),
+            _ => {},
+        }
+    }
+    Ok((mode != 0).then_some((mode, program_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_capabilities, check_kernel, effective_capabilities, kernel_version, parse_xdp_attachment, DoctorReport, Severity};
+
+    fn empty_report() -> DoctorReport {
+        DoctorReport { interface: "eth0".to_string(), findings: Vec::new() }
+    }
+
+    #[test]
+    fn test_kernel_checks() {
+        assert_eq!(kernel_version("6.8.0-45-generic"), Some((6, 8)));
+        assert_eq!(kernel_version("5.15.167.4-microsoft-standard-WSL2"), Some((5, 15)));
+        assert_eq!(kernel_version("weird"), None);
+
+        let severity = |release: &str| {
+            let mut report = empty_report();
+            check_kernel(&mut report, release);
+            report.findings[0].severity
+        };
+        assert_eq!(severity("4.19.0"), Severity::Blocker);
+        assert_eq!(severity("5.15.0"), Severity::Warning);
+        assert_eq!(severity("6.18.44-fc"), Severity::Info);
+    }
+
+    #[test]
+    fn test_capability_checks() {
+        let status = "Name:\tcat\nCapInh:\t0000000000000000\nCapEff:\t0000000000003000\n";
+        assert_eq!(effective_capabilities(status), Some(0x3000));
+
+        // net_admin and net_raw, but not bpf
+        let mut report = empty_report();
+        check_capabilities(&mut report, effective_capabilities(status));
+        assert_eq!(report.blockers().count(), 1);
+        assert!(! report.is_ok());
+        assert!(report.to_string().starts_with("[BLOCKER] capabilities: CAP_BPF"));
+
+        let mut report = empty_report();
+        check_capabilities(&mut report, Some(0x3000 | 1 << 39));
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn test_parse_xdp_attachment() {
+        let mut message = vec![0_u8; 32];
+        message[4..6].copy_from_slice(&libc::RTM_NEWLINK.to_ne_bytes());
+        message.extend_from_slice(&8_u16.to_ne_bytes());
+        message.extend_from_slice(&libc::IFLA_MTU.to_ne_bytes());
+        message.extend_from_slice(&1500_u32.to_ne_bytes());
+        message.extend_from_slice(&20_u16.to_ne_bytes());
+        message.extend_from_slice(&(libc::IFLA_XDP | 0x8000).to_ne_bytes());
+        message.extend_from_slice(&[5, 0, 2, 0, 1, 0, 0, 0]);
+        message.extend_from_slice(&8_u16.to_ne_bytes());
+        message.extend_from_slice(&4_u16.to_ne_bytes());
+        message.extend_from_slice(&42_u32.to_ne_bytes());
+        let message_len = message.len() as u32;
+        message[0..4].copy_from_slice(&message_len.to_ne_bytes());
+        assert_eq!(parse_xdp_attachment(&message).unwrap(), Some((1, 42)));
+
+        // no program
+        message.truncate(40);
+        message[0..4].copy_from_slice(&40_u32.to_ne_bytes());
+        assert_eq!(parse_xdp_attachment(&message).unwrap(), None);
+    }
+}
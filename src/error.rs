@@ -1,12 +1,81 @@
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("BPF failure (error = {error})")] BpfFailure { error: libbpf_rs::Error },
+    #[error("Buffer too small (required = {required}, available = {available})")] BufferTooSmall { required: usize, available: usize },
+    #[error("Capture format failure ({reason})")] CaptureFormatFailure { reason: &'static str },
+    #[error("Capture I/O failure (error = {error})")] CaptureIOFailure { error: std::io::Error },
+    #[error("Chunk already borrowed (index = {index})")] ChunkAlreadyBorrowed { index: usize },
+    #[error("Clock failure (error = {error})")] ClockFailure { error: std::io::Error },
+    #[error("Command failure (command = {command}, reason = {reason})")] CommandFailure { command: String, reason: String },
+    #[error("Corrupt descriptor (addr = {addr}, len = {len})")] CorruptDescriptor { addr: u64, len: u32 },
+    #[error("Ethtool failure (command = {command}, error = {error})")] EthtoolFailure { command: u32, error: std::io::Error },
+    #[error("Eventfd failure (error = {error})")] EventFdFailure { error: std::io::Error },
+    #[error("Fragmentation failure ({reason})")] FragmentationFailure { reason: &'static str },
+    #[error("Frame too large (length = {length}, chunk size = {chunk_size})")] FrameTooLarge { length: usize, chunk_size: usize },
+    #[error("Handoff failure ({reason})")] HandoffFailure { reason: &'static str },
+    #[error("Interface not found ({interface})")] InterfaceNotFound { interface: String },
+    #[error("Invalid configuration ({reason})")] InvalidConfiguration { reason: String },
+    #[error("Invalid filter expression (expression = {expression:?}, reason = {reason})")] InvalidFilterExpression { expression: String, reason: String },
+    #[error("Invalid interface name (name = {name:?}, reason = {reason})")] InvalidInterfaceName { name: String, reason: &'static str },
     #[error("Memory allocation failure")] MemoryAllocationFailure,
     #[error("Memory map failure")] MemoryMapFailure,
-    #[error("Poll failure")] PollFailure,
+    #[error("Memory unmap failure (error = {error})")] MemoryUnmapFailure { error: std::io::Error },
+    #[error("Poll failure (error = {error})")] PollFailure { error: std::io::Error },
     #[error("Socket bind failure")] SocketBindFailure { error: std::io::Error },
+    #[error("Socket close failure (error = {error})")] SocketCloseFailure { error: std::io::Error },
+    #[error("Socket closed")] SocketClosed,
     #[error("Socket creation failure")] SocketCreationFailure,
+    #[error("Socket error (error = {error})")] SocketError { error: std::io::Error },
     #[error("Socket getsockopt failure (error = {error}, level = {level}, name = {name})")] SocketGetOptionFailure { error: std::io::Error, level: libc::c_int, name: libc::c_int },
     #[error("Socket getsockopt failure (expecting size {expecting} received size {received})")] SocketGetOptionSizeFailure { expecting: usize, received: usize },
+    #[error("Socket invalid")] SocketInvalid,
+    #[error("Socket operation failure (interface = {if_index}, queue = {queue}, operation = {operation}, error = {error})")] SocketOperationFailure { if_index: u32, queue: u32, operation: SocketOperation, error: Box<Error> },
+    #[error("Socket receive failure (error = {error})")] SocketReceiveFailure { error: std::io::Error },
     #[error("Socket send failure (error = {error})")] SocketSendFailure { error: std::io::Error },
     #[error("Socket setsockopt failure (error = {error}, level = {level}, name = {name})")] SocketSetOptionFailure { error: std::io::Error, level: libc::c_int, name: libc::c_int },
-}
\ No newline at end of file
+    #[error("Stats I/O failure (error = {error})")] StatsIOFailure { error: std::io::Error },
+    #[error("Unknown neighbor (address = {address})")] UnknownNeighbor { address: std::net::IpAddr },
+}
+impl Error {
+    /// Wrap this error with the socket and the operation it comes from
+    pub(crate) fn on_socket(self, if_index: u32, queue: u32, operation: SocketOperation) -> Self {
+        Self::SocketOperationFailure { if_index, queue, operation, error: Box::new(self) }
+    }
+
+    /// The error underneath any [`Self::SocketOperationFailure`], to match on what actually went wrong
+    pub fn root_cause(&self) -> &Self {
+        match self {
+            Self::SocketOperationFailure { error, .. } => error.root_cause(),
+            error => error,
+        }
+    }
+}
+
+/// What was being done to a socket when it failed, see [`Error::SocketOperationFailure`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketOperation {
+    Create,
+    RegisterUmem,
+    ConfigureRings,
+    MapRings,
+    Bind,
+    QueryOptions,
+    Poll,
+    Wakeup,
+    Close,
+}
+impl std::fmt::Display for SocketOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Create => "create",
+            Self::RegisterUmem => "register umem",
+            Self::ConfigureRings => "configure rings",
+            Self::MapRings => "map rings",
+            Self::Bind => "bind",
+            Self::QueryOptions => "query options",
+            Self::Poll => "poll",
+            Self::Wakeup => "wakeup",
+            Self::Close => "close",
+        })
+    }
+}
@@ -0,0 +1,166 @@
+//! Driver settings reached through the ethtool ioctl, which AF_XDP performance depends on as much as on socket options
+//!
+//! Every setter requires `CAP_NET_ADMIN`. Drivers reject settings they do not support with `EOPNOTSUPP` or `EINVAL`
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+// commands, see linux/ethtool.h
+const ETHTOOL_GCOALESCE: u32 = 0x0e;
+const ETHTOOL_SCOALESCE: u32 = 0x0f;
+const ETHTOOL_GET_TS_INFO: u32 = 0x41;
+
+/// `struct ethtool_coalesce`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct EthtoolCoalesce {
+    cmd: u32,
+    rx_coalesce_usecs: u32,
+    rx_max_coalesced_frames: u32,
+    rx_coalesce_usecs_irq: u32,
+    rx_max_coalesced_frames_irq: u32,
+    tx_coalesce_usecs: u32,
+    tx_max_coalesced_frames: u32,
+    tx_coalesce_usecs_irq: u32,
+    tx_max_coalesced_frames_irq: u32,
+    stats_block_coalesce_usecs: u32,
+    use_adaptive_rx_coalesce: u32,
+    use_adaptive_tx_coalesce: u32,
+    pkt_rate_low: u32,
+    rx_coalesce_usecs_low: u32,
+    rx_max_coalesced_frames_low: u32,
+    tx_coalesce_usecs_low: u32,
+    tx_max_coalesced_frames_low: u32,
+    pkt_rate_high: u32,
+    rx_coalesce_usecs_high: u32,
+    rx_max_coalesced_frames_high: u32,
+    tx_coalesce_usecs_high: u32,
+    tx_max_coalesced_frames_high: u32,
+    rate_sample_interval: u32,
+}
+
+/// `struct ethtool_ts_info`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct EthtoolTsInfo {
+    cmd: u32,
+    so_timestamping: u32,
+    phc_index: i32,
+    tx_types: u32,
+    tx_reserved: [u32; 3],
+    rx_filters: u32,
+    rx_reserved: [u32; 3],
+}
+
+/// How long the NIC holds back interrupts, as `ethtool -c` shows it
+///
+/// A frame raises an interrupt once it waited `usecs` microseconds or `max_frames` frames piled up, whichever comes first.
+/// A value of 0 disables the corresponding limit. Adaptive coalescing lets the driver tune both to the traffic,
+/// overriding them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Coalescing {
+    pub rx_usecs: u32,
+    pub rx_max_frames: u32,
+    pub tx_usecs: u32,
+    pub tx_max_frames: u32,
+    pub adaptive_rx: bool,
+    pub adaptive_tx: bool,
+}
+impl Coalescing {
+    /// Interrupt on every frame, for latency sensitive deployments
+    pub const fn disabled() -> Self {
+        Self { rx_usecs: 0, rx_max_frames: 1, tx_usecs: 0, tx_max_frames: 1, adaptive_rx: false, adaptive_tx: false }
+    }
+
+    fn from_raw(raw: &EthtoolCoalesce) -> Self {
+        Self {
+            rx_usecs: raw.rx_coalesce_usecs,
+            rx_max_frames: raw.rx_max_coalesced_frames,
+            tx_usecs: raw.tx_coalesce_usecs,
+            tx_max_frames: raw.tx_max_coalesced_frames,
+            adaptive_rx: raw.use_adaptive_rx_coalesce != 0,
+            adaptive_tx: raw.use_adaptive_tx_coalesce != 0,
+        }
+    }
+
+    /// Overwrite the fields of `raw` known to this struct, leaving the others as the driver reported them
+    fn apply_to(&self, raw: &mut EthtoolCoalesce) {
+        raw.rx_coalesce_usecs = self.rx_usecs;
+        raw.rx_max_coalesced_frames = self.rx_max_frames;
+        raw.tx_coalesce_usecs = self.tx_usecs;
+        raw.tx_max_coalesced_frames = self.tx_max_frames;
+        raw.use_adaptive_rx_coalesce = self.adaptive_rx as u32;
+        raw.use_adaptive_tx_coalesce = self.adaptive_tx as u32;
+    }
+}
+
+/// The interrupt coalescing of the interface named `if_name`
+pub fn coalescing(if_name: &str) -> Result<Coalescing, crate::Error> {
+    Ok(Coalescing::from_raw(&get_coalesce(if_name)?))
+}
+
+/// Change the interrupt coalescing of the interface named `if_name`, returning the previous one
+///
+/// Settings outside of [`Coalescing`], e.g. the adaptive thresholds, are kept as they are
+pub fn set_coalescing(if_name: &str, coalescing: &Coalescing) -> Result<Coalescing, crate::Error> {
+    let mut raw = get_coalesce(if_name)?;
+    let previous = Coalescing::from_raw(&raw);
+    coalescing.apply_to(&mut raw);
+    raw.cmd = ETHTOOL_SCOALESCE;
+    ioctl(if_name, &mut raw)?;
+    Ok(previous)
+}
+
+/// The index of the PTP hardware clock of the interface named `if_name`, as in `/dev/ptp<index>`, `None` if it has none
+pub fn phc_index(if_name: &str) -> Result<Option<u32>, crate::Error> {
+    let mut raw = EthtoolTsInfo { cmd: ETHTOOL_GET_TS_INFO, ..Default::default() };
+    ioctl(if_name, &mut raw)?;
+    Ok(u32::try_from(raw.phc_index).ok())
+}
+
+fn get_coalesce(if_name: &str) -> Result<EthtoolCoalesce, crate::Error> {
+    let mut raw = EthtoolCoalesce { cmd: ETHTOOL_GCOALESCE, ..Default::default() };
+    ioctl(if_name, &mut raw)?;
+    Ok(raw)
+}
+
+/// Issue the ethtool command `data`, starting with its `u32` command number, on the interface named `if_name`
+fn ioctl<T>(if_name: &str, data: &mut T) -> Result<(), crate::Error> {
+    let command = unsafe { *(data as *mut T).cast::<u32>() };
+    crate::utils::validate_interface_name(if_name)?;
+
+    // any socket will do
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(crate::Error::SocketCreationFailure);
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // point the request at the command
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (destination, source) in request.ifr_name.iter_mut().zip(if_name.bytes()) {
+        *destination = source as libc::c_char;
+    }
+    request.ifr_ifru.ifru_data = (data as *mut T).cast();
+    if unsafe { libc::ioctl(fd.as_raw_fd(), libc::SIOCETHTOOL, &mut request) } < 0 {
+        return Err(crate::Error::EthtoolFailure { command, error: std::io::Error::last_os_error() });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Coalescing, EthtoolCoalesce, EthtoolTsInfo};
+
+    #[test]
+    fn test_coalescing_round_trip() {
+        assert_eq!(std::mem::size_of::<EthtoolCoalesce>(), 92);
+        assert_eq!(std::mem::size_of::<EthtoolTsInfo>(), 44);
+
+        // thresholds unknown to Coalescing survive
+        let mut raw = EthtoolCoalesce { rx_coalesce_usecs: 50, use_adaptive_rx_coalesce: 1, pkt_rate_high: 1000, ..Default::default() };
+        assert_eq!(Coalescing::from_raw(&raw), Coalescing { rx_usecs: 50, adaptive_rx: true, ..Default::default() });
+        Coalescing::disabled().apply_to(&mut raw);
+        assert_eq!(Coalescing::from_raw(&raw), Coalescing::disabled());
+        assert_eq!(raw.pkt_rate_high, 1000);
+    }
+}
@@ -0,0 +1,166 @@
+//! Transmitting one frame on several sockets, e.g. to flood a broadcast out of every port of a switch
+//!
+//! Sockets sharing a umem can all be handed the very same chunk with [`send_shared`]. Sockets with umems of their own
+//! need a copy each, which [`Fanout`] makes straight from a single staged copy of the frame
+
+use std::ops::Range;
+
+use crate::{Frame, RefCountingAllocator, UmemAllocator, XDPSocket};
+
+/// Enqueue `frame`, held in a chunk from `allocator`, on the TX ring of every one of `sockets`, returning on how many
+///
+/// No byte is copied: the chunk gets a reference per socket it was enqueued on and goes back to the allocator with the
+/// last completion, so it must no longer be written to. The reference of the caller is consumed, even on failure.
+/// Panics if a socket does not use the umem of `allocator`
+pub fn send_shared<'s, 'a: 's, A: UmemAllocator>(
+    sockets: impl IntoIterator<Item = &'s mut XDPSocket<'a>>,
+    allocator: &RefCountingAllocator<A>,
+    frame: Frame,
+) -> Result<usize, crate::Error> {
+    let mut count = 0;
+    let mut result = Ok(());
+    for socket in sockets {
+        assert!(std::ptr::eq(socket.umem.as_ref(), allocator.umem_reference()), "socket does not share the umem of the allocator");
+
+        // the completion of this copy releases the new reference
+        allocator.retain_offset(frame.addr, 1);
+        let mut batch = socket.tx_batch();
+        if ! batch.push(frame) {
+            allocator.release_offset(frame.addr);
+            continue;
+        }
+        count += 1;
+        if let Err(error) = batch.commit() {
+            result = result.and(Err(error));
+        }
+    }
+    allocator.release_offset(frame.addr);
+    result.map(|()| count)
+}
+
+#[derive(Debug, Clone)]
+struct StagedFrame {
+    bytes: Range<usize>,
+    destinations: Range<usize>,
+}
+
+/// Frames waiting to be transmitted on several sockets which do not share a umem
+///
+/// A pushed frame is copied once into a staging area, then once into a chunk of each destination when flushed, which
+/// is as few copies as separate umems allow. Staging is needed because frames are usually pushed while the socket they
+/// came from is borrowed to receive them
+#[derive(Debug, Clone, Default)]
+pub struct Fanout {
+    staging: Vec<u8>,
+    frames: Vec<StagedFrame>,
+    destinations: Vec<usize>,
+}
+impl Fanout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of staged frames
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Whether no frame is staged
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Stage `frame` for transmission on each of `destinations`, ignoring it if there are none
+    pub fn push(&mut self, frame: &[u8], destinations: impl IntoIterator<Item = usize>) {
+        let destinations_start = self.destinations.len();
+        self.destinations.extend(destinations);
+        if self.destinations.len() == destinations_start {
+            return;
+        }
+        let bytes_start = self.staging.len();
+        self.staging.extend_from_slice(frame);
+        self.frames.push(StagedFrame { bytes: bytes_start..self.staging.len(), destinations: destinations_start..self.destinations.len() });
+    }
+
+    /// Hand every staged frame to `send` once per destination, in order, returning how many times it succeeded
+    ///
+    /// `send` gets a destination and a frame and returns whether the frame was enqueued, as [`XDPSocket::send`] does.
+    /// The staging area is emptied either way, its memory kept for the next frames
+    pub fn flush(&mut self, mut send: impl FnMut(usize, &[u8]) -> Result<bool, crate::Error>) -> Result<usize, crate::Error> {
+        let mut sent = 0;
+        let mut result = Ok(());
+        'frames: for frame in &self.frames {
+            for &destination in &self.destinations[frame.destinations.clone()] {
+                match send(destination, &self.staging[frame.bytes.clone()]) {
+                    Ok(true) => sent += 1,
+                    Ok(false) => {},
+                    Err(error) => {
+                        result = Err(error);
+                        break 'frames;
+                    },
+                }
+            }
+        }
+        self.clear();
+        result.map(|()| sent)
+    }
+
+    /// Drop every staged frame
+    pub fn clear(&mut self) {
+        self.staging.clear();
+        self.frames.clear();
+        self.destinations.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{send_shared, Fanout};
+    use crate::{testing::MockXDP, DefaultAllocator, Frame, RefCountingAllocator, Umem, UmemAllocator, UmemAllocatorFactory};
+
+    #[test]
+    fn test_send_shared() {
+        let umem = Arc::new(Umem::new_2k(16).unwrap());
+        let allocator = RefCountingAllocator::<DefaultAllocator>::for_umem(umem.clone());
+        let (mut first, mut first_mock) = MockXDP::new(umem.clone(), 4).unwrap();
+        let (mut second, mut second_mock) = MockXDP::new(umem.clone(), 4).unwrap();
+
+        // one chunk, two transmissions
+        let index = allocator.try_allocate().unwrap();
+        umem.chunk(index)[..5].copy_from_slice(b"flood");
+        let frame = Frame { addr: umem.chunk_start_offset_for_index(index), len: 5 };
+        assert_eq!(send_shared([&mut first, &mut second], &allocator, frame).unwrap(), 2);
+        assert_eq!(allocator.references(index), 2);
+        assert_eq!(first_mock.transmitted_frames(), [b"flood"]);
+        assert_eq!(second_mock.transmitted_frames(), [b"flood"]);
+
+        // back to the allocator after both completions
+        first.reap_completions(&allocator);
+        assert_eq!(allocator.references(index), 1);
+        second.reap_completions(&allocator);
+        assert_eq!(allocator.references(index), 0);
+        assert!(! allocator.try_release(index));
+    }
+
+    #[test]
+    fn test_fanout() {
+        let mut fanout = Fanout::new();
+        fanout.push(b"broadcast", [0, 2, 3]);
+        fanout.push(b"nowhere", []);
+        fanout.push(b"unicast", [1]);
+        assert_eq!(fanout.len(), 2);
+
+        // failures to enqueue are not errors
+        let mut sent = Vec::new();
+        let count = fanout.flush(|destination, frame| {
+            sent.push((destination, frame.to_vec()));
+            Ok(destination != 3)
+        }).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(sent.iter().map(|(destination, _)| *destination).collect::<Vec<_>>(), [0, 2, 3, 1]);
+        assert_eq!(sent[3].1, b"unicast");
+        assert!(fanout.is_empty());
+    }
+}
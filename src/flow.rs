@@ -0,0 +1,291 @@
+//! Per-flow state: a [`FlowTable`] keyed on 5-tuples, evicting flows once idle or when full
+//!
+//! A building block for stages needing to remember flows, e.g. NAT, load balancing or telemetry. Tables are aged by
+//! [`FlowTable::expire`], usually called from [`crate::pipeline::Stage::tick`]
+
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use crate::packet::FiveTuple;
+
+/// Marks the ends of the recency list
+const NIL: usize = usize::MAX;
+
+#[derive(Debug, Clone)]
+struct Slot<S> {
+    key: FiveTuple,
+    state: S,
+    last_seen: Instant,
+    /// The flow seen just before this one
+    previous: usize,
+    /// The flow seen just after this one
+    next: usize,
+}
+
+/// Counters kept by a [`FlowTable`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowTableStats {
+    pub inserted: u64,
+    /// Flows removed because they were idle for longer than the timeout
+    pub expired: u64,
+    /// Flows removed to make room for new ones
+    pub evicted: u64,
+}
+
+/// Flows and their state `S`, holding at most a fixed number of them
+///
+/// Flows are kept in order of last use: looking one up refreshes it, aging removes idle flows from the least recently used
+/// one, and inserting into a full table evicts the least recently used flow. Every operation is O(1) but aging, which is
+/// O(1) per expired flow
+#[derive(Debug, Clone)]
+pub struct FlowTable<S> {
+    capacity: usize,
+    idle_timeout: Duration,
+    index: HashMap<FiveTuple, usize>,
+    slots: Vec<Slot<S>>,
+    /// The least recently used flow
+    head: usize,
+    /// The most recently used flow
+    tail: usize,
+    stats: FlowTableStats,
+}
+impl<S> FlowTable<S> {
+    /// An empty table for up to `capacity` flows, expiring them after `idle_timeout` without being used
+    pub fn new(capacity: usize, idle_timeout: Duration) -> Self {
+        assert!(capacity > 0, "Capacity must be positive");
+        Self {
+            capacity,
+            idle_timeout,
+            index: HashMap::with_capacity(capacity),
+            slots: Vec::with_capacity(capacity),
+            head: NIL,
+            tail: NIL,
+            stats: FlowTableStats::default(),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub const fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    /// The number of flows
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Whether there are no flows
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub const fn stats(&self) -> &FlowTableStats {
+        &self.stats
+    }
+
+    /// The state of `flow`, without refreshing it
+    pub fn get(&self, flow: &FiveTuple) -> Option<&S> {
+        self.index.get(flow).map(|&slot| &self.slots[slot].state)
+    }
+
+    /// The state of `flow`, refreshing it
+    pub fn lookup(&mut self, flow: &FiveTuple) -> Option<&mut S> {
+        self.lookup_at(flow, Instant::now())
+    }
+
+    fn lookup_at(&mut self, flow: &FiveTuple, now: Instant) -> Option<&mut S> {
+        let slot = *self.index.get(flow)?;
+        self.touch(slot, now);
+        Some(&mut self.slots[slot].state)
+    }
+
+    /// The state of `flow`, refreshing it, or inserting the one made by `state` if missing
+    pub fn get_or_insert_with(&mut self, flow: FiveTuple, state: impl FnOnce() -> S) -> &mut S {
+        self.get_or_insert_with_at(flow, state, Instant::now())
+    }
+
+    fn get_or_insert_with_at(&mut self, flow: FiveTuple, state: impl FnOnce() -> S, now: Instant) -> &mut S {
+        let slot = match self.index.get(&flow) {
+            Some(&slot) => {
+                self.touch(slot, now);
+                slot
+            },
+            None => {
+                self.insert_new(flow, state(), now);
+                self.tail
+            },
+        };
+        &mut self.slots[slot].state
+    }
+
+    /// Set the state of `flow`, refreshing it, returning the flow it replaced or the one evicted to make room
+    pub fn insert(&mut self, flow: FiveTuple, state: S) -> Option<(FiveTuple, S)> {
+        self.insert_at(flow, state, Instant::now())
+    }
+
+    fn insert_at(&mut self, flow: FiveTuple, state: S, now: Instant) -> Option<(FiveTuple, S)> {
+        match self.index.get(&flow) {
+            Some(&slot) => {
+                self.touch(slot, now);
+                Some((flow, std::mem::replace(&mut self.slots[slot].state, state)))
+            },
+            None => self.insert_new(flow, state, now),
+        }
+    }
+
+    /// Forget `flow`, returning its state
+    pub fn remove(&mut self, flow: &FiveTuple) -> Option<S> {
+        let slot = *self.index.get(flow)?;
+        Some(self.remove_slot(slot).1)
+    }
+
+    /// Remove the flows idle since before `now` minus the timeout, passing each to `on_expired`, returning how many there were
+    pub fn expire(&mut self, now: Instant, mut on_expired: impl FnMut(FiveTuple, S)) -> usize {
+        let mut count = 0;
+        while self.head != NIL && now.saturating_duration_since(self.slots[self.head].last_seen) > self.idle_timeout {
+            let (flow, state) = self.remove_slot(self.head);
+            on_expired(flow, state);
+            count += 1;
+        }
+        self.stats.expired += count as u64;
+        count
+    }
+
+    /// The flows and their state, from the least recently used
+    pub fn iter(&self) -> impl Iterator<Item = (&FiveTuple, &S)> {
+        std::iter::successors((self.head != NIL).then_some(self.head), |&slot| {
+            let next = self.slots[slot].next;
+            (next != NIL).then_some(next)
+        }).map(|slot| (&self.slots[slot].key, &self.slots[slot].state))
+    }
+
+    /// Append a flow known to be missing, evicting the least recently used one if full
+    fn insert_new(&mut self, flow: FiveTuple, state: S, now: Instant) -> Option<(FiveTuple, S)> {
+        let evicted = (self.slots.len() == self.capacity).then(|| {
+            self.stats.evicted += 1;
+            self.remove_slot(self.head)
+        });
+
+        let slot = self.slots.len();
+        self.slots.push(Slot { key: flow, state, last_seen: now, previous: NIL, next: NIL });
+        self.index.insert(flow, slot);
+        self.link_tail(slot);
+        self.stats.inserted += 1;
+        evicted
+    }
+
+    /// Mark `slot` as the most recently used
+    fn touch(&mut self, slot: usize, now: Instant) {
+        self.slots[slot].last_seen = now;
+        if slot != self.tail {
+            self.unlink(slot);
+            self.link_tail(slot);
+        }
+    }
+
+    fn link_tail(&mut self, slot: usize) {
+        self.slots[slot].previous = self.tail;
+        self.slots[slot].next = NIL;
+        match self.tail {
+            NIL => self.head = slot,
+            tail => self.slots[tail].next = slot,
+        }
+        self.tail = slot;
+    }
+
+    fn unlink(&mut self, slot: usize) {
+        let Slot { previous, next, .. } = self.slots[slot];
+        match previous {
+            NIL => self.head = next,
+            previous => self.slots[previous].next = next,
+        }
+        match next {
+            NIL => self.tail = previous,
+            next => self.slots[next].previous = previous,
+        }
+    }
+
+    fn remove_slot(&mut self, slot: usize) -> (FiveTuple, S) {
+        self.unlink(slot);
+        let removed = self.slots.swap_remove(slot);
+        self.index.remove(&removed.key);
+
+        // the last slot moved into the hole
+        if slot < self.slots.len() {
+            let Slot { key, previous, next, .. } = self.slots[slot];
+            self.index.insert(key, slot);
+            match previous {
+                NIL => self.head = slot,
+                previous => self.slots[previous].next = slot,
+            }
+            match next {
+                NIL => self.tail = slot,
+                next => self.slots[next].previous = slot,
+            }
+        }
+        (removed.key, removed.state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{FlowTable, FlowTableStats};
+    use crate::packet::{ip_protocol, FiveTuple};
+
+    fn flow(source_port: u16) -> FiveTuple {
+        FiveTuple {
+            source: "10.0.0.1".parse().unwrap(),
+            destination: "10.0.0.2".parse().unwrap(),
+            protocol: ip_protocol::UDP,
+            source_port,
+            destination_port: 53,
+        }
+    }
+
+    #[test]
+    fn test_flow_table_lru() {
+        let t0 = Instant::now();
+        let mut table = FlowTable::new(3, Duration::from_secs(10));
+        for port in 1..=3 {
+            assert!(table.insert_at(flow(port), port as u32, t0).is_none());
+        }
+
+        // refreshing flow 1 makes flow 2 the least recently used
+        *table.lookup_at(&flow(1), t0).unwrap() += 10;
+        assert_eq!(table.insert_at(flow(4), 4, t0), Some((flow(2), 2)));
+        assert_eq!(table.iter().map(|(flow, &state)| (flow.source_port, state)).collect::<Vec<_>>(), [(3, 3), (1, 11), (4, 4)]);
+
+        // replacing does not evict
+        assert_eq!(table.insert_at(flow(3), 30, t0), Some((flow(3), 3)));
+        assert_eq!(*table.get_or_insert_with_at(flow(4), || 0, t0), 4);
+        assert_eq!(table.remove(&flow(1)), Some(11));
+        assert_eq!(table.remove(&flow(1)), None);
+        assert_eq!(*table.get_or_insert_with_at(flow(5), || 5, t0), 5);
+        assert_eq!(table.iter().map(|(flow, _)| flow.source_port).collect::<Vec<_>>(), [3, 4, 5]);
+        assert_eq!(*table.stats(), FlowTableStats { inserted: 5, expired: 0, evicted: 1 });
+    }
+
+    #[test]
+    fn test_flow_table_expiry() {
+        let t0 = Instant::now();
+        let mut table = FlowTable::new(16, Duration::from_secs(10));
+        for port in 1..=4 {
+            table.insert_at(flow(port), (), t0 + Duration::from_secs(port as u64));
+        }
+        table.lookup_at(&flow(1), t0 + Duration::from_secs(5));
+
+        // flows 2 and 3 were last seen more than 10 seconds before
+        let mut expired = Vec::new();
+        assert_eq!(table.expire(t0 + Duration::from_secs(14), |flow, ()| expired.push(flow.source_port)), 2);
+        assert_eq!(expired, [2, 3]);
+        assert_eq!(table.expire(t0 + Duration::from_secs(14), |_, _| unreachable!()), 0);
+        assert_eq!(table.iter().map(|(flow, _)| flow.source_port).collect::<Vec<_>>(), [4, 1]);
+        assert_eq!(table.expire(t0 + Duration::from_secs(60), |_, _| ()), 2);
+        assert!(table.is_empty());
+        assert_eq!(table.stats().expired, 4);
+    }
+}
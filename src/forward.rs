@@ -0,0 +1,263 @@
+use std::{os::fd::AsRawFd, sync::Arc};
+
+use crate::{Frame, UmemAllocator, XDPSocket};
+
+/// The direction a frame is travelling through a [`Forwarder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Received on the first socket, transmitted on the second
+    AToB,
+    /// Received on the second socket, transmitted on the first
+    BToA,
+}
+
+/// What a hook decided about a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Forward,
+    Drop,
+}
+
+/// A per-packet hook, able to inspect and modify frames in place before they are forwarded
+pub trait ForwardHook {
+    fn on_frame(&mut self, direction: Direction, frame: &mut [u8]) -> Verdict;
+}
+impl<F: FnMut(Direction, &mut [u8]) -> Verdict> ForwardHook for F {
+    fn on_frame(&mut self, direction: Direction, frame: &mut [u8]) -> Verdict {
+        self(direction, frame)
+    }
+}
+
+/// The default hook, forwarding everything untouched
+pub struct ForwardAll;
+impl ForwardHook for ForwardAll {
+    fn on_frame(&mut self, _direction: Direction, _frame: &mut [u8]) -> Verdict {
+        Verdict::Forward
+    }
+}
+
+/// Counters kept for each direction of a [`Forwarder`]
+#[derive(Debug, Clone, Default)]
+pub struct ForwarderStats {
+    pub forwarded: u64,
+    pub forwarded_bytes: u64,
+    /// Frames dropped by the hook
+    pub dropped_by_hook: u64,
+    /// Frames dropped because the TX ring was full or no chunk was available
+    pub dropped_no_space: u64,
+}
+
+/// A bump-in-the-wire pairing two sockets, everything received on one is transmitted on the other
+///
+/// When both sockets were created on the same [`crate::Umem`] and share one allocator, zero-copy mode
+/// moves descriptors from one RX ring to the other TX ring without touching frame data
+pub struct Forwarder<'a, A: UmemAllocator + ?Sized, H: ForwardHook = ForwardAll> {
+    sockets: [XDPSocket<'a>; 2],
+    allocators: [Arc<A>; 2],
+    hook: H,
+    zero_copy: bool,
+    stats: [ForwarderStats; 2],
+}
+impl<'a, A: UmemAllocator + ?Sized> Forwarder<'a, A> {
+    /// Pair socket `a` (taking chunks from `allocator_a`) with socket `b` (taking chunks from `allocator_b`)
+    pub fn new(a: XDPSocket<'a>, allocator_a: Arc<A>, b: XDPSocket<'a>, allocator_b: Arc<A>) -> Self {
+        let mut this = Self {
+            sockets: [a, b],
+            allocators: [allocator_a, allocator_b],
+            hook: ForwardAll,
+            zero_copy: false,
+            stats: Default::default(),
+        };
+        this.refill();
+        this
+    }
+}
+impl<'a, A: UmemAllocator + ?Sized, H: ForwardHook> Forwarder<'a, A, H> {
+    // constants
+    const BATCH_SIZE: usize = 64;
+
+    /// Invoke `hook` on every frame before forwarding it
+    pub fn with_hook<H2: ForwardHook>(self, hook: H2) -> Forwarder<'a, A, H2> {
+        Forwarder {
+            sockets: self.sockets,
+            allocators: self.allocators,
+            hook,
+            zero_copy: self.zero_copy,
+            stats: self.stats,
+        }
+    }
+
+    /// Move descriptors between sockets instead of copying frames
+    ///
+    /// Panics unless both sockets share the same umem and allocator
+    pub fn with_zero_copy(mut self) -> Self {
+        assert!(Arc::ptr_eq(&self.sockets[0].umem, &self.sockets[1].umem), "Zero-copy forwarding requires a shared umem");
+        assert!(Arc::ptr_eq(&self.allocators[0], &self.allocators[1]), "Zero-copy forwarding requires a shared allocator");
+        self.zero_copy = true;
+        self
+    }
+
+    /// The counters of `direction`
+    pub fn stats(&self, direction: Direction) -> &ForwarderStats {
+        &self.stats[direction as usize]
+    }
+
+    /// Get back the sockets
+    pub fn into_sockets(self) -> (XDPSocket<'a>, XDPSocket<'a>) {
+        let [a, b] = self.sockets;
+        (a, b)
+    }
+
+    /// Wait up to `timeout` (forever if `None`) for traffic, then forward it, returning the number of frames received
+    pub fn run_once(&mut self, timeout: Option<std::time::Duration>) -> Result<usize, crate::Error> {
+        // poll
+        let mut poll_fds = self.sockets.each_ref().map(|socket| libc::pollfd {
+            fd: socket.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        let timeout_ms = crate::utils::poll_timeout_ms(timeout);
+        if unsafe { libc::poll(poll_fds.as_mut_ptr(), 2, timeout_ms) } < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                return Err(crate::Error::PollFailure { error });
+            }
+        }
+
+        // forward both directions
+        let mut received = 0;
+        for (index, (direction, poll_fd)) in [Direction::AToB, Direction::BToA].into_iter().zip(poll_fds).enumerate() {
+            crate::utils::check_poll_events(poll_fd.fd, poll_fd.revents).map_err(|error| self.sockets[index].context(error, crate::SocketOperation::Poll))?;
+            if poll_fd.revents & libc::POLLIN != 0 {
+                received += self.forward(direction)?;
+            }
+        }
+
+        // recycle
+        for (socket, allocator) in self.sockets.iter_mut().zip(&self.allocators) {
+            socket.reap_completions(allocator.as_ref());
+        }
+        self.refill();
+
+        Ok(received)
+    }
+
+    /// Forward traffic until `stop` returns `true`, checking it at least every `check_interval`
+    pub fn run(&mut self, check_interval: std::time::Duration, mut stop: impl FnMut() -> bool) -> Result<(), crate::Error> {
+        while ! stop() {
+            self.run_once(Some(check_interval))?;
+        }
+        Ok(())
+    }
+
+    fn forward(&mut self, direction: Direction) -> Result<usize, crate::Error> {
+        let (rx_index, tx_index) = match direction {
+            Direction::AToB => (0, 1),
+            Direction::BToA => (1, 0),
+        };
+        let [a, b] = &mut self.sockets;
+        let (rx_socket, tx_socket) = if rx_index == 0 { (a, b) } else { (b, a) };
+        let rx_allocator = self.allocators[rx_index].as_ref();
+        let tx_allocator = self.allocators[tx_index].as_ref();
+        let stats = &mut self.stats[rx_index];
+
+        let tx_umem = tx_socket.umem.clone();
+        let mut tx_batch = tx_socket.tx_batch();
+        let mut received = 0;
+        while received < Self::BATCH_SIZE && rx_socket.rx_ring.can_consume() {
+            received += 1;
+
+            // run the hook
+            let rx_ring_index = rx_socket.rx_ring.get_consumer_index() as usize;
+            let rx_descriptor = *rx_socket.rx_ring.get_nth_descriptor(rx_ring_index);
+            let verdict = self.hook.on_frame(direction, &mut rx_socket.rx_ring.get_nth_slice_mut(rx_ring_index, &rx_socket.umem, None, None));
+
+            // forward
+            let mut recycle_rx_chunk = true;
+            let mut no_space = false;
+            if verdict == Verdict::Drop {
+                stats.dropped_by_hook += 1;
+            } else if tx_batch.remaining() == 0 {
+                tx_batch.report_ring_full();
+                no_space = true;
+            } else if self.zero_copy {
+                // hand over the chunk itself, it comes back on the TX completion ring
+                tx_batch.push(Frame { addr: rx_descriptor.addr, len: rx_descriptor.len });
+                recycle_rx_chunk = false;
+                stats.forwarded += 1;
+                stats.forwarded_bytes += rx_descriptor.len as u64;
+            } else if let Some(chunk_index) = tx_allocator.try_allocate() {
+                // copy into a chunk of the other umem
+                let (rx_chunk, rx_frame) = rx_socket.rx_ring.get_nth_chunk_mut(rx_ring_index, &rx_socket.umem);
+                let mut tx_chunk = tx_umem.chunk(chunk_index);
+                let tx_frame = crate::utils::copy_frame(&mut tx_chunk, &rx_chunk, rx_frame);
+                tx_batch.push(Frame { addr: tx_chunk.offset() + tx_frame.start as u64, len: tx_frame.len() as _ });
+                stats.forwarded += 1;
+                stats.forwarded_bytes += rx_descriptor.len as u64;
+            } else {
+                if let Some(hooks) = &tx_batch.socket().hooks {
+                    hooks.on_alloc_fail(tx_batch.socket());
+                }
+                no_space = true;
+            }
+            if no_space {
+                stats.dropped_no_space += 1;
+                if let Some(hooks) = &rx_socket.hooks {
+                    hooks.on_rx_drop(rx_socket, rx_descriptor.len as usize);
+                }
+            }
+
+            // give back the rx slot, then the rx chunk
+            rx_socket.rx_ring.advance_consumer_index();
+            if recycle_rx_chunk {
+                if rx_socket.fill_ring.can_produce() {
+                    rx_socket.fill_ring.produce_umem_offset(rx_descriptor.addr);
+                } else {
+                    rx_allocator.release_offset(rx_descriptor.addr);
+                }
+            }
+        }
+
+        // one wakeup per batch
+        tx_batch.commit()?;
+        Ok(received)
+    }
+
+    /// Top up both fill rings from the allocators
+    fn refill(&mut self) {
+        for (socket, allocator) in self.sockets.iter_mut().zip(&self.allocators) {
+            socket.refill_fill_ring(allocator.as_ref(), usize::MAX);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Direction, Forwarder};
+    use crate::{testing::MockXDP, DefaultAllocator, Umem, UmemAllocatorFactory};
+
+    #[test]
+    fn test_forward_copy() {
+        let umem_a = Arc::new(Umem::new_4k(32).unwrap());
+        let umem_b = Arc::new(Umem::new_2k(32).unwrap());
+        let (a, mock_a) = MockXDP::new(umem_a.clone(), 16).unwrap();
+        let (b, mock_b) = MockXDP::new(umem_b.clone(), 16).unwrap();
+        let (mut mock_a, mut mock_b) = (mock_a.with_headroom(2000), mock_b.with_headroom(100));
+        let mut forwarder = Forwarder::new(
+            a, Arc::new(DefaultAllocator::for_umem(umem_a)),
+            b, Arc::new(DefaultAllocator::for_umem(umem_b)),
+        );
+
+        // frames keep their content whatever the offset, and do not fit at the same offset of a smaller chunk
+        let large = (0..1800).map(|i| i as u8).collect::<Vec<_>>();
+        assert!(mock_a.inject(&large));
+        assert!(mock_b.inject(b"reply"));
+        assert_eq!(forwarder.run_once(None).unwrap(), 2);
+        assert_eq!(mock_b.transmitted_frames(), [large]);
+        assert_eq!(mock_a.transmitted_frames(), [b"reply"]);
+        assert_eq!(forwarder.stats(Direction::AToB).forwarded, 1);
+        assert_eq!(forwarder.stats(Direction::BToA).forwarded, 1);
+    }
+}
@@ -0,0 +1,182 @@
+//! A minimal generic netlink client, for the families which have no other interface, e.g. `netdev`
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+// netlink layout
+pub(crate) const NLMSG_HEADER_SIZE: usize = 16;
+const NLATTR_HEADER_SIZE: usize = 4;
+const GENL_HEADER_SIZE: usize = 4;
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+// family resolution, see linux/genetlink.h
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+/// The id of the generic netlink family named `name`
+///
+/// Fails with [`crate::Error::SocketReceiveFailure`] carrying `ENOENT` when the kernel lacks the family
+pub(crate) fn family(name: &str) -> Result<u16, crate::Error> {
+    let name = [name.as_bytes(), &[0]].concat();
+    let messages = query(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, false, &attribute(CTRL_ATTR_FAMILY_NAME, &name))?;
+    messages.iter()
+        .flat_map(|message| attributes(message))
+        .find_map(|(kind, payload)| (kind == CTRL_ATTR_FAMILY_ID && payload.len() >= 2).then(|| u16::from_ne_bytes([payload[0], payload[1]])))
+        .ok_or(crate::Error::SocketReceiveFailure { error: std::io::Error::from_raw_os_error(libc::ENOENT) })
+}
+
+/// Send `command` with `attributes` to `family`, returning the attributes of every answer
+pub(crate) fn query(family: u16, command: u8, dump: bool, attributes: &[u8]) -> Result<Vec<Vec<u8>>, crate::Error> {
+    // create netlink socket
+    let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_GENERIC) };
+    if fd < 0 {
+        return Err(crate::Error::SocketCreationFailure);
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // send the request, single answers are followed by an acknowledgement
+    let mut request = vec![0_u8; NLMSG_HEADER_SIZE + GENL_HEADER_SIZE];
+    let flags = libc::NLM_F_REQUEST | if dump { libc::NLM_F_DUMP } else { libc::NLM_F_ACK };
+    request[4..6].copy_from_slice(&family.to_ne_bytes());
+    request[6..8].copy_from_slice(&(flags as u16).to_ne_bytes());
+    request[NLMSG_HEADER_SIZE] = command;
+    request[NLMSG_HEADER_SIZE + 1] = 1;
+    request.extend_from_slice(attributes);
+    let request_len = request.len() as u32;
+    request[0..4].copy_from_slice(&request_len.to_ne_bytes());
+    if unsafe { libc::send(fd.as_raw_fd(), request.as_ptr().cast(), request.len(), 0) } < 0 {
+        return Err(crate::Error::SocketSendFailure { error: std::io::Error::last_os_error() });
+    }
+
+    // collect answers
+    let mut messages = Vec::new();
+    let mut buffer = vec![0_u8; 32 * 1024];
+    loop {
+        let received = unsafe { libc::recv(fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len(), 0) };
+        if received < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(crate::Error::SocketReceiveFailure { error });
+        }
+        if parse(&buffer[..received as usize], family, &mut messages)? {
+            return Ok(messages);
+        }
+    }
+}
+
+/// Push the attributes of the messages of `family` in `buffer`, returning whether the answer is over
+fn parse(buffer: &[u8], family: u16, messages: &mut Vec<Vec<u8>>) -> Result<bool, crate::Error> {
+    let u16_at = |bytes: &[u8], at: usize| u16::from_ne_bytes([bytes[at], bytes[at + 1]]);
+    let u32_at = |bytes: &[u8], at: usize| u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap());
+
+    let mut remaining = buffer;
+    while remaining.len() >= NLMSG_HEADER_SIZE {
+        let message_len = u32_at(remaining, 0) as usize;
+        if message_len < NLMSG_HEADER_SIZE || message_len > remaining.len() {
+            break;
+        }
+        let message = &remaining[..message_len];
+        remaining = &remaining[message_len.next_multiple_of(4).min(remaining.len())..];
+
+        match u16_at(message, 4) {
+            message_type if message_type as libc::c_int == libc::NLMSG_DONE => return Ok(true),
+            message_type if message_type as libc::c_int == libc::NLMSG_ERROR && message.len() >= NLMSG_HEADER_SIZE + 4 => {
+                // an error of 0 is the acknowledgement
+                let errno = -(u32_at(message, NLMSG_HEADER_SIZE) as i32);
+                if errno == 0 {
+                    return Ok(true);
+                }
+                return Err(crate::Error::SocketReceiveFailure { error: std::io::Error::from_raw_os_error(errno) });
+            },
+            message_type if message_type == family && message.len() >= NLMSG_HEADER_SIZE + GENL_HEADER_SIZE => {
+                messages.push(message[NLMSG_HEADER_SIZE + GENL_HEADER_SIZE..].to_vec());
+            },
+            _ => {},
+        }
+    }
+    Ok(false)
+}
+
+/// A netlink attribute, padded
+pub(crate) fn attribute(kind: u16, payload: &[u8]) -> Vec<u8> {
+    let mut attribute = Vec::with_capacity((NLATTR_HEADER_SIZE + payload.len()).next_multiple_of(4));
+    attribute.extend_from_slice(&((NLATTR_HEADER_SIZE + payload.len()) as u16).to_ne_bytes());
+    attribute.extend_from_slice(&kind.to_ne_bytes());
+    attribute.extend_from_slice(payload);
+    attribute.resize(attribute.capacity(), 0);
+    attribute
+}
+
+/// The type and payload of the netlink attributes in `attributes`
+pub(crate) fn attributes(mut attributes: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if attributes.len() < NLATTR_HEADER_SIZE {
+            return None;
+        }
+        let attribute_len = u16::from_ne_bytes([attributes[0], attributes[1]]) as usize;
+        if attribute_len < NLATTR_HEADER_SIZE || attribute_len > attributes.len() {
+            return None;
+        }
+        let kind = u16::from_ne_bytes([attributes[2], attributes[3]]) & NLA_TYPE_MASK;
+        let attribute = (kind, &attributes[NLATTR_HEADER_SIZE..attribute_len]);
+        attributes = &attributes[attribute_len.next_multiple_of(4).min(attributes.len())..];
+        Some(attribute)
+    })
+}
+
+/// The value of an unsigned attribute, which the kernel sends as 4 or 8 bytes depending on its magnitude
+pub(crate) fn uint(payload: &[u8]) -> Option<u64> {
+    match payload.len() {
+        4 => Some(u32::from_ne_bytes(payload.try_into().unwrap()) as u64),
+        8 => Some(u64::from_ne_bytes(payload.try_into().unwrap())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{attribute, attributes, parse, uint, GENL_HEADER_SIZE, NLMSG_HEADER_SIZE};
+
+    fn message(family: u16, command: u8, attributes: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&((NLMSG_HEADER_SIZE + GENL_HEADER_SIZE + attributes.len()) as u32).to_ne_bytes());
+        message.extend_from_slice(&family.to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        message.extend_from_slice(&[command, 1, 0, 0]);
+        message.extend_from_slice(attributes);
+        message
+    }
+
+    fn control(kind: libc::c_int, payload: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&(16 + payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&(kind as u16).to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        message.extend_from_slice(payload);
+        message
+    }
+
+    #[test]
+    fn test_parse_genl_messages() {
+        // a dump of two messages, one of another family
+        let family = 0x20;
+        let mut buffer = message(family, 1, &attribute(1, b"abc"));
+        buffer.extend(message(0x21, 1, &attribute(1, b"other")));
+        buffer.extend(message(family, 1, &[attribute(2, &7_u32.to_ne_bytes()), attribute(3, &7_u64.to_ne_bytes())].concat()));
+        buffer.extend(control(libc::NLMSG_DONE, &0_u32.to_ne_bytes()));
+        let mut messages = Vec::new();
+        assert!(parse(&buffer, family, &mut messages).unwrap());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(attributes(&messages[0]).collect::<Vec<_>>(), [(1, &b"abc"[..])]);
+        assert_eq!(attributes(&messages[1]).map(|(_, payload)| uint(payload)).collect::<Vec<_>>(), [Some(7), Some(7)]);
+
+        // single answers end with the acknowledgement, errors are reported
+        let mut messages = Vec::new();
+        assert!(! parse(&message(family, 1, &[]), family, &mut messages).unwrap());
+        assert!(parse(&control(libc::NLMSG_ERROR, &0_u32.to_ne_bytes()), family, &mut messages).unwrap());
+        assert!(parse(&control(libc::NLMSG_ERROR, &(-libc::ENOENT).to_ne_bytes()), family, &mut messages).is_err());
+    }
+}
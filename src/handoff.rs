@@ -0,0 +1,177 @@
+//! Hand AF_XDP sockets over to another process through a Unix domain socket
+//!
+//! A privileged process creates the socket over a shareable umem (see [`Umem::new_2k_shareable`]) and sets up the
+//! redirection, then [`send_socket`] passes the socket and umem file descriptors (SCM_RIGHTS) to an unprivileged worker,
+//! which maps the rings and the umem again with [`recv_socket`] and needs no capabilities at all.
+
+use std::{io::IoSlice, os::{fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd}, unix::net::UnixStream}, sync::Arc};
+
+use crate::{BindFlags, RingSizes, Umem, XDPSocket};
+
+// the metadata sent along with the file descriptors
+const HEADER_FIELDS: usize = 9;
+const HEADER_SIZE: usize = HEADER_FIELDS * std::mem::size_of::<u64>();
+
+/// Send `socket` and its umem over `channel`, the socket stays usable in this process as well
+///
+/// Fails with [`crate::Error::HandoffFailure`] unless the umem was created shareable
+pub fn send_socket(channel: &UnixStream, socket: &XDPSocket) -> Result<(), crate::Error> {
+    let Some(memfd) = socket.umem.memfd() else {
+        return Err(crate::Error::HandoffFailure { reason: "the umem is not shareable" });
+    };
+    let ring_sizes = socket.ring_sizes();
+    let header = [
+        socket.if_index as u64,
+        socket.if_queue as u64,
+        socket.umem.chunk_size() as u64,
+        socket.umem.num_chunks() as u64,
+        ring_sizes.rx as u64,
+        ring_sizes.tx as u64,
+        ring_sizes.fill as u64,
+        ring_sizes.completion as u64,
+        socket.bind_flags.bits() as u64,
+    ];
+    let mut payload = [0_u8; HEADER_SIZE];
+    for (field, bytes) in header.iter().zip(payload.chunks_exact_mut(std::mem::size_of::<u64>())) {
+        bytes.copy_from_slice(&field.to_ne_bytes());
+    }
+    send_fds(channel, &payload, &[socket.as_raw_fd(), memfd.as_raw_fd()])
+}
+
+/// Receive a socket sent with [`send_socket`] from `channel`, mapping its rings and umem in this process
+///
+/// Its redirection stays with the sender
+pub fn recv_socket<'a>(channel: &UnixStream) -> Result<XDPSocket<'a>, crate::Error> {
+    let mut payload = [0_u8; HEADER_SIZE];
+    let [socket_fd, memfd] = recv_fds::<2>(channel, &mut payload)?;
+    let mut header = [0_u64; HEADER_FIELDS];
+    for (field, bytes) in header.iter_mut().zip(payload.chunks_exact(std::mem::size_of::<u64>())) {
+        *field = u64::from_ne_bytes(bytes.try_into().unwrap());
+    }
+    let [if_index, if_queue, chunk_size, num_chunks, rx, tx, fill, completion, bind_flags] = header.map(|field| field as usize);
+
+    // umem
+    let umem = Umem::from_memfd(memfd, chunk_size)?;
+    if umem.num_chunks() != num_chunks {
+        return Err(crate::Error::HandoffFailure { reason: "the umem size does not match" });
+    }
+
+    // rings
+    let ring_sizes = RingSizes { rx, tx, fill, completion };
+    if ! ring_sizes.are_valid() {
+        return Err(crate::Error::HandoffFailure { reason: "invalid ring sizes" });
+    }
+    let (rx_ring, tx_ring, completion_ring, fill_ring) = XDPSocket::map_rings(socket_fd.as_raw_fd(), ring_sizes)?;
+
+    Ok(XDPSocket {
+        if_index: if_index as _,
+        if_queue: if_queue as _,
+        umem: Arc::new(umem),
+        fd: socket_fd.into_raw_fd(),
+        bind_flags: BindFlags::from_bits(bind_flags as u16),
+        rx_ring,
+        tx_ring,
+        completion_ring,
+        fill_ring,
+        fill_order: Default::default(),
+        tx_rate_limiter: None,
+        tx_watermarks: None,
+        tx_queue: None,
+        hooks: None,
+        traffic: Default::default(),
+    })
+}
+
+/// Send `payload` along with duplicates of `fds`
+fn send_fds(channel: &UnixStream, payload: &[u8], fds: &[RawFd]) -> Result<(), crate::Error> {
+    let fds_size = std::mem::size_of_val(fds);
+    let mut control = vec![0_u8; unsafe { libc::CMSG_SPACE(fds_size as _) } as usize];
+    let mut iov = [IoSlice::new(payload)];
+    let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+    message.msg_iov = iov.as_mut_ptr().cast();
+    message.msg_iovlen = iov.len() as _;
+    message.msg_control = control.as_mut_ptr().cast();
+    message.msg_controllen = control.len() as _;
+    unsafe {
+        let header = libc::CMSG_FIRSTHDR(&message);
+        (*header).cmsg_level = libc::SOL_SOCKET;
+        (*header).cmsg_type = libc::SCM_RIGHTS;
+        (*header).cmsg_len = libc::CMSG_LEN(fds_size as _) as _;
+        std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(header).cast(), fds.len());
+    }
+    let sent = unsafe { libc::sendmsg(channel.as_raw_fd(), &message, libc::MSG_NOSIGNAL) };
+    if sent < 0 {
+        return Err(crate::Error::SocketSendFailure { error: std::io::Error::last_os_error() });
+    }
+    if sent as usize != payload.len() {
+        return Err(crate::Error::HandoffFailure { reason: "short send" });
+    }
+    Ok(())
+}
+
+/// Receive exactly `payload.len()` bytes along with `N` file descriptors
+fn recv_fds<const N: usize>(channel: &UnixStream, payload: &mut [u8]) -> Result<[OwnedFd; N], crate::Error> {
+    let fds_size = N * std::mem::size_of::<RawFd>();
+    let mut control = vec![0_u8; unsafe { libc::CMSG_SPACE(fds_size as _) } as usize];
+    let mut iov = libc::iovec { iov_base: payload.as_mut_ptr().cast(), iov_len: payload.len() };
+    let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr().cast();
+    message.msg_controllen = control.len() as _;
+    let received = unsafe { libc::recvmsg(channel.as_raw_fd(), &mut message, libc::MSG_CMSG_CLOEXEC) };
+    if received < 0 {
+        return Err(crate::Error::SocketReceiveFailure { error: std::io::Error::last_os_error() });
+    }
+
+    // take ownership of whatever was received before validating
+    let mut fds = Vec::new();
+    unsafe {
+        let mut header = libc::CMSG_FIRSTHDR(&message);
+        while ! header.is_null() {
+            if (*header).cmsg_level == libc::SOL_SOCKET && (*header).cmsg_type == libc::SCM_RIGHTS {
+                let count = ((*header).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / std::mem::size_of::<RawFd>();
+                let data = libc::CMSG_DATA(header).cast::<RawFd>();
+                for index in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(data.add(index).read_unaligned()));
+                }
+            }
+            header = libc::CMSG_NXTHDR(&message, header);
+        }
+    }
+    if message.msg_flags & (libc::MSG_TRUNC | libc::MSG_CTRUNC) != 0 || received as usize != payload.len() {
+        return Err(crate::Error::HandoffFailure { reason: "truncated message" });
+    }
+    fds.try_into().map_err(|_| crate::Error::HandoffFailure { reason: "unexpected number of file descriptors" })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::{fd::{AsFd, AsRawFd}, unix::net::UnixStream};
+
+    use super::{recv_fds, send_fds};
+    use crate::Umem;
+
+    #[test]
+    fn test_umem_handoff() {
+        let (sender, receiver) = UnixStream::pair().unwrap();
+        let umem = Umem::new_2k_shareable(16).unwrap();
+        unsafe { umem.memory_ptr().add(4096).write(42) };
+        assert!(Umem::new_2k(16).unwrap().memfd().is_none());
+
+        // the receiver sees the same memory
+        send_fds(&sender, b"umem", &[umem.memfd().unwrap().as_raw_fd()]).unwrap();
+        let mut payload = [0_u8; 4];
+        let [memfd] = recv_fds::<1>(&receiver, &mut payload).unwrap();
+        assert_eq!(&payload, b"umem");
+        let shared = Umem::from_memfd(memfd, 2048).unwrap();
+        assert_eq!(shared.num_chunks(), 16);
+        assert_eq!(unsafe { shared.memory_ptr().add(4096).read() }, 42);
+        unsafe { shared.memory_ptr().add(4097).write(7) };
+        assert_eq!(unsafe { umem.memory_ptr().add(4097).read() }, 7);
+
+        // a wrong number of descriptors is refused
+        send_fds(&sender, b"umem", &[umem.memfd().unwrap().as_raw_fd(), receiver.as_fd().as_raw_fd()]).unwrap();
+        assert!(recv_fds::<1>(&receiver, &mut payload).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Periodic transmission of a template frame, e.g. keepalives or BFD-like liveness probes, driven by a
+//! [`crate::worker::Worker`]
+
+use std::time::{Duration, Instant};
+
+use crate::{UmemAllocator, XDPSocket};
+
+/// Changes a frame copied out of the template, given its sequence number
+type UpdateFn = Box<dyn FnMut(&mut [u8], u64) + Send>;
+
+/// Counters kept by a [`Heartbeat`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeartbeatStats {
+    pub sent: u64,
+    /// Intervals which went by without a frame, because the heartbeat was checked too late
+    pub skipped: u64,
+    /// Attempts which found the TX ring full or no chunk available, retried the next time the heartbeat is checked
+    pub no_space: u64,
+}
+
+/// A frame transmitted on a port every interval
+///
+/// The first frame is due as soon as the heartbeat is added. Frames are copied out of the template, then handed to the
+/// update callback if any, together with their sequence number, e.g. to fill in a counter or a timestamp. Late checks
+/// send a single frame and skip the intervals they missed, so that peers never see bursts
+pub struct Heartbeat {
+    port: usize,
+    template: Vec<u8>,
+    interval: Duration,
+    update: Option<UpdateFn>,
+    next_due: Instant,
+    sequence: u64,
+    stats: HeartbeatStats,
+}
+impl Heartbeat {
+    /// Transmit `template` on `port` every `interval`
+    pub fn new(port: usize, template: impl Into<Vec<u8>>, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "Interval must be positive");
+        Self {
+            port,
+            template: template.into(),
+            interval,
+            update: None,
+            next_due: Instant::now(),
+            sequence: 0,
+            stats: HeartbeatStats::default(),
+        }
+    }
+
+    /// Let `update` change every frame after the template is copied, it receives the frame and its sequence number
+    pub fn with_update(mut self, update: impl FnMut(&mut [u8], u64) + Send + 'static) -> Self {
+        self.update = Some(Box::new(update));
+        self
+    }
+
+    pub const fn port(&self) -> usize {
+        self.port
+    }
+
+    /// The frame every transmitted one is copied out of
+    pub fn template(&self) -> &[u8] {
+        &self.template
+    }
+
+    pub const fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Change the interval, taking effect from the next frame
+    pub fn set_interval(&mut self, interval: Duration) {
+        assert!(!interval.is_zero(), "Interval must be positive");
+        self.interval = interval;
+    }
+
+    /// When the next frame should be transmitted
+    pub const fn next_due(&self) -> Instant {
+        self.next_due
+    }
+
+    pub const fn stats(&self) -> &HeartbeatStats {
+        &self.stats
+    }
+
+    /// Transmit a frame on `socket` if one is due at `now`, returning whether it was enqueued
+    pub fn transmit_if_due(&mut self, socket: &mut XDPSocket, allocator: &(impl UmemAllocator + ?Sized), now: Instant) -> Result<bool, crate::Error> {
+        if now < self.next_due {
+            return Ok(false);
+        }
+        if self.template.len() > socket.umem.chunk_size() {
+            return Err(crate::Error::FrameTooLarge { length: self.template.len(), chunk_size: socket.umem.chunk_size() });
+        }
+
+        // build in place
+        let (template, sequence, update) = (&self.template, self.sequence, &mut self.update);
+        let sent = socket.send_with(allocator, 0, |chunk| {
+            let frame = &mut chunk[..template.len()];
+            frame.copy_from_slice(template);
+            if let Some(update) = update {
+                update(frame, sequence);
+            }
+            Ok(frame.len())
+        })?;
+        if !sent {
+            self.stats.no_space += 1;
+            return Ok(false);
+        }
+
+        // schedule the next one, skipping what was missed
+        self.sequence += 1;
+        self.stats.sent += 1;
+        self.stats.skipped += crate::utils::advance_schedule(&mut self.next_due, self.interval, now);
+        Ok(true)
+    }
+}
+impl std::fmt::Debug for Heartbeat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Heartbeat")
+            .field("port", &self.port)
+            .field("len", &self.template.len())
+            .field("interval", &self.interval)
+            .field("next_due", &self.next_due)
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{Heartbeat, HeartbeatStats};
+    use crate::{testing::MockXDP, DefaultAllocator, Umem, UmemAllocatorFactory};
+
+    #[test]
+    fn test_heartbeat_schedule() {
+        let umem = Arc::new(Umem::new_2k(16).unwrap());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 8).unwrap();
+        let allocator = DefaultAllocator::for_umem(umem);
+        let interval = Duration::from_millis(100);
+        let mut heartbeat = Heartbeat::new(0, *b"alive-?", interval).with_update(|frame, sequence| frame[6] = b'0' + sequence as u8);
+        let start = heartbeat.next_due();
+
+        // due at once, then every interval
+        assert!(heartbeat.transmit_if_due(&mut socket, &allocator, start).unwrap());
+        assert!(!heartbeat.transmit_if_due(&mut socket, &allocator, start + interval / 2).unwrap());
+        assert!(heartbeat.transmit_if_due(&mut socket, &allocator, start + interval).unwrap());
+
+        // late by more than two intervals
+        assert!(heartbeat.transmit_if_due(&mut socket, &allocator, start + interval * 4 + interval / 2).unwrap());
+        assert_eq!(heartbeat.next_due(), start + interval * 5);
+        assert_eq!(mock.transmitted_frames(), [b"alive-0".to_vec(), b"alive-1".to_vec(), b"alive-2".to_vec()]);
+        assert_eq!(*heartbeat.stats(), HeartbeatStats { sent: 3, skipped: 2, no_space: 0 });
+    }
+}
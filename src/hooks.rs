@@ -0,0 +1,88 @@
+use crate::XDPSocket;
+
+/// One of the four rings of a socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RingKind {
+    Rx,
+    Tx,
+    Fill,
+    Completion,
+}
+
+/// Telemetry callbacks invoked by the high level paths of a socket, see [`XDPSocket::hooks`]
+///
+/// Every method does nothing by default, implement the ones to wire into counters or alerts.
+/// They run on the hot path: keep them cheap, e.g. bumping atomics
+pub trait XdpHooks: Send + Sync {
+    /// A frame received on `socket` was dropped because it could not be handled, e.g. forwarded without room on the other side
+    fn on_rx_drop(&self, socket: &XDPSocket, len: usize) {
+        let _ = (socket, len);
+    }
+
+    /// An allocator ran out of chunks while `socket` needed one
+    fn on_alloc_fail(&self, socket: &XDPSocket) {
+        let _ = socket;
+    }
+
+    /// `ring` of `socket` was full when something had to be produced to it
+    fn on_ring_full(&self, socket: &XDPSocket, ring: RingKind) {
+        let _ = (socket, ring);
+    }
+
+    /// A syscall was made to wake `socket` up for transmission
+    fn on_wakeup_syscall(&self, socket: &XDPSocket) {
+        let _ = socket;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+
+    use super::{RingKind, XdpHooks};
+    use crate::{testing::MockXDP, DefaultAllocator, Umem, UmemAllocatorFactory, XDPSocket};
+
+    #[derive(Default)]
+    struct Counters {
+        alloc_fail: AtomicU64,
+        tx_full: AtomicU64,
+        wakeups: AtomicU64,
+    }
+    impl XdpHooks for Counters {
+        fn on_alloc_fail(&self, _: &XDPSocket) {
+            self.alloc_fail.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_ring_full(&self, _: &XDPSocket, ring: RingKind) {
+            assert_eq!(ring, RingKind::Tx);
+            self.tx_full.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_wakeup_syscall(&self, _: &XDPSocket) {
+            self.wakeups.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_hooks_are_invoked() {
+        let umem = Arc::new(Umem::new_2k(8).unwrap());
+        let allocator = DefaultAllocator::for_umem(umem.clone());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+        let counters = Arc::new(Counters::default());
+        socket.hooks = Some(counters.clone());
+
+        // the umem runs out before the ring
+        mock.set_tx_needs_wakeup(true);
+        while socket.send(&allocator, b"frame").unwrap() {}
+        assert_eq!(counters.alloc_fail.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.wakeups.load(Ordering::Relaxed), 8);
+
+        // the ring runs out before the umem
+        mock.transmit(|_| {});
+        socket.reap_completions(&allocator);
+        let (mut socket, _mock) = MockXDP::new(umem.clone(), 4).unwrap();
+        socket.hooks = Some(counters.clone());
+        while socket.send(&allocator, b"frame").unwrap() {}
+        assert_eq!(counters.tx_full.load(Ordering::Relaxed), 1);
+    }
+}
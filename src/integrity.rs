@@ -0,0 +1,193 @@
+//! Detection of spikes of invalid descriptors, which the kernel silently drops, and repair of the fill ring they come from
+
+use std::collections::HashSet;
+
+use crate::{SocketStatus, UmemAllocator, XDPSocket};
+
+/// Growth of the invalid descriptor counters of a socket between two checks, see [`DescriptorMonitor`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescriptorSpike {
+    /// Fill ring entries the kernel could not use
+    pub rx_invalid_descs: u64,
+    /// TX descriptors the kernel dropped, their chunks never show up on the completion ring
+    pub tx_invalid_descs: u64,
+}
+
+/// What [`repair_fill_ring`] found among the entries the kernel did not take yet
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FillRingRepair {
+    /// Entries looked at
+    pub checked: usize,
+    /// Offsets outside of the umem, or of a chunk already on the ring
+    pub offending: Vec<u64>,
+    /// Offending entries replaced with a chunk from the allocator, the others were left in place for lack of chunks
+    pub replaced: usize,
+}
+
+/// What a [`DescriptorMonitor`] found on a spike
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorReport {
+    pub spike: DescriptorSpike,
+    pub fill_ring: FillRingRepair,
+    /// The address and length of the descriptors waiting on the TX ring which do not fit in a chunk, left in place
+    pub offending_tx: Vec<(u64, u32)>,
+}
+
+/// Counters kept by a [`DescriptorMonitor`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DescriptorMonitorStats {
+    pub spikes: u64,
+    /// Fill ring entries replaced
+    pub repaired: u64,
+}
+
+/// Watches the invalid descriptor counters of a socket, repairing its fill ring when they spike
+///
+/// The kernel counts invalid descriptors and drops them, which is all that shows of a corrupted ring. A spike is a growth
+/// of at least `threshold` between two checks; on one, the fill ring is repaired and the TX ring validated, and offending
+/// offsets are logged as warnings
+#[derive(Debug, Clone)]
+pub struct DescriptorMonitor {
+    threshold: u64,
+    last: Option<(u64, u64)>,
+    stats: DescriptorMonitorStats,
+}
+impl DescriptorMonitor {
+    /// Report spikes of at least `threshold` invalid descriptors
+    pub fn new(threshold: u64) -> Self {
+        assert!(threshold > 0, "Threshold must be positive");
+        Self { threshold, last: None, stats: DescriptorMonitorStats::default() }
+    }
+
+    pub const fn threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    pub const fn stats(&self) -> &DescriptorMonitorStats {
+        &self.stats
+    }
+
+    /// Compare the counters of `status` with the previous ones, returning a spike if they grew by `threshold` or more
+    ///
+    /// The first status only sets the baseline
+    pub fn observe(&mut self, status: &SocketStatus) -> Option<DescriptorSpike> {
+        let current = (status.rx_invalid_descs, status.tx_invalid_descs);
+        let (last_rx, last_tx) = self.last.replace(current)?;
+        let spike = DescriptorSpike {
+            rx_invalid_descs: current.0.wrapping_sub(last_rx),
+            tx_invalid_descs: current.1.wrapping_sub(last_tx),
+        };
+        if spike.rx_invalid_descs < self.threshold && spike.tx_invalid_descs < self.threshold {
+            return None;
+        }
+        self.stats.spikes += 1;
+        Some(spike)
+    }
+
+    /// Look at the counters of `socket`, repairing its rings on a spike, see [`repair_fill_ring`] and [`validate_tx_ring`]
+    pub fn check(&mut self, socket: &mut XDPSocket, allocator: &(impl UmemAllocator + ?Sized)) -> Result<Option<DescriptorReport>, crate::Error> {
+        let Some(spike) = self.observe(&socket.status()?) else {
+            return Ok(None);
+        };
+        tracing::warn!(if_index = socket.if_index, queue = socket.if_queue, rx = spike.rx_invalid_descs, tx = spike.tx_invalid_descs, "invalid descriptors spike");
+        let fill_ring = repair_fill_ring(socket, allocator);
+        self.stats.repaired += fill_ring.replaced as u64;
+        let offending_tx = validate_tx_ring(socket);
+        Ok(Some(DescriptorReport { spike, fill_ring, offending_tx }))
+    }
+}
+
+/// Check the entries of the fill ring the kernel did not take yet, replacing offending ones with chunks from `allocator`
+///
+/// Entries are offending when outside of the umem, or when their chunk is already on the ring, which would get two frames
+/// written to it. The kernel keeps consuming meanwhile: a chunk replacing an entry it took at the same time is leaked
+pub fn repair_fill_ring(socket: &mut XDPSocket, allocator: &(impl UmemAllocator + ?Sized)) -> FillRingRepair {
+    let mut repair = FillRingRepair::default();
+    let mut chunks = HashSet::new();
+    let (start, end) = (socket.fill_ring.consumer_position(), socket.fill_ring.producer_position());
+    for position in 0..end.wrapping_sub(start) {
+        let index = start.wrapping_add(position) as usize % socket.fill_ring.num_elements();
+        let offset = *socket.fill_ring.get_nth_descriptor(index);
+        repair.checked += 1;
+        if socket.umem.contains_frame(offset, 0) && chunks.insert(socket.umem.chunk_index_for_offset(offset)) {
+            continue;
+        }
+
+        // replace
+        tracing::warn!(if_index = socket.if_index, queue = socket.if_queue, offset, "offending fill ring entry");
+        repair.offending.push(offset);
+        if let Some(chunk_index) = allocator.try_allocate() {
+            *socket.fill_ring.get_nth_descriptor_mut(index) = socket.umem.chunk_start_offset_for_index(chunk_index);
+            chunks.insert(chunk_index);
+            repair.replaced += 1;
+        }
+    }
+    repair
+}
+
+/// The address and length of the descriptors waiting on the TX ring which do not fit in a chunk, logged as warnings
+///
+/// The kernel will drop them, their chunks have to be recovered by the application
+pub fn validate_tx_ring(socket: &XDPSocket) -> Vec<(u64, u32)> {
+    let (start, end) = (socket.tx_ring.consumer_position(), socket.tx_ring.producer_position());
+    (0..end.wrapping_sub(start))
+        .map(|position| *socket.tx_ring.get_nth_descriptor(start.wrapping_add(position) as usize % socket.tx_ring.num_elements()))
+        .filter(|descriptor| !socket.umem.contains_frame(descriptor.addr, descriptor.len as _))
+        .map(|descriptor| {
+            tracing::warn!(if_index = socket.if_index, queue = socket.if_queue, addr = descriptor.addr, len = descriptor.len, "offending TX descriptor");
+            (descriptor.addr, descriptor.len)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{repair_fill_ring, validate_tx_ring, DescriptorMonitor, DescriptorSpike};
+    use crate::{testing::MockXDP, DefaultAllocator, SocketStatus, Umem, UmemAllocatorFactory};
+
+    #[test]
+    fn test_spike_detection() {
+        let mut status = SocketStatus {
+            if_index: 1, if_queue: 0, zero_copy: false, rx_dropped: 0, rx_invalid_descs: 5, tx_invalid_descs: 0,
+            rx_packets: 0, rx_bytes: 0, tx_packets: 0, tx_bytes: 0, tx_wakeups: 0, tx_wakeups_skipped: 0,
+        };
+        let mut monitor = DescriptorMonitor::new(10);
+        assert_eq!(monitor.observe(&status), None);
+        status.rx_invalid_descs += 9;
+        assert_eq!(monitor.observe(&status), None);
+        status.tx_invalid_descs += 10;
+        assert_eq!(monitor.observe(&status), Some(DescriptorSpike { rx_invalid_descs: 0, tx_invalid_descs: 10 }));
+        assert_eq!(monitor.stats().spikes, 1);
+    }
+
+    #[test]
+    fn test_ring_repair() {
+        let umem = Arc::new(Umem::new_2k(16).unwrap());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 8).unwrap();
+        let allocator = DefaultAllocator::for_umem(umem);
+        assert_eq!(socket.refill_fill_ring(&allocator, 4), 4);
+
+        // corrupt two entries
+        let start = socket.fill_ring.get_consumer_index() as usize;
+        let duplicate = *socket.fill_ring.get_nth_descriptor(start);
+        *socket.fill_ring.get_nth_descriptor_mut(start + 1) = duplicate + 100;
+        *socket.fill_ring.get_nth_descriptor_mut(start + 2) = 1 << 40;
+        let repair = repair_fill_ring(&mut socket, &allocator);
+        assert_eq!((repair.checked, repair.offending, repair.replaced), (4, vec![duplicate + 100, 1 << 40], 2));
+        assert!(repair_fill_ring(&mut socket, &allocator).offending.is_empty());
+
+        // every repaired entry can take a frame
+        for _ in 0..4 {
+            assert!(mock.inject(b"frame"));
+        }
+
+        // TX descriptors crossing their chunk
+        assert!(socket.send(&allocator, b"frame").unwrap());
+        let tx_index = socket.tx_ring.get_consumer_index() as usize;
+        socket.tx_ring.get_nth_descriptor_mut(tx_index).len = 4096;
+        let addr = socket.tx_ring.get_nth_descriptor(tx_index).addr;
+        assert_eq!(validate_tx_ring(&socket), [(addr, 4096)]);
+    }
+}
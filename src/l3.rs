@@ -0,0 +1,284 @@
+//! IP routing: a longest prefix match [`RouteTable`] and the [`Router`] pipeline stage forwarding along it
+
+use std::{collections::HashMap, net::IpAddr};
+
+use crate::{
+    packet::{checksum, ethertype, EthernetFrame, Ipv4Packet, Ipv6Packet, MacAddress},
+    pipeline::{FrameCtx, Stage, Verdict},
+};
+
+/// Where to send packets matching a route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NextHop {
+    /// The port to transmit on
+    pub port: usize,
+    /// The address of the router on that port, placed as source
+    pub source_mac: MacAddress,
+    /// The address of the gateway or directly connected host, placed as destination
+    pub destination_mac: MacAddress,
+}
+
+/// Routes of one address family, with a hash map per prefix length probed from the longest one in use
+#[derive(Debug, Clone)]
+struct PrefixTable {
+    bits: u8,
+    by_length: Vec<HashMap<u128, NextHop>>,
+    lengths: Vec<u8>,
+}
+impl PrefixTable {
+    fn new(bits: u8) -> Self {
+        Self { bits, by_length: vec![HashMap::new(); bits as usize + 1], lengths: Vec::new() }
+    }
+
+    fn mask(&self, length: u8) -> u128 {
+        let address_bits = u128::MAX >> (128 - self.bits);
+        address_bits & ! address_bits.checked_shr(length as u32).unwrap_or(0)
+    }
+
+    fn insert(&mut self, prefix: u128, length: u8, next_hop: NextHop) -> Option<NextHop> {
+        assert!(length <= self.bits, "Prefix length exceeds the address size");
+        let key = prefix & self.mask(length);
+        let previous = self.by_length[length as usize].insert(key, next_hop);
+        if let Err(position) = self.lengths.binary_search_by(|probe| length.cmp(probe)) {
+            self.lengths.insert(position, length);
+        }
+        previous
+    }
+
+    fn remove(&mut self, prefix: u128, length: u8) -> Option<NextHop> {
+        let key = prefix & self.mask(length);
+        let routes = self.by_length.get_mut(length as usize)?;
+        let removed = routes.remove(&key);
+        if routes.is_empty() {
+            self.lengths.retain(|&probe| probe != length);
+        }
+        removed
+    }
+
+    fn lookup(&self, address: u128) -> Option<&NextHop> {
+        self.lengths.iter().find_map(|&length| self.by_length[length as usize].get(&(address & self.mask(length))))
+    }
+
+    fn len(&self) -> usize {
+        self.by_length.iter().map(HashMap::len).sum()
+    }
+}
+
+/// IPv4 and IPv6 routes, looked up by longest prefix match
+#[derive(Debug, Clone)]
+pub struct RouteTable {
+    v4: PrefixTable,
+    v6: PrefixTable,
+}
+impl Default for RouteTable {
+    fn default() -> Self {
+        Self { v4: PrefixTable::new(32), v6: PrefixTable::new(128) }
+    }
+}
+impl RouteTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Route `prefix/length` to `next_hop`, returning the route it replaced
+    ///
+    /// Host bits of `prefix` are ignored, a zero `length` makes a default route
+    pub fn insert(&mut self, prefix: IpAddr, length: u8, next_hop: NextHop) -> Option<NextHop> {
+        match prefix {
+            IpAddr::V4(prefix) => self.v4.insert(u32::from(prefix) as u128, length, next_hop),
+            IpAddr::V6(prefix) => self.v6.insert(u128::from(prefix), length, next_hop),
+        }
+    }
+
+    /// Remove the route for `prefix/length`, returning it
+    pub fn remove(&mut self, prefix: IpAddr, length: u8) -> Option<NextHop> {
+        match prefix {
+            IpAddr::V4(prefix) => self.v4.remove(u32::from(prefix) as u128, length),
+            IpAddr::V6(prefix) => self.v6.remove(u128::from(prefix), length),
+        }
+    }
+
+    /// The next hop of the most specific route covering `address`
+    pub fn lookup(&self, address: IpAddr) -> Option<&NextHop> {
+        match address {
+            IpAddr::V4(address) => self.v4.lookup(u32::from(address) as u128),
+            IpAddr::V6(address) => self.v6.lookup(u128::from(address)),
+        }
+    }
+
+    /// The number of routes
+    pub fn len(&self) -> usize {
+        self.v4.len() + self.v6.len()
+    }
+
+    /// Whether there are no routes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Counters kept by a [`Router`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouterStats {
+    pub routed: u64,
+    /// Packets dropped because no route covered their destination
+    pub no_route: u64,
+    /// Packets dropped because their TTL or hop limit ran out
+    pub ttl_expired: u64,
+}
+
+/// A pipeline stage routing IP packets along a [`RouteTable`]
+///
+/// Routed packets get the MAC addresses of their next hop, a decremented TTL or hop limit and, for IPv4, an incrementally
+/// updated header checksum, then are forwarded to the port of the next hop. Expiring or unroutable packets are dropped
+/// without ICMP errors, anything else (e.g. ARP) continues down the pipeline
+#[derive(Debug, Clone, Default)]
+pub struct Router {
+    table: RouteTable,
+    stats: RouterStats,
+}
+impl Router {
+    pub fn new(table: RouteTable) -> Self {
+        Self { table, stats: RouterStats::default() }
+    }
+
+    pub fn table(&mut self) -> &mut RouteTable {
+        &mut self.table
+    }
+
+    pub const fn stats(&self) -> &RouterStats {
+        &self.stats
+    }
+
+    /// Route `frame`, see [`Stage::process`]
+    pub fn route(&mut self, frame: &mut [u8]) -> Verdict {
+        let Some(ethernet) = EthernetFrame::new_checked(&*frame) else {
+            return Verdict::Continue;
+        };
+        let (network_offset, network_ethertype) = (ethernet.payload_offset(), ethernet.inner_ethertype());
+
+        // find the next hop and age the packet
+        let network = &mut frame[network_offset..];
+        let next_hop = match network_ethertype {
+            ethertype::IPV4 => {
+                let Some(mut ipv4) = Ipv4Packet::new_checked(network) else {
+                    return Verdict::Continue;
+                };
+                let ttl = ipv4.ttl();
+                if ttl <= 1 {
+                    self.stats.ttl_expired += 1;
+                    return Verdict::Drop;
+                }
+                let Some(next_hop) = self.table.v4.lookup(u32::from(ipv4.destination()) as u128) else {
+                    self.stats.no_route += 1;
+                    return Verdict::Drop;
+                };
+
+                // the TTL shares a 16-bit word with the protocol
+                let protocol = ipv4.protocol() as u16;
+                ipv4.set_ttl(ttl - 1);
+                ipv4.set_checksum(checksum::update_u16(ipv4.checksum(), (ttl as u16) << 8 | protocol, ((ttl - 1) as u16) << 8 | protocol));
+                *next_hop
+            },
+            ethertype::IPV6 => {
+                let Some(mut ipv6) = Ipv6Packet::new_checked(network) else {
+                    return Verdict::Continue;
+                };
+                let hop_limit = ipv6.hop_limit();
+                if hop_limit <= 1 {
+                    self.stats.ttl_expired += 1;
+                    return Verdict::Drop;
+                }
+                let Some(next_hop) = self.table.v6.lookup(u128::from(ipv6.destination())) else {
+                    self.stats.no_route += 1;
+                    return Verdict::Drop;
+                };
+                ipv6.set_hop_limit(hop_limit - 1);
+                *next_hop
+            },
+            _ => return Verdict::Continue,
+        };
+
+        // rewrite the link layer
+        let mut ethernet = EthernetFrame::new_checked(frame).expect("the frame was already checked");
+        ethernet.set_source(next_hop.source_mac);
+        ethernet.set_destination(next_hop.destination_mac);
+        self.stats.routed += 1;
+        Verdict::Forward(next_hop.port)
+    }
+}
+impl Stage for Router {
+    fn process(&mut self, ctx: &mut FrameCtx) -> Verdict {
+        self.route(ctx.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::{NextHop, RouteTable, Router};
+    use crate::{packet::{FrameBuilder, Ipv4Packet, Ipv6Packet, MacAddress}, pipeline::Verdict};
+
+    fn hop(port: usize) -> NextHop {
+        NextHop { port, source_mac: MacAddress([2, 0, 0, 0, 0, port as u8]), destination_mac: MacAddress([4, 0, 0, 0, 0, port as u8]) }
+    }
+
+    #[test]
+    fn test_longest_prefix_match() {
+        let mut table = RouteTable::new();
+        assert_eq!(table.insert(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0, hop(0)), None);
+        assert_eq!(table.insert(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 8, hop(1)), None);
+        assert_eq!(table.insert(IpAddr::V4(Ipv4Addr::new(10, 1, 2, 99)), 24, hop(2)), None);
+        assert_eq!(table.insert(IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3)), 32, hop(3)), None);
+        assert_eq!(table.insert(IpAddr::V6("2001:db8::".parse().unwrap()), 32, hop(4)), None);
+        assert_eq!(table.insert(IpAddr::V6("2001:db8:1::".parse().unwrap()), 48, hop(5)), None);
+        assert_eq!(table.len(), 6);
+
+        let lookup = |table: &RouteTable, address: &str| table.lookup(address.parse().unwrap()).map(|hop| hop.port);
+        assert_eq!(lookup(&table, "192.168.1.1"), Some(0));
+        assert_eq!(lookup(&table, "10.200.0.1"), Some(1));
+        assert_eq!(lookup(&table, "10.1.2.4"), Some(2));
+        assert_eq!(lookup(&table, "10.1.2.3"), Some(3));
+        assert_eq!(lookup(&table, "2001:db8:1::1"), Some(5));
+        assert_eq!(lookup(&table, "2001:db8:2::1"), Some(4));
+        assert_eq!(lookup(&table, "2001:db9::1"), None);
+
+        // removing falls back to shorter prefixes
+        assert_eq!(table.remove(IpAddr::V4(Ipv4Addr::new(10, 1, 2, 0)), 24), Some(hop(2)));
+        assert_eq!(lookup(&table, "10.1.2.4"), Some(1));
+        assert_eq!(table.remove(IpAddr::V4(Ipv4Addr::new(10, 1, 2, 0)), 24), None);
+        assert_eq!(table.len(), 5);
+    }
+
+    #[test]
+    fn test_router_rewrites_frames() {
+        let mut table = RouteTable::new();
+        table.insert(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 0)), 16, hop(1));
+        table.insert(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0, hop(2));
+        let mut router = Router::new(table);
+        let builder = FrameBuilder::ethernet(MacAddress([6; 6]), MacAddress([8; 6]));
+
+        // ipv4
+        let mut frame = [0_u8; 128];
+        let len = builder.clone().ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(192, 168, 7, 7)).ttl(64).udp(1000, 2000).write_with_payload(&mut frame, b"routed").unwrap();
+        assert_eq!(router.route(&mut frame[..len]), Verdict::Forward(1));
+        assert_eq!(frame[..12], [4, 0, 0, 0, 0, 1, 2, 0, 0, 0, 0, 1]);
+        let ipv4 = Ipv4Packet::new_checked(&frame[14..len]).unwrap();
+        assert_eq!(ipv4.ttl(), 63);
+        assert!(ipv4.verify_checksum());
+
+        // ipv6, until the hop limit runs out
+        let len = builder.clone().ipv6(Ipv6Addr::LOCALHOST, "2001:db8::1".parse().unwrap()).ttl(2).udp(1000, 2000).write_with_payload(&mut frame, b"routed").unwrap();
+        assert_eq!(router.route(&mut frame[..len]), Verdict::Forward(2));
+        assert_eq!(Ipv6Packet::new_checked(&frame[14..len]).unwrap().hop_limit(), 1);
+        assert_eq!(router.route(&mut frame[..len]), Verdict::Drop);
+
+        // unroutable, then not ip
+        let len = builder.clone().ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(172, 16, 0, 1)).udp(1000, 2000).write_with_payload(&mut frame, b"lost").unwrap();
+        assert_eq!(router.route(&mut frame[..len]), Verdict::Drop);
+        let len = builder.ethertype(crate::packet::ethertype::ARP).write_with_payload(&mut frame, &[0; 28]).unwrap();
+        assert_eq!(router.route(&mut frame[..len]), Verdict::Continue);
+        assert_eq!(*router.stats(), super::RouterStats { routed: 2, no_route: 1, ttl_expired: 1 });
+    }
+}
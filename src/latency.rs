@@ -0,0 +1,178 @@
+use crate::{pktgen::{PacketGenerator, PacketTemplate, SizeDistribution}, UmemAllocator, XDPSocket};
+
+/// Offset of the probe payload within the Ethernet/IPv4/UDP frames generated by [`LatencyMeter`]
+pub const PROBE_OFFSET: usize = 14 + 20 + 8;
+
+/// Size of the probe payload: magic, sequence number, transmission timestamp
+pub const PROBE_SIZE: usize = 4 + 8 + 8;
+
+const PROBE_MAGIC: [u8; 4] = *b"XDRL";
+
+/// Write a probe carrying `sequence` and `timestamp_ns` into `frame` at `offset`
+pub fn write_probe(frame: &mut [u8], offset: usize, sequence: u64, timestamp_ns: u64) {
+    let probe = &mut frame[offset..offset + PROBE_SIZE];
+    probe[0..4].copy_from_slice(&PROBE_MAGIC);
+    probe[4..12].copy_from_slice(&sequence.to_be_bytes());
+    probe[12..20].copy_from_slice(&timestamp_ns.to_be_bytes());
+}
+
+/// Read back the sequence number and timestamp of a probe found in `frame` at `offset`
+pub fn read_probe(frame: &[u8], offset: usize) -> Option<(u64, u64)> {
+    let probe = frame.get(offset..offset + PROBE_SIZE)?;
+    if probe[0..4] != PROBE_MAGIC {
+        return None;
+    }
+    Some((
+        u64::from_be_bytes(probe[4..12].try_into().unwrap()),
+        u64::from_be_bytes(probe[12..20].try_into().unwrap()),
+    ))
+}
+
+/// Turn an Ethernet/IPv4/UDP frame around in place, swapping source and destination addresses and ports
+///
+/// This is what the far end of a measurement should do with probes; returns `false` if the frame is not IPv4/UDP
+pub fn reflect(frame: &mut [u8]) -> bool {
+    if frame.len() < PROBE_OFFSET || frame[12..14] != [0x08, 0x00] || frame[14] != 0x45 || frame[23] != 17 {
+        return false;
+    }
+    // mac
+    let (dst, src) = frame[0..12].split_at_mut(6);
+    dst.swap_with_slice(src);
+    // ip, the header checksum is unaffected by the swap
+    let (src, dst) = frame[26..34].split_at_mut(4);
+    src.swap_with_slice(dst);
+    // udp, the checksum is unaffected by the swap
+    let (src, dst) = frame[34..38].split_at_mut(2);
+    src.swap_with_slice(dst);
+    true
+}
+
+/// Summary of the round trip times measured by a [`LatencyMeter`]
+#[derive(Debug, Clone, Default)]
+pub struct LatencyReport {
+    pub sent: u64,
+    pub received: u64,
+    pub min: std::time::Duration,
+    pub mean: std::time::Duration,
+    pub p50: std::time::Duration,
+    pub p90: std::time::Duration,
+    pub p99: std::time::Duration,
+    pub p999: std::time::Duration,
+    pub max: std::time::Duration,
+}
+
+/// Sends timestamped probes and measures the round trip time of their echoes
+///
+/// The far end is expected to send probes back unmodified beyond the headers, e.g. by means of [`reflect`]
+pub struct LatencyMeter {
+    generator: PacketGenerator,
+    epoch: std::time::Instant,
+    next_sequence: u64,
+    received: u64,
+    histogram: hdrhistogram::Histogram<u64>,
+}
+impl LatencyMeter {
+    /// Create a meter sending probes built out of `template`
+    ///
+    /// Sizes are raised to fit the probe payload, the IMIX distribution is replaced by minimum-size probes
+    pub fn new(mut template: PacketTemplate) -> Self {
+        const MIN_SIZE: usize = PROBE_OFFSET + PROBE_SIZE;
+        template.size = match template.size {
+            SizeDistribution::Fixed(size) => SizeDistribution::Fixed(size.max(MIN_SIZE)),
+            SizeDistribution::Uniform { min, max } => SizeDistribution::Uniform { min: min.max(MIN_SIZE), max: max.max(MIN_SIZE) },
+            SizeDistribution::Imix => SizeDistribution::Fixed(MIN_SIZE),
+        };
+        Self {
+            generator: PacketGenerator::new(template),
+            epoch: std::time::Instant::now(),
+            next_sequence: 0,
+            received: 0,
+            // 1ns to 60s, 3 significant digits
+            histogram: hdrhistogram::Histogram::new_with_bounds(1, 60_000_000_000, 3).unwrap(),
+        }
+    }
+
+    /// Transmit one probe on `socket`, returning `false` if there was no space for it
+    pub fn send_probe(&mut self, socket: &mut XDPSocket, allocator: &(impl UmemAllocator + ?Sized)) -> Result<bool, crate::Error> {
+        let mut frame = [0_u8; 2048];
+        let len = self.generator.next_into(&mut frame[..socket.umem.chunk_size().min(2048)]);
+        write_probe(&mut frame, PROBE_OFFSET, self.next_sequence, self.now_ns());
+        let sent = socket.send(allocator, &frame[..len])?;
+        if sent {
+            self.next_sequence += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Account for a received frame, returning its round trip time if it is one of our probes
+    pub fn handle_frame(&mut self, frame: &[u8]) -> Option<std::time::Duration> {
+        let (sequence, timestamp_ns) = read_probe(frame, PROBE_OFFSET)?;
+        if sequence >= self.next_sequence {
+            return None;
+        }
+        let rtt_ns = self.now_ns().saturating_sub(timestamp_ns);
+        self.histogram.saturating_record(rtt_ns.max(1));
+        self.received += 1;
+        Some(std::time::Duration::from_nanos(rtt_ns))
+    }
+
+    /// Consume the RX ring of `socket`, accounting for every probe found, returning the number of frames received
+    pub fn poll_rx(&mut self, socket: &mut XDPSocket, allocator: &(impl UmemAllocator + ?Sized)) -> usize {
+        socket.recv(allocator, |frame| { self.handle_frame(frame); })
+    }
+
+    /// The underlying histogram of round trip times, in nanoseconds
+    pub fn histogram(&self) -> &hdrhistogram::Histogram<u64> {
+        &self.histogram
+    }
+
+    /// Summarize the measurements so far
+    pub fn report(&self) -> LatencyReport {
+        let at = |quantile: f64| std::time::Duration::from_nanos(self.histogram.value_at_quantile(quantile));
+        if self.histogram.is_empty() {
+            return LatencyReport { sent: self.next_sequence, ..Default::default() };
+        }
+        LatencyReport {
+            sent: self.next_sequence,
+            received: self.received,
+            min: std::time::Duration::from_nanos(self.histogram.min()),
+            mean: std::time::Duration::from_nanos(self.histogram.mean() as u64),
+            p50: at(0.5),
+            p90: at(0.9),
+            p99: at(0.99),
+            p999: at(0.999),
+            max: std::time::Duration::from_nanos(self.histogram.max()),
+        }
+    }
+
+    /// Forget all measurements
+    pub fn reset(&mut self) {
+        self.histogram.reset();
+        self.received = 0;
+        self.next_sequence = 0;
+    }
+
+    fn now_ns(&self) -> u64 {
+        self.epoch.elapsed().as_nanos() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_probe, reflect, write_probe, PROBE_OFFSET};
+    use crate::pktgen::{PacketGenerator, PacketTemplate, SizeDistribution};
+
+    #[test]
+    fn test_probe_reflection() {
+        let mut frame = [0_u8; 64];
+        let template = PacketTemplate { size: SizeDistribution::Fixed(64), ..Default::default() };
+        let len = PacketGenerator::new(template).next_into(&mut frame);
+        write_probe(&mut frame[..len], PROBE_OFFSET, 42, 123_456);
+        let original = frame;
+
+        assert!(reflect(&mut frame[..len]));
+        assert_eq!(frame[0..6], original[6..12]);
+        assert_eq!(frame[26..30], original[30..34]);
+        assert_eq!(read_probe(&frame[..len], PROBE_OFFSET), Some((42, 123_456)));
+    }
+}
@@ -1,7 +1,55 @@
-mod bpf; pub use bpf::BPFRedirectManager;
-mod ring; pub use ring::XDPRing;
-mod socket; pub use socket::XDPSocket;
-mod umem; pub use umem::Umem;
+mod backpressure; pub use backpressure::{TxBacklog, TxWatermarks};
+mod bpf; pub use bpf::{compile_program, BPFRedirectManager, PacketSample, PacketSampler, ProgramSource, RedirectFilter, SteeringAction, SteeringRule, XdpAttachMode, XdpMode};
+mod device; pub use device::{Frame, XdpDevice};
+mod doctor; pub use doctor::{doctor, DoctorReport, Finding, Severity};
+mod genl;
+mod hooks; pub use hooks::{RingKind, XdpHooks};
+mod link; pub use link::{LinkEvent, LinkMonitor, LinkState};
+mod manager; pub use manager::UmemManager;
+mod rate_limiter; pub use rate_limiter::{FlowRateLimiter, TokenBucket, TxRateLimiter};
+mod recovery; pub use recovery::RecoveringSocket;
+mod ring; pub use ring::{RingMapping, XDPRing};
+mod shared_socket; pub use shared_socket::SharedXDPSocket;
+mod socket; pub use socket::{BindFlags, BindMode, FillOrder, MemoryFootprint, RingSizes, SocketStatus, TrafficCounters, XDPSocket};
+mod socket_set; pub use socket_set::{Waker, XDPSocketSet};
+mod tx_batch; pub use tx_batch::TxBatch;
+mod tx_queue; pub use tx_queue::{TxOverflowPolicy, TxQueue};
+mod udp; pub use udp::XdpUdpSocket;
+mod umem; pub use umem::{Chunk2K, Chunk4K, ChunkGuard, ChunkSize, FrameGuard, TypedUmem, Umem};
 mod umem_allocator; pub use umem_allocator::*;
-mod error; pub use error::Error;
+mod wait; pub use wait::WaitStrategy;
+mod error; pub use error::{Error, SocketOperation};
+pub mod acl;
+#[cfg(feature = "async-io")]
+pub mod async_socket;
+pub mod capture;
+pub mod config;
+pub mod conntrack;
+pub mod diag;
+pub mod ethtool;
+pub mod fanout;
+pub mod flow;
+pub mod forward;
+pub mod handoff;
+pub mod heartbeat;
+pub mod integrity;
+pub mod l3;
+pub mod latency;
+pub mod napi;
+pub mod ndp;
+pub mod pacing;
+pub mod packet;
+pub mod phc;
+pub mod pipeline;
+pub mod pktgen;
+pub mod qstats;
+pub mod replay;
+pub mod stats;
+pub mod switch;
+#[cfg(feature = "metrics")]
+pub mod telemetry;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod utils;
+pub mod watchdog;
+pub mod worker;
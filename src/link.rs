@@ -0,0 +1,248 @@
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// Something that happened to a monitored interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The interface is administratively up and has carrier
+    Up,
+    /// The interface was brought down or lost carrier, sockets bound to it stop receiving
+    Down,
+    /// The MTU changed, frames larger than the previous one may now arrive or be rejected
+    MtuChanged { mtu: u32 },
+    /// The interface was deleted or moved to another namespace, sockets bound to it are dead
+    Removed,
+}
+
+/// The last known state of a monitored interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkState {
+    pub up: bool,
+    pub mtu: u32,
+}
+
+/// Watches an interface through rtnetlink link notifications
+///
+/// Use [`Self::poll`] from a dedicated thread, or register [`Self::as_raw_fd`] with a reactor and call
+/// [`Self::poll`] with a zero timeout when it becomes readable
+pub struct LinkMonitor {
+    if_index: libc::c_uint,
+    fd: OwnedFd,
+    state: Option<LinkState>,
+    buffer: Vec<u8>,
+}
+impl LinkMonitor {
+    // netlink layout
+    const NLMSG_HEADER_SIZE: usize = 16;
+    const IFINFOMSG_SIZE: usize = 16;
+    const RTATTR_HEADER_SIZE: usize = 4;
+
+    /// Subscribe to the link notifications of the interface with index `interface_index` and fetch its current state
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "debug"))]
+    pub fn new(interface_index: libc::c_uint) -> Result<Self, crate::Error> {
+        // create netlink socket
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) };
+        if fd < 0 {
+            return Err(crate::Error::SocketCreationFailure);
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // join the link group
+        let mut bind_address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        bind_address.nl_family = libc::AF_NETLINK as _;
+        bind_address.nl_groups = libc::RTMGRP_LINK as _;
+        let bind_result = unsafe { libc::bind(fd.as_raw_fd(), &bind_address as *const _ as *const _, std::mem::size_of::<libc::sockaddr_nl>() as _) };
+        if bind_result < 0 {
+            return Err(crate::Error::SocketBindFailure { error: std::io::Error::last_os_error() });
+        }
+
+        // ask for the current state, the answer arrives as any other notification
+        let mut request = [0_u8; Self::NLMSG_HEADER_SIZE + Self::IFINFOMSG_SIZE];
+        let request_len = request.len() as u32;
+        request[0..4].copy_from_slice(&request_len.to_ne_bytes());
+        request[4..6].copy_from_slice(&libc::RTM_GETLINK.to_ne_bytes());
+        request[6..8].copy_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+        request[Self::NLMSG_HEADER_SIZE] = libc::AF_UNSPEC as u8;
+        request[Self::NLMSG_HEADER_SIZE + 4..Self::NLMSG_HEADER_SIZE + 8].copy_from_slice(&(interface_index as i32).to_ne_bytes());
+        if unsafe { libc::send(fd.as_raw_fd(), request.as_ptr().cast(), request.len(), 0) } < 0 {
+            return Err(crate::Error::SocketSendFailure { error: std::io::Error::last_os_error() });
+        }
+
+        let mut monitor = Self {
+            if_index: interface_index,
+            fd,
+            state: None,
+            buffer: vec![0; 32 * 1024],
+        };
+        monitor.poll(Some(std::time::Duration::from_secs(1)))?;
+        if monitor.state.is_none() {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("no interface with index {interface_index}") });
+        }
+        Ok(monitor)
+    }
+
+    /// The index of the monitored interface
+    pub const fn interface_index(&self) -> libc::c_uint {
+        self.if_index
+    }
+
+    /// The last known state, `None` once the interface is gone
+    pub const fn state(&self) -> Option<LinkState> {
+        self.state
+    }
+
+    /// Wait up to `timeout` (forever if `None`) for notifications, returning the events they caused
+    ///
+    /// Notifications about other interfaces or not changing the state yield no events
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(if_index = self.if_index)))]
+    pub fn poll(&mut self, timeout: Option<std::time::Duration>) -> Result<Vec<LinkEvent>, crate::Error> {
+        // wait
+        let mut poll_fd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = crate::utils::poll_timeout_ms(timeout);
+        if unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) } < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                return Err(crate::Error::PollFailure { error });
+            }
+        }
+
+        crate::utils::check_poll_events(poll_fd.fd, poll_fd.revents)?;
+
+        // drain
+        let mut events = Vec::new();
+        loop {
+            let received = unsafe { libc::recv(self.fd.as_raw_fd(), self.buffer.as_mut_ptr().cast(), self.buffer.len(), libc::MSG_DONTWAIT) };
+            if received < 0 {
+                let error = std::io::Error::last_os_error();
+                match error.kind() {
+                    std::io::ErrorKind::WouldBlock => break,
+                    std::io::ErrorKind::Interrupted => continue,
+                    _ => return Err(crate::Error::SocketReceiveFailure { error }),
+                }
+            }
+            Self::parse(&self.buffer[..received as usize], self.if_index, &mut self.state, &mut events);
+        }
+        Ok(events)
+    }
+
+    /// Block until the interface is removed or `handler` returns `false`, handing it every event
+    pub fn watch(&mut self, mut handler: impl FnMut(LinkEvent) -> bool) -> Result<(), crate::Error> {
+        loop {
+            for event in self.poll(None)? {
+                if ! handler(event) || event == LinkEvent::Removed {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Update `state` with the netlink messages in `buffer` concerning `if_index`, pushing the resulting events
+    fn parse(buffer: &[u8], if_index: libc::c_uint, state: &mut Option<LinkState>, events: &mut Vec<LinkEvent>) {
+        let u16_at = |bytes: &[u8], at: usize| u16::from_ne_bytes([bytes[at], bytes[at + 1]]);
+        let u32_at = |bytes: &[u8], at: usize| u32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap());
+
+        let mut messages = buffer;
+        while messages.len() >= Self::NLMSG_HEADER_SIZE {
+            let message_len = u32_at(messages, 0) as usize;
+            if message_len < Self::NLMSG_HEADER_SIZE || message_len > messages.len() {
+                return;
+            }
+            let message = &messages[..message_len];
+            messages = &messages[message_len.next_multiple_of(4).min(messages.len())..];
+
+            // only link messages about our interface
+            let message_type = u16_at(message, 4);
+            if (message_type != libc::RTM_NEWLINK && message_type != libc::RTM_DELLINK) || message.len() < Self::NLMSG_HEADER_SIZE + Self::IFINFOMSG_SIZE {
+                continue;
+            }
+            let info = &message[Self::NLMSG_HEADER_SIZE..];
+            if u32_at(info, 4) != if_index {
+                continue;
+            }
+
+            // removal
+            if message_type == libc::RTM_DELLINK {
+                if state.take().is_some() {
+                    events.push(LinkEvent::Removed);
+                }
+                continue;
+            }
+
+            // flags and attributes
+            let flags = u32_at(info, 8);
+            let up = flags & (libc::IFF_UP | libc::IFF_RUNNING) as u32 == (libc::IFF_UP | libc::IFF_RUNNING) as u32;
+            let mut mtu = state.map(|state| state.mtu).unwrap_or(0);
+            let mut attributes = &info[Self::IFINFOMSG_SIZE..];
+            while attributes.len() >= Self::RTATTR_HEADER_SIZE {
+                let attribute_len = u16_at(attributes, 0) as usize;
+                if attribute_len < Self::RTATTR_HEADER_SIZE || attribute_len > attributes.len() {
+                    break;
+                }
+                if u16_at(attributes, 2) == libc::IFLA_MTU && attribute_len >= Self::RTATTR_HEADER_SIZE + 4 {
+                    mtu = u32_at(attributes, Self::RTATTR_HEADER_SIZE);
+                }
+                attributes = &attributes[attribute_len.next_multiple_of(4).min(attributes.len())..];
+            }
+
+            // compare with what we knew, the first state is not an event
+            let new_state = LinkState { up, mtu };
+            if let Some(old_state) = state.replace(new_state) {
+                if old_state.up != new_state.up {
+                    events.push(if new_state.up { LinkEvent::Up } else { LinkEvent::Down });
+                }
+                if old_state.mtu != new_state.mtu {
+                    events.push(LinkEvent::MtuChanged { mtu: new_state.mtu });
+                }
+            }
+        }
+    }
+}
+impl AsRawFd for LinkMonitor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LinkEvent, LinkMonitor, LinkState};
+
+    fn link_message(message_type: u16, if_index: u32, flags: u32, mtu: u32) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&40_u32.to_ne_bytes());
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(&[0; 10]);
+        message.extend_from_slice(&[0; 4]);
+        message.extend_from_slice(&if_index.to_ne_bytes());
+        message.extend_from_slice(&flags.to_ne_bytes());
+        message.extend_from_slice(&[0; 4]);
+        message.extend_from_slice(&8_u16.to_ne_bytes());
+        message.extend_from_slice(&libc::IFLA_MTU.to_ne_bytes());
+        message.extend_from_slice(&mtu.to_ne_bytes());
+        message
+    }
+
+    #[test]
+    fn test_parse_link_messages() {
+        let up = (libc::IFF_UP | libc::IFF_RUNNING) as u32;
+        let mut state = None;
+        let mut events = Vec::new();
+
+        // the first message only sets the state
+        LinkMonitor::parse(&link_message(libc::RTM_NEWLINK, 3, up, 1500), 3, &mut state, &mut events);
+        assert_eq!(state, Some(LinkState { up: true, mtu: 1500 }));
+        assert!(events.is_empty());
+
+        // other interfaces are ignored, batched messages are all parsed
+        let mut batch = link_message(libc::RTM_NEWLINK, 4, 0, 1500);
+        batch.extend(link_message(libc::RTM_NEWLINK, 3, libc::IFF_UP as u32, 9000));
+        batch.extend(link_message(libc::RTM_NEWLINK, 3, up, 9000));
+        batch.extend(link_message(libc::RTM_DELLINK, 3, 0, 9000));
+        LinkMonitor::parse(&batch, 3, &mut state, &mut events);
+        assert_eq!(events, [LinkEvent::Down, LinkEvent::MtuChanged { mtu: 9000 }, LinkEvent::Up, LinkEvent::Removed]);
+        assert_eq!(state, None);
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use crate::{DefaultAllocator, RingSizes, Umem, UmemAllocator, UmemAllocatorFactory, XDPSocket};
+
+/// Owns an allocator and the sockets using its umem, keeping their fill and completion rings serviced
+///
+/// Completions are reaped before sending and fill rings are topped up after receiving, call [`Self::tick`] periodically
+/// to also service sockets which are idle in either direction. Sockets are referred to by the index returned when attaching
+pub struct UmemManager<'a, A: UmemAllocator + ?Sized = DefaultAllocator> {
+    umem: Arc<Umem>,
+    allocator: Arc<A>,
+    sockets: Vec<XDPSocket<'a>>,
+}
+impl<'a, A: UmemAllocatorFactory> UmemManager<'a, A> {
+    /// Manage `umem` with a new allocator
+    pub fn new(umem: Arc<Umem>) -> Self {
+        Self::with_allocator(umem.clone(), Arc::new(A::for_umem(umem)))
+    }
+}
+impl<'a, A: UmemAllocator + ?Sized> UmemManager<'a, A> {
+    /// Manage `umem` with `allocator`, which must be an allocator for it
+    pub fn with_allocator(umem: Arc<Umem>, allocator: Arc<A>) -> Self {
+        assert!(std::ptr::eq(allocator.umem_reference(), umem.as_ref()), "allocator does not belong to the umem");
+        Self { umem, allocator, sockets: Vec::new() }
+    }
+
+    /// The managed umem
+    pub const fn umem(&self) -> &Arc<Umem> {
+        &self.umem
+    }
+
+    /// The allocator of the managed umem
+    pub const fn allocator(&self) -> &Arc<A> {
+        &self.allocator
+    }
+
+    /// Create a socket over the managed umem, see [`XDPSocket::with_ring_sizes`], and attach it
+    pub fn create_socket(&mut self, interface_index: libc::c_uint, queue_id: libc::c_uint, ring_sizes: RingSizes) -> Result<usize, crate::Error> {
+        let socket = XDPSocket::with_ring_sizes(interface_index, queue_id, self.umem.clone(), ring_sizes)?;
+        Ok(self.attach(socket))
+    }
+
+    /// Take charge of `socket`, which must use the managed umem, filling its fill ring and returning its index
+    pub fn attach(&mut self, mut socket: XDPSocket<'a>) -> usize {
+        assert!(Arc::ptr_eq(&socket.umem, &self.umem), "socket does not use the managed umem");
+        socket.refill_fill_ring(self.allocator.as_ref(), usize::MAX);
+        self.sockets.push(socket);
+        self.sockets.len() - 1
+    }
+
+    /// The number of attached sockets
+    pub fn len(&self) -> usize {
+        self.sockets.len()
+    }
+
+    /// Whether no socket is attached
+    pub fn is_empty(&self) -> bool {
+        self.sockets.is_empty()
+    }
+
+    /// The socket at `index`, for anything not covered by the manager
+    ///
+    /// Chunks taken from its rings by hand must go back to [`Self::allocator`]
+    pub fn socket(&mut self, index: usize) -> &mut XDPSocket<'a> {
+        &mut self.sockets[index]
+    }
+
+    /// Receive every frame waiting on the socket at `index`, see [`XDPSocket::recv`], then top up its fill ring
+    pub fn recv(&mut self, index: usize, handler: impl FnMut(&[u8])) -> usize {
+        let socket = &mut self.sockets[index];
+        let count = socket.recv(self.allocator.as_ref(), handler);
+        socket.refill_fill_ring(self.allocator.as_ref(), usize::MAX);
+        count
+    }
+
+    /// Send `frame` on the socket at `index` after reaping its completions, see [`XDPSocket::send`]
+    pub fn send(&mut self, index: usize, frame: &[u8]) -> Result<bool, crate::Error> {
+        let socket = &mut self.sockets[index];
+        socket.reap_completions(self.allocator.as_ref());
+        socket.send(self.allocator.as_ref(), frame)
+    }
+
+    /// Reap the completions of every socket, then top up every fill ring
+    ///
+    /// Reaping comes first so that chunks freed by one socket can fill the rings of the others
+    pub fn tick(&mut self) {
+        for socket in self.sockets.iter_mut() {
+            socket.reap_completions(self.allocator.as_ref());
+        }
+        for socket in self.sockets.iter_mut() {
+            socket.refill_fill_ring(self.allocator.as_ref(), usize::MAX);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::UmemManager;
+    use crate::{testing::MockXDP, Umem, UmemAllocator};
+
+    #[test]
+    fn test_manager_services_rings() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let mut manager = UmemManager::<crate::DefaultAllocator>::new(umem.clone());
+        let (socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+        let index = manager.attach(socket);
+
+        // the fill ring is filled right away and kept full while receiving
+        assert_eq!(mock.fill_ring_len(), 15);
+        assert!(mock.inject(b"hello"));
+        assert_eq!(mock.fill_ring_len(), 14);
+        assert_eq!(manager.recv(index, |frame| assert_eq!(frame, b"hello")), 1);
+        assert_eq!(mock.fill_ring_len(), 15);
+
+        // transmitted chunks come back
+        let before = manager.allocator().num_available();
+        assert!(manager.send(index, b"outgoing").unwrap());
+        assert_eq!(mock.transmitted_frames(), [b"outgoing"]);
+        manager.tick();
+        assert_eq!(manager.allocator().num_available(), before);
+    }
+}
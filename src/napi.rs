@@ -0,0 +1,191 @@
+//! Which NAPI context, interrupt and CPUs serve a socket, to check that busy polling and pinning hit the right ones
+//!
+//! A socket learns the NAPI id of its queue with the first frame it receives, see [`crate::XDPSocket::napi_id`]. The
+//! queues and NAPI contexts of an interface come from the `netdev` generic netlink family, which needs Linux 6.9, and
+//! drivers without NAPI ids report none
+
+use crate::{genl, XDPSocket};
+
+// netdev family, see linux/netdev.h
+const NETDEV_CMD_QUEUE_GET: u8 = 10;
+const NETDEV_CMD_NAPI_GET: u8 = 11;
+const NETDEV_A_QUEUE_ID: u16 = 1;
+const NETDEV_A_QUEUE_IFINDEX: u16 = 2;
+const NETDEV_A_QUEUE_TYPE: u16 = 3;
+const NETDEV_A_QUEUE_NAPI_ID: u16 = 4;
+const NETDEV_A_NAPI_IFINDEX: u16 = 1;
+const NETDEV_A_NAPI_ID: u16 = 2;
+const NETDEV_A_NAPI_IRQ: u16 = 3;
+const NETDEV_A_NAPI_PID: u16 = 4;
+const NETDEV_QUEUE_TYPE_RX: u32 = 0;
+
+/// The direction of a queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueKind {
+    Rx,
+    Tx,
+}
+
+/// A queue of an interface and the NAPI context serving it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NapiQueue {
+    pub queue_id: u32,
+    pub kind: QueueKind,
+    /// `None` if the driver does not report it, e.g. while the interface is down
+    pub napi_id: Option<u32>,
+}
+
+/// A NAPI context, as the kernel reports it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NapiInfo {
+    pub napi_id: u32,
+    pub if_index: u32,
+    /// The interrupt scheduling the context
+    pub irq: Option<u32>,
+    /// The kernel thread running the context when threaded NAPI is enabled, softirqs run it otherwise
+    pub thread_pid: Option<u32>,
+}
+
+/// Where the frames of a socket are processed in the kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NapiPlacement {
+    /// The NAPI context the socket received from
+    pub napi_id: u32,
+    /// The NAPI context of the RX queue the socket is bound to
+    pub queue_napi_id: Option<u32>,
+    pub irq: Option<u32>,
+    /// The CPUs the interrupt may fire on, and so the softirqs run on, empty if unknown
+    pub cpus: Vec<usize>,
+}
+impl NapiPlacement {
+    /// Whether the socket receives from the NAPI context of its own queue, which is the one busy polling spins on
+    pub fn matches_queue(&self) -> bool {
+        self.queue_napi_id == Some(self.napi_id)
+    }
+}
+
+/// List the queues of the interface with index `if_index`
+///
+/// Fails with [`crate::Error::SocketReceiveFailure`] carrying `ENOENT` when the kernel lacks the `netdev` family
+pub fn queues(if_index: u32) -> Result<Vec<NapiQueue>, crate::Error> {
+    let family = genl::family("netdev")?;
+    let messages = genl::query(family, NETDEV_CMD_QUEUE_GET, true, &genl::attribute(NETDEV_A_QUEUE_IFINDEX, &if_index.to_ne_bytes()))?;
+    Ok(messages.iter().filter_map(|message| parse_queue(message)).collect())
+}
+
+/// The RX queue of the interface with index `if_index` served by the NAPI context `napi_id`
+pub fn rx_queue_for_napi(if_index: u32, napi_id: u32) -> Result<Option<u32>, crate::Error> {
+    Ok(queues(if_index)?.into_iter()
+        .find(|queue| queue.kind == QueueKind::Rx && queue.napi_id == Some(napi_id))
+        .map(|queue| queue.queue_id))
+}
+
+/// Describe the NAPI context `napi_id`
+pub fn napi(napi_id: u32) -> Result<NapiInfo, crate::Error> {
+    let family = genl::family("netdev")?;
+    let messages = genl::query(family, NETDEV_CMD_NAPI_GET, false, &genl::attribute(NETDEV_A_NAPI_ID, &napi_id.to_ne_bytes()))?;
+    messages.iter().find_map(|message| parse_napi(message))
+        .ok_or(crate::Error::SocketReceiveFailure { error: std::io::Error::from_raw_os_error(libc::ENOENT) })
+}
+
+/// The CPUs the interrupt `irq` may fire on, `None` if its affinity cannot be read
+pub fn irq_cpus(irq: u32) -> Option<Vec<usize>> {
+    let affinity = std::fs::read_to_string(format!("/proc/irq/{irq}/smp_affinity_list")).ok()?;
+    parse_cpu_list(affinity.trim())
+}
+
+/// Find out where the frames of `socket` are processed, `None` until it received a frame
+pub fn placement(socket: &XDPSocket) -> Result<Option<NapiPlacement>, crate::Error> {
+    let Some(napi_id) = socket.napi_id()? else {
+        return Ok(None);
+    };
+    let queue_napi_id = queues(socket.if_index)?.into_iter()
+        .find(|queue| queue.kind == QueueKind::Rx && queue.queue_id == socket.if_queue)
+        .and_then(|queue| queue.napi_id);
+    let irq = napi(napi_id)?.irq;
+    let cpus = irq.and_then(irq_cpus).unwrap_or_default();
+    Ok(Some(NapiPlacement { napi_id, queue_napi_id, irq, cpus }))
+}
+
+fn parse_queue(message: &[u8]) -> Option<NapiQueue> {
+    let (mut queue_id, mut kind, mut napi_id) = (None, None, None);
+    for (attribute, payload) in genl::attributes(message) {
+        let Some(value) = payload.get(..4).map(|value| u32::from_ne_bytes(value.try_into().unwrap())) else {
+            continue;
+        };
+        match attribute {
+            NETDEV_A_QUEUE_ID => queue_id = Some(value),
+            NETDEV_A_QUEUE_TYPE => kind = Some(if value == NETDEV_QUEUE_TYPE_RX { QueueKind::Rx } else { QueueKind::Tx }),
+            NETDEV_A_QUEUE_NAPI_ID => napi_id = Some(value),
+            _ => {},
+        }
+    }
+    Some(NapiQueue { queue_id: queue_id?, kind: kind?, napi_id })
+}
+
+fn parse_napi(message: &[u8]) -> Option<NapiInfo> {
+    let mut napi_id = None;
+    let mut info = NapiInfo::default();
+    for (attribute, payload) in genl::attributes(message) {
+        let Some(value) = payload.get(..4).map(|value| u32::from_ne_bytes(value.try_into().unwrap())) else {
+            continue;
+        };
+        match attribute {
+            NETDEV_A_NAPI_ID => napi_id = Some(value),
+            NETDEV_A_NAPI_IFINDEX => info.if_index = value,
+            NETDEV_A_NAPI_IRQ => info.irq = Some(value),
+            NETDEV_A_NAPI_PID => info.thread_pid = Some(value),
+            _ => {},
+        }
+    }
+    Some(NapiInfo { napi_id: napi_id?, ..info })
+}
+
+/// Parse a list of CPUs such as `0-3,8`
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.split(',').filter(|range| ! range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => cpus.extend(first.parse::<usize>().ok()?..=last.parse().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    Some(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_cpu_list, parse_napi, parse_queue, NapiInfo, NapiQueue, QueueKind, NETDEV_A_NAPI_ID, NETDEV_A_NAPI_IRQ, NETDEV_A_QUEUE_ID,
+        NETDEV_A_QUEUE_IFINDEX, NETDEV_A_QUEUE_NAPI_ID, NETDEV_A_QUEUE_TYPE,
+    };
+    use crate::genl::attribute;
+
+    #[test]
+    fn test_parse_netdev_messages() {
+        // rx queue 2 of interface 3 served by napi 8193
+        let rx = [
+            attribute(NETDEV_A_QUEUE_ID, &2_u32.to_ne_bytes()),
+            attribute(NETDEV_A_QUEUE_IFINDEX, &3_u32.to_ne_bytes()),
+            attribute(NETDEV_A_QUEUE_TYPE, &0_u32.to_ne_bytes()),
+            attribute(NETDEV_A_QUEUE_NAPI_ID, &8193_u32.to_ne_bytes()),
+        ].concat();
+        assert_eq!(parse_queue(&rx), Some(NapiQueue { queue_id: 2, kind: QueueKind::Rx, napi_id: Some(8193) }));
+
+        // tx queues may have no napi, queues without id are ignored
+        let tx = [attribute(NETDEV_A_QUEUE_ID, &0_u32.to_ne_bytes()), attribute(NETDEV_A_QUEUE_TYPE, &1_u32.to_ne_bytes())].concat();
+        assert_eq!(parse_queue(&tx), Some(NapiQueue { queue_id: 0, kind: QueueKind::Tx, napi_id: None }));
+        assert_eq!(parse_queue(&attribute(NETDEV_A_QUEUE_TYPE, &1_u32.to_ne_bytes())), None);
+
+        let napi = [attribute(NETDEV_A_NAPI_ID, &8193_u32.to_ne_bytes()), attribute(NETDEV_A_NAPI_IRQ, &42_u32.to_ne_bytes())].concat();
+        assert_eq!(parse_napi(&napi), Some(NapiInfo { napi_id: 8193, irq: Some(42), ..Default::default() }));
+    }
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0-3,8"), Some(vec![0, 1, 2, 3, 8]));
+        assert_eq!(parse_cpu_list("5"), Some(vec![5]));
+        assert_eq!(parse_cpu_list(""), Some(vec![]));
+        assert_eq!(parse_cpu_list("0-x"), None);
+    }
+}
@@ -0,0 +1,233 @@
+//! IPv6 neighbor discovery: the [`NeighborResponder`] pipeline stage answering solicitations for the addresses of the
+//! application, which the kernel does not know about since their traffic never reaches its stack
+
+use std::{collections::HashMap, net::Ipv6Addr};
+
+use crate::{
+    packet::{checksum, ethertype, ip_protocol, EthernetFrame, Ipv6Packet, MacAddress},
+    pipeline::{FrameCtx, Stage, Verdict},
+};
+
+// ICMPv6 message types and options, see RFC 4861
+const NEIGHBOR_SOLICITATION: u8 = 135;
+const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+const TARGET_LINK_LAYER_ADDRESS: u8 = 2;
+
+/// The size of a neighbor solicitation or advertisement without options
+const MESSAGE_SIZE: usize = 24;
+/// The size of a link-layer address option for ethernet
+const OPTION_SIZE: usize = 8;
+
+/// Advertisement flags
+const FLAG_ROUTER: u8 = 0x80;
+const FLAG_SOLICITED: u8 = 0x40;
+const FLAG_OVERRIDE: u8 = 0x20;
+
+/// Counters kept by a [`NeighborResponder`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NeighborResponderStats {
+    /// Solicitations turned into advertisements
+    pub answered: u64,
+    /// Solicitations for addresses which are not ours, left to the next stages
+    pub not_ours: u64,
+    /// Solicitations dropped for failing validation, e.g. a hop limit other than 255 or a bad checksum
+    pub invalid: u64,
+    /// Solicitations which could not be answered, i.e. duplicate address detection probes and multicast solicitations
+    /// without room for the target link-layer address option
+    pub unanswerable: u64,
+}
+
+/// A pipeline stage answering IPv6 neighbor solicitations for its addresses, so that peers can resolve them
+///
+/// Solicitations are validated as RFC 4861 asks and turned in place into solicited advertisements carrying the MAC
+/// address of the target, which are sent back where they came from. Without an advertisement, IPv6 peers never learn
+/// where to send their traffic. Duplicate address detection probes are not defended, the frames carrying them have no
+/// room for the answer. Anything else continues down the pipeline
+#[derive(Debug, Clone, Default)]
+pub struct NeighborResponder {
+    addresses: HashMap<Ipv6Addr, MacAddress>,
+    router: bool,
+    stats: NeighborResponderStats,
+}
+impl NeighborResponder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer for `address` with `mac`
+    pub fn with_address(mut self, address: Ipv6Addr, mac: MacAddress) -> Self {
+        self.add_address(address, mac);
+        self
+    }
+
+    /// Set the router flag of advertisements, telling peers the application routes packets
+    pub fn with_router_flag(mut self, router: bool) -> Self {
+        self.router = router;
+        self
+    }
+
+    /// Answer for `address` with `mac`, returning the MAC address it was answered with before
+    pub fn add_address(&mut self, address: Ipv6Addr, mac: MacAddress) -> Option<MacAddress> {
+        assert!(!address.is_multicast() && !address.is_unspecified(), "Only unicast addresses can be resolved");
+        self.addresses.insert(address, mac)
+    }
+
+    /// Stop answering for `address`, returning the MAC address it was answered with
+    pub fn remove_address(&mut self, address: Ipv6Addr) -> Option<MacAddress> {
+        self.addresses.remove(&address)
+    }
+
+    pub const fn stats(&self) -> &NeighborResponderStats {
+        &self.stats
+    }
+
+    /// Answer `frame` if it is a neighbor solicitation for one of our addresses, see [`Stage::process`]
+    pub fn respond(&mut self, frame: &mut [u8]) -> Verdict {
+        let Some(ethernet) = EthernetFrame::new_checked(&*frame) else {
+            return Verdict::Continue;
+        };
+        if ethernet.inner_ethertype() != ethertype::IPV6 {
+            return Verdict::Continue;
+        }
+        let (network_offset, requester_mac) = (ethernet.payload_offset(), ethernet.source());
+        let Some(ipv6) = Ipv6Packet::new_checked(&frame[network_offset..]) else {
+            return Verdict::Continue;
+        };
+        let message = ipv6.payload();
+        if ipv6.next_header() != ip_protocol::ICMPV6 || message.first() != Some(&NEIGHBOR_SOLICITATION) {
+            return Verdict::Continue;
+        }
+
+        // validate
+        let (source, destination) = (ipv6.source(), ipv6.destination());
+        let pseudo_header = checksum::ipv6_pseudo_header(source, destination, ip_protocol::ICMPV6, message.len());
+        if ipv6.hop_limit() != 255 || message.len() < MESSAGE_SIZE || message[1] != 0
+            || checksum::transport(pseudo_header, message, 2) != u16::from_be_bytes([message[2], message[3]]) {
+            self.stats.invalid += 1;
+            return Verdict::Drop;
+        }
+        let target = Ipv6Addr::from(<[u8; 16]>::try_from(&message[8..24]).unwrap());
+        if target.is_multicast() {
+            self.stats.invalid += 1;
+            return Verdict::Drop;
+        }
+        let Some(&target_mac) = self.addresses.get(&target) else {
+            self.stats.not_ours += 1;
+            return Verdict::Continue;
+        };
+
+        // the target option takes the room of the source one, a unicast solicitation may do without
+        let has_option = message.len() >= MESSAGE_SIZE + OPTION_SIZE;
+        if source.is_unspecified() || (!has_option && destination.is_multicast()) {
+            self.stats.unanswerable += 1;
+            return Verdict::Drop;
+        }
+        // longer solicitations leave trailing bytes, past the payload length like link layer padding
+        let reply_len = if has_option { MESSAGE_SIZE + OPTION_SIZE } else { MESSAGE_SIZE };
+
+        // answer in place, from the target to the solicitor
+        let mut ethernet = EthernetFrame::new_checked(&mut *frame).expect("the frame was already checked");
+        ethernet.set_destination(requester_mac);
+        ethernet.set_source(target_mac);
+        let network = &mut frame[network_offset..];
+        network[0..4].copy_from_slice(&0x6000_0000_u32.to_be_bytes());
+        network[4..6].copy_from_slice(&(reply_len as u16).to_be_bytes());
+        let mut ipv6 = Ipv6Packet::new_checked(&mut *network).expect("the packet was already checked");
+        ipv6.set_hop_limit(255);
+        ipv6.set_source(target);
+        ipv6.set_destination(source);
+        let message = &mut ipv6.payload_mut()[..reply_len];
+        message[0] = NEIGHBOR_ADVERTISEMENT;
+        message[4..8].copy_from_slice(&[FLAG_SOLICITED | FLAG_OVERRIDE | if self.router { FLAG_ROUTER } else { 0 }, 0, 0, 0]);
+        if has_option {
+            message[MESSAGE_SIZE] = TARGET_LINK_LAYER_ADDRESS;
+            message[MESSAGE_SIZE + 1] = 1;
+            message[MESSAGE_SIZE + 2..MESSAGE_SIZE + OPTION_SIZE].copy_from_slice(&target_mac.0);
+        }
+        let pseudo_header = checksum::ipv6_pseudo_header(target, source, ip_protocol::ICMPV6, reply_len);
+        let message_checksum = checksum::transport(pseudo_header, message, 2);
+        message[2..4].copy_from_slice(&message_checksum.to_be_bytes());
+        self.stats.answered += 1;
+        Verdict::Transmit
+    }
+}
+impl Stage for NeighborResponder {
+    fn process(&mut self, ctx: &mut FrameCtx) -> Verdict {
+        self.respond(ctx.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::{NeighborResponder, NeighborResponderStats};
+    use crate::{packet::{checksum, ethertype, ip_protocol, FrameBuilder, Ipv6Packet, MacAddress}, pipeline::Verdict};
+
+    const PEER_MAC: MacAddress = MacAddress([2, 0, 0, 0, 0, 2]);
+    const LOCAL_MAC: MacAddress = MacAddress([2, 0, 0, 0, 0, 1]);
+
+    /// A neighbor solicitation for `target` from `source` to `destination`, with the source link-layer address option
+    fn solicitation(source: Ipv6Addr, destination: Ipv6Addr, target: Ipv6Addr, option: bool) -> Vec<u8> {
+        let mut message = vec![135, 0, 0, 0, 0, 0, 0, 0];
+        message.extend_from_slice(&target.octets());
+        if option {
+            message.extend_from_slice(&[1, 1]);
+            message.extend_from_slice(&PEER_MAC.0);
+        }
+        let mut frame = vec![0_u8; 128];
+        let len = FrameBuilder::ethernet(PEER_MAC, MacAddress([0x33, 0x33, 0xFF, 0, 0, 1]))
+            .ipv6(source, destination)
+            .ttl(255)
+            .write_with_payload(&mut frame, &message)
+            .unwrap();
+        frame.truncate(len);
+        frame[20] = ip_protocol::ICMPV6;
+        let pseudo_header = checksum::ipv6_pseudo_header(source, destination, ip_protocol::ICMPV6, message.len());
+        let message_checksum = checksum::transport(pseudo_header, &frame[54..], 2);
+        frame[56..58].copy_from_slice(&message_checksum.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn test_answers_solicitations() {
+        let local: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let peer: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let solicited_node: Ipv6Addr = "ff02::1:ff00:1".parse().unwrap();
+        let mut responder = NeighborResponder::new().with_address(local, LOCAL_MAC);
+
+        // multicast solicitation
+        let mut frame = solicitation(peer, solicited_node, local, true);
+        assert_eq!(responder.respond(&mut frame), Verdict::Transmit);
+        assert_eq!(frame[0..12], [PEER_MAC.0, LOCAL_MAC.0].concat());
+        let ipv6 = Ipv6Packet::new_checked(&frame[14..]).unwrap();
+        assert_eq!((ipv6.source(), ipv6.destination(), ipv6.hop_limit()), (local, peer, 255));
+        let message = ipv6.payload();
+        assert_eq!(message[0], 136);
+        assert_eq!(message[4], 0x60);
+        assert_eq!(message[8..24], local.octets());
+        assert_eq!(message[24..32], [&[2, 1][..], &LOCAL_MAC.0].concat());
+        let pseudo_header = checksum::ipv6_pseudo_header(local, peer, ip_protocol::ICMPV6, message.len());
+        assert_eq!(checksum::transport(pseudo_header, message, 2), u16::from_be_bytes([message[2], message[3]]));
+
+        // unicast solicitation without option, as sent to confirm reachability
+        let mut frame = solicitation(peer, local, local, false);
+        assert_eq!(responder.respond(&mut frame), Verdict::Transmit);
+        assert_eq!(Ipv6Packet::new_checked(&frame[14..]).unwrap().payload().len(), 24);
+
+        // someone else's, duplicate address detection, corrupted, not a solicitation
+        let mut frame = solicitation(peer, solicited_node, peer, true);
+        assert_eq!(responder.respond(&mut frame), Verdict::Continue);
+        let mut frame = solicitation(Ipv6Addr::UNSPECIFIED, solicited_node, local, false);
+        assert_eq!(responder.respond(&mut frame), Verdict::Drop);
+        let mut frame = solicitation(peer, solicited_node, local, true);
+        frame[60] ^= 0xFF;
+        assert_eq!(responder.respond(&mut frame), Verdict::Drop);
+        let mut frame = [0_u8; 64];
+        let len = FrameBuilder::ethernet(PEER_MAC, LOCAL_MAC).ethertype(ethertype::ARP).write_with_payload(&mut frame, &[0; 28]).unwrap();
+        assert_eq!(responder.respond(&mut frame[..len]), Verdict::Continue);
+
+        assert_eq!(*responder.stats(), NeighborResponderStats { answered: 2, not_ours: 1, invalid: 1, unanswerable: 1 });
+        assert_eq!(responder.remove_address(local), Some(LOCAL_MAC));
+    }
+}
@@ -0,0 +1,310 @@
+//! Earliest departure time pacing: every frame is given the time it should leave at, and is held until then
+//!
+//! NICs with launch time offload (e.g. igc, stmmac) hold frames themselves, precisely and without burning a core. Others
+//! need them held in software, by spinning on the clock or sleeping on a timerfd first. [`DeparturePacer::for_socket`]
+//! picks the hardware when it is there and falls back to software otherwise, so paced traffic works on any NIC
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    time::{Duration, Instant},
+};
+
+use crate::{genl, phc::SystemClock, Frame, XDPSocket};
+
+/// The TX metadata needed in front of frames to request a launch time, see [`crate::TxBatch::push_with_launch_time`]
+pub const LAUNCH_TIME_METADATA_LEN: u32 = 24;
+
+/// How close to the departure time [`PacingMode::fallback`] stops sleeping and starts spinning
+const FALLBACK_SPIN: Duration = Duration::from_micros(50);
+
+// netdev family, see linux/netdev.h
+const NETDEV_CMD_DEV_GET: u8 = 1;
+const NETDEV_A_DEV_IFINDEX: u16 = 1;
+const NETDEV_A_DEV_XSK_FEATURES: u16 = 6;
+const NETDEV_XSK_FLAGS_TX_LAUNCH_TIME_FIFO: u64 = 1 << 2;
+
+/// How frames are held until their departure time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacingMode {
+    /// Spin on the clock, precise to a few hundred nanoseconds at the cost of a core
+    Spin,
+    /// Sleep on a timerfd until `spin` before the departure time, then spin
+    Timer { spin: Duration },
+    /// Hand frames to the NIC right away along with their launch time, see [`crate::TxBatch::push_with_launch_time`]
+    LaunchTime,
+}
+impl PacingMode {
+    /// The software mode used when launch time offload is unavailable
+    pub const fn fallback() -> Self {
+        Self::Timer { spin: FALLBACK_SPIN }
+    }
+}
+
+/// Whether AF_XDP sockets on the interface with index `if_index` can request launch times, which needs Linux 6.15
+///
+/// Fails with [`crate::Error::SocketReceiveFailure`] carrying `ENOENT` when the kernel lacks the `netdev` family
+pub fn launch_time_supported(if_index: u32) -> Result<bool, crate::Error> {
+    let family = genl::family("netdev")?;
+    let messages = genl::query(family, NETDEV_CMD_DEV_GET, false, &genl::attribute(NETDEV_A_DEV_IFINDEX, &if_index.to_ne_bytes()))?;
+    Ok(messages.iter()
+        .flat_map(|message| genl::attributes(message))
+        .filter(|(attribute, _)| *attribute == NETDEV_A_DEV_XSK_FEATURES)
+        .find_map(|(_, payload)| genl::uint(payload))
+        .is_some_and(|features| features & NETDEV_XSK_FLAGS_TX_LAUNCH_TIME_FIFO != 0))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Departure {
+    at: Instant,
+    /// Keeps frames departing at the same time in order
+    sequence: u64,
+    addr: u64,
+    len: u32,
+}
+
+/// Frames waiting for their departure time, the earliest first
+///
+/// Frames must already be written into their chunks, which go back to the allocator through the completion ring once
+/// transmitted as usual
+#[derive(Debug)]
+pub struct DeparturePacer {
+    mode: PacingMode,
+    queue: BinaryHeap<Reverse<Departure>>,
+    sequence: u64,
+    timer: Option<OwnedFd>,
+}
+impl DeparturePacer {
+    /// Create a pacer holding frames as `mode` says, which does not check that launch times are supported
+    pub fn new(mode: PacingMode) -> Result<Self, crate::Error> {
+        let timer = match mode {
+            PacingMode::Timer { .. } => {
+                let fd = unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_CLOEXEC) };
+                if fd < 0 {
+                    return Err(crate::Error::ClockFailure { error: std::io::Error::last_os_error() });
+                }
+                Some(unsafe { OwnedFd::from_raw_fd(fd) })
+            },
+            PacingMode::Spin | PacingMode::LaunchTime => None,
+        };
+        Ok(Self { mode, queue: BinaryHeap::new(), sequence: 0, timer })
+    }
+
+    /// Create a pacer for `socket`, falling back from [`PacingMode::LaunchTime`] to [`PacingMode::fallback`] unless the
+    /// NIC supports it and the umem has room for the TX metadata
+    pub fn for_socket(socket: &XDPSocket, mode: PacingMode) -> Result<Self, crate::Error> {
+        let mode = match mode {
+            PacingMode::LaunchTime if socket.umem.tx_metadata_len() < LAUNCH_TIME_METADATA_LEN => PacingMode::fallback(),
+            PacingMode::LaunchTime if ! launch_time_supported(socket.if_index).unwrap_or(false) => PacingMode::fallback(),
+            mode => mode,
+        };
+        Self::new(mode)
+    }
+
+    pub const fn mode(&self) -> PacingMode {
+        self.mode
+    }
+
+    /// The number of frames waiting
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Whether no frame is waiting
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// When the earliest frame departs
+    pub fn next_departure(&self) -> Option<Instant> {
+        self.queue.peek().map(|Reverse(departure)| departure.at)
+    }
+
+    /// A timerfd becoming readable shortly before the earliest departure, to wait along with other descriptors
+    ///
+    /// Only [`PacingMode::Timer`] has one. It is rearmed by [`Self::push`] and [`Self::transmit`]
+    pub fn timer_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.timer.as_ref().map(|timer| timer.as_fd())
+    }
+
+    /// Hold `frame` until `departure`
+    ///
+    /// With [`PacingMode::LaunchTime`], the frame needs room for the TX metadata in front of it
+    pub fn push(&mut self, frame: Frame, departure: Instant) -> Result<(), crate::Error> {
+        let earliest = self.next_departure().is_none_or(|earliest| departure < earliest);
+        self.queue.push(Reverse(Departure { at: departure, sequence: self.sequence, addr: frame.addr, len: frame.len }));
+        self.sequence += 1;
+        if earliest {
+            self.arm()?;
+        }
+        Ok(())
+    }
+
+    /// Block until the earliest frame is due, right away if there is none or the NIC holds frames itself
+    pub fn wait(&self) -> Result<(), crate::Error> {
+        let Some(departure) = self.next_departure() else {
+            return Ok(());
+        };
+        match self.mode {
+            PacingMode::LaunchTime => return Ok(()),
+            PacingMode::Spin => {},
+            PacingMode::Timer { spin } => if departure.saturating_duration_since(Instant::now()) > spin {
+                self.arm()?;
+                self.read_timer()?;
+            },
+        }
+        while Instant::now() < departure {
+            std::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Enqueue the frames which are due on the TX ring of `socket`, every frame with [`PacingMode::LaunchTime`], returning
+    /// how many were enqueued
+    ///
+    /// Frames which do not fit on the TX ring stay for the next call. With [`PacingMode::LaunchTime`], fails like
+    /// [`crate::TxBatch::push_with_launch_time`] on the earliest frame without room for the TX metadata, which stays
+    /// queued until taken out with [`Self::drain`]
+    pub fn transmit(&mut self, socket: &mut XDPSocket) -> Result<usize, crate::Error> {
+        let now = Instant::now();
+        let tai_now = match self.mode {
+            PacingMode::LaunchTime => SystemClock::Tai.now()?,
+            PacingMode::Spin | PacingMode::Timer { .. } => 0,
+        };
+        self.transmit_at(socket, now, tai_now)
+    }
+
+    fn transmit_at(&mut self, socket: &mut XDPSocket, now: Instant, tai_now: u64) -> Result<usize, crate::Error> {
+        let mut batch = socket.tx_batch();
+        while let Some(Reverse(departure)) = self.queue.peek().copied() {
+            let frame = Frame { addr: departure.addr, len: departure.len };
+            let pushed = match self.mode {
+                PacingMode::LaunchTime => {
+                    let launch_time_ns = if departure.at > now {
+                        tai_now + (departure.at - now).as_nanos() as u64
+                    } else {
+                        tai_now.saturating_sub((now - departure.at).as_nanos() as u64)
+                    };
+                    batch.push_with_launch_time(frame, launch_time_ns)?
+                },
+                PacingMode::Spin | PacingMode::Timer { .. } if departure.at > now => break,
+                PacingMode::Spin | PacingMode::Timer { .. } => batch.push(frame),
+            };
+            if ! pushed {
+                break;
+            }
+            self.queue.pop();
+        }
+        let count = batch.commit()?;
+        self.arm()?;
+        Ok(count)
+    }
+
+    /// Take every waiting frame out, earliest first, e.g. to give their chunks back to the allocator
+    pub fn drain(&mut self) -> impl Iterator<Item = Frame> + '_ {
+        std::iter::from_fn(|| self.queue.pop().map(|Reverse(departure)| Frame { addr: departure.addr, len: departure.len }))
+    }
+
+    /// Arm the timer for the earliest departure, disarming it if there is none
+    fn arm(&self) -> Result<(), crate::Error> {
+        let (Some(timer), PacingMode::Timer { spin }) = (&self.timer, self.mode) else {
+            return Ok(());
+        };
+        let mut value: libc::itimerspec = unsafe { std::mem::zeroed() };
+        if let Some(departure) = self.next_departure() {
+            // a zero value disarms the timer, so due frames fire it right away instead
+            let remaining = departure.saturating_duration_since(Instant::now()).saturating_sub(spin).max(Duration::from_nanos(1));
+            value.it_value.tv_sec = remaining.as_secs() as _;
+            value.it_value.tv_nsec = remaining.subsec_nanos() as _;
+        }
+        if unsafe { libc::timerfd_settime(timer.as_raw_fd(), 0, &value, std::ptr::null_mut()) } < 0 {
+            return Err(crate::Error::ClockFailure { error: std::io::Error::last_os_error() });
+        }
+        Ok(())
+    }
+
+    /// Block until the timer fires
+    fn read_timer(&self) -> Result<(), crate::Error> {
+        let Some(timer) = &self.timer else {
+            return Ok(());
+        };
+        let mut expirations = 0_u64;
+        loop {
+            if unsafe { libc::read(timer.as_raw_fd(), (&mut expirations as *mut u64).cast(), 8) } == 8 {
+                return Ok(());
+            }
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                return Err(crate::Error::ClockFailure { error });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::{Duration, Instant}};
+
+    use super::{DeparturePacer, PacingMode};
+    use crate::{testing::MockXDP, Frame, Umem};
+
+    #[test]
+    fn test_departure_order() {
+        let umem = Arc::new(Umem::new_2k(16).unwrap());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 8).unwrap();
+        let mut pacer = DeparturePacer::new(PacingMode::Spin).unwrap();
+        let start = Instant::now();
+        for (index, (frame, delay)) in [(b"third", 30), (b"first", 10), (b"fourt", 30), (b"secnd", 20)].into_iter().enumerate() {
+            umem.chunk(index)[..5].copy_from_slice(frame);
+            pacer.push(Frame { addr: umem.chunk_start_offset_for_index(index), len: 5 }, start + Duration::from_millis(delay)).unwrap();
+        }
+        assert_eq!(pacer.next_departure(), Some(start + Duration::from_millis(10)));
+
+        // only due frames leave, ties in order
+        assert_eq!(pacer.transmit_at(&mut socket, start, 0).unwrap(), 0);
+        assert_eq!(pacer.transmit_at(&mut socket, start + Duration::from_millis(20), 0).unwrap(), 2);
+        assert_eq!(mock.transmitted_frames(), [b"first", b"secnd"]);
+        assert_eq!(pacer.transmit_at(&mut socket, start + Duration::from_millis(40), 0).unwrap(), 2);
+        assert_eq!(mock.transmitted_frames(), [b"third", b"fourt"]);
+        assert!(pacer.is_empty());
+    }
+
+    #[test]
+    fn test_software_wait() {
+        for mode in [PacingMode::Spin, PacingMode::Timer { spin: Duration::from_micros(100) }] {
+            let mut pacer = DeparturePacer::new(mode).unwrap();
+            assert_eq!(pacer.timer_fd().is_some(), mode != PacingMode::Spin);
+            pacer.wait().unwrap();
+            let departure = Instant::now() + Duration::from_millis(2);
+            pacer.push(Frame { addr: 0, len: 64 }, departure).unwrap();
+            pacer.wait().unwrap();
+            assert!(Instant::now() >= departure);
+            assert_eq!(pacer.drain().count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_launch_time() {
+        let umem = Arc::new(Umem::new_2k(16).unwrap().with_tx_metadata_len(24));
+        let (mut socket, _mock) = MockXDP::new(umem.clone(), 8).unwrap();
+        let mut pacer = DeparturePacer::new(PacingMode::LaunchTime).unwrap();
+
+        // frames leave right away with their launch time in front of them
+        let now = Instant::now();
+        pacer.push(Frame { addr: 64, len: 60 }, now + Duration::from_micros(5)).unwrap();
+        pacer.wait().unwrap();
+        assert_eq!(pacer.transmit_at(&mut socket, now, 1_000_000).unwrap(), 1);
+        let descriptor = *socket.tx_ring.get_nth_descriptor(0);
+        assert_eq!((descriptor.addr, descriptor.options), (64, libc::XDP_TX_METADATA));
+        let chunk = umem.chunk(0);
+        assert_eq!(u64::from_ne_bytes(chunk[40..48].try_into().unwrap()), 1 << 2);
+        assert_eq!(u64::from_ne_bytes(chunk[56..64].try_into().unwrap()), 1_005_000);
+        drop(chunk);
+
+        // frames without room for the metadata are refused
+        pacer.push(Frame { addr: 2048 + 16, len: 60 }, now).unwrap();
+        assert!(matches!(pacer.transmit_at(&mut socket, now, 1_000_000), Err(crate::Error::BufferTooSmall { required: 24, available: 16 })));
+        assert_eq!(pacer.drain().count(), 1);
+    }
+}
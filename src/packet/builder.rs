@@ -0,0 +1,242 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use super::{checksum, ethertype, ip_protocol, write_u16, MacAddress, VlanTag};
+
+#[derive(Debug, Clone, Copy)]
+enum NetworkLayer {
+    Ipv4 { source: Ipv4Addr, destination: Ipv4Addr },
+    Ipv6 { source: Ipv6Addr, destination: Ipv6Addr },
+}
+
+/// Writes Ethernet/VLAN/IP/UDP headers in place, in front of a payload
+///
+/// The builder only describes headers, the same one can be used to write any number of frames.
+/// Paired with [`crate::XDPSocket::send_with`], frames are assembled straight into umem chunks:
+/// 
+/// ```ignore
+/// socket.send_with(&allocator, headroom, |chunk| builder.write_with_payload(chunk, b"hello"))?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct FrameBuilder {
+    source: MacAddress,
+    destination: MacAddress,
+    vlans: [Option<VlanTag>; 2],
+    ethertype: u16,
+    network: Option<NetworkLayer>,
+    ttl: u8,
+    dscp: u8,
+    udp: Option<(u16, u16)>,
+    udp_checksum: bool,
+}
+impl FrameBuilder {
+    /// Start a frame from `source` to `destination`, carrying a raw ethernet payload
+    pub fn ethernet(source: MacAddress, destination: MacAddress) -> Self {
+        Self {
+            source,
+            destination,
+            vlans: [None; 2],
+            ethertype: 0,
+            network: None,
+            ttl: 64,
+            dscp: 0,
+            udp: None,
+            udp_checksum: true,
+        }
+    }
+
+    /// Set the ethertype of a raw ethernet payload, overridden by IP layers
+    pub fn ethertype(mut self, value: u16) -> Self {
+        self.ethertype = value;
+        self
+    }
+
+    /// Add a VLAN tag, after those already present (at most two)
+    pub fn vlan(mut self, tag: VlanTag) -> Self {
+        let slot = self.vlans.iter_mut().find(|slot| slot.is_none()).expect("At most two VLAN tags are supported");
+        *slot = Some(tag);
+        self
+    }
+
+    /// Add an IPv4 header
+    pub fn ipv4(mut self, source: Ipv4Addr, destination: Ipv4Addr) -> Self {
+        self.network = Some(NetworkLayer::Ipv4 { source, destination });
+        self
+    }
+
+    /// Add an IPv6 header
+    pub fn ipv6(mut self, source: Ipv6Addr, destination: Ipv6Addr) -> Self {
+        self.network = Some(NetworkLayer::Ipv6 { source, destination });
+        self
+    }
+
+    /// Set the TTL / hop limit of the IP header
+    pub fn ttl(mut self, value: u8) -> Self {
+        self.ttl = value;
+        self
+    }
+
+    /// Set the DSCP of the IP header
+    pub fn dscp(mut self, value: u8) -> Self {
+        self.dscp = value & 0x3F;
+        self
+    }
+
+    /// Add a UDP header, requires an IP header
+    pub fn udp(mut self, source_port: u16, destination_port: u16) -> Self {
+        self.udp = Some((source_port, destination_port));
+        self
+    }
+
+    /// Whether to compute the UDP checksum, enabled by default (it can only be omitted over IPv4)
+    pub fn udp_checksum(mut self, enabled: bool) -> Self {
+        self.udp_checksum = enabled;
+        self
+    }
+
+    /// The size of the ethernet header and VLAN tags, i.e. the part of frames not counted in the MTU
+    pub fn link_header_len(&self) -> usize {
+        14 + self.vlans.iter().flatten().count() * 4
+    }
+
+    /// The size of all the headers, i.e. the offset at which the payload starts
+    pub fn header_len(&self) -> usize {
+        let network = match self.network {
+            Some(NetworkLayer::Ipv4 { .. }) => 20,
+            Some(NetworkLayer::Ipv6 { .. }) => 40,
+            None => 0,
+        };
+        let udp = if self.udp.is_some() { 8 } else { 0 };
+        self.link_header_len() + network + udp
+    }
+
+    /// The region of `buffer` where the payload goes
+    pub fn payload_mut<'b>(&self, buffer: &'b mut [u8]) -> &'b mut [u8] {
+        &mut buffer[self.header_len()..]
+    }
+
+    /// Copy `payload` after the headers and write the headers, returning the frame length
+    pub fn write_with_payload(&self, buffer: &mut [u8], payload: &[u8]) -> Result<usize, crate::Error> {
+        let header_len = self.header_len();
+        let required = header_len + payload.len();
+        if buffer.len() < required {
+            return Err(crate::Error::BufferTooSmall { required, available: buffer.len() });
+        }
+        buffer[header_len..required].copy_from_slice(payload);
+        self.write(buffer, payload.len())
+    }
+
+    /// Write the headers in front of the `payload_len` bytes already found in [`Self::payload_mut`], returning the frame length
+    pub fn write(&self, buffer: &mut [u8], payload_len: usize) -> Result<usize, crate::Error> {
+        assert!(self.udp.is_none() || self.network.is_some(), "UDP requires an IP header");
+        let header_len = self.header_len();
+        let frame_len = header_len + payload_len;
+        if buffer.len() < frame_len {
+            return Err(crate::Error::BufferTooSmall { required: frame_len, available: buffer.len() });
+        }
+        let frame = &mut buffer[..frame_len];
+
+        // ethernet
+        frame[0..6].copy_from_slice(&self.destination.0);
+        frame[6..12].copy_from_slice(&self.source.0);
+        let mut offset = 12;
+        for tag in self.vlans.iter().flatten() {
+            write_u16(frame, offset, tag.tpid);
+            write_u16(frame, offset + 2, tag.tci());
+            offset += 4;
+        }
+        let ethertype = match self.network {
+            Some(NetworkLayer::Ipv4 { .. }) => ethertype::IPV4,
+            Some(NetworkLayer::Ipv6 { .. }) => ethertype::IPV6,
+            None => self.ethertype,
+        };
+        write_u16(frame, offset, ethertype);
+        offset += 2;
+
+        // network
+        let protocol = if self.udp.is_some() { ip_protocol::UDP } else { 0 };
+        let transport_len = frame_len - offset - match self.network {
+            Some(NetworkLayer::Ipv4 { .. }) => 20,
+            Some(NetworkLayer::Ipv6 { .. }) => 40,
+            None => 0,
+        };
+        let pseudo_header = match self.network {
+            Some(NetworkLayer::Ipv4 { source, destination }) => {
+                let ip = &mut frame[offset..offset + 20];
+                ip[0] = 0x45;
+                ip[1] = self.dscp << 2;
+                write_u16(ip, 2, (20 + transport_len) as u16);
+                write_u16(ip, 4, 0);
+                write_u16(ip, 6, 0x4000);
+                ip[8] = self.ttl;
+                ip[9] = protocol;
+                ip[12..16].copy_from_slice(&source.octets());
+                ip[16..20].copy_from_slice(&destination.octets());
+                let header_checksum = checksum::ipv4_header(ip);
+                write_u16(ip, 10, header_checksum);
+                offset += 20;
+                Some(checksum::ipv4_pseudo_header(source, destination, protocol, transport_len))
+            },
+            Some(NetworkLayer::Ipv6 { source, destination }) => {
+                let ip = &mut frame[offset..offset + 40];
+                ip[0..4].copy_from_slice(&(0x6000_0000_u32 | ((self.dscp as u32) << 22)).to_be_bytes());
+                write_u16(ip, 4, transport_len as u16);
+                ip[6] = protocol;
+                ip[7] = self.ttl;
+                ip[8..24].copy_from_slice(&source.octets());
+                ip[24..40].copy_from_slice(&destination.octets());
+                offset += 40;
+                Some(checksum::ipv6_pseudo_header(source, destination, protocol, transport_len))
+            },
+            None => None,
+        };
+
+        // transport
+        if let (Some((source_port, destination_port)), Some(pseudo_header)) = (self.udp, pseudo_header) {
+            let udp = &mut frame[offset..];
+            write_u16(udp, 0, source_port);
+            write_u16(udp, 2, destination_port);
+            write_u16(udp, 4, transport_len as u16);
+            let is_ipv6 = matches!(self.network, Some(NetworkLayer::Ipv6 { .. }));
+            let udp_checksum = if self.udp_checksum || is_ipv6 {
+                match checksum::transport(pseudo_header, udp, 6) {
+                    0 => 0xFFFF,
+                    x => x,
+                }
+            } else {
+                0
+            };
+            write_u16(udp, 6, udp_checksum);
+        }
+
+        Ok(frame_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::FrameBuilder;
+    use crate::packet::{checksum, ethertype, parse, MacAddress, NetworkHeader, TransportHeader, VlanTag};
+
+    #[test]
+    fn test_build_and_parse() {
+        let builder = FrameBuilder::ethernet(MacAddress([2, 0, 0, 0, 0, 1]), MacAddress::BROADCAST)
+            .vlan(VlanTag { tpid: ethertype::VLAN, pcp: 0, dei: false, vid: 42 })
+            .ipv6(Ipv6Addr::LOCALHOST, Ipv6Addr::LOCALHOST)
+            .udp(1234, 5678);
+        let mut chunk = [0_u8; 2048];
+        let len = builder.write_with_payload(&mut chunk[256..], b"hello world").unwrap();
+        assert_eq!(len, 14 + 4 + 40 + 8 + 11);
+
+        let parsed = parse(&chunk[256..256 + len]).unwrap();
+        assert_eq!(parsed.ethernet.vlan_tags().next().unwrap().vid, 42);
+        let Some(NetworkHeader::Ipv6(ipv6)) = parsed.network else { panic!("not ipv6") };
+        let Some(TransportHeader::Udp(udp)) = parsed.transport else { panic!("not udp") };
+        assert_eq!(udp.payload(), b"hello world");
+        assert_eq!(checksum::udp_ipv6(ipv6.source(), ipv6.destination(), ipv6.payload()), udp.checksum());
+
+        // too small
+        assert!(builder.write_with_payload(&mut chunk[..70], b"hello world").is_err());
+    }
+}
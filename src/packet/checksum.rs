@@ -0,0 +1,129 @@
+//! Internet checksum helpers operating directly on frame bytes
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Accumulate the 16-bit one's complement sum of `data` into `sum`, padding odd lengths with a zero byte
+pub fn accumulate(mut sum: u32, data: &[u8]) -> u32 {
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum = sum.wrapping_add(u16::from_be_bytes([word[0], word[1]]) as u32);
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    if let [last] = words.remainder() {
+        sum = sum.wrapping_add((*last as u32) << 8);
+    }
+    (sum & 0xFFFF) + (sum >> 16)
+}
+
+/// Fold an accumulated sum into the final checksum
+pub fn finish(mut sum: u32) -> u16 {
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    ! (sum as u16)
+}
+
+/// The checksum of an IPv4 header, options included, ignoring the current content of its checksum field
+pub fn ipv4_header(header: &[u8]) -> u16 {
+    let sum = accumulate(0, &header[..10]);
+    finish(accumulate(sum, &header[12..]))
+}
+
+/// The partial sum of the IPv4 pseudo header, for `length` bytes of `protocol`
+pub fn ipv4_pseudo_header(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8, length: usize) -> u32 {
+    let sum = accumulate(0, &source.octets());
+    let sum = accumulate(sum, &destination.octets());
+    accumulate(sum, &[0, protocol, (length >> 8) as u8, length as u8])
+}
+
+/// The partial sum of the IPv6 pseudo header, for `length` bytes of `next_header`
+pub fn ipv6_pseudo_header(source: Ipv6Addr, destination: Ipv6Addr, next_header: u8, length: usize) -> u32 {
+    let sum = accumulate(0, &source.octets());
+    let sum = accumulate(sum, &destination.octets());
+    accumulate(sum, &(length as u32).to_be_bytes()).wrapping_add(next_header as u32)
+}
+
+/// The checksum of a TCP or UDP segment given the partial sum of its pseudo header,
+/// ignoring the current content of the checksum field found at `checksum_offset`
+pub fn transport(pseudo_header: u32, segment: &[u8], checksum_offset: usize) -> u16 {
+    let sum = accumulate(pseudo_header, &segment[..checksum_offset]);
+    finish(accumulate(sum, &segment[checksum_offset + 2..]))
+}
+
+/// The checksum of a UDP datagram carried over IPv4, zero being transmitted as all ones
+pub fn udp_ipv4(source: Ipv4Addr, destination: Ipv4Addr, datagram: &[u8]) -> u16 {
+    let checksum = transport(ipv4_pseudo_header(source, destination, super::ip_protocol::UDP, datagram.len()), datagram, 6);
+    if checksum == 0 { 0xFFFF } else { checksum }
+}
+
+/// The checksum of a UDP datagram carried over IPv6, zero being transmitted as all ones
+pub fn udp_ipv6(source: Ipv6Addr, destination: Ipv6Addr, datagram: &[u8]) -> u16 {
+    let checksum = transport(ipv6_pseudo_header(source, destination, super::ip_protocol::UDP, datagram.len()), datagram, 6);
+    if checksum == 0 { 0xFFFF } else { checksum }
+}
+
+/// The checksum of a TCP segment carried over IPv4
+pub fn tcp_ipv4(source: Ipv4Addr, destination: Ipv4Addr, segment: &[u8]) -> u16 {
+    transport(ipv4_pseudo_header(source, destination, super::ip_protocol::TCP, segment.len()), segment, 16)
+}
+
+/// The checksum of a TCP segment carried over IPv6
+pub fn tcp_ipv6(source: Ipv6Addr, destination: Ipv6Addr, segment: &[u8]) -> u16 {
+    transport(ipv6_pseudo_header(source, destination, super::ip_protocol::TCP, segment.len()), segment, 16)
+}
+
+/// Incrementally update `checksum` after a 16-bit field changed from `old` to `new` (RFC 1624, eqn. 3)
+pub fn update_u16(checksum: u16, old: u16, new: u16) -> u16 {
+    let sum = (! checksum) as u32 + (! old) as u32 + new as u32;
+    finish(sum)
+}
+
+/// Incrementally update `checksum` after a 32-bit field (e.g. an IPv4 address) changed from `old` to `new`
+pub fn update_u32(checksum: u16, old: u32, new: u32) -> u16 {
+    let checksum = update_u16(checksum, (old >> 16) as u16, (new >> 16) as u16);
+    update_u16(checksum, old as u16, new as u16)
+}
+
+/// Incrementally update `checksum` after an even-length field (e.g. an IPv6 address) changed from `old` to `new`
+pub fn update_bytes(checksum: u16, old: &[u8], new: &[u8]) -> u16 {
+    assert_eq!(old.len(), new.len(), "Fields must have the same length");
+    assert!(old.len().is_multiple_of(2), "Fields must have an even length");
+    old.chunks_exact(2).zip(new.chunks_exact(2))
+        .fold(checksum, |checksum, (old, new)| update_u16(checksum, u16::from_be_bytes([old[0], old[1]]), u16::from_be_bytes([new[0], new[1]])))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_checksums() {
+        let mut header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11,
+            0xb8, 0x61, 0xc0, 0xa8, 0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(super::ipv4_header(&header), 0xb861);
+
+        // rewrite the destination, the incremental update must match a full computation
+        let old = u32::from_be_bytes(header[16..20].try_into().unwrap());
+        let new = u32::from(Ipv4Addr::new(10, 42, 0, 80));
+        header[16..20].copy_from_slice(&new.to_be_bytes());
+        assert_eq!(super::update_u32(0xb861, old, new), super::ipv4_header(&header));
+
+        // decrement the ttl
+        let old = u16::from_be_bytes([header[8], header[9]]);
+        let checksum = super::ipv4_header(&header);
+        header[8] -= 1;
+        let new = u16::from_be_bytes([header[8], header[9]]);
+        assert_eq!(super::update_u16(checksum, old, new), super::ipv4_header(&header));
+
+        // an odd-length udp datagram checksums to zero once its checksum is set
+        let source = Ipv4Addr::new(10, 0, 0, 1);
+        let destination = Ipv4Addr::new(10, 0, 0, 2);
+        let mut datagram = [0x30, 0x39, 0x00, 0x35, 0x00, 0x0b, 0x00, 0x00, b'a', b'b', b'c'];
+        let checksum = super::udp_ipv4(source, destination, &datagram);
+        datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+        let sum = super::accumulate(super::ipv4_pseudo_header(source, destination, 17, datagram.len()), &datagram);
+        assert_eq!(super::finish(sum), 0);
+    }
+}
@@ -0,0 +1,162 @@
+use super::{ethertype, read_u16, write_u16};
+
+/// An ethernet MAC address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct MacAddress(pub [u8; 6]);
+impl MacAddress {
+    pub const BROADCAST: Self = Self([0xFF; 6]);
+
+    /// Whether this is the broadcast address
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// Whether this is a group address (broadcast included)
+    pub const fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+}
+impl From<[u8; 6]> for MacAddress {
+    fn from(value: [u8; 6]) -> Self {
+        Self(value)
+    }
+}
+impl std::fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+impl std::str::FromStr for MacAddress {
+    type Err = crate::Error;
+
+    /// Parse the `aa:bb:cc:dd:ee:ff` notation, as found in `/sys/class/net/<if>/address`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || crate::Error::InvalidConfiguration { reason: format!("invalid MAC address {s:?}") };
+        let mut address = [0_u8; 6];
+        let mut octets = s.trim().split(':');
+        for octet in &mut address {
+            let digits = octets.next().filter(|digits| digits.len() == 2).ok_or_else(invalid)?;
+            *octet = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+        }
+        if octets.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self(address))
+    }
+}
+
+/// An 802.1Q or 802.1ad tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VlanTag {
+    /// Tag protocol identifier, i.e. [`ethertype::VLAN`] or [`ethertype::QINQ`]
+    pub tpid: u16,
+    /// Priority code point
+    pub pcp: u8,
+    /// Drop eligible indicator
+    pub dei: bool,
+    /// VLAN identifier
+    pub vid: u16,
+}
+impl VlanTag {
+    /// Decode a tag out of its TPID and TCI fields
+    pub const fn from_tci(tpid: u16, tci: u16) -> Self {
+        Self { tpid, pcp: (tci >> 13) as u8, dei: tci & 0x1000 != 0, vid: tci & 0x0FFF }
+    }
+
+    /// Encode the TCI field of this tag
+    pub const fn tci(&self) -> u16 {
+        ((self.pcp as u16 & 0x7) << 13) | ((self.dei as u16) << 12) | (self.vid & 0x0FFF)
+    }
+}
+
+/// A view over an ethernet frame, possibly VLAN tagged
+#[derive(Debug, Clone, Copy)]
+pub struct EthernetFrame<T> {
+    buffer: T,
+}
+impl<T: AsRef<[u8]>> EthernetFrame<T> {
+    /// Size of the untagged header
+    pub const HEADER_SIZE: usize = 14;
+
+    /// Wrap `buffer`, checking it can contain the header and all of its VLAN tags
+    pub fn new_checked(buffer: T) -> Option<Self> {
+        let this = Self { buffer };
+        let bytes = this.buffer.as_ref();
+        if bytes.len() < Self::HEADER_SIZE {
+            return None;
+        }
+        let mut offset = 12;
+        while matches!(read_u16(bytes, offset), ethertype::VLAN | ethertype::QINQ) {
+            offset += 4;
+            if bytes.len() < offset + 2 {
+                return None;
+            }
+        }
+        Some(this)
+    }
+
+    /// Get back the wrapped buffer
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
+    pub fn destination(&self) -> MacAddress {
+        MacAddress(self.buffer.as_ref()[0..6].try_into().unwrap())
+    }
+
+    pub fn source(&self) -> MacAddress {
+        MacAddress(self.buffer.as_ref()[6..12].try_into().unwrap())
+    }
+
+    /// The ethertype found right after the addresses, i.e. a TPID if the frame is tagged
+    pub fn ethertype(&self) -> u16 {
+        read_u16(self.buffer.as_ref(), 12)
+    }
+
+    /// The ethertype found after all VLAN tags
+    pub fn inner_ethertype(&self) -> u16 {
+        read_u16(self.buffer.as_ref(), self.payload_offset() - 2)
+    }
+
+    /// The VLAN tags, outermost first
+    pub fn vlan_tags(&self) -> impl Iterator<Item = VlanTag> + '_ {
+        let bytes = self.buffer.as_ref();
+        (12..self.payload_offset() - 2).step_by(4)
+            .map(move |offset| VlanTag::from_tci(read_u16(bytes, offset), read_u16(bytes, offset + 2)))
+    }
+
+    /// The offset at which the payload starts, after all VLAN tags
+    pub fn payload_offset(&self) -> usize {
+        let bytes = self.buffer.as_ref();
+        let mut offset = 12;
+        while matches!(read_u16(bytes, offset), ethertype::VLAN | ethertype::QINQ) {
+            offset += 4;
+        }
+        offset + 2
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer.as_ref()[self.payload_offset()..]
+    }
+}
+impl<T: AsRef<[u8]> + AsMut<[u8]>> EthernetFrame<T> {
+    pub fn set_destination(&mut self, address: MacAddress) {
+        self.buffer.as_mut()[0..6].copy_from_slice(&address.0);
+    }
+
+    pub fn set_source(&mut self, address: MacAddress) {
+        self.buffer.as_mut()[6..12].copy_from_slice(&address.0);
+    }
+
+    /// Set the ethertype found after all VLAN tags
+    pub fn set_inner_ethertype(&mut self, value: u16) {
+        let offset = self.payload_offset() - 2;
+        write_u16(self.buffer.as_mut(), offset, value);
+    }
+
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let offset = self.payload_offset();
+        &mut self.buffer.as_mut()[offset..]
+    }
+}
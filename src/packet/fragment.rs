@@ -0,0 +1,477 @@
+use std::{collections::HashMap, net::Ipv4Addr, ops::Range, time::{Duration, Instant}};
+
+use super::{write_u16, EthernetFrame, Ipv4Packet};
+
+/// The largest IPv4 datagram, the length field being 16 bits wide
+const MAX_TOTAL_LEN: usize = u16::MAX as usize;
+
+/// Splits IPv4 frames larger than the MTU into fragments, the way routers do
+///
+/// The first fragment keeps every IP option, the others only those flagged to be copied. Frames with the don't fragment
+/// bit set are refused: routers answer them with an ICMP "fragmentation needed" instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Fragmenter {
+    mtu: usize,
+}
+impl Ipv4Fragmenter {
+    /// Fragment into packets of at most `mtu` bytes, link headers excluded
+    pub fn new(mtu: usize) -> Self {
+        assert!(mtu >= 68, "IPv4 requires an MTU of at least 68");
+        Self { mtu }
+    }
+
+    pub const fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// How many fragments `frame` is split into, 1 if it fits as it is
+    pub fn fragment_count(&self, frame: &[u8]) -> Result<usize, crate::Error> {
+        let layout = self.layout(frame)?;
+        let payload_len = layout.ipv4.payload().len();
+        if layout.fits {
+            return Ok(1);
+        }
+        Ok(1 + payload_len.saturating_sub(layout.first_step).div_ceil(layout.later_step))
+    }
+
+    /// Write fragment `index` of `frame` into `buffer`, with the link header of `frame`, returning the fragment length
+    pub fn write_fragment(&self, buffer: &mut [u8], frame: &[u8], index: usize) -> Result<usize, crate::Error> {
+        let layout = self.layout(frame)?;
+        if layout.fits {
+            assert_eq!(index, 0, "Fragment {index} does not exist");
+            let len = layout.link_len + layout.ipv4.total_len();
+            if buffer.len() < len {
+                return Err(crate::Error::BufferTooSmall { required: len, available: buffer.len() });
+            }
+            buffer[..len].copy_from_slice(&frame[..len]);
+            return Ok(len);
+        }
+
+        // what goes in
+        let payload = layout.ipv4.payload();
+        let start = match index {
+            0 => 0,
+            index => layout.first_step + (index - 1) * layout.later_step,
+        };
+        assert!(start < payload.len(), "Fragment {index} does not exist");
+        let end = (start + if index == 0 { layout.first_step } else { layout.later_step }).min(payload.len());
+        let header = match index {
+            0 => layout.ipv4.header(),
+            _ => &layout.later_header[..layout.later_header_len],
+        };
+        let len = layout.link_len + header.len() + end - start;
+        if buffer.len() < len {
+            return Err(crate::Error::BufferTooSmall { required: len, available: buffer.len() });
+        }
+
+        // copy
+        buffer[..layout.link_len].copy_from_slice(&frame[..layout.link_len]);
+        let ip = &mut buffer[layout.link_len..len];
+        ip[..header.len()].copy_from_slice(header);
+        ip[header.len()..].copy_from_slice(&payload[start..end]);
+
+        // fix up the header
+        ip[0] = 0x40 | (header.len() / 4) as u8;
+        write_u16(ip, 2, (header.len() + end - start) as u16);
+        let more_fragments = end < payload.len() || layout.ipv4.more_fragments();
+        let offset = (layout.ipv4.fragment_offset() + start) / 8;
+        write_u16(ip, 6, if more_fragments { 0x2000 } else { 0 } | offset as u16);
+        let mut packet = Ipv4Packet::new_checked(ip).unwrap();
+        packet.fill_checksum();
+        Ok(len)
+    }
+
+    fn layout<'a>(&self, frame: &'a [u8]) -> Result<FragmentLayout<'a>, crate::Error> {
+        let not_ipv4 = || crate::Error::FragmentationFailure { reason: "not an IPv4 frame" };
+        let ethernet = EthernetFrame::new_checked(frame).filter(|ethernet| ethernet.inner_ethertype() == super::ethertype::IPV4).ok_or_else(not_ipv4)?;
+        let link_len = ethernet.payload_offset();
+        let ipv4 = Ipv4Packet::new_checked(&frame[link_len..]).ok_or_else(not_ipv4)?;
+        let fits = ipv4.total_len() <= self.mtu;
+        if ! fits && ipv4.dont_fragment() {
+            return Err(crate::Error::FragmentationFailure { reason: "the don't fragment bit is set" });
+        }
+
+        // later fragments only carry the options flagged to be copied
+        let mut later_header = [0_u8; 60];
+        later_header[..Ipv4Packet::<&[u8]>::MIN_HEADER_SIZE].copy_from_slice(&ipv4.header()[..Ipv4Packet::<&[u8]>::MIN_HEADER_SIZE]);
+        let options = copied_options(&ipv4.header()[Ipv4Packet::<&[u8]>::MIN_HEADER_SIZE..], &mut later_header[Ipv4Packet::<&[u8]>::MIN_HEADER_SIZE..]);
+        let later_header_len = Ipv4Packet::<&[u8]>::MIN_HEADER_SIZE + options;
+
+        Ok(FragmentLayout {
+            link_len,
+            first_step: (self.mtu - ipv4.header_len()) & ! 7,
+            later_step: (self.mtu - later_header_len) & ! 7,
+            ipv4,
+            fits,
+            later_header,
+            later_header_len,
+        })
+    }
+}
+
+struct FragmentLayout<'a> {
+    link_len: usize,
+    ipv4: Ipv4Packet<&'a [u8]>,
+    fits: bool,
+    /// The payload bytes carried by the first fragment
+    first_step: usize,
+    /// The payload bytes carried by the others
+    later_step: usize,
+    later_header: [u8; 60],
+    later_header_len: usize,
+}
+
+/// Copy the options of `options` flagged to be copied into fragments to `copied`, returning their padded length
+fn copied_options(options: &[u8], copied: &mut [u8]) -> usize {
+    let mut len = 0;
+    let mut at = 0;
+    while at < options.len() {
+        match options[at] {
+            // end of options
+            0 => break,
+            // no operation
+            1 => at += 1,
+            kind => {
+                let Some(&option_len) = options.get(at + 1) else { break };
+                let option_len = option_len as usize;
+                if option_len < 2 || at + option_len > options.len() {
+                    break;
+                }
+                if kind & 0x80 != 0 {
+                    copied[len..len + option_len].copy_from_slice(&options[at..at + option_len]);
+                    len += option_len;
+                }
+                at += option_len;
+            },
+        }
+    }
+    copied[len..len.next_multiple_of(4)].fill(0);
+    len.next_multiple_of(4)
+}
+
+/// Counters kept by an [`Ipv4Reassembler`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReassemblyStats {
+    pub reassembled: u64,
+    /// Datagrams given up on because some fragment did not arrive in time
+    pub timed_out: u64,
+    /// Fragments dropped because the memory cap was reached
+    pub dropped: u64,
+    /// Fragments which were not, or were inconsistent with the others of their datagram
+    pub invalid: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct DatagramKey {
+    source: Ipv4Addr,
+    destination: Ipv4Addr,
+    protocol: u8,
+    identification: u16,
+}
+
+#[derive(Debug)]
+struct PendingDatagram {
+    first_seen: Instant,
+    /// The link and IP headers of the first fragment, empty until it arrives
+    headers: Vec<u8>,
+    payload: Vec<u8>,
+    /// The ranges of the payload received so far, sorted and merged
+    received: Vec<Range<usize>>,
+    /// The payload length, known once the last fragment arrives
+    total_len: Option<usize>,
+}
+impl PendingDatagram {
+    fn size(&self) -> usize {
+        self.headers.len() + self.payload.len()
+    }
+
+    /// The IP header length of the first fragment, once it arrived
+    fn ip_header_len(&self) -> Option<usize> {
+        let link_len = EthernetFrame::new_checked(&self.headers[..])?.payload_offset();
+        Some(self.headers.len() - link_len)
+    }
+}
+
+/// Puts fragmented IPv4 datagrams back together
+///
+/// Datagrams missing fragments for longer than the timeout are dropped by [`Self::expire`], and fragments which would
+/// take the buffered bytes past the memory cap are dropped on arrival, so that floods of bogus fragments cannot exhaust
+/// memory. Overlapping fragments are accepted, the last one winning
+#[derive(Debug)]
+pub struct Ipv4Reassembler {
+    timeout: Duration,
+    max_bytes: usize,
+    buffered_bytes: usize,
+    pending: HashMap<DatagramKey, PendingDatagram>,
+    stats: ReassemblyStats,
+}
+impl Ipv4Reassembler {
+    /// Reassemble datagrams whose fragments arrive within `timeout`, buffering at most `max_bytes` of them
+    pub fn new(timeout: Duration, max_bytes: usize) -> Self {
+        Self { timeout, max_bytes, buffered_bytes: 0, pending: HashMap::new(), stats: ReassemblyStats::default() }
+    }
+
+    pub const fn stats(&self) -> &ReassemblyStats {
+        &self.stats
+    }
+
+    /// The number of datagrams missing fragments
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether no datagram is missing fragments
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// The bytes held by datagrams missing fragments
+    pub const fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    /// Take in the fragment `frame`, returning the whole datagram, with the link header of its first fragment, once complete
+    ///
+    /// Frames which are not IPv4 fragments are counted as invalid, check [`Ipv4Packet::is_fragment`] first
+    pub fn push(&mut self, frame: &[u8]) -> Option<Vec<u8>> {
+        self.push_at(frame, Instant::now())
+    }
+
+    fn push_at(&mut self, frame: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let Some((key, link_len, ipv4)) = parse_fragment(frame) else {
+            self.stats.invalid += 1;
+            return None;
+        };
+        let data = ipv4.payload();
+        let start = ipv4.fragment_offset();
+        let end = start + data.len();
+
+        // the datagram gets the header of the first fragment, options included
+        let header_len = if start == 0 { Some(ipv4.header_len()) } else { self.pending.get(&key).and_then(PendingDatagram::ip_header_len) };
+        let header_len = header_len.unwrap_or(Ipv4Packet::<&[u8]>::MIN_HEADER_SIZE);
+        if end + header_len > MAX_TOTAL_LEN || (ipv4.more_fragments() && ! data.len().is_multiple_of(8)) {
+            self.stats.invalid += 1;
+            return None;
+        }
+
+        // account for the memory before taking it
+        let pending = self.pending.entry(key).or_insert_with(|| PendingDatagram {
+            first_seen: now,
+            headers: Vec::new(),
+            payload: Vec::new(),
+            received: Vec::new(),
+            total_len: None,
+        });
+        let headers_len = if start == 0 { link_len + ipv4.header_len() } else { pending.headers.len() };
+        let growth = (headers_len + end.max(pending.payload.len())).saturating_sub(pending.size());
+        if self.buffered_bytes + growth > self.max_bytes {
+            self.stats.dropped += 1;
+            if pending.size() == 0 {
+                self.pending.remove(&key);
+            }
+            return None;
+        }
+
+        // the last fragment sets the length, which every fragment must agree with
+        let total_len = if ipv4.more_fragments() { pending.total_len } else { Some(end) };
+        let inconsistent = (! ipv4.more_fragments() && pending.total_len.is_some_and(|known| known != end))
+            || total_len.is_some_and(|total_len| end > total_len || pending.payload.len() > total_len)
+            || pending.payload.len() + header_len > MAX_TOTAL_LEN;
+        if inconsistent {
+            self.stats.invalid += 1;
+            let removed = self.pending.remove(&key).unwrap();
+            self.buffered_bytes -= removed.size();
+            return None;
+        }
+        pending.total_len = total_len;
+
+        // store
+        let before = pending.size();
+        if start == 0 {
+            pending.headers.clear();
+            pending.headers.extend_from_slice(&frame[..link_len + ipv4.header_len()]);
+        }
+        if pending.payload.len() < end {
+            pending.payload.resize(end, 0);
+        }
+        pending.payload[start..end].copy_from_slice(data);
+        insert_range(&mut pending.received, start..end);
+        self.buffered_bytes = self.buffered_bytes + pending.size() - before;
+
+        // complete?
+        let complete = ! pending.headers.is_empty() && pending.total_len.is_some_and(|total_len| matches!(&pending.received[..], [only] if *only == (0..total_len)));
+        if ! complete {
+            return None;
+        }
+        let pending = self.pending.remove(&key).unwrap();
+        self.buffered_bytes -= pending.size();
+        self.stats.reassembled += 1;
+        Some(assemble(pending))
+    }
+
+    /// Drop the datagrams whose first fragment arrived before `now` minus the timeout, returning how many there were
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.pending.len();
+        let mut freed = 0;
+        self.pending.retain(|_, pending| {
+            let keep = now.saturating_duration_since(pending.first_seen) <= self.timeout;
+            if ! keep {
+                freed += pending.size();
+            }
+            keep
+        });
+        let expired = before - self.pending.len();
+        self.buffered_bytes -= freed;
+        self.stats.timed_out += expired as u64;
+        expired
+    }
+}
+
+fn parse_fragment(frame: &[u8]) -> Option<(DatagramKey, usize, Ipv4Packet<&[u8]>)> {
+    let ethernet = EthernetFrame::new_checked(frame)?;
+    if ethernet.inner_ethertype() != super::ethertype::IPV4 {
+        return None;
+    }
+    let link_len = ethernet.payload_offset();
+    let ipv4 = Ipv4Packet::new_checked(&frame[link_len..]).filter(Ipv4Packet::is_fragment)?;
+    let key = DatagramKey {
+        source: ipv4.source(),
+        destination: ipv4.destination(),
+        protocol: ipv4.protocol(),
+        identification: ipv4.identification(),
+    };
+    Some((key, link_len, ipv4))
+}
+
+/// Add `range` to the sorted and merged `ranges`
+fn insert_range(ranges: &mut Vec<Range<usize>>, range: Range<usize>) {
+    ranges.push(range);
+    ranges.sort_unstable_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges.drain(..) {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    *ranges = merged;
+}
+
+/// The frame made of the headers of the first fragment and the whole payload
+fn assemble(pending: PendingDatagram) -> Vec<u8> {
+    let PendingDatagram { mut headers, payload, total_len, .. } = pending;
+    let link_len = EthernetFrame::new_checked(&headers[..]).unwrap().payload_offset();
+    let ip = &mut headers[link_len..];
+    let header_len = ip.len();
+    write_u16(ip, 2, (header_len + total_len.unwrap()) as u16);
+    write_u16(ip, 6, 0);
+    headers.extend_from_slice(&payload);
+    Ipv4Packet::new_checked(&mut headers[link_len..]).unwrap().fill_checksum();
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, time::{Duration, Instant}};
+
+    use super::{Ipv4Fragmenter, Ipv4Reassembler, ReassemblyStats};
+    use crate::packet::{parse, FrameBuilder, MacAddress, NetworkHeader, TransportHeader};
+
+    fn datagram(payload_len: usize) -> Vec<u8> {
+        let builder = FrameBuilder::ethernet(MacAddress([2, 0, 0, 0, 0, 1]), MacAddress([2, 0, 0, 0, 0, 2]))
+            .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2))
+            .udp(1000, 2000);
+        let mut frame = vec![0_u8; builder.header_len() + payload_len];
+        let payload = (0..payload_len).map(|i| i as u8).collect::<Vec<_>>();
+        builder.write_with_payload(&mut frame, &payload).unwrap();
+        frame[14 + 4..14 + 6].copy_from_slice(&0x1234_u16.to_be_bytes());
+        frame[14 + 6] = 0;
+        crate::packet::Ipv4Packet::new_checked(&mut frame[14..]).unwrap().fill_checksum();
+        frame
+    }
+
+    fn fragments(frame: &[u8], mtu: usize) -> Vec<Vec<u8>> {
+        let fragmenter = Ipv4Fragmenter::new(mtu);
+        (0..fragmenter.fragment_count(frame).unwrap()).map(|index| {
+            let mut buffer = vec![0_u8; 2048];
+            let len = fragmenter.write_fragment(&mut buffer, frame, index).unwrap();
+            buffer.truncate(len);
+            buffer
+        }).collect()
+    }
+
+    #[test]
+    fn test_fragment() {
+        let frame = datagram(3000);
+        let fragments = fragments(&frame, 1500);
+        assert_eq!(fragments.iter().map(Vec::len).collect::<Vec<_>>(), [1514, 1514, 14 + 20 + 3008 - 2 * 1480]);
+        for (index, fragment) in fragments.iter().enumerate() {
+            let Some(NetworkHeader::Ipv4(ipv4)) = parse(fragment).unwrap().network else { panic!("not ipv4") };
+            assert!(ipv4.verify_checksum());
+            assert_eq!(ipv4.fragment_offset(), index * 1480);
+            assert_eq!(ipv4.more_fragments(), index < 2);
+        }
+
+        // small frames are left alone, unless they may not be fragmented
+        assert_eq!(self::fragments(&datagram(100), 1500), [datagram(100)]);
+        let mut frame = datagram(3000);
+        frame[14 + 6] = 0x40;
+        assert!(Ipv4Fragmenter::new(1500).fragment_count(&frame).is_err());
+    }
+
+    #[test]
+    fn test_reassemble() {
+        let t0 = Instant::now();
+        let frame = datagram(3000);
+        let mut fragments = fragments(&frame, 576);
+        fragments.reverse();
+
+        // in any order, duplicates included
+        let mut reassembler = Ipv4Reassembler::new(Duration::from_secs(1), 64 * 1024);
+        let last = fragments.pop().unwrap();
+        for fragment in &fragments {
+            assert_eq!(reassembler.push_at(fragment, t0), None);
+        }
+        assert_eq!(reassembler.push_at(&fragments[0], t0), None);
+        assert_eq!(reassembler.len(), 1);
+        let reassembled = reassembler.push_at(&last, t0).unwrap();
+        assert_eq!(reassembled, frame);
+        let Some(TransportHeader::Udp(udp)) = parse(&reassembled).unwrap().transport else { panic!("not udp") };
+        assert_eq!(udp.payload().len(), 3000);
+        assert_eq!(reassembler.buffered_bytes(), 0);
+
+        // missing fragments time out, the memory cap holds
+        let mut reassembler = Ipv4Reassembler::new(Duration::from_secs(1), 1024);
+        assert_eq!(reassembler.push_at(&last, t0), None);
+        assert_eq!(reassembler.push_at(&fragments[0], t0), None);
+        assert_eq!(reassembler.push_at(&frame, t0), None);
+        assert_eq!(reassembler.expire(t0 + Duration::from_secs(2)), 1);
+        assert!(reassembler.is_empty());
+        assert_eq!(reassembler.buffered_bytes(), 0);
+        assert_eq!(*reassembler.stats(), ReassemblyStats { reassembled: 0, timed_out: 1, dropped: 1, invalid: 1 });
+    }
+
+    #[test]
+    fn test_reassemble_oversized() {
+        let t0 = Instant::now();
+
+        // a last fragment fitting a 20 bytes header, a first fragment with options
+        let mut last = datagram(0);
+        last[14 + 6..14 + 8].copy_from_slice(&(65504_u16 / 8).to_be_bytes());
+        crate::packet::Ipv4Packet::new_checked(&mut last[14..]).unwrap().fill_checksum();
+        let mut first = datagram(40);
+        first.splice(14 + 20..14 + 20, [1, 1, 1, 1]);
+        first[14] = 0x46;
+        first[14 + 2..14 + 4].copy_from_slice(&(24_u16 + 48).to_be_bytes());
+        first[14 + 6] = 0x20;
+        crate::packet::Ipv4Packet::new_checked(&mut first[14..]).unwrap().fill_checksum();
+
+        // the total length would not fit in the header
+        let mut reassembler = Ipv4Reassembler::new(Duration::from_secs(1), 256 * 1024);
+        assert_eq!(reassembler.push_at(&last, t0), None);
+        assert_eq!(reassembler.push_at(&first, t0), None);
+        assert_eq!(reassembler.push_at(&first, t0), None);
+        assert_eq!(reassembler.push_at(&last, t0), None);
+        assert_eq!(reassembler.stats().invalid, 2);
+    }
+}
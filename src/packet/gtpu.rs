@@ -0,0 +1,277 @@
+use std::ops::Range;
+
+use super::{parse, read_u16, read_u32, write_u16, FrameBuilder, TransportHeader};
+
+/// The IANA assigned GTP-U UDP port
+pub const GTPU_PORT: u16 = 2152;
+
+/// GTP-U message types
+pub mod gtpu_message {
+    pub const ECHO_REQUEST: u8 = 1;
+    pub const ECHO_RESPONSE: u8 = 2;
+    pub const ERROR_INDICATION: u8 = 26;
+    pub const END_MARKER: u8 = 254;
+    /// A user plane packet
+    pub const G_PDU: u8 = 255;
+}
+
+/// The extension header type of the 5G PDU session container (TS 38.415)
+pub const PDU_SESSION_CONTAINER: u8 = 0x85;
+
+/// A GTP-U extension header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GtpuExtension<'a> {
+    pub kind: u8,
+    /// The content, without the length and next type octets
+    pub content: &'a [u8],
+}
+
+/// A view over a GTPv1-U header, including the optional fields and extension headers
+#[derive(Debug, Clone, Copy)]
+pub struct GtpuHeader<T> {
+    buffer: T,
+}
+impl<T: AsRef<[u8]>> GtpuHeader<T> {
+    /// Size of the mandatory part of the header
+    pub const HEADER_SIZE: usize = 8;
+
+    const FLAG_EXTENSION: u8 = 0x04;
+    const FLAG_SEQUENCE: u8 = 0x02;
+    const FLAG_N_PDU: u8 = 0x01;
+
+    /// Wrap `buffer`, checking the version, the length field and the extension header chain
+    pub fn new_checked(buffer: T) -> Option<Self> {
+        let this = Self { buffer };
+        let bytes = this.buffer.as_ref();
+        if bytes.len() < Self::HEADER_SIZE || bytes[0] >> 5 != 1 || bytes[0] & 0x10 == 0 {
+            return None;
+        }
+        if Self::HEADER_SIZE + this.length() > bytes.len() || this.header_len()? > Self::HEADER_SIZE + this.length() {
+            return None;
+        }
+        Some(this)
+    }
+
+    /// Get back the wrapped buffer
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
+    pub fn message_type(&self) -> u8 {
+        self.buffer.as_ref()[1]
+    }
+
+    /// The length of everything after the mandatory header
+    pub fn length(&self) -> usize {
+        read_u16(self.buffer.as_ref(), 2) as usize
+    }
+
+    /// The tunnel endpoint identifier
+    pub fn teid(&self) -> u32 {
+        read_u32(self.buffer.as_ref(), 4)
+    }
+
+    pub fn sequence_number(&self) -> Option<u16> {
+        let bytes = self.buffer.as_ref();
+        (bytes[0] & Self::FLAG_SEQUENCE != 0).then(|| read_u16(bytes, 8))
+    }
+
+    pub fn n_pdu_number(&self) -> Option<u8> {
+        let bytes = self.buffer.as_ref();
+        (bytes[0] & Self::FLAG_N_PDU != 0).then(|| bytes[10])
+    }
+
+    /// The extension headers, in order
+    pub fn extensions(&self) -> impl Iterator<Item = GtpuExtension<'_>> + '_ {
+        let bytes = self.buffer.as_ref();
+        let mut next = self.first_extension_type();
+        let mut offset = Self::HEADER_SIZE + 4;
+        std::iter::from_fn(move || {
+            if next == 0 {
+                return None;
+            }
+            let len = *bytes.get(offset)? as usize * 4;
+            let extension = bytes.get(offset..offset + len)?;
+            let kind = next;
+            next = extension[len - 1];
+            offset += len;
+            Some(GtpuExtension { kind, content: &extension[1..len - 1] })
+        })
+    }
+
+    /// The QoS flow identifier carried in a PDU session container, if any
+    pub fn qfi(&self) -> Option<u8> {
+        self.extensions()
+            .find(|extension| extension.kind == PDU_SESSION_CONTAINER && extension.content.len() >= 2)
+            .map(|extension| extension.content[1] & 0x3F)
+    }
+
+    /// The size of the whole header, including optional fields and extension headers
+    pub fn header_len(&self) -> Option<usize> {
+        let bytes = self.buffer.as_ref();
+        if bytes[0] & (Self::FLAG_EXTENSION | Self::FLAG_SEQUENCE | Self::FLAG_N_PDU) == 0 {
+            return Some(Self::HEADER_SIZE);
+        }
+        let mut next = self.first_extension_type();
+        let mut offset = Self::HEADER_SIZE + 4;
+        while next != 0 {
+            let len = *bytes.get(offset)? as usize * 4;
+            if len == 0 {
+                return None;
+            }
+            next = *bytes.get(offset + len - 1)?;
+            offset += len;
+        }
+        (offset <= bytes.len()).then_some(offset)
+    }
+
+    /// The T-PDU, i.e. the tunnelled IP packet
+    pub fn payload(&self) -> &[u8] {
+        let start = self.header_len().unwrap();
+        &self.buffer.as_ref()[start..Self::HEADER_SIZE + self.length()]
+    }
+
+    fn first_extension_type(&self) -> u8 {
+        let bytes = self.buffer.as_ref();
+        if bytes[0] & Self::FLAG_EXTENSION != 0 { bytes.get(11).copied().unwrap_or(0) } else { 0 }
+    }
+}
+impl<T: AsRef<[u8]> + AsMut<[u8]>> GtpuHeader<T> {
+    pub fn set_teid(&mut self, value: u32) {
+        self.buffer.as_mut()[4..8].copy_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// Wraps IP packets into GTP-U G-PDUs, writing the outer headers into the chunk headroom
+#[derive(Debug, Clone)]
+pub struct GtpuEncap {
+    outer: FrameBuilder,
+    teid: u32,
+    source_port: u16,
+    sequence_number: Option<u16>,
+    pdu_session: Option<(u8, bool)>,
+}
+impl GtpuEncap {
+    /// Encapsulate into `teid`, with the outer headers described by `outer` (without its UDP layer)
+    pub fn new(outer: FrameBuilder, teid: u32) -> Self {
+        Self { outer, teid, source_port: GTPU_PORT, sequence_number: None, pdu_session: None }
+    }
+
+    /// Use a UDP source port other than [`GTPU_PORT`]
+    pub fn source_port(mut self, port: u16) -> Self {
+        self.source_port = port;
+        self
+    }
+
+    /// Carry sequence numbers, starting from `first` and incremented on every encapsulation
+    pub fn sequence_number(mut self, first: u16) -> Self {
+        self.sequence_number = Some(first);
+        self
+    }
+
+    /// Add a PDU session container carrying `qfi`, marked as uplink or downlink
+    pub fn pdu_session_container(mut self, qfi: u8, uplink: bool) -> Self {
+        assert!(qfi < 64, "QFI must fit in 6 bits");
+        self.pdu_session = Some((qfi, uplink));
+        self
+    }
+
+    /// The size of the GTP-U header alone
+    pub fn gtpu_header_len(&self) -> usize {
+        let optional = if self.sequence_number.is_some() || self.pdu_session.is_some() { 4 } else { 0 };
+        let extensions = if self.pdu_session.is_some() { 4 } else { 0 };
+        GtpuHeader::<&[u8]>::HEADER_SIZE + optional + extensions
+    }
+
+    /// The headroom needed in front of a packet to encapsulate it
+    pub fn overhead(&self) -> usize {
+        self.outer.clone().udp(0, 0).header_len() + self.gtpu_header_len()
+    }
+
+    /// Encapsulate the IP packet found at `packet` within `chunk`, returning where the encapsulated frame is
+    pub fn encapsulate(&mut self, chunk: &mut [u8], packet: Range<usize>) -> Result<Range<usize>, crate::Error> {
+        let overhead = self.overhead();
+        if packet.start < overhead {
+            return Err(crate::Error::BufferTooSmall { required: overhead, available: packet.start });
+        }
+
+        // gtp-u
+        let gtpu_len = self.gtpu_header_len();
+        let gtpu_start = packet.start - gtpu_len;
+        let gtpu = &mut chunk[gtpu_start..packet.start];
+        gtpu.fill(0);
+        gtpu[0] = 0x30;
+        gtpu[1] = gtpu_message::G_PDU;
+        write_u16(gtpu, 2, (packet.end - gtpu_start - GtpuHeader::<&[u8]>::HEADER_SIZE) as u16);
+        gtpu[4..8].copy_from_slice(&self.teid.to_be_bytes());
+        if let Some(sequence_number) = self.sequence_number.as_mut() {
+            gtpu[0] |= GtpuHeader::<&[u8]>::FLAG_SEQUENCE;
+            write_u16(gtpu, 8, *sequence_number);
+            *sequence_number = sequence_number.wrapping_add(1);
+        }
+        if let Some((qfi, uplink)) = self.pdu_session {
+            gtpu[0] |= GtpuHeader::<&[u8]>::FLAG_EXTENSION;
+            gtpu[11] = PDU_SESSION_CONTAINER;
+            gtpu[12..16].copy_from_slice(&[1, (uplink as u8) << 4, qfi, 0]);
+        }
+
+        // outer headers
+        let start = packet.start - overhead;
+        let builder = self.outer.clone().udp(self.source_port, GTPU_PORT);
+        builder.write(&mut chunk[start..packet.end], packet.end - gtpu_start)?;
+        Ok(start..packet.end)
+    }
+}
+
+/// Look for a G-PDU, returning its TEID and the range of the tunnelled IP packet within `frame`
+pub fn decapsulate_gtpu(frame: &[u8]) -> Option<(u32, Range<usize>)> {
+    let Some(TransportHeader::Udp(udp)) = parse(frame)?.transport else {
+        return None;
+    };
+    if udp.destination_port() != GTPU_PORT {
+        return None;
+    }
+    let gtpu = GtpuHeader::new_checked(udp.payload())?;
+    if gtpu.message_type() != gtpu_message::G_PDU {
+        return None;
+    }
+    let inner = gtpu.payload();
+    let start = inner.as_ptr() as usize - frame.as_ptr() as usize;
+    Some((gtpu.teid(), start..start + inner.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::{decapsulate_gtpu, GtpuEncap, GtpuHeader, PDU_SESSION_CONTAINER};
+    use crate::packet::{parse, FrameBuilder, MacAddress, TransportHeader};
+
+    #[test]
+    fn test_encap_decap() {
+        let mut chunk = [0_u8; 256];
+        let packet = 128..148;
+        chunk[packet.clone()].copy_from_slice(&[0x45; 20]);
+
+        let outer = FrameBuilder::ethernet(MacAddress([2, 0, 0, 0, 0, 0xA]), MacAddress([2, 0, 0, 0, 0, 0xB]))
+            .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2));
+        let mut encap = GtpuEncap::new(outer, 0x1234_5678)
+            .sequence_number(7)
+            .pdu_session_container(9, false);
+        assert_eq!(encap.gtpu_header_len(), 16);
+        let frame = encap.encapsulate(&mut chunk, packet).unwrap();
+        assert_eq!(frame.start, 128 - 14 - 20 - 8 - 16);
+
+        // header fields
+        let parsed = parse(&chunk[frame.clone()]).unwrap();
+        let Some(TransportHeader::Udp(udp)) = parsed.transport else { panic!("not udp") };
+        let gtpu = GtpuHeader::new_checked(udp.payload()).unwrap();
+        assert_eq!((gtpu.teid(), gtpu.sequence_number(), gtpu.qfi()), (0x1234_5678, Some(7), Some(9)));
+        assert_eq!(gtpu.extensions().map(|extension| extension.kind).collect::<Vec<_>>(), [PDU_SESSION_CONTAINER]);
+
+        // decap
+        let (teid, inner) = decapsulate_gtpu(&chunk[frame.clone()]).unwrap();
+        assert_eq!(teid, 0x1234_5678);
+        assert_eq!(chunk[frame][inner], [0x45; 20]);
+    }
+}
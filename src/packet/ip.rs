@@ -0,0 +1,249 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use super::{read_u16, write_u16};
+
+/// A view over an IPv4 packet
+#[derive(Debug, Clone, Copy)]
+pub struct Ipv4Packet<T> {
+    buffer: T,
+}
+impl<T: AsRef<[u8]>> Ipv4Packet<T> {
+    /// Size of the header without options
+    pub const MIN_HEADER_SIZE: usize = 20;
+
+    /// Wrap `buffer`, checking version and that both header and total length fit
+    pub fn new_checked(buffer: T) -> Option<Self> {
+        let bytes = buffer.as_ref();
+        if bytes.len() < Self::MIN_HEADER_SIZE || bytes[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (bytes[0] & 0x0F) as usize * 4;
+        let total_len = read_u16(bytes, 2) as usize;
+        if header_len < Self::MIN_HEADER_SIZE || total_len < header_len || total_len > bytes.len() {
+            return None;
+        }
+        Some(Self { buffer })
+    }
+
+    /// Get back the wrapped buffer
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
+    pub fn header_len(&self) -> usize {
+        (self.buffer.as_ref()[0] & 0x0F) as usize * 4
+    }
+
+    pub fn dscp(&self) -> u8 {
+        self.buffer.as_ref()[1] >> 2
+    }
+
+    pub fn ecn(&self) -> u8 {
+        self.buffer.as_ref()[1] & 0x03
+    }
+
+    pub fn total_len(&self) -> usize {
+        read_u16(self.buffer.as_ref(), 2) as usize
+    }
+
+    pub fn identification(&self) -> u16 {
+        read_u16(self.buffer.as_ref(), 4)
+    }
+
+    pub fn dont_fragment(&self) -> bool {
+        self.buffer.as_ref()[6] & 0x40 != 0
+    }
+
+    pub fn more_fragments(&self) -> bool {
+        self.buffer.as_ref()[6] & 0x20 != 0
+    }
+
+    /// The fragment offset, in bytes
+    pub fn fragment_offset(&self) -> usize {
+        (read_u16(self.buffer.as_ref(), 6) & 0x1FFF) as usize * 8
+    }
+
+    /// Whether this packet is a fragment of a larger one
+    pub fn is_fragment(&self) -> bool {
+        self.more_fragments() || self.fragment_offset() != 0
+    }
+
+    pub fn ttl(&self) -> u8 {
+        self.buffer.as_ref()[8]
+    }
+
+    pub fn protocol(&self) -> u8 {
+        self.buffer.as_ref()[9]
+    }
+
+    pub fn checksum(&self) -> u16 {
+        read_u16(self.buffer.as_ref(), 10)
+    }
+
+    pub fn source(&self) -> Ipv4Addr {
+        Ipv4Addr::from(<[u8; 4]>::try_from(&self.buffer.as_ref()[12..16]).unwrap())
+    }
+
+    pub fn destination(&self) -> Ipv4Addr {
+        Ipv4Addr::from(<[u8; 4]>::try_from(&self.buffer.as_ref()[16..20]).unwrap())
+    }
+
+    /// Whether the header checksum is correct
+    pub fn verify_checksum(&self) -> bool {
+        super::checksum::ipv4_header(self.header()) == self.checksum()
+    }
+
+    /// The header, options included
+    pub fn header(&self) -> &[u8] {
+        &self.buffer.as_ref()[..self.header_len()]
+    }
+
+    /// The payload, excluding any link layer padding
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer.as_ref()[self.header_len()..self.total_len()]
+    }
+}
+impl<T: AsRef<[u8]> + AsMut<[u8]>> Ipv4Packet<T> {
+    pub fn set_ttl(&mut self, value: u8) {
+        self.buffer.as_mut()[8] = value;
+    }
+
+    pub fn set_checksum(&mut self, value: u16) {
+        write_u16(self.buffer.as_mut(), 10, value);
+    }
+
+    /// Recompute and store the header checksum
+    pub fn fill_checksum(&mut self) {
+        let checksum = super::checksum::ipv4_header(self.header());
+        self.set_checksum(checksum);
+    }
+
+    pub fn set_source(&mut self, address: Ipv4Addr) {
+        self.buffer.as_mut()[12..16].copy_from_slice(&address.octets());
+    }
+
+    pub fn set_destination(&mut self, address: Ipv4Addr) {
+        self.buffer.as_mut()[16..20].copy_from_slice(&address.octets());
+    }
+
+    pub fn header_mut(&mut self) -> &mut [u8] {
+        let header_len = self.header_len();
+        &mut self.buffer.as_mut()[..header_len]
+    }
+
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let (header_len, total_len) = (self.header_len(), self.total_len());
+        &mut self.buffer.as_mut()[header_len..total_len]
+    }
+}
+
+/// A view over an IPv6 packet
+#[derive(Debug, Clone, Copy)]
+pub struct Ipv6Packet<T> {
+    buffer: T,
+}
+impl<T: AsRef<[u8]>> Ipv6Packet<T> {
+    /// Size of the fixed header
+    pub const HEADER_SIZE: usize = 40;
+
+    // extension headers
+    const HOP_BY_HOP: u8 = 0;
+    const ROUTING: u8 = 43;
+    const FRAGMENT: u8 = 44;
+    const DESTINATION_OPTIONS: u8 = 60;
+
+    /// Wrap `buffer`, checking version and that the payload length fits
+    pub fn new_checked(buffer: T) -> Option<Self> {
+        let bytes = buffer.as_ref();
+        if bytes.len() < Self::HEADER_SIZE || bytes[0] >> 4 != 6 {
+            return None;
+        }
+        if Self::HEADER_SIZE + read_u16(bytes, 4) as usize > bytes.len() {
+            return None;
+        }
+        Some(Self { buffer })
+    }
+
+    /// Get back the wrapped buffer
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
+    pub fn traffic_class(&self) -> u8 {
+        (read_u16(self.buffer.as_ref(), 0) >> 4) as u8
+    }
+
+    pub fn flow_label(&self) -> u32 {
+        super::read_u32(self.buffer.as_ref(), 0) & 0x000F_FFFF
+    }
+
+    pub fn payload_len(&self) -> usize {
+        read_u16(self.buffer.as_ref(), 4) as usize
+    }
+
+    pub fn next_header(&self) -> u8 {
+        self.buffer.as_ref()[6]
+    }
+
+    pub fn hop_limit(&self) -> u8 {
+        self.buffer.as_ref()[7]
+    }
+
+    pub fn source(&self) -> Ipv6Addr {
+        Ipv6Addr::from(<[u8; 16]>::try_from(&self.buffer.as_ref()[8..24]).unwrap())
+    }
+
+    pub fn destination(&self) -> Ipv6Addr {
+        Ipv6Addr::from(<[u8; 16]>::try_from(&self.buffer.as_ref()[24..40]).unwrap())
+    }
+
+    /// The payload, extension headers included, excluding any link layer padding
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer.as_ref()[Self::HEADER_SIZE..Self::HEADER_SIZE + self.payload_len()]
+    }
+
+    /// Skip extension headers, returning the upper layer protocol and its offset within [`Self::payload`]
+    /// 
+    /// Returns `None` for truncated extension headers and non-first fragments
+    pub fn upper_layer(&self) -> Option<(u8, usize)> {
+        let payload = self.payload();
+        let mut next_header = self.next_header();
+        let mut offset = 0;
+        loop {
+            match next_header {
+                Self::HOP_BY_HOP | Self::ROUTING | Self::DESTINATION_OPTIONS => {
+                    let header = payload.get(offset..offset + 2)?;
+                    next_header = header[0];
+                    offset += (header[1] as usize + 1) * 8;
+                },
+                Self::FRAGMENT => {
+                    let header = payload.get(offset..offset + 8)?;
+                    if read_u16(header, 2) & 0xFFF8 != 0 {
+                        return None;
+                    }
+                    next_header = header[0];
+                    offset += 8;
+                },
+                protocol => return (offset <= payload.len()).then_some((protocol, offset)),
+            }
+        }
+    }
+}
+impl<T: AsRef<[u8]> + AsMut<[u8]>> Ipv6Packet<T> {
+    pub fn set_hop_limit(&mut self, value: u8) {
+        self.buffer.as_mut()[7] = value;
+    }
+
+    pub fn set_source(&mut self, address: Ipv6Addr) {
+        self.buffer.as_mut()[8..24].copy_from_slice(&address.octets());
+    }
+
+    pub fn set_destination(&mut self, address: Ipv6Addr) {
+        self.buffer.as_mut()[24..40].copy_from_slice(&address.octets());
+    }
+
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let payload_len = self.payload_len();
+        &mut self.buffer.as_mut()[Self::HEADER_SIZE..Self::HEADER_SIZE + payload_len]
+    }
+}
@@ -0,0 +1,199 @@
+//! Zero-copy views over the headers of frames found in umem chunks
+//!
+//! Every view wraps a buffer (`&[u8]`, `&mut [u8]`, ...) checked at construction to be long enough for its fixed-size header,
+//! accessors then read directly from it, and setters are available when the buffer is mutable
+
+use std::net::IpAddr;
+
+mod builder; pub use builder::FrameBuilder;
+pub mod checksum;
+mod ethernet; pub use ethernet::{EthernetFrame, MacAddress, VlanTag};
+mod fragment; pub use fragment::{Ipv4Fragmenter, Ipv4Reassembler, ReassemblyStats};
+mod gtpu; pub use gtpu::{decapsulate_gtpu, gtpu_message, GtpuEncap, GtpuExtension, GtpuHeader, GTPU_PORT, PDU_SESSION_CONTAINER};
+mod ip; pub use ip::{Ipv4Packet, Ipv6Packet};
+mod segment; pub use segment::Segmenter;
+mod transport; pub use transport::{TcpSegment, UdpDatagram};
+mod vlan; pub use vlan::{pop_vlan_tag, push_vlan_tag, VLAN_TAG_SIZE};
+mod vxlan; pub use vxlan::{decapsulate_vxlan, VxlanEncap, VxlanHeader, VXLAN_PORT};
+
+/// Well-known ethertypes
+pub mod ethertype {
+    pub const IPV4: u16 = 0x0800;
+    pub const ARP: u16 = 0x0806;
+    pub const VLAN: u16 = 0x8100;
+    pub const IPV6: u16 = 0x86DD;
+    pub const QINQ: u16 = 0x88A8;
+}
+
+/// Well-known IP protocol numbers
+pub mod ip_protocol {
+    pub const ICMP: u8 = 1;
+    pub const TCP: u8 = 6;
+    pub const UDP: u8 = 17;
+    pub const ICMPV6: u8 = 58;
+}
+
+/// The network layer header of a parsed frame
+#[derive(Debug, Clone, Copy)]
+pub enum NetworkHeader<'a> {
+    Ipv4(Ipv4Packet<&'a [u8]>),
+    Ipv6(Ipv6Packet<&'a [u8]>),
+}
+
+/// The transport layer header of a parsed frame
+#[derive(Debug, Clone, Copy)]
+pub enum TransportHeader<'a> {
+    Tcp(TcpSegment<&'a [u8]>),
+    Udp(UdpDatagram<&'a [u8]>),
+}
+
+/// A frame parsed as deep as this module understands
+#[derive(Debug, Clone, Copy)]
+pub struct ParsedFrame<'a> {
+    pub ethernet: EthernetFrame<&'a [u8]>,
+    pub network: Option<NetworkHeader<'a>>,
+    pub transport: Option<TransportHeader<'a>>,
+}
+
+impl ParsedFrame<'_> {
+    /// The flow the frame belongs to, `None` if it is not IP
+    pub fn five_tuple(&self) -> Option<FiveTuple> {
+        let (source, destination, protocol) = match self.network? {
+            NetworkHeader::Ipv4(ipv4) => (IpAddr::V4(ipv4.source()), IpAddr::V4(ipv4.destination()), ipv4.protocol()),
+            NetworkHeader::Ipv6(ipv6) => {
+                let protocol = ipv6.upper_layer().map_or(ipv6.next_header(), |(protocol, _)| protocol);
+                (IpAddr::V6(ipv6.source()), IpAddr::V6(ipv6.destination()), protocol)
+            },
+        };
+        let (source_port, destination_port) = match self.transport {
+            Some(TransportHeader::Tcp(tcp)) => (tcp.source_port(), tcp.destination_port()),
+            Some(TransportHeader::Udp(udp)) => (udp.source_port(), udp.destination_port()),
+            None => (0, 0),
+        };
+        Some(FiveTuple { source, destination, protocol, source_port, destination_port })
+    }
+}
+
+/// The addresses, protocol and ports identifying a flow
+///
+/// Ports are zero when the protocol has none or the transport header could not be parsed, e.g. in non-first fragments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FiveTuple {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub protocol: u8,
+    pub source_port: u16,
+    pub destination_port: u16,
+}
+impl FiveTuple {
+    /// The five tuple of `frame`, see [`ParsedFrame::five_tuple`]
+    pub fn from_frame(frame: &[u8]) -> Option<Self> {
+        parse(frame)?.five_tuple()
+    }
+
+    /// The same flow seen from the other direction
+    pub fn reversed(&self) -> Self {
+        Self {
+            source: self.destination,
+            destination: self.source,
+            protocol: self.protocol,
+            source_port: self.destination_port,
+            destination_port: self.source_port,
+        }
+    }
+}
+
+/// Parse an ethernet frame, skipping VLAN tags, down to its transport header
+/// 
+/// Returns `None` only when the ethernet header itself is truncated, deeper layers are `None` when unknown or malformed
+pub fn parse(frame: &[u8]) -> Option<ParsedFrame<'_>> {
+    let ethernet = EthernetFrame::new_checked(frame)?;
+
+    // network
+    let network_bytes = &frame[ethernet.payload_offset()..];
+    let network = match ethernet.inner_ethertype() {
+        ethertype::IPV4 => Ipv4Packet::new_checked(network_bytes).map(NetworkHeader::Ipv4),
+        ethertype::IPV6 => Ipv6Packet::new_checked(network_bytes).map(NetworkHeader::Ipv6),
+        _ => None,
+    };
+
+    // transport
+    let transport_bytes = match &network {
+        Some(NetworkHeader::Ipv4(ipv4)) if ipv4.fragment_offset() == 0 => {
+            Some((ipv4.protocol(), &network_bytes[ipv4.header_len()..ipv4.total_len()]))
+        },
+        Some(NetworkHeader::Ipv6(ipv6)) => ipv6.upper_layer().map(|(protocol, offset)| {
+            (protocol, &network_bytes[Ipv6Packet::<&[u8]>::HEADER_SIZE + offset..Ipv6Packet::<&[u8]>::HEADER_SIZE + ipv6.payload_len()])
+        }),
+        _ => None,
+    };
+    let transport = match transport_bytes {
+        Some((ip_protocol::TCP, bytes)) => TcpSegment::new_checked(bytes).map(TransportHeader::Tcp),
+        Some((ip_protocol::UDP, bytes)) => UdpDatagram::new_checked(bytes).map(TransportHeader::Udp),
+        _ => None,
+    };
+
+    Some(ParsedFrame { ethernet, network, transport })
+}
+
+pub(crate) fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+pub(crate) fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buffer[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn write_u16(buffer: &mut [u8], offset: usize, value: u16) {
+    buffer[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::{parse, MacAddress, NetworkHeader, TransportHeader};
+    use crate::pktgen::{PacketGenerator, PacketTemplate, ValueRange};
+
+    #[test]
+    fn test_parse_udp_ipv4() {
+        let mut frame = [0_u8; 128];
+        let len = PacketGenerator::new(PacketTemplate {
+            dst_mac: ValueRange::single([0x54, 0, 0, 0, 0, 0x10]),
+            dst_ip: ValueRange::single(Ipv4Addr::new(10, 42, 0, 10)),
+            dst_port: ValueRange::single(53),
+            ..Default::default()
+        }).next_into(&mut frame);
+
+        let parsed = parse(&frame[..len]).unwrap();
+        assert_eq!(parsed.ethernet.destination(), MacAddress([0x54, 0, 0, 0, 0, 0x10]));
+        let Some(NetworkHeader::Ipv4(ipv4)) = parsed.network else { panic!("not ipv4") };
+        assert_eq!(ipv4.destination(), Ipv4Addr::new(10, 42, 0, 10));
+        let Some(TransportHeader::Udp(udp)) = parsed.transport else { panic!("not udp") };
+        assert_eq!(udp.destination_port(), 53);
+        assert_eq!(udp.payload().len(), len - 42);
+    }
+
+    #[test]
+    fn test_parse_tcp_ipv6_vlan() {
+        let mut frame = vec![0_u8; 14 + 4 + 40 + 20];
+        frame[12..14].copy_from_slice(&0x8100_u16.to_be_bytes());
+        frame[14..16].copy_from_slice(&0x2064_u16.to_be_bytes());
+        frame[16..18].copy_from_slice(&0x86DD_u16.to_be_bytes());
+        frame[18] = 0x60;
+        frame[22..24].copy_from_slice(&20_u16.to_be_bytes());
+        frame[24] = 6;
+        frame[58..60].copy_from_slice(&443_u16.to_be_bytes());
+        frame[70] = 5 << 4;
+        frame[71] = 0x02;
+
+        let parsed = parse(&frame).unwrap();
+        let tags = parsed.ethernet.vlan_tags().collect::<Vec<_>>();
+        assert_eq!(tags.len(), 1);
+        assert_eq!((tags[0].pcp, tags[0].vid), (1, 100));
+        assert!(matches!(parsed.network, Some(NetworkHeader::Ipv6(_))));
+        let Some(TransportHeader::Tcp(tcp)) = parsed.transport else { panic!("not tcp") };
+        assert_eq!(tcp.source_port(), 443);
+        assert!(tcp.syn() && ! tcp.ack());
+    }
+}
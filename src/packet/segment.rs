@@ -0,0 +1,90 @@
+use std::ops::Range;
+
+use super::FrameBuilder;
+
+/// Splits payloads too large for a single frame into several, each carrying its own copy of the headers
+///
+/// This is what UDP GSO does in the kernel: every segment is a complete datagram of up to [`Self::segment_size`] bytes
+/// of payload, so the receiver sees a burst of datagrams rather than one. Lengths and checksums are computed per segment
+/// by the [`FrameBuilder`]
+#[derive(Debug, Clone)]
+pub struct Segmenter {
+    builder: FrameBuilder,
+    segment_size: usize,
+}
+impl Segmenter {
+    /// Segment payloads behind the headers of `builder` into packets of at most `mtu` bytes, link headers excluded
+    pub fn new(builder: FrameBuilder, mtu: usize) -> Self {
+        let headers = builder.header_len() - builder.link_header_len();
+        assert!(mtu > headers, "MTU leaves no room for the payload");
+        Self { segment_size: mtu - headers, builder }
+    }
+
+    pub const fn builder(&self) -> &FrameBuilder {
+        &self.builder
+    }
+
+    /// The most payload bytes carried by a segment
+    pub const fn segment_size(&self) -> usize {
+        self.segment_size
+    }
+
+    /// The length of the largest frame written, with headers
+    pub fn max_frame_len(&self) -> usize {
+        self.builder.header_len() + self.segment_size
+    }
+
+    /// How many frames `payload_len` bytes take, an empty payload taking one
+    pub const fn segment_count(&self, payload_len: usize) -> usize {
+        if payload_len == 0 { 1 } else { payload_len.div_ceil(self.segment_size) }
+    }
+
+    /// The range of a `payload_len` bytes payload carried by each segment, in order
+    pub fn segments(&self, payload_len: usize) -> impl Iterator<Item = Range<usize>> + use<> {
+        let segment_size = self.segment_size;
+        (0..self.segment_count(payload_len)).map(move |index| index * segment_size..((index + 1) * segment_size).min(payload_len))
+    }
+
+    /// Write segment `index` of `payload` into `buffer`, returning the frame length
+    pub fn write_segment(&self, buffer: &mut [u8], payload: &[u8], index: usize) -> Result<usize, crate::Error> {
+        let start = index * self.segment_size;
+        assert!(start < payload.len() || index == 0, "Segment {index} lies past the payload");
+        self.builder.write_with_payload(buffer, &payload[start..(start + self.segment_size).min(payload.len())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::Segmenter;
+    use crate::packet::{checksum, parse, FrameBuilder, MacAddress, NetworkHeader, TransportHeader};
+
+    #[test]
+    fn test_segment_udp() {
+        let builder = FrameBuilder::ethernet(MacAddress([2, 0, 0, 0, 0, 1]), MacAddress([2, 0, 0, 0, 0, 2]))
+            .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2))
+            .udp(1000, 2000);
+        let segmenter = Segmenter::new(builder, 1500);
+        assert_eq!(segmenter.segment_size(), 1472);
+        assert_eq!(segmenter.max_frame_len(), 1514);
+        assert_eq!(segmenter.segment_count(0), 1);
+        assert_eq!(segmenter.segment_count(1472), 1);
+        assert_eq!(segmenter.segments(3000).collect::<Vec<_>>(), [0..1472, 1472..2944, 2944..3000]);
+
+        // every segment is a complete datagram
+        let payload = (0..3000).map(|i| i as u8).collect::<Vec<_>>();
+        let mut reassembled = Vec::new();
+        for index in 0..3 {
+            let mut frame = [0_u8; 2048];
+            let len = segmenter.write_segment(&mut frame, &payload, index).unwrap();
+            let parsed = parse(&frame[..len]).unwrap();
+            let Some(NetworkHeader::Ipv4(ipv4)) = parsed.network else { panic!("not ipv4") };
+            let Some(TransportHeader::Udp(udp)) = parsed.transport else { panic!("not udp") };
+            assert!(ipv4.total_len() <= 1500);
+            assert_eq!(checksum::udp_ipv4(ipv4.source(), ipv4.destination(), ipv4.payload()), udp.checksum());
+            reassembled.extend_from_slice(udp.payload());
+        }
+        assert_eq!(reassembled, payload);
+    }
+}
@@ -0,0 +1,162 @@
+use super::{read_u16, read_u32, write_u16};
+
+/// A view over a UDP datagram
+#[derive(Debug, Clone, Copy)]
+pub struct UdpDatagram<T> {
+    buffer: T,
+}
+impl<T: AsRef<[u8]>> UdpDatagram<T> {
+    /// Size of the header
+    pub const HEADER_SIZE: usize = 8;
+
+    /// Wrap `buffer`, checking that the header fits and the length field is coherent
+    pub fn new_checked(buffer: T) -> Option<Self> {
+        let bytes = buffer.as_ref();
+        if bytes.len() < Self::HEADER_SIZE {
+            return None;
+        }
+        let length = read_u16(bytes, 4) as usize;
+        if length < Self::HEADER_SIZE || length > bytes.len() {
+            return None;
+        }
+        Some(Self { buffer })
+    }
+
+    /// Get back the wrapped buffer
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
+    pub fn source_port(&self) -> u16 {
+        read_u16(self.buffer.as_ref(), 0)
+    }
+
+    pub fn destination_port(&self) -> u16 {
+        read_u16(self.buffer.as_ref(), 2)
+    }
+
+    /// The length of header and payload
+    pub fn length(&self) -> usize {
+        read_u16(self.buffer.as_ref(), 4) as usize
+    }
+
+    pub fn checksum(&self) -> u16 {
+        read_u16(self.buffer.as_ref(), 6)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer.as_ref()[Self::HEADER_SIZE..self.length()]
+    }
+}
+impl<T: AsRef<[u8]> + AsMut<[u8]>> UdpDatagram<T> {
+    pub fn set_source_port(&mut self, value: u16) {
+        write_u16(self.buffer.as_mut(), 0, value);
+    }
+
+    pub fn set_destination_port(&mut self, value: u16) {
+        write_u16(self.buffer.as_mut(), 2, value);
+    }
+
+    pub fn set_checksum(&mut self, value: u16) {
+        write_u16(self.buffer.as_mut(), 6, value);
+    }
+
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let length = self.length();
+        &mut self.buffer.as_mut()[Self::HEADER_SIZE..length]
+    }
+}
+
+/// A view over a TCP segment
+#[derive(Debug, Clone, Copy)]
+pub struct TcpSegment<T> {
+    buffer: T,
+}
+impl<T: AsRef<[u8]>> TcpSegment<T> {
+    /// Size of the header without options
+    pub const MIN_HEADER_SIZE: usize = 20;
+
+    /// Wrap `buffer`, checking that the header, options included, fits
+    pub fn new_checked(buffer: T) -> Option<Self> {
+        let bytes = buffer.as_ref();
+        if bytes.len() < Self::MIN_HEADER_SIZE {
+            return None;
+        }
+        let header_len = (bytes[12] >> 4) as usize * 4;
+        if header_len < Self::MIN_HEADER_SIZE || header_len > bytes.len() {
+            return None;
+        }
+        Some(Self { buffer })
+    }
+
+    /// Get back the wrapped buffer
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+
+    pub fn source_port(&self) -> u16 {
+        read_u16(self.buffer.as_ref(), 0)
+    }
+
+    pub fn destination_port(&self) -> u16 {
+        read_u16(self.buffer.as_ref(), 2)
+    }
+
+    pub fn sequence_number(&self) -> u32 {
+        read_u32(self.buffer.as_ref(), 4)
+    }
+
+    pub fn acknowledgment_number(&self) -> u32 {
+        read_u32(self.buffer.as_ref(), 8)
+    }
+
+    pub fn header_len(&self) -> usize {
+        (self.buffer.as_ref()[12] >> 4) as usize * 4
+    }
+
+    /// The flags byte, i.e. CWR ECE URG ACK PSH RST SYN FIN from the most significant bit
+    pub fn flags(&self) -> u8 {
+        self.buffer.as_ref()[13]
+    }
+
+    pub fn fin(&self) -> bool {
+        self.flags() & 0x01 != 0
+    }
+
+    pub fn syn(&self) -> bool {
+        self.flags() & 0x02 != 0
+    }
+
+    pub fn rst(&self) -> bool {
+        self.flags() & 0x04 != 0
+    }
+
+    pub fn ack(&self) -> bool {
+        self.flags() & 0x10 != 0
+    }
+
+    pub fn window(&self) -> u16 {
+        read_u16(self.buffer.as_ref(), 14)
+    }
+
+    pub fn checksum(&self) -> u16 {
+        read_u16(self.buffer.as_ref(), 16)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer.as_ref()[self.header_len()..]
+    }
+}
+impl<T: AsRef<[u8]> + AsMut<[u8]>> TcpSegment<T> {
+    pub fn set_source_port(&mut self, value: u16) {
+        write_u16(self.buffer.as_mut(), 0, value);
+    }
+
+    pub fn set_destination_port(&mut self, value: u16) {
+        write_u16(self.buffer.as_mut(), 2, value);
+    }
+
+    pub fn set_checksum(&mut self, value: u16) {
+        write_u16(self.buffer.as_mut(), 16, value);
+    }
+}
@@ -0,0 +1,81 @@
+use std::ops::Range;
+
+use super::{ethertype, read_u16, write_u16, VlanTag};
+
+/// Size of an 802.1Q/802.1ad tag
+pub const VLAN_TAG_SIZE: usize = 4;
+
+/// Insert `tag` as the outermost tag of the frame found at `frame` within `chunk`, returning where the frame now is
+///
+/// The MAC addresses are moved into the headroom in front of the frame, which must be at least [`VLAN_TAG_SIZE`] bytes
+pub fn push_vlan_tag(chunk: &mut [u8], frame: Range<usize>, tag: VlanTag) -> Result<Range<usize>, crate::Error> {
+    if frame.start < VLAN_TAG_SIZE {
+        return Err(crate::Error::BufferTooSmall { required: VLAN_TAG_SIZE, available: frame.start });
+    }
+    if frame.len() < 12 {
+        return Err(crate::Error::BufferTooSmall { required: 12, available: frame.len() });
+    }
+
+    // shift the addresses
+    let start = frame.start - VLAN_TAG_SIZE;
+    chunk.copy_within(frame.start..frame.start + 12, start);
+
+    // write the tag
+    write_u16(chunk, start + 12, tag.tpid);
+    write_u16(chunk, start + 14, tag.tci());
+    Ok(start..frame.end)
+}
+
+/// Remove the outermost tag of the frame found at `frame` within `chunk`, returning it along with where the frame now is
+///
+/// The MAC addresses are moved over the tag, leaving its bytes as headroom; returns `None` if the frame is untagged
+pub fn pop_vlan_tag(chunk: &mut [u8], frame: Range<usize>) -> Option<(VlanTag, Range<usize>)> {
+    if frame.len() < 12 + VLAN_TAG_SIZE + 2 {
+        return None;
+    }
+    let tpid = read_u16(chunk, frame.start + 12);
+    if ! matches!(tpid, ethertype::VLAN | ethertype::QINQ) {
+        return None;
+    }
+    let tag = VlanTag::from_tci(tpid, read_u16(chunk, frame.start + 14));
+
+    // shift the addresses
+    let start = frame.start + VLAN_TAG_SIZE;
+    chunk.copy_within(frame.start..frame.start + 12, start);
+    Some((tag, start..frame.end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pop_vlan_tag, push_vlan_tag};
+    use crate::packet::{ethertype, EthernetFrame, MacAddress, VlanTag};
+
+    #[test]
+    fn test_push_pop() {
+        let mut chunk = [0_u8; 64];
+        chunk[8..14].copy_from_slice(&[0x54, 0, 0, 0, 0, 0x02]);
+        chunk[14..20].copy_from_slice(&[0x54, 0, 0, 0, 0, 0x01]);
+        chunk[20..22].copy_from_slice(&ethertype::IPV4.to_be_bytes());
+        let frame = 8..60;
+
+        // not enough headroom
+        let tag = VlanTag { tpid: ethertype::VLAN, pcp: 3, dei: false, vid: 42 };
+        assert!(push_vlan_tag(&mut chunk, 2..60, tag).is_err());
+
+        // push a C-tag then an S-tag
+        let frame = push_vlan_tag(&mut chunk, frame, tag).unwrap();
+        let frame = push_vlan_tag(&mut chunk, frame, VlanTag { tpid: ethertype::QINQ, vid: 7, ..tag }).unwrap();
+        assert_eq!(frame, 0..60);
+        let ethernet = EthernetFrame::new_checked(&chunk[frame.clone()]).unwrap();
+        assert_eq!(ethernet.source(), MacAddress([0x54, 0, 0, 0, 0, 0x01]));
+        assert_eq!(ethernet.vlan_tags().map(|tag| tag.vid).collect::<Vec<_>>(), [7, 42]);
+        assert_eq!(ethernet.inner_ethertype(), ethertype::IPV4);
+
+        // pop them back
+        let (outer, frame) = pop_vlan_tag(&mut chunk, frame).unwrap();
+        let (inner, frame) = pop_vlan_tag(&mut chunk, frame).unwrap();
+        assert_eq!((outer.tpid, outer.vid, inner, frame.clone()), (ethertype::QINQ, 7, tag, 8..60));
+        assert!(pop_vlan_tag(&mut chunk, frame).is_none());
+        assert_eq!(chunk[8..14], [0x54, 0, 0, 0, 0, 0x02]);
+    }
+}
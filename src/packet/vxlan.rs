@@ -0,0 +1,167 @@
+use std::ops::Range;
+
+use super::{parse, read_u32, FrameBuilder, NetworkHeader, TransportHeader};
+
+/// The IANA assigned VXLAN UDP port
+pub const VXLAN_PORT: u16 = 4789;
+
+/// A view over a VXLAN header
+#[derive(Debug, Clone, Copy)]
+pub struct VxlanHeader<T> {
+    buffer: T,
+}
+impl<T: AsRef<[u8]>> VxlanHeader<T> {
+    /// Size of the header
+    pub const HEADER_SIZE: usize = 8;
+
+    /// Wrap `buffer`, checking that the header fits and carries a valid VNI
+    pub fn new_checked(buffer: T) -> Option<Self> {
+        let bytes = buffer.as_ref();
+        if bytes.len() < Self::HEADER_SIZE || bytes[0] & 0x08 == 0 {
+            return None;
+        }
+        Some(Self { buffer })
+    }
+
+    /// The VXLAN network identifier
+    pub fn vni(&self) -> u32 {
+        read_u32(self.buffer.as_ref(), 4) >> 8
+    }
+
+    /// The encapsulated ethernet frame
+    pub fn payload(&self) -> &[u8] {
+        &self.buffer.as_ref()[Self::HEADER_SIZE..]
+    }
+}
+impl<T: AsRef<[u8]> + AsMut<[u8]>> VxlanHeader<T> {
+    /// Write a header carrying `vni` at the start of `buffer`, which must hold at least [`Self::HEADER_SIZE`] bytes
+    pub fn write(mut buffer: T, vni: u32) -> Self {
+        let header = &mut buffer.as_mut()[..Self::HEADER_SIZE];
+        header.fill(0);
+        header[0] = 0x08;
+        header[4..8].copy_from_slice(&(vni << 8).to_be_bytes());
+        Self { buffer }
+    }
+
+    pub fn set_vni(&mut self, value: u32) {
+        let bytes = self.buffer.as_mut();
+        bytes[4..7].copy_from_slice(&value.to_be_bytes()[1..4]);
+    }
+}
+
+/// Wraps ethernet frames into VXLAN, writing the outer headers into the chunk headroom
+///
+/// The outer Ethernet/IP headers come from a [`FrameBuilder`], the UDP source port is derived from the inner flow
+/// so that ECMP spreads tunnelled traffic; RFC 7348 recommends disabling UDP checksums over IPv4
+#[derive(Debug, Clone)]
+pub struct VxlanEncap {
+    outer: FrameBuilder,
+    vni: u32,
+    destination_port: u16,
+}
+impl VxlanEncap {
+    /// Encapsulate into `vni`, with the outer headers described by `outer` (without its UDP layer)
+    pub fn new(outer: FrameBuilder, vni: u32) -> Self {
+        assert!(vni < 1 << 24, "VNI must fit in 24 bits");
+        Self { outer, vni, destination_port: VXLAN_PORT }
+    }
+
+    /// Use a UDP destination port other than [`VXLAN_PORT`]
+    pub fn destination_port(mut self, port: u16) -> Self {
+        self.destination_port = port;
+        self
+    }
+
+    /// The headroom needed in front of a frame to encapsulate it
+    pub fn overhead(&self) -> usize {
+        self.outer.clone().udp(0, 0).header_len() + VxlanHeader::<&[u8]>::HEADER_SIZE
+    }
+
+    /// Encapsulate the frame found at `frame` within `chunk`, returning where the encapsulated frame is
+    pub fn encapsulate(&self, chunk: &mut [u8], frame: Range<usize>) -> Result<Range<usize>, crate::Error> {
+        let overhead = self.overhead();
+        if frame.start < overhead {
+            return Err(crate::Error::BufferTooSmall { required: overhead, available: frame.start });
+        }
+        let source_port = flow_source_port(&chunk[frame.clone()]);
+
+        // vxlan
+        let vxlan_start = frame.start - VxlanHeader::<&[u8]>::HEADER_SIZE;
+        VxlanHeader::write(&mut chunk[vxlan_start..frame.start], self.vni);
+
+        // outer headers
+        let start = frame.start - overhead;
+        let builder = self.outer.clone().udp(source_port, self.destination_port);
+        builder.write(&mut chunk[start..frame.end], frame.end - vxlan_start)?;
+        Ok(start..frame.end)
+    }
+}
+
+/// Look for a VXLAN packet sent to `port`, returning its VNI and the range of the inner frame within `frame`
+pub fn decapsulate_vxlan(frame: &[u8], port: u16) -> Option<(u32, Range<usize>)> {
+    let Some(TransportHeader::Udp(udp)) = parse(frame)?.transport else {
+        return None;
+    };
+    if udp.destination_port() != port {
+        return None;
+    }
+    let vxlan = VxlanHeader::new_checked(udp.payload())?;
+    let inner = vxlan.payload();
+    let start = inner.as_ptr() as usize - frame.as_ptr() as usize;
+    Some((vxlan.vni(), start..start + inner.len()))
+}
+
+/// A UDP source port in the dynamic range, stable for every flow
+fn flow_source_port(inner: &[u8]) -> u16 {
+    // fnv-1a over addresses and ports
+    let mut hash = 0xcbf29ce484222325_u64;
+    let mut feed = |bytes: &[u8]| for byte in bytes {
+        hash = (hash ^ *byte as u64).wrapping_mul(0x100000001b3);
+    };
+    feed(&inner[..inner.len().min(12)]);
+    if let Some(parsed) = parse(inner) {
+        match parsed.network {
+            Some(NetworkHeader::Ipv4(ipv4)) => { feed(&ipv4.source().octets()); feed(&ipv4.destination().octets()); },
+            Some(NetworkHeader::Ipv6(ipv6)) => { feed(&ipv6.source().octets()); feed(&ipv6.destination().octets()); },
+            None => {},
+        }
+        match parsed.transport {
+            Some(TransportHeader::Tcp(tcp)) => { feed(&tcp.source_port().to_be_bytes()); feed(&tcp.destination_port().to_be_bytes()); },
+            Some(TransportHeader::Udp(udp)) => { feed(&udp.source_port().to_be_bytes()); feed(&udp.destination_port().to_be_bytes()); },
+            None => {},
+        }
+    }
+    49152 + (hash % 16384) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::{decapsulate_vxlan, VxlanEncap, VXLAN_PORT};
+    use crate::packet::{FrameBuilder, MacAddress};
+
+    #[test]
+    fn test_encap_decap() {
+        let mut chunk = [0_u8; 256];
+        let inner = FrameBuilder::ethernet(MacAddress([2, 0, 0, 0, 0, 1]), MacAddress([2, 0, 0, 0, 0, 2]))
+            .ipv4(Ipv4Addr::new(192, 168, 0, 1), Ipv4Addr::new(192, 168, 0, 2))
+            .udp(1000, 2000);
+        let len = inner.write_with_payload(&mut chunk[128..], b"inner").unwrap();
+        let frame = 128..128 + len;
+        let original = chunk[frame.clone()].to_vec();
+
+        let outer = FrameBuilder::ethernet(MacAddress([2, 0, 0, 0, 0, 0xA]), MacAddress([2, 0, 0, 0, 0, 0xB]))
+            .ipv4(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2))
+            .udp_checksum(false);
+        let encap = VxlanEncap::new(outer, 0xABCDE);
+        assert_eq!(encap.overhead(), 50);
+        assert!(encap.encapsulate(&mut chunk, 40..40 + len).is_err());
+        let outer_frame = encap.encapsulate(&mut chunk, frame).unwrap();
+        assert_eq!(outer_frame.start, 128 - 50);
+
+        let (vni, inner_range) = decapsulate_vxlan(&chunk[outer_frame.clone()], VXLAN_PORT).unwrap();
+        assert_eq!(vni, 0xABCDE);
+        assert_eq!(chunk[outer_frame][inner_range], original[..]);
+    }
+}
@@ -0,0 +1,145 @@
+//! The PTP hardware clock (PHC) of a NIC, which hardware timestamps are taken from
+//!
+//! RX hardware timestamps, e.g. the ones `bpf_xdp_metadata_rx_timestamp` hands to XDP programs, count nanoseconds of the
+//! PHC. Unless something like `phc2sys` keeps it in step with the system, the PHC has an arbitrary offset, so timestamps
+//! of different NICs or hosts are only comparable once converted with a [`PhcCorrelation`]
+
+use std::{fs::File, os::fd::AsRawFd, path::Path};
+
+/// How many readings [`PhcClock::correlate`] takes, keeping the tightest
+const CORRELATION_SAMPLES: usize = 8;
+
+/// A clock of the system which PHC timestamps can be converted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SystemClock {
+    Realtime,
+    /// Realtime without leap seconds, which PTP distributes
+    Tai,
+    Monotonic,
+}
+impl SystemClock {
+    const fn id(self) -> libc::clockid_t {
+        match self {
+            Self::Realtime => libc::CLOCK_REALTIME,
+            Self::Tai => libc::CLOCK_TAI,
+            Self::Monotonic => libc::CLOCK_MONOTONIC,
+        }
+    }
+
+    /// The current time, in nanoseconds
+    pub fn now(self) -> Result<u64, crate::Error> {
+        read_clock(self.id())
+    }
+}
+
+/// An open PTP hardware clock
+#[derive(Debug)]
+pub struct PhcClock {
+    device: File,
+}
+impl PhcClock {
+    /// Open the PHC of the interface named `if_name`
+    ///
+    /// Fails with [`crate::Error::ClockFailure`] carrying `ENODEV` if the interface has no PHC
+    pub fn for_interface(if_name: &str) -> Result<Self, crate::Error> {
+        let index = crate::ethtool::phc_index(if_name)?
+            .ok_or(crate::Error::ClockFailure { error: std::io::Error::from_raw_os_error(libc::ENODEV) })?;
+        Self::open(format!("/dev/ptp{index}"))
+    }
+
+    /// Open the PHC device at `path`, e.g. `/dev/ptp0`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let device = File::open(path).map_err(|error| crate::Error::ClockFailure { error })?;
+        Ok(Self { device })
+    }
+
+    /// The dynamic clock id of the device, see `FD_TO_CLOCKID` in the kernel documentation
+    fn id(&self) -> libc::clockid_t {
+        clock_id(self.device.as_raw_fd())
+    }
+
+    /// The current time of the PHC, in nanoseconds
+    pub fn now(&self) -> Result<u64, crate::Error> {
+        read_clock(self.id())
+    }
+
+    /// Read the PHC along with `clock`, to convert timestamps of the former into the latter
+    ///
+    /// The clocks drift apart unless they are synchronized, so correlate again every now and then
+    pub fn correlate(&self, clock: SystemClock) -> Result<PhcCorrelation, crate::Error> {
+        correlate(clock, || self.now(), || clock.now())
+    }
+}
+
+/// A simultaneous reading of a PHC and of a system clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhcCorrelation {
+    pub clock: SystemClock,
+    pub phc_ns: u64,
+    pub system_ns: u64,
+    /// How long reading the PHC took, bounding the error of conversions
+    pub uncertainty_ns: u64,
+}
+impl PhcCorrelation {
+    /// Convert `phc_ns`, e.g. a hardware timestamp, into nanoseconds of [`Self::clock`]
+    pub fn convert(&self, phc_ns: u64) -> u64 {
+        self.system_ns.wrapping_add(phc_ns.wrapping_sub(self.phc_ns))
+    }
+
+    /// How far ahead of [`Self::clock`] the PHC is, in nanoseconds
+    pub fn offset_ns(&self) -> i64 {
+        self.phc_ns.wrapping_sub(self.system_ns) as i64
+    }
+}
+
+/// Read the PHC between two readings of `clock`, keeping the tightest of a few attempts, like `phc2sys` does
+fn correlate(
+    clock: SystemClock,
+    mut phc: impl FnMut() -> Result<u64, crate::Error>,
+    mut system: impl FnMut() -> Result<u64, crate::Error>,
+) -> Result<PhcCorrelation, crate::Error> {
+    let mut best: Option<PhcCorrelation> = None;
+    for _ in 0..CORRELATION_SAMPLES {
+        let before = system()?;
+        let phc_ns = phc()?;
+        let after = system()?;
+        let uncertainty_ns = after.saturating_sub(before);
+        if best.is_none_or(|best| uncertainty_ns < best.uncertainty_ns) {
+            best = Some(PhcCorrelation { clock, phc_ns, system_ns: before + uncertainty_ns / 2, uncertainty_ns });
+        }
+    }
+    Ok(best.unwrap())
+}
+
+fn clock_id(fd: libc::c_int) -> libc::clockid_t {
+    ((! fd) << 3) | 3
+}
+
+fn read_clock(id: libc::clockid_t) -> Result<u64, crate::Error> {
+    let mut time: libc::timespec = unsafe { std::mem::zeroed() };
+    if unsafe { libc::clock_gettime(id, &mut time) } < 0 {
+        return Err(crate::Error::ClockFailure { error: std::io::Error::last_os_error() });
+    }
+    Ok(time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clock_id, correlate, PhcCorrelation, SystemClock};
+
+    #[test]
+    fn test_correlation() {
+        assert_eq!(clock_id(3), -29);
+
+        // the tightest reading wins, the PHC is 1000 ahead
+        let mut system = [0, 100, 200, 210, 300, 340].into_iter().cycle();
+        let mut phc = [1050, 1205, 1320].into_iter().cycle();
+        let correlation = correlate(SystemClock::Tai, || Ok(phc.next().unwrap()), || Ok(system.next().unwrap())).unwrap();
+        assert_eq!(correlation, PhcCorrelation { clock: SystemClock::Tai, phc_ns: 1205, system_ns: 205, uncertainty_ns: 10 });
+        assert_eq!(correlation.offset_ns(), 1000);
+        assert_eq!(correlation.convert(5000), 4000);
+
+        // timestamps from before the correlation work too
+        assert_eq!(correlation.convert(1100), 100);
+    }
+}
@@ -0,0 +1,116 @@
+//! Packet processing as a chain of stages, e.g. filter → NAT → forward, driven by a [`crate::worker::Worker`]
+
+/// A received frame travelling through a [`Pipeline`]
+#[derive(Debug)]
+pub struct FrameCtx<'f> {
+    /// The port the frame was received on
+    pub ingress: usize,
+    /// The frame, which stages may modify in place
+    pub data: &'f mut [u8],
+}
+
+/// What a stage decided about a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Hand the frame to the next stage, past the last one it is dropped
+    Continue,
+    /// Transmit the frame on another port
+    Forward(usize),
+    /// Transmit the frame back on the port it came from
+    Transmit,
+    /// Discard the frame
+    Drop,
+    /// The stage is done with the frame, e.g. it answered or recorded it, and its chunk can be reused
+    Consume,
+}
+
+/// One step of a [`Pipeline`]
+pub trait Stage {
+    fn process(&mut self, ctx: &mut FrameCtx) -> Verdict;
+
+    /// Periodic housekeeping, e.g. aging a [`crate::flow::FlowTable`], see [`crate::worker::Worker::with_tick_interval`]
+    fn tick(&mut self, now: std::time::Instant) {
+        let _ = now;
+    }
+}
+impl<F: FnMut(&mut FrameCtx) -> Verdict> Stage for F {
+    fn process(&mut self, ctx: &mut FrameCtx) -> Verdict {
+        self(ctx)
+    }
+}
+
+/// Stages run in order until one of them returns something other than [`Verdict::Continue`]
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage + Send>>,
+}
+impl Pipeline {
+    /// An empty pipeline, dropping everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `stage`
+    pub fn with_stage(mut self, stage: impl Stage + Send + 'static) -> Self {
+        self.push(stage);
+        self
+    }
+
+    /// Append `stage`
+    pub fn push(&mut self, stage: impl Stage + Send + 'static) {
+        self.stages.push(Box::new(stage));
+    }
+
+    /// The number of stages
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    /// Whether there are no stages
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Run the stages over `ctx`, returning the first verdict other than [`Verdict::Continue`], or [`Verdict::Drop`]
+    pub fn process(&mut self, ctx: &mut FrameCtx) -> Verdict {
+        for stage in self.stages.iter_mut() {
+            match stage.process(ctx) {
+                Verdict::Continue => continue,
+                verdict => return verdict,
+            }
+        }
+        Verdict::Drop
+    }
+
+    /// Let every stage do its housekeeping
+    pub fn tick(&mut self, now: std::time::Instant) {
+        for stage in self.stages.iter_mut() {
+            stage.tick(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameCtx, Pipeline, Verdict};
+
+    #[test]
+    fn test_pipeline_order() {
+        let mut pipeline = Pipeline::new()
+            .with_stage(|ctx: &mut FrameCtx| if ctx.data[0] == b'x' { Verdict::Drop } else { Verdict::Continue })
+            .with_stage(|ctx: &mut FrameCtx| { ctx.data[0] = b'n'; Verdict::Continue })
+            .with_stage(|ctx: &mut FrameCtx| if ctx.ingress == 0 { Verdict::Forward(1) } else { Verdict::Continue });
+
+        let mut data = *b"abc";
+        assert_eq!(pipeline.process(&mut FrameCtx { ingress: 0, data: &mut data }), Verdict::Forward(1));
+        assert_eq!(&data, b"nbc");
+
+        // filtered frames are not modified, unhandled ones are dropped
+        let mut data = *b"xyz";
+        assert_eq!(pipeline.process(&mut FrameCtx { ingress: 0, data: &mut data }), Verdict::Drop);
+        assert_eq!(&data, b"xyz");
+        let mut data = *b"abc";
+        assert_eq!(pipeline.process(&mut FrameCtx { ingress: 1, data: &mut data }), Verdict::Drop);
+        assert_eq!(&data, b"nbc");
+    }
+}
@@ -0,0 +1,372 @@
+use std::net::Ipv4Addr;
+
+use crate::{packet::checksum, utils, UmemAllocator, XDPSocket};
+
+/// An inclusive range of values, cycled through one packet after the other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueRange<T> {
+    pub first: T,
+    pub last: T,
+}
+impl<T: Copy> ValueRange<T> {
+    /// A range containing only `value`
+    pub const fn single(value: T) -> Self {
+        Self { first: value, last: value }
+    }
+}
+
+/// How the size of generated frames (excluding the FCS) is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeDistribution {
+    /// Every frame has the same size
+    Fixed(usize),
+    /// Sizes are uniformly distributed in the inclusive range
+    Uniform { min: usize, max: usize },
+    /// The simple IMIX distribution, i.e. 7 frames of 60 bytes, 4 of 590 bytes, 1 of 1514 bytes
+    Imix,
+}
+
+/// What fills the UDP payload of generated frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadPattern {
+    /// A repeated byte
+    Byte(u8),
+    /// Bytes counting up from zero, wrapping around
+    Incrementing,
+    /// Pseudo-random bytes
+    Random,
+}
+
+/// The description of the Ethernet/IPv4/UDP frames to generate
+#[derive(Debug, Clone)]
+pub struct PacketTemplate {
+    pub src_mac: ValueRange<[u8; 6]>,
+    pub dst_mac: ValueRange<[u8; 6]>,
+    pub src_ip: ValueRange<Ipv4Addr>,
+    pub dst_ip: ValueRange<Ipv4Addr>,
+    pub src_port: ValueRange<u16>,
+    pub dst_port: ValueRange<u16>,
+    pub ttl: u8,
+    pub size: SizeDistribution,
+    pub payload: PayloadPattern,
+}
+impl Default for PacketTemplate {
+    fn default() -> Self {
+        Self {
+            src_mac: ValueRange::single([0x02, 0, 0, 0, 0, 0x01]),
+            dst_mac: ValueRange::single([0xFF; 6]),
+            src_ip: ValueRange::single(Ipv4Addr::new(10, 0, 0, 1)),
+            dst_ip: ValueRange::single(Ipv4Addr::new(10, 0, 0, 2)),
+            src_port: ValueRange::single(9),
+            dst_port: ValueRange::single(9),
+            ttl: 64,
+            size: SizeDistribution::Fixed(60),
+            payload: PayloadPattern::Byte(0),
+        }
+    }
+}
+
+/// Generates frames out of a [`PacketTemplate`], varying fields within their ranges
+pub struct PacketGenerator {
+    template: PacketTemplate,
+    sequence: u64,
+    random_state: u64,
+}
+impl PacketGenerator {
+    // constants
+    const HEADERS_SIZE: usize = 14 + 20 + 8;
+    const IMIX: [usize; 12] = [ 60, 60, 60, 60, 60, 60, 60, 590, 590, 590, 590, 1514 ];
+
+    /// Create a generator for `template`
+    pub fn new(template: PacketTemplate) -> Self {
+        Self { template, sequence: 0, random_state: 0x9E37_79B9_7F4A_7C15 }
+    }
+
+    /// The template this generator draws frames from
+    pub const fn template(&self) -> &PacketTemplate {
+        &self.template
+    }
+
+    /// Write the next frame into `buffer`, returning its length
+    ///
+    /// Frames are truncated to fit into `buffer`, but never below the size of the headers
+    pub fn next_into(&mut self, buffer: &mut [u8]) -> usize {
+        assert!(buffer.len() >= Self::HEADERS_SIZE, "Buffer can not fit Ethernet/IPv4/UDP headers");
+
+        // pick the frame size
+        let sequence = self.sequence;
+        self.sequence += 1;
+        let size = match self.template.size {
+            SizeDistribution::Fixed(size) => size,
+            SizeDistribution::Uniform { min, max } => min + (self.next_random() % (max.saturating_sub(min) as u64 + 1)) as usize,
+            SizeDistribution::Imix => Self::IMIX[(sequence % Self::IMIX.len() as u64) as usize],
+        }.clamp(Self::HEADERS_SIZE, buffer.len());
+        let frame = &mut buffer[..size];
+
+        // pick the fields
+        let src_mac = pick_mac(&self.template.src_mac, sequence);
+        let dst_mac = pick_mac(&self.template.dst_mac, sequence);
+        let src_ip = Ipv4Addr::from(pick(u32::from(self.template.src_ip.first) as u64, u32::from(self.template.src_ip.last) as u64, sequence) as u32);
+        let dst_ip = Ipv4Addr::from(pick(u32::from(self.template.dst_ip.first) as u64, u32::from(self.template.dst_ip.last) as u64, sequence) as u32);
+        let src_port = pick(self.template.src_port.first as u64, self.template.src_port.last as u64, sequence) as u16;
+        let dst_port = pick(self.template.dst_port.first as u64, self.template.dst_port.last as u64, sequence) as u16;
+
+        // ethernet
+        frame[0..6].copy_from_slice(&dst_mac);
+        frame[6..12].copy_from_slice(&src_mac);
+        frame[12..14].copy_from_slice(&0x0800_u16.to_be_bytes());
+
+        // ipv4
+        let ip_len = (size - 14) as u16;
+        let ip = &mut frame[14..34];
+        ip[0] = 0x45;
+        ip[1] = 0;
+        ip[2..4].copy_from_slice(&ip_len.to_be_bytes());
+        ip[4..6].copy_from_slice(&(sequence as u16).to_be_bytes());
+        ip[6..8].copy_from_slice(&0x4000_u16.to_be_bytes());
+        ip[8] = self.template.ttl;
+        ip[9] = 17;
+        ip[12..16].copy_from_slice(&src_ip.octets());
+        ip[16..20].copy_from_slice(&dst_ip.octets());
+        let checksum = checksum::ipv4_header(ip);
+        ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+        // udp, with checksum disabled
+        let udp = &mut frame[34..42];
+        udp[0..2].copy_from_slice(&src_port.to_be_bytes());
+        udp[2..4].copy_from_slice(&dst_port.to_be_bytes());
+        udp[4..6].copy_from_slice(&(ip_len - 20).to_be_bytes());
+        udp[6..8].fill(0);
+
+        // payload
+        match self.template.payload {
+            PayloadPattern::Byte(byte) => frame[42..].fill(byte),
+            PayloadPattern::Incrementing => frame[42..].iter_mut().enumerate().for_each(|(i, x)| *x = i as u8),
+            PayloadPattern::Random => for chunk in frame[42..].chunks_mut(8) {
+                let random = self.next_random().to_ne_bytes();
+                chunk.copy_from_slice(&random[..chunk.len()]);
+            },
+        }
+
+        size
+    }
+
+    /// xorshift64*
+    fn next_random(&mut self) -> u64 {
+        self.random_state ^= self.random_state >> 12;
+        self.random_state ^= self.random_state << 25;
+        self.random_state ^= self.random_state >> 27;
+        self.random_state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+/// Throughput achieved by a [`PktGen`] run
+#[derive(Debug, Clone, Default)]
+pub struct PktGenReport {
+    /// Frames handed over to the TX rings
+    pub packets: u64,
+    /// Bytes handed over to the TX rings
+    pub bytes: u64,
+    /// Time elapsed since the start of the run
+    pub elapsed: std::time::Duration,
+}
+impl PktGenReport {
+    /// Achieved packets per second, zero when no time elapsed
+    pub fn pps(&self) -> f64 {
+        self.per_second(self.packets as f64)
+    }
+
+    /// Achieved bits per second, excluding preamble, FCS and inter-frame gap, zero when no time elapsed
+    pub fn bps(&self) -> f64 {
+        self.per_second((self.bytes * 8) as f64)
+    }
+
+    fn per_second(&self, amount: f64) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        amount / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Transmits generated frames over one or more sockets, optionally at a target rate
+pub struct PktGen {
+    generator: PacketGenerator,
+    rate_pps: Option<f64>,
+    max_packets: Option<u64>,
+    max_duration: Option<std::time::Duration>,
+    report_interval: std::time::Duration,
+}
+impl PktGen {
+    // constants
+    const BURST_SIZE: usize = 64;
+
+    /// Create a traffic generator transmitting frames out of `template` as fast as possible, forever
+    pub fn new(template: PacketTemplate) -> Self {
+        Self {
+            generator: PacketGenerator::new(template),
+            rate_pps: None,
+            max_packets: None,
+            max_duration: None,
+            report_interval: std::time::Duration::from_secs(1),
+        }
+    }
+
+    /// Transmit at `packets_per_second` overall, across all sockets
+    pub fn rate_pps(mut self, packets_per_second: f64) -> Self {
+        assert!(packets_per_second > 0.0, "Rate must be positive");
+        self.rate_pps = Some(packets_per_second);
+        self
+    }
+
+    /// Stop after transmitting `packets` frames
+    pub fn max_packets(mut self, packets: u64) -> Self {
+        self.max_packets = Some(packets);
+        self
+    }
+
+    /// Stop after `duration` has elapsed
+    pub fn max_duration(mut self, duration: std::time::Duration) -> Self {
+        self.max_duration = Some(duration);
+        self
+    }
+
+    /// How often the progress callback of [`Self::run`] is invoked
+    pub fn report_interval(mut self, interval: std::time::Duration) -> Self {
+        self.report_interval = interval;
+        self
+    }
+
+    /// Transmit in a round-robin fashion over `targets`, each socket drawing chunks from its own allocator
+    ///
+    /// `progress` is invoked periodically with the cumulative report
+    pub fn run<A: UmemAllocator + ?Sized>(&mut self, targets: &mut [(&mut XDPSocket, &A)], mut progress: impl FnMut(&PktGenReport)) -> Result<PktGenReport, crate::Error> {
+        assert!(! targets.is_empty(), "No sockets to transmit on");
+
+        let start = std::time::Instant::now();
+        let mut report = PktGenReport::default();
+        let mut next_report = start + self.report_interval;
+
+        'run: loop {
+            for (socket, allocator) in targets.iter_mut() {
+                // check limits
+                report.elapsed = start.elapsed();
+                if self.max_packets.is_some_and(|max| report.packets >= max) || self.max_duration.is_some_and(|max| report.elapsed >= max) {
+                    break 'run;
+                }
+                if std::time::Instant::now() >= next_report {
+                    progress(&report);
+                    next_report += self.report_interval;
+                }
+
+                // give back completed chunks
+                socket.reap_completions(*allocator);
+
+                // enqueue a burst, generating in place
+                let mut batch = socket.tx_batch();
+                let mut enqueued = 0;
+                while enqueued < Self::BURST_SIZE && self.max_packets.is_none_or(|max| report.packets < max) {
+                    // pace
+                    if let Some(rate) = self.rate_pps {
+                        utils::wait_until(start + std::time::Duration::from_secs_f64(report.packets as f64 / rate));
+                    }
+
+                    // stop when out of room, chunks or admission by the rate limiter of the socket
+                    let mut len = 0;
+                    let generator = &mut self.generator;
+                    let sent = batch.send_with(*allocator, 0, |chunk| {
+                        len = generator.next_into(chunk);
+                        Ok(len)
+                    })?;
+                    if ! sent {
+                        break;
+                    }
+
+                    report.packets += 1;
+                    report.bytes += len as u64;
+                    enqueued += 1;
+                }
+
+                // one wakeup per burst
+                batch.commit()?;
+            }
+        }
+
+        // give back what has been transmitted so far
+        for (socket, allocator) in targets.iter_mut() {
+            socket.reap_completions(*allocator);
+        }
+        progress(&report);
+        Ok(report)
+    }
+}
+
+/// Pick the value at position `sequence` of the inclusive range
+fn pick(first: u64, last: u64, sequence: u64) -> u64 {
+    if last <= first {
+        return first;
+    }
+    first + sequence % (last - first + 1)
+}
+
+fn pick_mac(range: &ValueRange<[u8; 6]>, sequence: u64) -> [u8; 6] {
+    let to_u64 = |mac: &[u8; 6]| u64::from_be_bytes([0, 0, mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]]);
+    let value = pick(to_u64(&range.first), to_u64(&range.last), sequence).to_be_bytes();
+    value[2..8].try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::{PacketGenerator, PacketTemplate, PktGen, PktGenReport, SizeDistribution, ValueRange};
+    use crate::UmemAllocatorFactory;
+
+    #[test]
+    fn test_generator_ranges() {
+        let mut generator = PacketGenerator::new(PacketTemplate {
+            dst_ip: ValueRange { first: Ipv4Addr::new(10, 0, 0, 254), last: Ipv4Addr::new(10, 0, 1, 1) },
+            dst_port: ValueRange { first: 1000, last: 1001 },
+            size: SizeDistribution::Imix,
+            ..Default::default()
+        });
+        let mut buffer = [0_u8; 2048];
+        let mut sizes = Vec::new();
+        for i in 0..12 {
+            let len = generator.next_into(&mut buffer);
+            sizes.push(len);
+            let frame = &buffer[..len];
+            assert_eq!(&frame[30..34], &[[10, 0, 0, 254], [10, 0, 0, 255], [10, 0, 1, 0], [10, 0, 1, 1]][i % 4]);
+            assert_eq!(u16::from_be_bytes([frame[36], frame[37]]), 1000 + (i % 2) as u16);
+            assert_eq!(u16::from_be_bytes([frame[16], frame[17]]) as usize, len - 14);
+            assert_eq!(crate::packet::checksum::ipv4_header(&frame[14..34]), u16::from_be_bytes([frame[24], frame[25]]));
+        }
+        assert_eq!(sizes.iter().sum::<usize>(), 7 * 60 + 4 * 590 + 1514);
+    }
+
+    #[test]
+    fn test_report_rates() {
+        let mut report = PktGenReport { packets: 10, bytes: 1000, elapsed: std::time::Duration::ZERO };
+        assert_eq!((report.pps(), report.bps()), (0.0, 0.0));
+        report.elapsed = std::time::Duration::from_secs(2);
+        assert_eq!((report.pps(), report.bps()), (5.0, 4000.0));
+    }
+
+    #[test]
+    fn test_pktgen() {
+        let umem = std::sync::Arc::new(crate::Umem::new_2k(64).unwrap());
+        let (mut socket, mut mock) = crate::testing::MockXDP::new(umem.clone(), 16).unwrap();
+        let allocator = crate::DefaultAllocator::for_umem(umem);
+        socket.tx_rate_limiter = Some(crate::TxRateLimiter::new().with_pps(1.0, 3.0));
+
+        // frames go through the socket, its counters and its rate limiter
+        let mut pktgen = PktGen::new(PacketTemplate::default()).max_packets(5).max_duration(std::time::Duration::from_millis(50));
+        let report = pktgen.run(&mut [(&mut socket, &allocator)], |_| {}).unwrap();
+        assert_eq!(report.packets, 3);
+        assert_eq!(socket.traffic.tx_packets, 3);
+        assert_eq!(mock.transmitted_frames().len(), 3);
+
+        assert!(std::panic::catch_unwind(|| PktGen::new(PacketTemplate::default()).rate_pps(0.0)).is_err());
+        assert!(std::panic::catch_unwind(|| PktGen::new(PacketTemplate::default()).rate_pps(f64::NAN)).is_err());
+    }
+}
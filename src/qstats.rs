@@ -0,0 +1,176 @@
+//! Per-queue counters of the kernel, to tell whether frames are lost by the NIC, by the driver or by the socket
+//!
+//! The counters come from the `netdev` generic netlink family, which reports them from Linux 6.10 on. Drivers only
+//! report what they track, which is why every counter is optional
+
+use crate::{genl, napi::QueueKind, utils, XDPSocket};
+
+// netdev family, see linux/netdev.h
+const NETDEV_CMD_QSTATS_GET: u8 = 12;
+const NETDEV_A_QSTATS_IFINDEX: u16 = 1;
+const NETDEV_A_QSTATS_QUEUE_TYPE: u16 = 2;
+const NETDEV_A_QSTATS_QUEUE_ID: u16 = 3;
+const NETDEV_A_QSTATS_SCOPE: u16 = 4;
+const NETDEV_A_QSTATS_RX_PACKETS: u16 = 8;
+const NETDEV_A_QSTATS_RX_BYTES: u16 = 9;
+const NETDEV_A_QSTATS_TX_PACKETS: u16 = 10;
+const NETDEV_A_QSTATS_TX_BYTES: u16 = 11;
+const NETDEV_A_QSTATS_RX_ALLOC_FAIL: u16 = 12;
+const NETDEV_A_QSTATS_RX_HW_DROPS: u16 = 13;
+const NETDEV_A_QSTATS_RX_HW_DROP_OVERRUNS: u16 = 14;
+const NETDEV_A_QSTATS_RX_HW_DROP_RATELIMITS: u16 = 23;
+const NETDEV_A_QSTATS_TX_HW_DROPS: u16 = 24;
+const NETDEV_A_QSTATS_TX_HW_DROP_ERRORS: u16 = 25;
+const NETDEV_QSTATS_SCOPE_QUEUE: u32 = 1;
+const NETDEV_QUEUE_TYPE_RX: u64 = 0;
+
+/// The counters of a queue, those of RX queues being about reception and those of TX queues about transmission
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    pub queue_id: u32,
+    pub kind: QueueKind,
+    pub packets: Option<u64>,
+    pub bytes: Option<u64>,
+    /// Buffers the driver failed to allocate, which for zero-copy sockets means the fill ring was empty
+    pub alloc_failures: Option<u64>,
+    /// Frames dropped by the NIC, for any reason
+    pub hw_drops: Option<u64>,
+    /// Frames dropped by the NIC for lack of descriptors or bus bandwidth
+    pub hw_drop_overruns: Option<u64>,
+    /// Frames dropped by the NIC to enforce a rate limit
+    pub hw_drop_ratelimits: Option<u64>,
+    /// Frames the NIC failed to transmit because they were invalid
+    pub hw_drop_errors: Option<u64>,
+}
+impl QueueStats {
+    fn new(queue_id: u32, kind: QueueKind) -> Self {
+        Self {
+            queue_id,
+            kind,
+            packets: None,
+            bytes: None,
+            alloc_failures: None,
+            hw_drops: None,
+            hw_drop_overruns: None,
+            hw_drop_ratelimits: None,
+            hw_drop_errors: None,
+        }
+    }
+}
+
+/// Where the frames of the queue of a socket were lost, counters unknown to the driver being 0
+///
+/// Each counter is cumulative since the queue or the socket was set up, so compare two samples. Sockets sharing a
+/// queue see the same queue counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RxLoss {
+    /// Dropped by the NIC before reaching the driver
+    pub nic: u64,
+    /// Dropped by the driver for lack of buffers
+    pub driver: u64,
+    /// Found the fill ring empty, the application not handing chunks back fast enough
+    pub fill_ring_empty: u64,
+    /// Found the RX ring full, the application not consuming it fast enough
+    pub rx_ring_full: u64,
+    /// Dropped by the socket for any other reason, e.g. frames larger than a chunk
+    pub socket: u64,
+}
+impl RxLoss {
+    fn from_counters(queue: Option<&QueueStats>, socket: &libc::xdp_statistics) -> Self {
+        let queue_counter = |counter: fn(&QueueStats) -> Option<u64>| queue.and_then(counter).unwrap_or(0);
+        Self {
+            nic: queue_counter(|queue| queue.hw_drops),
+            driver: queue_counter(|queue| queue.alloc_failures),
+            fill_ring_empty: socket.rx_fill_ring_empty_descs,
+            rx_ring_full: socket.rx_ring_full,
+            socket: socket.rx_dropped,
+        }
+    }
+
+    /// The sum of every counter
+    pub const fn total(&self) -> u64 {
+        self.nic + self.driver + self.fill_ring_empty + self.rx_ring_full + self.socket
+    }
+}
+
+/// The counters of every queue of the interface with index `if_index`
+///
+/// Fails with [`crate::Error::SocketReceiveFailure`] carrying `ENOENT` or `EOPNOTSUPP` when the kernel or the driver
+/// lack per-queue counters
+pub fn queue_stats(if_index: u32) -> Result<Vec<QueueStats>, crate::Error> {
+    let family = genl::family("netdev")?;
+    let request = [
+        genl::attribute(NETDEV_A_QSTATS_IFINDEX, &if_index.to_ne_bytes()),
+        genl::attribute(NETDEV_A_QSTATS_SCOPE, &NETDEV_QSTATS_SCOPE_QUEUE.to_ne_bytes()),
+    ].concat();
+    let messages = genl::query(family, NETDEV_CMD_QSTATS_GET, true, &request)?;
+    Ok(messages.iter().filter_map(|message| parse_queue_stats(message)).collect())
+}
+
+/// The counters of RX queue `queue_id` of the interface with index `if_index`
+pub fn rx_queue_stats(if_index: u32, queue_id: u32) -> Result<Option<QueueStats>, crate::Error> {
+    Ok(queue_stats(if_index)?.into_iter().find(|stats| stats.kind == QueueKind::Rx && stats.queue_id == queue_id))
+}
+
+/// Break down the frames lost on the way to `socket`
+///
+/// Kernels or drivers without per-queue counters only fill in the counters of the socket
+pub fn rx_loss(socket: &XDPSocket) -> Result<RxLoss, crate::Error> {
+    let statistics: libc::xdp_statistics = utils::getsockopt(socket.fd, libc::SOL_XDP, libc::XDP_STATISTICS)?;
+    let queue = rx_queue_stats(socket.if_index, socket.if_queue).ok().flatten();
+    Ok(RxLoss::from_counters(queue.as_ref(), &statistics))
+}
+
+fn parse_queue_stats(message: &[u8]) -> Option<QueueStats> {
+    let (mut queue_id, mut kind) = (None, None);
+    let mut stats = QueueStats::new(0, QueueKind::Rx);
+    for (attribute, payload) in genl::attributes(message) {
+        let Some(value) = genl::uint(payload) else {
+            continue;
+        };
+        match attribute {
+            NETDEV_A_QSTATS_QUEUE_ID => queue_id = u32::try_from(value).ok(),
+            NETDEV_A_QSTATS_QUEUE_TYPE => kind = Some(if value == NETDEV_QUEUE_TYPE_RX { QueueKind::Rx } else { QueueKind::Tx }),
+            NETDEV_A_QSTATS_RX_PACKETS | NETDEV_A_QSTATS_TX_PACKETS => stats.packets = Some(value),
+            NETDEV_A_QSTATS_RX_BYTES | NETDEV_A_QSTATS_TX_BYTES => stats.bytes = Some(value),
+            NETDEV_A_QSTATS_RX_ALLOC_FAIL => stats.alloc_failures = Some(value),
+            NETDEV_A_QSTATS_RX_HW_DROPS | NETDEV_A_QSTATS_TX_HW_DROPS => stats.hw_drops = Some(value),
+            NETDEV_A_QSTATS_RX_HW_DROP_OVERRUNS => stats.hw_drop_overruns = Some(value),
+            NETDEV_A_QSTATS_RX_HW_DROP_RATELIMITS => stats.hw_drop_ratelimits = Some(value),
+            NETDEV_A_QSTATS_TX_HW_DROP_ERRORS => stats.hw_drop_errors = Some(value),
+            _ => {},
+        }
+    }
+    Some(QueueStats { queue_id: queue_id?, kind: kind?, ..stats })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_queue_stats, QueueStats, RxLoss, NETDEV_A_QSTATS_QUEUE_ID, NETDEV_A_QSTATS_QUEUE_TYPE, NETDEV_A_QSTATS_RX_ALLOC_FAIL,
+        NETDEV_A_QSTATS_RX_HW_DROPS, NETDEV_A_QSTATS_RX_PACKETS,
+    };
+    use crate::{genl::attribute, napi::QueueKind};
+
+    #[test]
+    fn test_parse_queue_stats() {
+        // counters come as 4 or 8 bytes
+        let message = [
+            attribute(NETDEV_A_QSTATS_QUEUE_ID, &3_u32.to_ne_bytes()),
+            attribute(NETDEV_A_QSTATS_QUEUE_TYPE, &0_u32.to_ne_bytes()),
+            attribute(NETDEV_A_QSTATS_RX_PACKETS, &(1_u64 << 40).to_ne_bytes()),
+            attribute(NETDEV_A_QSTATS_RX_HW_DROPS, &12_u32.to_ne_bytes()),
+            attribute(NETDEV_A_QSTATS_RX_ALLOC_FAIL, &5_u32.to_ne_bytes()),
+        ].concat();
+        let stats = parse_queue_stats(&message).unwrap();
+        assert_eq!(stats, QueueStats { packets: Some(1 << 40), hw_drops: Some(12), alloc_failures: Some(5), ..QueueStats::new(3, QueueKind::Rx) });
+        assert_eq!(parse_queue_stats(&attribute(NETDEV_A_QSTATS_QUEUE_ID, &3_u32.to_ne_bytes())), None);
+
+        // counters the driver does not track are 0
+        let socket = libc::xdp_statistics { rx_dropped: 1, rx_invalid_descs: 0, tx_invalid_descs: 0, rx_ring_full: 2, rx_fill_ring_empty_descs: 3, tx_ring_empty_descs: 0 };
+        let loss = RxLoss::from_counters(Some(&stats), &socket);
+        assert_eq!(loss, RxLoss { nic: 12, driver: 5, fill_ring_empty: 3, rx_ring_full: 2, socket: 1 });
+        assert_eq!(loss.total(), 23);
+        assert_eq!(RxLoss::from_counters(None, &socket).total(), 6);
+    }
+}
@@ -0,0 +1,211 @@
+use std::{collections::HashMap, hash::Hash};
+
+/// A token bucket refilling at a constant rate up to a maximum burst
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: std::time::Instant,
+}
+impl TokenBucket {
+    /// Create a full bucket gaining `rate` tokens per second, holding at most `burst` tokens
+    pub fn new(rate: f64, burst: f64) -> Self {
+        assert!(rate > 0.0, "Rate must be positive");
+        assert!(burst >= 1.0, "Burst must allow at least one token");
+        Self { rate, burst, tokens: burst, last_refill: std::time::Instant::now() }
+    }
+
+    /// Take `tokens` out of the bucket if they are available
+    pub fn try_consume(&mut self, tokens: f64) -> bool {
+        self.try_consume_at(tokens, std::time::Instant::now())
+    }
+
+    /// How long until `tokens` will be available
+    pub fn time_until_available(&mut self, tokens: f64) -> std::time::Duration {
+        self.refill(std::time::Instant::now());
+        if self.tokens >= tokens {
+            std::time::Duration::ZERO
+        } else {
+            std::time::Duration::from_secs_f64((tokens - self.tokens) / self.rate)
+        }
+    }
+
+    fn try_consume_at(&mut self, tokens: f64, now: std::time::Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= tokens {
+            self.tokens -= tokens;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn available_at(&mut self, tokens: f64, now: std::time::Instant) -> bool {
+        self.refill(now);
+        self.tokens >= tokens
+    }
+
+    fn refill(&mut self, now: std::time::Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last_refill = now;
+    }
+}
+
+/// Paces transmissions to a maximum packet rate and/or bit rate
+///
+/// Install one on [`crate::XDPSocket::tx_rate_limiter`] to pace the whole socket, or use a [`FlowRateLimiter`] to pace individual flows
+#[derive(Debug, Clone, Default)]
+pub struct TxRateLimiter {
+    packets: Option<TokenBucket>,
+    bits: Option<TokenBucket>,
+}
+impl TxRateLimiter {
+    /// A limiter admitting everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit to `packets_per_second`, allowing bursts of `burst_packets`
+    pub fn with_pps(mut self, packets_per_second: f64, burst_packets: f64) -> Self {
+        self.packets = Some(TokenBucket::new(packets_per_second, burst_packets));
+        self
+    }
+
+    /// Limit to `bits_per_second` of frame data, allowing bursts of `burst_bits`
+    ///
+    /// The burst must hold at least a chunk of the smallest size; frames larger than it wait for a full bucket and
+    /// empty it
+    pub fn with_bps(mut self, bits_per_second: f64, burst_bits: f64) -> Self {
+        assert!(burst_bits >= (crate::Umem::CHUNK_SIZE_2K * 8) as f64, "Burst must allow at least one chunk");
+        self.bits = Some(TokenBucket::new(bits_per_second, burst_bits));
+        self
+    }
+
+    /// Check whether a frame of `frame_len` bytes can be transmitted now, accounting for it if so
+    pub fn try_admit(&mut self, frame_len: usize) -> bool {
+        self.try_admit_at(frame_len, std::time::Instant::now())
+    }
+
+    /// Check whether a frame of up to `frame_len` bytes can be transmitted now, without accounting for it
+    pub fn can_admit(&mut self, frame_len: usize) -> bool {
+        self.can_admit_at(frame_len, std::time::Instant::now())
+    }
+
+    /// How long until a frame of `frame_len` bytes will be admitted
+    pub fn time_until_admit(&mut self, frame_len: usize) -> std::time::Duration {
+        let packets = self.packets.as_mut().map(|bucket| bucket.time_until_available(1.0)).unwrap_or_default();
+        let bits = self.bits.as_mut().map(|bucket| bucket.time_until_available(Self::bits_for(bucket, frame_len))).unwrap_or_default();
+        packets.max(bits)
+    }
+
+    /// The bits taken by a frame of `frame_len` bytes, frames larger than the burst take a full bucket
+    fn bits_for(bucket: &TokenBucket, frame_len: usize) -> f64 {
+        ((frame_len * 8) as f64).min(bucket.burst)
+    }
+
+    fn can_admit_at(&mut self, frame_len: usize, now: std::time::Instant) -> bool {
+        self.packets.as_mut().is_none_or(|bucket| bucket.available_at(1.0, now))
+            && self.bits.as_mut().is_none_or(|bucket| bucket.available_at(Self::bits_for(bucket, frame_len), now))
+    }
+
+    fn try_admit_at(&mut self, frame_len: usize, now: std::time::Instant) -> bool {
+        // both buckets must agree before consuming from either
+        if ! self.can_admit_at(frame_len, now) {
+            return false;
+        }
+
+        // consume
+        if let Some(bucket) = self.packets.as_mut() {
+            bucket.try_consume_at(1.0, now);
+        }
+        if let Some(bucket) = self.bits.as_mut() {
+            bucket.try_consume_at(Self::bits_for(bucket, frame_len), now);
+        }
+        true
+    }
+}
+
+/// Paces transmissions independently for every flow, identified by a key of choice (e.g. a 5-tuple)
+#[derive(Debug, Clone)]
+pub struct FlowRateLimiter<K> {
+    template: TxRateLimiter,
+    flows: HashMap<K, TxRateLimiter>,
+}
+impl<K: Hash + Eq> FlowRateLimiter<K> {
+    /// Create a limiter giving every new flow a copy of `template`
+    pub fn new(template: TxRateLimiter) -> Self {
+        Self { template, flows: HashMap::new() }
+    }
+
+    /// Check whether a frame of `frame_len` bytes belonging to `flow` can be transmitted now, accounting for it if so
+    pub fn try_admit(&mut self, flow: K, frame_len: usize) -> bool {
+        self.flows.entry(flow)
+            .or_insert_with(|| self.template.clone())
+            .try_admit(frame_len)
+    }
+
+    /// Forget the state of `flow`
+    pub fn remove(&mut self, flow: &K) {
+        self.flows.remove(flow);
+    }
+
+    /// The number of flows being tracked
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    /// Whether no flows are being tracked
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TxRateLimiter;
+
+    #[test]
+    fn test_rate_limiter() {
+        let t0 = std::time::Instant::now();
+        let mut limiter = TxRateLimiter::new()
+            .with_pps(1000.0, 10.0)
+            .with_bps(1_000_000.0, 16384.0);
+
+        // a burst of 10 small frames goes through, then the packet bucket is empty
+        for _ in 0..10 {
+            assert!(limiter.try_admit_at(64, t0));
+        }
+        assert!(! limiter.try_admit_at(64, t0));
+
+        // one millisecond later one more packet is allowed
+        let t1 = t0 + std::time::Duration::from_millis(1);
+        assert!(limiter.try_admit_at(64, t1));
+        assert!(! limiter.try_admit_at(64, t1));
+
+        // large frames are limited by the bit bucket, without consuming packet tokens
+        let t2 = t1 + std::time::Duration::from_millis(10);
+        assert!(limiter.try_admit_at(1500, t2));
+        assert!(! limiter.try_admit_at(1500, t2));
+        assert!(limiter.try_admit_at(64, t2));
+    }
+
+    #[test]
+    fn test_rate_limiter_oversized() {
+        // frames larger than the burst go through with a full bucket, emptying it
+        let t0 = std::time::Instant::now();
+        let mut limiter = TxRateLimiter::new().with_bps(1_000_000.0, 16384.0);
+        assert!(limiter.can_admit_at(4000, t0));
+        assert!(limiter.try_admit_at(4000, t0));
+        assert!(! limiter.try_admit_at(64, t0));
+        let t1 = t0 + std::time::Duration::from_millis(16);
+        assert!(! limiter.can_admit_at(4000, t1));
+        let t2 = t0 + std::time::Duration::from_micros(16385);
+        assert!(limiter.can_admit_at(4000, t2));
+        assert!(limiter.try_admit_at(4000, t2));
+
+        // bursts smaller than a chunk are refused
+        assert!(std::panic::catch_unwind(|| TxRateLimiter::new().with_bps(1_000_000.0, 8000.0)).is_err());
+    }
+}
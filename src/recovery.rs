@@ -0,0 +1,136 @@
+use std::{os::fd::AsRawFd, sync::Arc};
+
+use crate::{config::SocketConfig, BPFRedirectManager, LinkEvent, LinkMonitor, LinkState, Umem, UmemAllocatorFactory, XDPSocket};
+
+/// An [`XDPSocket`] which is re-created, along with its redirection, when its interface comes back from a reset
+///
+/// The socket is torn down when the interface goes down or disappears, and bound again once it is up, e.g. after
+/// a driver reset or an `ethtool -L` change. Every rebind starts over with a fresh allocator over the same umem:
+/// chunks obtained from the previous one must not be used anymore, fetch [`Self::allocator`] again.
+///
+/// With [`SocketConfig::redirect`] the socket attaches its own redirect program, so only one recovering socket per
+/// interface can redirect
+pub struct RecoveringSocket<'a, A: UmemAllocatorFactory> {
+    config: SocketConfig,
+    umem: Arc<Umem>,
+    allocator: Arc<A>,
+    monitor: Option<LinkMonitor>,
+    bpf_manager: Option<BPFRedirectManager>,
+    socket: Option<XDPSocket<'a>>,
+    rebinds: u64,
+}
+impl<'a, A: UmemAllocatorFactory> RecoveringSocket<'a, A> {
+    /// How often a vanished interface is looked up again by name
+    const RESOLVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+    /// Create the socket described by `config` and start watching its interface
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "debug"))]
+    pub fn new(config: SocketConfig) -> Result<Self, crate::Error> {
+        config.ring_sizes()?;
+        let umem = config.umem.build()?;
+        let mut recovering = Self {
+            allocator: Arc::new(A::for_umem(umem.clone())),
+            umem,
+            config,
+            monitor: None,
+            bpf_manager: None,
+            socket: None,
+            rebinds: 0,
+        };
+        recovering.bind()?;
+        Ok(recovering)
+    }
+
+    /// The socket, `None` while the interface is down or gone
+    pub fn socket(&mut self) -> Option<&mut XDPSocket<'a>> {
+        self.socket.as_mut()
+    }
+
+    /// The allocator for the chunks of the current socket
+    pub fn allocator(&self) -> &Arc<A> {
+        &self.allocator
+    }
+
+    /// The last known state of the interface, `None` while it is gone
+    pub fn link_state(&self) -> Option<LinkState> {
+        self.monitor.as_ref().and_then(LinkMonitor::state)
+    }
+
+    /// How many times the socket was re-created
+    pub const fn rebinds(&self) -> u64 {
+        self.rebinds
+    }
+
+    /// Wait up to `timeout` (forever if `None`) for link changes and react to them, returning whether the socket was re-created
+    ///
+    /// While the interface is gone it is looked up again after `timeout`, or every second if `None`
+    pub fn check_link(&mut self, timeout: Option<std::time::Duration>) -> Result<bool, crate::Error> {
+        // interface gone, look for it again
+        let Some(monitor) = self.monitor.as_mut() else {
+            std::thread::sleep(timeout.unwrap_or(Self::RESOLVE_INTERVAL));
+            if self.config.interface_index().is_err() {
+                return Ok(false);
+            }
+            self.rebind()?;
+            return Ok(true);
+        };
+
+        // react to events
+        for event in monitor.poll(timeout)? {
+            match event {
+                LinkEvent::Down => self.teardown(),
+                LinkEvent::Removed => {
+                    self.teardown();
+                    self.monitor = None;
+                    self.bpf_manager = None;
+                    return Ok(false);
+                },
+                LinkEvent::Up | LinkEvent::MtuChanged { .. } => {},
+            }
+        }
+
+        // come back up
+        if self.socket.is_none() && self.link_state().is_some_and(|state| state.up) {
+            self.rebind()?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Re-create the socket right away, e.g. after it reported an error
+    pub fn rebind(&mut self) -> Result<(), crate::Error> {
+        self.teardown();
+        self.bind()?;
+        self.rebinds += 1;
+        Ok(())
+    }
+
+    fn teardown(&mut self) {
+        // closing the socket also removes it from the xsks_map
+        if self.socket.take().is_some() {
+            self.allocator = Arc::new(A::for_umem(self.umem.clone()));
+        }
+    }
+
+    fn bind(&mut self) -> Result<(), crate::Error> {
+        // watch the interface, which may have come back with another index
+        let if_index = self.config.interface_index()?;
+        if self.monitor.as_ref().is_none_or(|monitor| monitor.interface_index() != if_index) {
+            self.monitor = Some(LinkMonitor::new(if_index)?);
+            self.bpf_manager = None;
+        }
+
+        // socket
+        let mut socket = self.config.build_over(if_index, self.umem.clone())?;
+        socket.refill_fill_ring(self.allocator.as_ref(), usize::MAX);
+
+        // redirection
+        if self.config.redirect {
+            self.bpf_manager.get_or_insert_with(|| BPFRedirectManager::attach(if_index))
+                .add_redirect(self.config.queue_id, socket.as_raw_fd())?;
+        }
+
+        self.socket = Some(socket);
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use std::io::Read;
+
+use crate::{capture::PcapReader, utils, UmemAllocator, XDPSocket};
+
+/// How a [`PcapPlayer`] spaces transmitted frames in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayPacing {
+    /// Transmit frames back to back
+    AsFastAsPossible,
+    /// Reproduce the inter-packet gaps found in the capture
+    Original,
+    /// Transmit at a constant rate
+    FixedRate { packets_per_second: f64 },
+}
+
+/// The outcome of a replay
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    /// Frames handed over to the TX ring
+    pub frames_sent: usize,
+    /// Frames not fitting into an umem chunk
+    pub frames_skipped: usize,
+    /// Bytes handed over to the TX ring
+    pub bytes_sent: usize,
+    /// Time elapsed between the first and the last transmission
+    pub elapsed: std::time::Duration,
+}
+
+/// Transmits the frames found in a pcap or pcapng file over an [`XDPSocket`]
+pub struct PcapPlayer<R: Read> {
+    reader: PcapReader<R>,
+    pacing: ReplayPacing,
+    send_timeout: std::time::Duration,
+}
+impl<R: Read> PcapPlayer<R> {
+    /// How long [`Self::play`] waits for room on the TX ring by default
+    pub const DEFAULT_SEND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+    /// Create a player for the frames coming out of `reader`, transmitting as fast as possible
+    pub fn new(reader: PcapReader<R>) -> Self {
+        Self { reader, pacing: ReplayPacing::AsFastAsPossible, send_timeout: Self::DEFAULT_SEND_TIMEOUT }
+    }
+
+    /// Set the pacing of transmitted frames
+    ///
+    /// Fails with [`crate::Error::InvalidConfiguration`] if a fixed rate is not positive
+    pub fn with_pacing(mut self, pacing: ReplayPacing) -> Result<Self, crate::Error> {
+        if let ReplayPacing::FixedRate { packets_per_second } = pacing && (packets_per_second.is_nan() || packets_per_second <= 0.0) {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("a replay rate of {packets_per_second} packets per second is not positive") });
+        }
+        self.pacing = pacing;
+        Ok(self)
+    }
+
+    /// Set how long to wait for room on the TX ring before giving up on a frame
+    pub fn with_send_timeout(mut self, send_timeout: std::time::Duration) -> Self {
+        self.send_timeout = send_timeout;
+        self
+    }
+
+    /// Transmit every frame over `socket`, taking chunks from `allocator`
+    /// 
+    /// Blocks until every frame has been handed over to the TX ring, failing with a timed out
+    /// [`crate::Error::SocketSendFailure`] when a frame finds no room for the send timeout, e.g. because the kernel
+    /// stopped completing transmissions or `allocator` ran out of chunks
+    pub fn play(&mut self, socket: &mut XDPSocket, allocator: &(impl UmemAllocator + ?Sized)) -> Result<ReplayReport, crate::Error> {
+        let mut report = ReplayReport::default();
+        let mut start: Option<(std::time::Instant, std::time::Duration)> = None;
+
+        while let Some(frame) = self.reader.next_frame()? {
+            // skip what can not be transmitted
+            if frame.data.len() > socket.umem.chunk_size() {
+                report.frames_skipped += 1;
+                continue;
+            }
+
+            // wait for the departure time
+            let (start_instant, start_timestamp) = *start.get_or_insert((std::time::Instant::now(), frame.timestamp));
+            let departure = match self.pacing {
+                ReplayPacing::AsFastAsPossible => None,
+                ReplayPacing::Original => Some(start_instant + frame.timestamp.saturating_sub(start_timestamp)),
+                ReplayPacing::FixedRate { packets_per_second } => Some(start_instant + std::time::Duration::from_secs_f64(report.frames_sent as f64 / packets_per_second)),
+            };
+            if let Some(departure) = departure {
+                utils::wait_until(departure);
+            }
+
+            // transmit, reaping completions until there is space
+            let deadline = std::time::Instant::now() + self.send_timeout;
+            loop {
+                socket.reap_completions(allocator);
+                if socket.send(allocator, &frame.data)? {
+                    break;
+                }
+                if std::time::Instant::now() >= deadline {
+                    return Err(crate::Error::SocketSendFailure { error: std::io::ErrorKind::TimedOut.into() });
+                }
+                std::hint::spin_loop();
+            }
+            report.frames_sent += 1;
+            report.bytes_sent += frame.data.len();
+            report.elapsed = start_instant.elapsed();
+        }
+
+        // give back what has been transmitted so far
+        socket.reap_completions(allocator);
+        Ok(report)
+    }
+}
+impl PcapPlayer<std::io::BufReader<std::fs::File>> {
+    /// Create a player for the pcap or pcapng file at `path`
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self, crate::Error> {
+        Ok(Self::new(PcapReader::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PcapPlayer, ReplayPacing};
+    use crate::capture::{PcapReader, PcapWriter};
+
+    #[test]
+    fn test_pacing_validation() {
+        let file = PcapWriter::new(Vec::new()).unwrap().into_inner();
+        let player = || PcapPlayer::new(PcapReader::new(file.as_slice()).unwrap());
+        assert!(player().with_pacing(ReplayPacing::FixedRate { packets_per_second: 1000.0 }).is_ok());
+        for packets_per_second in [0.0, -1.0, f64::NAN] {
+            let result = player().with_pacing(ReplayPacing::FixedRate { packets_per_second });
+            assert!(matches!(result, Err(crate::Error::InvalidConfiguration { .. })));
+        }
+    }
+}
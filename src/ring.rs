@@ -1,6 +1,92 @@
 use std::os::fd::AsRawFd;
 
-use crate::Umem;
+use crate::{ChunkGuard, FrameGuard, Umem};
+
+/// The memory mapping of a ring, as laid out by the kernel: producer index, consumer index, flags and descriptors
+///
+/// Unmapped on drop, from its base rather than from the descriptors found past the indices
+#[derive(Debug)]
+pub struct RingMapping {
+    base: std::ptr::NonNull<u8>,
+    size: usize,
+    offsets: [usize; 4],
+}
+impl RingMapping {
+    /// Map `size` bytes of the ring at `ring_offset` of the socket `sock_fd`, laid out according to `sock_offsets`
+    fn new(sock_fd: impl AsRawFd, size: usize, sock_offsets: &libc::xdp_ring_offset, ring_offset: libc::off_t) -> Result<Self, crate::Error> {
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                sock_fd.as_raw_fd(),
+                ring_offset
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(crate::Error::MemoryMapFailure);
+        }
+        let base = std::ptr::NonNull::new(base.cast()).ok_or(crate::Error::MemoryMapFailure)?;
+        let offsets = [sock_offsets.producer, sock_offsets.consumer, sock_offsets.flags, sock_offsets.desc].map(|offset| offset as usize);
+        Ok(Self { base, size, offsets })
+    }
+
+    /// The start of the mapping
+    pub const fn base(&self) -> *const u8 {
+        self.base.as_ptr()
+    }
+
+    /// The size of the mapping, zero once unmapped
+    pub const fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The offset of the producer index from [`Self::base`]
+    pub const fn producer_offset(&self) -> usize {
+        self.offsets[0]
+    }
+
+    /// The offset of the consumer index from [`Self::base`]
+    pub const fn consumer_offset(&self) -> usize {
+        self.offsets[1]
+    }
+
+    /// The offset of the flags from [`Self::base`]
+    pub const fn flags_offset(&self) -> usize {
+        self.offsets[2]
+    }
+
+    /// The offset of the first descriptor from [`Self::base`]
+    pub const fn descriptors_offset(&self) -> usize {
+        self.offsets[3]
+    }
+
+    /// A pointer `offset` bytes into the mapping
+    fn at<T>(&self, offset: usize) -> *mut T {
+        unsafe { self.base.as_ptr().byte_add(offset).cast() }
+    }
+
+    /// Unmap, doing nothing if it already was
+    fn unmap(&mut self) -> Result<(), crate::Error> {
+        if self.size == 0 {
+            return Ok(());
+        }
+        let result = unsafe { libc::munmap(self.base.as_ptr().cast(), self.size) };
+        self.size = 0;
+        if result < 0 {
+            return Err(crate::Error::MemoryUnmapFailure { error: std::io::Error::last_os_error() });
+        }
+        Ok(())
+    }
+}
+impl Drop for RingMapping {
+    fn drop(&mut self) {
+        let _ = self.unmap();
+    }
+}
+unsafe impl Send for RingMapping {}
+unsafe impl Sync for RingMapping {}
 
 /// The reference to an XDP ring (tx,rx,completion,fill)
 /// 
@@ -8,12 +94,13 @@ use crate::Umem;
 /// - TX and RX rings have [`u64`] as their `D` type parameter
 pub struct XDPRing<'a, D> {
     // metadata
-    mmap_size: usize,
+    mapping: RingMapping,
     num_elements: usize,
 
     // pointers
     consumer_index: &'a std::sync::atomic::AtomicU32,
     producer_index: &'a std::sync::atomic::AtomicU32,
+    flags: &'a std::sync::atomic::AtomicU32,
     descriptors: &'a mut [D],
 }
 impl<'a, D> XDPRing<'a, D> {
@@ -23,34 +110,24 @@ impl<'a, D> XDPRing<'a, D> {
 
     /// Construct a ring of `num_elements` size for the socket given in `sock_fd`
     /// 
-    /// - `sock_offsets` is one of the fields obtained in the [`libc::xdp_mmap_offsets_v1`] structure originated by a [`libc::XDP_MMAP_OFFSETS`] getsockopt call
+    /// - `sock_offsets` is one of the fields obtained in the [`libc::xdp_mmap_offsets`] structure originated by a [`libc::XDP_MMAP_OFFSETS`] getsockopt call
     /// - `ring_offset` is the mmap offset associated with the type of ring, i.e. [`libc::XDP_PGOFF_RX_RING`], [`libc::XDP_PGOFF_TX_RING`], [`libc::XDP_UMEM_PGOFF_COMPLETION_RING`], [`libc::XDP_UMEM_PGOFF_FILL_RING`]
-    pub fn new(num_elements: usize, sock_fd: impl AsRawFd, sock_offsets: &libc::xdp_ring_offset_v1, ring_offset: libc::off_t) -> Result<Self, crate::Error> {
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "debug", skip(sock_fd, sock_offsets)))]
+    pub fn new(num_elements: usize, sock_fd: impl AsRawFd, sock_offsets: &libc::xdp_ring_offset, ring_offset: libc::off_t) -> Result<Self, crate::Error> {
         // mmap ring
         let mmap_size = sock_offsets.desc as usize + std::mem::size_of::<D>() * num_elements;
-        let mmap_base = unsafe {
-            libc::mmap(
-                std::ptr::null_mut(),
-                mmap_size,
-                libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_SHARED | libc::MAP_POPULATE,
-                sock_fd.as_raw_fd(),
-                ring_offset
-            )
-        };
-        if mmap_base == libc::MAP_FAILED || mmap_base.is_null() {
-            return Err(crate::Error::MemoryMapFailure);
-        }
+        let mapping = RingMapping::new(sock_fd, mmap_size, sock_offsets, ring_offset)?;
 
         // create self
         unsafe {
             Ok(
                 Self {
-                    mmap_size,
+                    consumer_index: std::sync::atomic::AtomicU32::from_ptr(mapping.at(mapping.consumer_offset())),
+                    producer_index: std::sync::atomic::AtomicU32::from_ptr(mapping.at(mapping.producer_offset())),
+                    flags: std::sync::atomic::AtomicU32::from_ptr(mapping.at(mapping.flags_offset())),
+                    descriptors: std::slice::from_raw_parts_mut(mapping.at(mapping.descriptors_offset()), num_elements),
+                    mapping,
                     num_elements,
-                    consumer_index: std::sync::atomic::AtomicU32::from_ptr(mmap_base.byte_add(sock_offsets.consumer as _).cast()),
-                    producer_index: std::sync::atomic::AtomicU32::from_ptr(mmap_base.byte_add(sock_offsets.producer as _).cast()),
-                    descriptors: std::slice::from_raw_parts_mut(mmap_base.byte_add(sock_offsets.desc as _) as *mut D, num_elements),
                 }
             )
         }
@@ -65,6 +142,16 @@ impl<'a, D> XDPRing<'a, D> {
         self.num_elements
     }
 
+    /// The size of the mapping of this ring, header included
+    pub const fn mmap_size(&self) -> usize {
+        self.mapping.size()
+    }
+
+    /// The mapping of this ring, e.g. to inspect its layout
+    pub const fn mapping(&self) -> &RingMapping {
+        &self.mapping
+    }
+
     const fn num_elements_mask(&self) -> u32 {
         self.num_elements as u32 - 1
     }
@@ -77,10 +164,32 @@ impl<'a, D> XDPRing<'a, D> {
     }
 
     /// Advance the consumer index by one
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all))]
     pub fn advance_consumer_index(&mut self) {
         self.consumer_index.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
     }
 
+    /// Advance the consumer index by `count`, releasing a batch of elements at once
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all))]
+    pub fn advance_consumer_index_by(&mut self, count: u32) {
+        self.consumer_index.fetch_add(count, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// The consumer index before wrapping around the ring, which changes whenever the consumer makes progress
+    pub fn consumer_position(&self) -> u32 {
+        self.consumer_index.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// The number of elements a consumer can consume right now
+    pub fn num_consumable(&self) -> u32 {
+        self.producer_index.load(std::sync::atomic::Ordering::Relaxed).wrapping_sub(self.consumer_index.load(std::sync::atomic::Ordering::Relaxed))
+    }
+
+    /// The number of elements a producer can produce right now, see [`Self::can_produce`]
+    pub fn num_producible(&self) -> u32 {
+        self.num_elements_mask() - self.num_consumable()
+    }
+
     // producer
 
     /// The next index to which the producer should produce
@@ -88,11 +197,42 @@ impl<'a, D> XDPRing<'a, D> {
         self.producer_index.load(std::sync::atomic::Ordering::Relaxed) & self.num_elements_mask()
     }
 
+    /// The producer index before wrapping around the ring, which changes whenever the producer makes progress
+    pub fn producer_position(&self) -> u32 {
+        self.producer_index.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
     /// Advance the producer index by one
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all))]
     pub fn advance_producer_index(&mut self) {
         self.producer_index.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
     }
 
+    /// Advance the producer index by `count`, publishing a batch of elements at once
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all))]
+    pub fn advance_producer_index_by(&mut self, count: u32) {
+        self.producer_index.fetch_add(count, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    // flags
+
+    /// Whether the kernel asked to be woken up to process this ring ([`libc::XDP_RING_NEED_WAKEUP`])
+    ///
+    /// Only meaningful for sockets bound with [`libc::XDP_USE_NEED_WAKEUP`], which [`crate::XDPSocket`]s are
+    pub fn needs_wakeup(&self) -> bool {
+        self.flags.load(std::sync::atomic::Ordering::Relaxed) & libc::XDP_RING_NEED_WAKEUP != 0
+    }
+
+    /// Raise or clear the wakeup request, as the kernel does
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn set_needs_wakeup(&self, needs_wakeup: bool) {
+        if needs_wakeup {
+            self.flags.fetch_or(libc::XDP_RING_NEED_WAKEUP, std::sync::atomic::Ordering::Relaxed);
+        } else {
+            self.flags.fetch_and(! libc::XDP_RING_NEED_WAKEUP, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
     // descriptors
 
     /// Obtain an immutable reference to the contents of the nth descriptor
@@ -105,6 +245,14 @@ impl<'a, D> XDPRing<'a, D> {
         &mut self.descriptors[index]
     }
 
+    /// Hint the CPU to fetch the nth descriptor, wrapping around the ring, see [`crate::utils::prefetch`]
+    #[inline(always)]
+    pub fn prefetch_nth_descriptor(&self, index: usize) {
+        if cfg!(feature = "prefetch") {
+            crate::utils::prefetch(&self.descriptors[index & self.num_elements_mask() as usize]);
+        }
+    }
+
     //
     // utilities
     //
@@ -121,18 +269,61 @@ impl<'a, D> XDPRing<'a, D> {
 
 }
 impl<'a> XDPRing<'a, libc::xdp_desc> {
-    /// Obtain the immutable memory slice associated with the nth descriptor
-    pub const fn get_nth_slice(&self, index: usize, umem: &Umem) -> &[u8] {
+    /// Borrow the memory associated with the nth descriptor
+    /// 
+    /// Panics if the descriptor does not point within a single chunk of `umem` or its chunk is already borrowed, see
+    /// [`Self::try_get_nth_slice`]
+    pub fn get_nth_slice<'u>(&self, index: usize, umem: &'u Umem) -> FrameGuard<'u> {
+        self.try_get_nth_slice(index, umem).expect("Descriptor points outside of its chunk or the chunk is already borrowed")
+    }
+    /// Obtain the memory slice associated with the nth descriptor without borrowing its chunk
+    ///
+    /// Panics if the descriptor does not point within a single chunk of `umem`
+    ///
+    /// # Safety
+    /// No mutable reference to the bytes of the chunk may exist while the slice is alive, e.g. through a [`ChunkGuard`]
+    /// or a slice obtained from another ring sharing `umem`
+    pub unsafe fn get_nth_slice_unchecked(&self, index: usize, umem: &Umem) -> &[u8] {
         let descriptor = self.get_nth_descriptor(index);
-        unsafe {
-            std::slice::from_raw_parts(
-                umem.memory_ptr().byte_add(descriptor.addr as _),
-                descriptor.len as _,
-            )
+        unsafe { umem.frame_ptr(descriptor.addr, descriptor.len as _).as_ref() }
+    }
+    /// Hint the CPU to fetch the nth descriptor and the beginning of its frame, wrapping around the ring
+    ///
+    /// The descriptor may be stale, e.g. past the producer index: the hint is then wasted, never harmful
+    #[inline(always)]
+    pub fn prefetch_nth_frame(&self, index: usize, umem: &Umem) {
+        if cfg!(feature = "prefetch") {
+            let descriptor = self.get_nth_descriptor(index & self.num_elements_mask() as usize);
+            crate::utils::prefetch(umem.memory_ptr().wrapping_byte_add(descriptor.addr as _));
         }
     }
-    /// Obtain the mutable memory slice associated with the nth descriptor, eventually updating its offset and length beforehand
-    pub fn get_nth_slice_mut(&mut self, index: usize, umem: &Umem, set_offset: Option<u64>, set_length: Option<usize>) -> &mut [u8] {
+    /// Borrow the memory associated with the nth descriptor, unless it does not point within a single chunk of `umem` or
+    /// its chunk is already borrowed
+    pub fn try_get_nth_slice<'u>(&self, index: usize, umem: &'u Umem) -> Result<FrameGuard<'u>, crate::Error> {
+        let descriptor = self.get_nth_descriptor(index);
+        umem.try_frame(descriptor.addr, descriptor.len as _)
+    }
+    /// Borrow the memory associated with the nth descriptor, eventually updating its offset and length beforehand
+    ///
+    /// Panics like [`Self::get_nth_slice`]
+    pub fn get_nth_slice_mut<'u>(&mut self, index: usize, umem: &'u Umem, set_offset: Option<u64>, set_length: Option<usize>) -> FrameGuard<'u> {
+        self.set_nth_descriptor(index, set_offset, set_length);
+        self.get_nth_slice(index, umem)
+    }
+    /// Obtain the mutable memory slice associated with the nth descriptor without borrowing its chunk, eventually
+    /// updating its offset and length beforehand
+    ///
+    /// Panics if the descriptor does not point within a single chunk of `umem`
+    ///
+    /// # Safety
+    /// No other reference to the bytes of the chunk may exist while the slice is alive, e.g. through a [`ChunkGuard`] or
+    /// a slice obtained from another ring sharing `umem`
+    pub unsafe fn get_nth_slice_mut_unchecked(&mut self, index: usize, umem: &Umem, set_offset: Option<u64>, set_length: Option<usize>) -> &mut [u8] {
+        self.set_nth_descriptor(index, set_offset, set_length);
+        let descriptor = self.get_nth_descriptor(index);
+        unsafe { umem.frame_ptr(descriptor.addr, descriptor.len as _).as_mut() }
+    }
+    fn set_nth_descriptor(&mut self, index: usize, set_offset: Option<u64>, set_length: Option<usize>) {
         let descriptor = self.get_nth_descriptor_mut(index);
         if let Some(offset) = set_offset {
             descriptor.addr = offset;
@@ -140,12 +331,21 @@ impl<'a> XDPRing<'a, libc::xdp_desc> {
         if let Some(length) = set_length {
             descriptor.len = length as _;
         }
-        unsafe {
-            std::slice::from_raw_parts_mut(
-                umem.memory_ptr().cast_mut().byte_add(descriptor.addr as _),
-                descriptor.len as _,
-            )
-        }
+    }
+    /// Borrow the whole chunk the nth descriptor points into, along with the range of its frame within the chunk
+    ///
+    /// The bytes in front of the frame are headroom, usable e.g. by [`crate::packet::push_vlan_tag`]. Panics like
+    /// [`Self::get_nth_slice`]
+    pub fn get_nth_chunk_mut<'u>(&mut self, index: usize, umem: &'u Umem) -> (ChunkGuard<'u>, std::ops::Range<usize>) {
+        self.get_nth_slice(index, umem).into_chunk()
+    }
+    /// Point the nth descriptor to `frame`, a range within the chunk it already points into
+    pub fn set_nth_frame_in_chunk(&mut self, index: usize, umem: &Umem, frame: std::ops::Range<usize>) {
+        assert!(frame.end <= umem.chunk_size(), "Frame exceeds the chunk");
+        let descriptor = self.get_nth_descriptor_mut(index);
+        let chunk_start = umem.chunk_start_offset_for_index(umem.chunk_index_for_offset(descriptor.addr));
+        descriptor.addr = chunk_start + frame.start as u64;
+        descriptor.len = frame.len() as _;
     }
 }
 impl<'a> XDPRing<'a, u64> {
@@ -159,19 +359,123 @@ impl<'a> XDPRing<'a, u64> {
         *self.get_nth_descriptor_mut(index) = umem_offset
     }
 
+    /// Sort the `count` umem offsets from the nth descriptor on, in two runs if they wrap around the end of the ring
+    pub fn sort_umem_offsets(&mut self, index: usize, count: usize) {
+        let start = index & self.num_elements_mask() as usize;
+        let head = count.min(self.num_elements - start);
+        self.descriptors[start..start + head].sort_unstable();
+        self.descriptors[..count - head].sort_unstable();
+    }
+
     /// Produces to the ring one umem offset
     /// 
     /// Check [`Self::can_produce`] beforehand!
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip(self)))]
     pub fn produce_umem_offset(&mut self, umem_offset: u64) {
         self.set_nth_umem_offset(self.get_producer_index() as _, umem_offset);
         self.advance_producer_index();
     }
 }
-impl<'a, D> Drop for XDPRing<'a, D> {
-    fn drop(&mut self) {
-        unsafe {
-            let ptr = self.descriptors.as_mut_ptr();
-            libc::munmap(ptr.cast(), self.mmap_size);
+impl<'a, D> XDPRing<'a, D> {
+    /// Unmap the ring, which must not be used afterwards, doing nothing if it already was
+    pub(crate) fn unmap(&mut self) -> Result<(), crate::Error> {
+        self.descriptors = &mut [];
+        self.mapping.unmap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, os::fd::{AsRawFd, FromRawFd, OwnedFd}};
+
+    use proptest::prelude::*;
+
+    use super::XDPRing;
+    use crate::Umem;
+
+    /// A producer and a consumer view over the same ring, backed by a memfd, along with the memfd
+    fn ring_pair<'a, D>(num_elements: usize) -> (XDPRing<'a, D>, XDPRing<'a, D>, OwnedFd) {
+        let memory = unsafe { OwnedFd::from_raw_fd(libc::memfd_create(c"ring".as_ptr(), 0)) };
+        let offsets = libc::xdp_ring_offset { producer: 0, consumer: 64, desc: 128, flags: 96 };
+        assert_eq!(unsafe { libc::ftruncate(memory.as_raw_fd(), (128 + std::mem::size_of::<D>() * num_elements) as _) }, 0);
+        let producer = XDPRing::new(num_elements, memory.as_raw_fd(), &offsets, 0).unwrap();
+        let consumer = XDPRing::new(num_elements, memory.as_raw_fd(), &offsets, 0).unwrap();
+        (producer, consumer, memory)
+    }
+
+    #[test]
+    fn test_descriptor_validation() {
+        let umem = Umem::new_2k(4).unwrap();
+        let (mut ring, _, _memory) = ring_pair::<libc::xdp_desc>(4);
+        for (index, (addr, len, valid)) in [(2048 + 256, 1792, true), (2048 + 256, 1793, false), (4 * 2048, 0, false)].into_iter().enumerate() {
+            *ring.get_nth_descriptor_mut(index) = libc::xdp_desc { addr, len, options: 0 };
+            assert_eq!(ring.try_get_nth_slice(index, &umem).is_ok(), valid, "addr = {addr}, len = {len}");
+        }
+    }
+
+    #[test]
+    fn test_frame_borrowing() {
+        let umem = Umem::new_2k(4).unwrap();
+        let (mut ring, _, _memory) = ring_pair::<libc::xdp_desc>(4);
+        *ring.get_nth_descriptor_mut(0) = libc::xdp_desc { addr: 2048 + 256, len: 64, options: 0 };
+        *ring.get_nth_descriptor_mut(1) = libc::xdp_desc { addr: 2048 + 512, len: 64, options: 0 };
+
+        // frames of the same chunk exclude each other, the chunk is given back on drop
+        let frame = ring.get_nth_slice(0, &umem);
+        assert_eq!((frame.len(), frame.range()), (64, 256..320));
+        assert!(matches!(ring.try_get_nth_slice(1, &umem), Err(crate::Error::ChunkAlreadyBorrowed { index: 1 })));
+        assert!(umem.try_chunk(1).is_none());
+        drop(frame);
+        let mut frame = ring.get_nth_slice_mut(1, &umem, Some(3 * 2048), Some(16));
+        frame.fill(0xAA);
+        assert!(ring.try_get_nth_slice(0, &umem).is_ok());
+        let (chunk, range) = frame.into_chunk();
+        assert_eq!((chunk.index(), range), (3, 0..16));
+    }
+
+    #[test]
+    fn test_mapping_layout() {
+        let (mut ring, _, _memory) = ring_pair::<u64>(8);
+        let mapping = ring.mapping();
+        assert_eq!((mapping.producer_offset(), mapping.consumer_offset(), mapping.flags_offset(), mapping.descriptors_offset()), (0, 64, 96, 128));
+        assert_eq!(mapping.size(), 128 + 8 * 8);
+        assert_eq!(mapping.base() as usize % 4096, 0);
+        assert_eq!(unsafe { mapping.base().add(128) }, ring.descriptors.as_ptr().cast());
+
+        // unmapped from the base, only once
+        ring.unmap().unwrap();
+        assert_eq!(ring.mmap_size(), 0);
+        ring.unmap().unwrap();
+    }
+
+    proptest! {
+        #[test]
+        fn test_ring_against_model(size_log in 1_u32..8, start in any::<u32>(), ops in proptest::collection::vec(any::<Option<u64>>(), 0..512)) {
+            let num_elements = 1 << size_log;
+            let (mut producer, mut consumer, _memory) = ring_pair::<u64>(num_elements);
+            producer.producer_index.store(start, std::sync::atomic::Ordering::Relaxed);
+            producer.consumer_index.store(start, std::sync::atomic::Ordering::Relaxed);
+
+            // one slot is always kept free to tell a full ring from an empty one
+            let capacity = num_elements - 1;
+            let mut model = VecDeque::new();
+            for op in ops {
+                prop_assert_eq!(producer.can_produce(), model.len() < capacity);
+                prop_assert_eq!(consumer.can_consume(), ! model.is_empty());
+                prop_assert!((producer.get_producer_index() as usize) < num_elements);
+                prop_assert!((consumer.get_consumer_index() as usize) < num_elements);
+                match op {
+                    Some(value) if producer.can_produce() => {
+                        producer.produce_umem_offset(value);
+                        model.push_back(value);
+                    },
+                    None if consumer.can_consume() => {
+                        prop_assert_eq!(Some(consumer.get_nth_umem_offset(consumer.get_consumer_index() as _)), model.pop_front());
+                        consumer.advance_consumer_index();
+                    },
+                    _ => {},
+                }
+            }
         }
     }
 }
@@ -0,0 +1,199 @@
+use std::{os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd}, sync::Arc};
+
+use crate::{socket::page_aligned, utils, BindFlags, MemoryFootprint, SocketOperation, TrafficCounters, Umem, UmemAllocator, WaitStrategy, XDPRing, XDPSocket};
+
+/// An AF_XDP socket sharing the umem of an owner [`XDPSocket`] bound to the same <ifindex,ifqueue> pair (XDP_SHARED_UMEM)
+///
+/// Only RX and TX rings belong to this socket: chunks are supplied through the fill ring of the owner, and the
+/// chunks it transmitted show up on the completion ring of the owner. The redirect program picks one socket per
+/// queue, point [`crate::BPFRedirectManager::add_redirect`] to this socket for it to receive
+pub struct SharedXDPSocket<'a> {
+    // metadata
+    pub if_index: libc::c_uint,
+    pub if_queue: libc::c_uint,
+
+    // memory
+    pub umem: Arc<Umem>,
+
+    // socket
+    pub fd: RawFd,
+    /// The flags of the owner, which the kernel applies to this socket too
+    pub bind_flags: BindFlags,
+
+    // rings
+    pub rx_ring: XDPRing<'a, libc::xdp_desc>,
+    pub tx_ring: XDPRing<'a, libc::xdp_desc>,
+
+    // accounting
+    pub traffic: TrafficCounters,
+}
+impl<'a> SharedXDPSocket<'a> {
+
+    /// Create a new AF_XDP socket sharing the umem, interface and queue of `owner`, with rings of the given sizes
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "debug", skip(owner), fields(owner_fd = owner.fd)))]
+    pub fn new(owner: &XDPSocket, rx_ring_size: usize, tx_ring_size: usize) -> Result<Self, crate::Error> {
+        // check rings size
+        assert!(rx_ring_size.is_power_of_two() && tx_ring_size.is_power_of_two(), "ring sizes must be powers of two");
+
+        // create AF_XDP socket
+        let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW, 0) };
+        if fd < 0 {
+            return Err(owner.context(crate::Error::SocketCreationFailure, SocketOperation::Create));
+        }
+
+        // closed on failure, after the rings are unmapped
+        let owned_fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // prepare rings, the umem and its rings belong to the owner
+        let context = |operation| move |error| owner.context(error, operation);
+        utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_RX_RING, &rx_ring_size).map_err(context(SocketOperation::ConfigureRings))?;
+        utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_TX_RING, &tx_ring_size).map_err(context(SocketOperation::ConfigureRings))?;
+        let umem_offsets = utils::getsockopt::<libc::xdp_mmap_offsets>(fd, libc::SOL_XDP, libc::XDP_MMAP_OFFSETS).map_err(context(SocketOperation::MapRings))?;
+        let rx_ring = XDPRing::new(rx_ring_size, fd, &umem_offsets.rx, libc::XDP_PGOFF_RX_RING).map_err(context(SocketOperation::MapRings))?;
+        let tx_ring = XDPRing::new(tx_ring_size, fd, &umem_offsets.tx, libc::XDP_PGOFF_TX_RING).map_err(context(SocketOperation::MapRings))?;
+
+        // bind socket, the wakeup mode is inherited from the owner
+        let bind_address = libc::sockaddr_xdp {
+            sxdp_family: libc::AF_XDP as _,
+            sxdp_flags: libc::XDP_SHARED_UMEM,
+            sxdp_ifindex: owner.if_index,
+            sxdp_queue_id: owner.if_queue,
+            sxdp_shared_umem_fd: owner.fd as _,
+        };
+        let bind_result = unsafe { libc::bind(fd, &bind_address as *const _ as *const _, std::mem::size_of::<libc::sockaddr_xdp>() as _) };
+        if bind_result < 0 {
+            return Err(context(SocketOperation::Bind)(crate::Error::SocketBindFailure { error: std::io::Error::last_os_error() }));
+        }
+
+        Ok(Self {
+            if_index: owner.if_index,
+            if_queue: owner.if_queue,
+            umem: owner.umem.clone(),
+            fd: owned_fd.into_raw_fd(),
+            bind_flags: owner.bind_flags,
+            rx_ring,
+            tx_ring,
+            traffic: TrafficCounters::default(),
+        })
+    }
+
+    /// Gets the statistics associated with this AF_XDP socket
+    pub fn get_statistics(&self) -> Result<libc::xdp_statistics_v1, crate::Error> {
+        utils::getsockopt(self.fd, libc::SOL_XDP, libc::XDP_STATISTICS)
+    }
+
+    /// How much memory the rings of this socket take, the fill and completion rings being counted by the owner
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        MemoryFootprint {
+            rx_ring: page_aligned(self.rx_ring.mmap_size()),
+            tx_ring: page_aligned(self.tx_ring.mmap_size()),
+            umem: self.umem.memory_size(),
+            ..MemoryFootprint::default()
+        }
+    }
+
+    /// Wrap `error` with the interface and queue of this socket
+    fn context(&self, error: crate::Error, operation: SocketOperation) -> crate::Error {
+        error.on_socket(self.if_index, self.if_queue, operation)
+    }
+
+    /// Poll this socket for new packets, see [`XDPSocket::poll_for_reception`]
+    pub fn poll_for_reception(&self) -> Result<(), crate::Error> {
+        utils::poll_for_reception(self.fd).map_err(|error| self.context(error, SocketOperation::Poll))
+    }
+
+    /// Poll this socket for new packets for up to `timeout`, see [`XDPSocket::poll_for_reception_timeout`]
+    pub fn poll_for_reception_timeout(&self, timeout: Option<std::time::Duration>) -> Result<bool, crate::Error> {
+        utils::poll_for_reception_timeout(self.fd, timeout).map_err(|error| self.context(error, SocketOperation::Poll))
+    }
+
+    /// Wait for frames on the RX ring following `strategy`, see [`XDPSocket::wait_for_reception`]
+    pub fn wait_for_reception(&self, strategy: &WaitStrategy) -> Result<bool, crate::Error> {
+        strategy.wait(self.fd, || self.rx_ring.can_consume()).map_err(|error| self.context(error, SocketOperation::Poll))
+    }
+
+    /// Wake this socket up for transmission
+    pub fn wake_for_transmission(&self) -> Result<(), crate::Error> {
+        utils::wake_for_transmission(self.fd).map_err(|error| self.context(error, SocketOperation::Wakeup))
+    }
+
+    /// Wake this socket up for transmission only if the driver asked for it, see [`XDPSocket::wake_for_transmission_if_needed`]
+    pub fn wake_for_transmission_if_needed(&mut self) -> Result<bool, crate::Error> {
+        let needs_wakeup = ! self.bind_flags.need_wakeup || self.tx_ring.needs_wakeup();
+        if needs_wakeup {
+            self.wake_for_transmission()?;
+        }
+        self.traffic.record_tx_wakeup(needs_wakeup);
+        Ok(needs_wakeup)
+    }
+
+    /// Copy `frame` into a chunk obtained from `allocator` and enqueue it for transmission
+    ///
+    /// Returns `false` if the TX ring is full or no chunk could be allocated.
+    /// The chunk appears on the completion ring of the owner once transmitted
+    pub fn send(&mut self, allocator: &(impl UmemAllocator + ?Sized), frame: &[u8]) -> Result<bool, crate::Error> {
+        // check size
+        if frame.len() > self.umem.chunk_size() {
+            return Err(crate::Error::FrameTooLarge { length: frame.len(), chunk_size: self.umem.chunk_size() });
+        }
+
+        // check space
+        if ! self.tx_ring.can_produce() {
+            return Ok(false);
+        }
+        let Some(chunk_index) = allocator.try_allocate() else {
+            return Ok(false);
+        };
+
+        // write frame
+        let tx_offset = self.umem.chunk_start_offset_for_index(chunk_index);
+        let tx_index = self.tx_ring.get_producer_index() as usize;
+        self.tx_ring.get_nth_slice_mut(tx_index, &self.umem, Some(tx_offset), Some(frame.len())).copy_from_slice(frame);
+        self.tx_ring.advance_producer_index();
+        self.traffic.record_tx(frame.len());
+
+        // send message
+        self.wake_for_transmission_if_needed()?;
+        Ok(true)
+    }
+
+    /// Consume every frame waiting in the RX ring, handing each one to `handler`
+    ///
+    /// Chunks are given back to the fill ring of `owner`, or to `allocator` when it is full. Returns the number of frames received
+    pub fn recv(&mut self, owner: &mut XDPSocket, allocator: &(impl UmemAllocator + ?Sized), mut handler: impl FnMut(&[u8])) -> usize {
+        assert!(Arc::ptr_eq(&owner.umem, &self.umem), "owner does not share the umem of this socket");
+        let mut count = 0;
+        while self.rx_ring.can_consume() {
+            // process frame
+            let rx_index = self.rx_ring.get_consumer_index() as usize;
+            let rx_offset = self.rx_ring.get_nth_descriptor(rx_index).addr;
+            let frame = self.rx_ring.get_nth_slice(rx_index, &self.umem);
+            self.rx_ring.prefetch_nth_frame(rx_index + 1, &self.umem);
+            self.traffic.record_rx(frame.len());
+            handler(&frame);
+
+            // give back chunk
+            if owner.fill_ring.can_produce() {
+                owner.fill_ring.produce_umem_offset(rx_offset);
+            } else {
+                allocator.release_offset(rx_offset);
+            }
+
+            // advance rx index
+            self.rx_ring.advance_consumer_index();
+            count += 1;
+        }
+        count
+    }
+}
+impl<'a> AsRawFd for SharedXDPSocket<'a> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+impl<'a> Drop for SharedXDPSocket<'a> {
+    fn drop(&mut self) {
+        // close socket
+        unsafe { libc::close(self.fd) };
+    }
+}
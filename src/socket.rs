@@ -1,8 +1,228 @@
-use std::{os::fd::{AsRawFd, RawFd}, sync::Arc};
+use std::{os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd}, sync::Arc};
 
-use crate::{utils, Umem, XDPRing};
+use crate::{utils, Frame, SocketOperation, TxBacklog, TxBatch, TxQueue, TxRateLimiter, TxWatermarks, Umem, UmemAllocator, WaitStrategy, XDPRing, XdpHooks};
+
+/// How far ahead [`XDPSocket::reap_completions`] prefetches, a cache line of offsets
+const COMPLETION_PREFETCH_DISTANCE: usize = 8;
+
+/// The number of elements of each ring of an [`XDPSocket`], all powers of two
+///
+/// A common choice is a fill ring twice as large as the RX ring, and a small ring for a direction which is not used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingSizes {
+    pub rx: usize,
+    pub tx: usize,
+    pub fill: usize,
+    pub completion: usize,
+}
+impl RingSizes {
+    /// The same size for all rings
+    pub const fn uniform(size: usize) -> Self {
+        Self { rx: size, tx: size, fill: size, completion: size }
+    }
+
+    /// Whether every size is a power of two
+    pub const fn are_valid(&self) -> bool {
+        self.rx.is_power_of_two() && self.tx.is_power_of_two() && self.fill.is_power_of_two() && self.completion.is_power_of_two()
+    }
+}
+
+/// The order in which [`XDPSocket::refill_fill_ring`] publishes chunks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FillOrder {
+    /// As the allocator hands them out, which is essentially random once chunks have been recycled a few times
+    #[default]
+    Allocation,
+    /// By ascending offset, so that the NIC writes into neighbouring chunks, which is kinder to DMA and caches
+    ///
+    /// A batch wrapping around the end of the fill ring is sorted in two runs
+    Ascending,
+}
+
+/// The memory behind a socket in bytes, see [`XDPSocket::memory_footprint`]
+///
+/// Rings count in whole pages, as the kernel allocates them. The umem counts whole: sockets sharing one should count it
+/// once when their footprints are added up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryFootprint {
+    pub rx_ring: usize,
+    pub tx_ring: usize,
+    pub fill_ring: usize,
+    pub completion_ring: usize,
+    pub umem: usize,
+}
+impl MemoryFootprint {
+    /// Where the descriptors of a ring start in its mapping with current kernels, on 64 bytes cache lines
+    const RING_HEADER_SIZE: usize = 320;
+
+    /// Estimate the footprint of a socket with rings of `ring_sizes` and a umem of `umem_size` bytes, before creating it
+    pub fn estimate(ring_sizes: RingSizes, umem_size: usize) -> Self {
+        let ring = |num_elements: usize, descriptor_size: usize| page_aligned(Self::RING_HEADER_SIZE + num_elements * descriptor_size);
+        Self {
+            rx_ring: ring(ring_sizes.rx, std::mem::size_of::<libc::xdp_desc>()),
+            tx_ring: ring(ring_sizes.tx, std::mem::size_of::<libc::xdp_desc>()),
+            fill_ring: ring(ring_sizes.fill, std::mem::size_of::<u64>()),
+            completion_ring: ring(ring_sizes.completion, std::mem::size_of::<u64>()),
+            umem: umem_size,
+        }
+    }
+
+    /// The memory taken by the rings alone
+    pub const fn rings(&self) -> usize {
+        self.rx_ring + self.tx_ring + self.fill_ring + self.completion_ring
+    }
+
+    /// The memory taken by the rings and the umem
+    pub const fn total(&self) -> usize {
+        self.rings() + self.umem
+    }
+}
+
+/// `size` rounded up to whole pages
+pub(crate) fn page_aligned(size: usize) -> usize {
+    size.next_multiple_of(unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize)
+}
+
+/// Whether a socket must use the zero-copy path of the driver, see [`BindFlags::mode`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum BindMode {
+    /// Zero-copy when the driver supports it, copy otherwise
+    #[default]
+    Auto,
+    /// Zero-copy or fail to bind (XDP_ZEROCOPY)
+    ZeroCopy,
+    /// Copy, even when the driver supports zero-copy (XDP_COPY)
+    Copy,
+}
+
+/// The flags an [`XDPSocket`] is bound with
+///
+/// The defaults suit most setups, some drivers and kernels perform better or only work with other combinations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindFlags {
+    /// Only make syscalls when the driver asks for them (XDP_USE_NEED_WAKEUP), otherwise every transmission wakes the driver up
+    pub need_wakeup: bool,
+    /// Accept packets spread over several chunks (XDP_USE_SG), see [`XDPSocket::with_multi_buffer`]
+    pub multi_buffer: bool,
+    /// Whether frames are copied to and from the umem or handed over in place (XDP_COPY, XDP_ZEROCOPY), see [`BindMode`]
+    pub mode: BindMode,
+}
+impl Default for BindFlags {
+    fn default() -> Self {
+        Self { need_wakeup: true, multi_buffer: false, mode: BindMode::Auto }
+    }
+}
+impl BindFlags {
+    /// The value of `sxdp_flags`
+    ///
+    /// XDP_SHARED_UMEM is not among them, as sockets sharing a umem are [`crate::SharedXDPSocket`]s and inherit the flags of their owner
+    pub const fn bits(&self) -> u16 {
+        let mut bits = match self.mode {
+            BindMode::Auto => 0,
+            BindMode::ZeroCopy => libc::XDP_ZEROCOPY,
+            BindMode::Copy => libc::XDP_COPY,
+        };
+        if self.need_wakeup {
+            bits |= libc::XDP_USE_NEED_WAKEUP;
+        }
+        if self.multi_buffer {
+            bits |= libc::XDP_USE_SG;
+        }
+        bits
+    }
+
+    /// The flags making up `bits`, the inverse of [`Self::bits`]
+    pub const fn from_bits(bits: u16) -> Self {
+        let mode = if bits & libc::XDP_ZEROCOPY != 0 {
+            BindMode::ZeroCopy
+        } else if bits & libc::XDP_COPY != 0 {
+            BindMode::Copy
+        } else {
+            BindMode::Auto
+        };
+        Self { need_wakeup: bits & libc::XDP_USE_NEED_WAKEUP != 0, multi_buffer: bits & libc::XDP_USE_SG != 0, mode }
+    }
+}
+
+/// A snapshot of the state of an [`XDPSocket`], see [`XDPSocket::status`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketStatus {
+    pub if_index: libc::c_uint,
+    pub if_queue: libc::c_uint,
+    /// Whether the driver moves frames in and out of the umem directly, copy mode runs at about half the throughput
+    pub zero_copy: bool,
+    pub rx_dropped: u64,
+    pub rx_invalid_descs: u64,
+    pub tx_invalid_descs: u64,
+    /// Frames and bytes that went through [`XDPSocket::recv`] and [`XDPSocket::send_with`], see [`TrafficCounters`]
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_wakeups: u64,
+    pub tx_wakeups_skipped: u64,
+}
+impl SocketStatus {
+    /// The names of the columns of [`Self::to_csv_row`]
+    pub const CSV_HEADER: &str = "if_index,if_queue,zero_copy,rx_dropped,rx_invalid_descs,tx_invalid_descs,rx_packets,rx_bytes,tx_packets,tx_bytes,tx_wakeups,tx_wakeups_skipped";
+
+    /// This status as a JSON object on a single line, named like the fields
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"if_index\":{},\"if_queue\":{},\"zero_copy\":{},\"rx_dropped\":{},\"rx_invalid_descs\":{},\"tx_invalid_descs\":{},\"rx_packets\":{},\"rx_bytes\":{},\"tx_packets\":{},\"tx_bytes\":{},\"tx_wakeups\":{},\"tx_wakeups_skipped\":{}}}",
+            self.if_index, self.if_queue, self.zero_copy, self.rx_dropped, self.rx_invalid_descs, self.tx_invalid_descs,
+            self.rx_packets, self.rx_bytes, self.tx_packets, self.tx_bytes, self.tx_wakeups, self.tx_wakeups_skipped,
+        )
+    }
+
+    /// This status as a CSV row without line terminator, see [`Self::CSV_HEADER`]
+    pub fn to_csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.if_index, self.if_queue, self.zero_copy, self.rx_dropped, self.rx_invalid_descs, self.tx_invalid_descs,
+            self.rx_packets, self.rx_bytes, self.tx_packets, self.tx_bytes, self.tx_wakeups, self.tx_wakeups_skipped,
+        )
+    }
+}
+
+/// Frames and bytes moved by the receive and send paths of a socket, the kernel statistics only count errors
+///
+/// Wakeups for transmission which were performed, or skipped since the driver was already running, are counted as well.
+///
+/// Counters wrap around on overflow, compute rates with `wrapping_sub`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrafficCounters {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_wakeups: u64,
+    pub tx_wakeups_skipped: u64,
+}
+impl TrafficCounters {
+    pub(crate) const fn record_rx(&mut self, len: usize) {
+        self.rx_packets = self.rx_packets.wrapping_add(1);
+        self.rx_bytes = self.rx_bytes.wrapping_add(len as u64);
+    }
+
+    pub(crate) const fn record_tx(&mut self, len: usize) {
+        self.tx_packets = self.tx_packets.wrapping_add(1);
+        self.tx_bytes = self.tx_bytes.wrapping_add(len as u64);
+    }
+
+    pub(crate) const fn record_tx_wakeup(&mut self, performed: bool) {
+        if performed {
+            self.tx_wakeups = self.tx_wakeups.wrapping_add(1);
+        } else {
+            self.tx_wakeups_skipped = self.tx_wakeups_skipped.wrapping_add(1);
+        }
+    }
+}
 
 /// An AF_XDP socket bound to an <ifindex,ifqueue> pair
+///
+/// It registers the umem and owns its fill and completion rings, which [`crate::SharedXDPSocket`]s bound to the same pair also use
 pub struct XDPSocket<'a> {
     // metadata
     pub if_index: libc::c_uint,
@@ -13,12 +233,23 @@ pub struct XDPSocket<'a> {
 
     // socket
     pub fd: RawFd,
+    pub bind_flags: BindFlags,
 
     // rings
     pub rx_ring: XDPRing<'a, libc::xdp_desc>,
     pub tx_ring: XDPRing<'a, libc::xdp_desc>,
     pub completion_ring: XDPRing<'a, u64>,
     pub fill_ring: XDPRing<'a, u64>,
+    pub fill_order: FillOrder,
+
+    // pacing
+    pub tx_rate_limiter: Option<TxRateLimiter>,
+    pub tx_watermarks: Option<TxWatermarks>,
+    pub tx_queue: Option<TxQueue>,
+
+    // accounting
+    pub traffic: TrafficCounters,
+    pub hooks: Option<Arc<dyn XdpHooks>>,
 }
 impl<'a> XDPSocket<'a> {
 
@@ -30,50 +261,81 @@ impl<'a> XDPSocket<'a> {
         queue_id: libc::c_uint,
         umem: Arc<Umem>,
         rings_size: usize,
+    ) -> Result<Self, crate::Error> {
+        Self::with_ring_sizes(interface_index, queue_id, umem, RingSizes::uniform(rings_size))
+    }
+
+    /// Like [`Self::new`], sizing every ring independently
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "debug", skip(umem)))]
+    pub fn with_ring_sizes(
+        interface_index: libc::c_uint,
+        queue_id: libc::c_uint,
+        umem: Arc<Umem>,
+        ring_sizes: RingSizes,
+    ) -> Result<Self, crate::Error> {
+        Self::with_bind_flags(interface_index, queue_id, umem, ring_sizes, BindFlags::default())
+    }
+
+    /// Like [`Self::with_ring_sizes`], accepting packets spread over several chunks (XDP_USE_SG)
+    ///
+    /// Transmit them with [`Self::send_multi_buffer`]. Received packets larger than a chunk arrive as several descriptors,
+    /// all of them but the last carrying [`libc::XDP_PKT_CONTD`] in their options, which [`Self::recv`] hands over one by one
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "debug", skip(umem)))]
+    pub fn with_multi_buffer(
+        interface_index: libc::c_uint,
+        queue_id: libc::c_uint,
+        umem: Arc<Umem>,
+        ring_sizes: RingSizes,
+    ) -> Result<Self, crate::Error> {
+        Self::with_bind_flags(interface_index, queue_id, umem, ring_sizes, BindFlags { multi_buffer: true, ..BindFlags::default() })
+    }
+
+    /// Like [`Self::with_ring_sizes`], binding with `bind_flags`
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "debug", skip(umem)))]
+    pub fn with_bind_flags(
+        interface_index: libc::c_uint,
+        queue_id: libc::c_uint,
+        umem: Arc<Umem>,
+        ring_sizes: RingSizes,
+        bind_flags: BindFlags,
     ) -> Result<Self, crate::Error> {
         // check rings size
-        assert!(rings_size.is_power_of_two(), "rings_size must be a power of two");
+        assert!(ring_sizes.are_valid(), "ring sizes must be powers of two");
+
+        let context = |operation| move |error: crate::Error| error.on_socket(interface_index, queue_id, operation);
 
         // create AF_XDP socket
         let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW, 0) };
         if fd < 0 {
-            return Err(crate::Error::SocketCreationFailure);
+            return Err(context(SocketOperation::Create)(crate::Error::SocketCreationFailure));
         }
 
+        // closed on failure, after the rings are unmapped
+        let owned_fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
         // register umem with socket
-        utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_UMEM_REG, &libc::xdp_umem_reg_v1 {
-            addr: unsafe { umem.memory_ptr() } as usize as _,
-            len: umem.memory_size() as _,
-            chunk_size: umem.chunk_size() as _,
-            headroom: 0,
-        })?;
+        umem.register(fd).map_err(context(SocketOperation::RegisterUmem))?;
 
         // prepare rings
-        utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_RX_RING, &rings_size)?;
-        utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_TX_RING, &rings_size)?;
-        utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_UMEM_FILL_RING, &rings_size)?;
-        utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_UMEM_COMPLETION_RING, &rings_size)?;
-
-        // get rings umem offsets
-        let umem_offsets = utils::getsockopt::<libc::xdp_mmap_offsets_v1>(fd, libc::SOL_XDP, libc::XDP_MMAP_OFFSETS)?;
+        utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_RX_RING, &ring_sizes.rx).map_err(context(SocketOperation::ConfigureRings))?;
+        utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_TX_RING, &ring_sizes.tx).map_err(context(SocketOperation::ConfigureRings))?;
+        utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_UMEM_FILL_RING, &ring_sizes.fill).map_err(context(SocketOperation::ConfigureRings))?;
+        utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_UMEM_COMPLETION_RING, &ring_sizes.completion).map_err(context(SocketOperation::ConfigureRings))?;
 
         // mmap rings
-        let rx_ring = XDPRing::new(rings_size, fd, &umem_offsets.rx, libc::XDP_PGOFF_RX_RING)?;
-        let tx_ring = XDPRing::new(rings_size, fd, &umem_offsets.tx, libc::XDP_PGOFF_TX_RING)?;
-        let cp_ring = XDPRing::new(rings_size, fd, &umem_offsets.cr, libc::XDP_UMEM_PGOFF_COMPLETION_RING as _)?;
-        let fl_ring = XDPRing::new(rings_size, fd, &umem_offsets.fr, libc::XDP_UMEM_PGOFF_FILL_RING as _)?;
+        let (rx_ring, tx_ring, cp_ring, fl_ring) = Self::map_rings(fd, ring_sizes).map_err(context(SocketOperation::MapRings))?;
 
         // bind socket
         let bind_address = libc::sockaddr_xdp {
             sxdp_family: libc::AF_XDP as _,
-            sxdp_flags: libc::XDP_USE_NEED_WAKEUP,
+            sxdp_flags: bind_flags.bits(),
             sxdp_ifindex: interface_index,
             sxdp_queue_id: queue_id,
             sxdp_shared_umem_fd: 0,
         };
         let bind_result = unsafe { libc::bind(fd, &bind_address as *const _ as *const _, std::mem::size_of::<libc::sockaddr_xdp>() as _) };
         if bind_result < 0 {
-            return Err(crate::Error::SocketBindFailure { error: std::io::Error::last_os_error() });
+            return Err(context(SocketOperation::Bind)(crate::Error::SocketBindFailure { error: std::io::Error::last_os_error() }));
         }
 
         // assemble result
@@ -81,57 +343,434 @@ impl<'a> XDPSocket<'a> {
             if_index: interface_index,
             if_queue: queue_id,
             umem,
-            fd,
+            fd: owned_fd.into_raw_fd(),
+            bind_flags,
             rx_ring,
             tx_ring,
             completion_ring: cp_ring,
             fill_ring: fl_ring,
+            fill_order: FillOrder::default(),
+            tx_rate_limiter: None,
+            tx_watermarks: None,
+            tx_queue: None,
+            hooks: None,
+            traffic: TrafficCounters::default(),
         })
     }
 
+    /// Like [`Self::with_bind_flags`], trying zero-copy first then falling back to copy mode, returning the mode obtained
+    ///
+    /// The mode of `bind_flags` is ignored. Unlike [`BindMode::Auto`], which leaves the choice to the kernel, the outcome is
+    /// known without querying the socket, and drivers refusing zero-copy with an error still end up in copy mode. The last
+    /// step of the chain, generic mode, needs the program attached again: see [`crate::BPFRedirectManager::bind_with_fallback`]
+    pub fn bind_with_fallback(
+        interface_index: libc::c_uint,
+        queue_id: libc::c_uint,
+        umem: Arc<Umem>,
+        ring_sizes: RingSizes,
+        bind_flags: BindFlags,
+    ) -> Result<(Self, BindMode), crate::Error> {
+        match Self::with_bind_flags(interface_index, queue_id, umem.clone(), ring_sizes, BindFlags { mode: BindMode::ZeroCopy, ..bind_flags }) {
+            Ok(socket) => Ok((socket, BindMode::ZeroCopy)),
+            Err(error) if matches!(error.root_cause(), crate::Error::SocketBindFailure { .. }) => {
+                let socket = Self::with_bind_flags(interface_index, queue_id, umem, ring_sizes, BindFlags { mode: BindMode::Copy, ..bind_flags })?;
+                Ok((socket, BindMode::Copy))
+            },
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Unmap the rings, then close the socket, reporting the failures dropping it ignores
+    ///
+    /// The kernel removes the socket from the XSKMAPs it was added to once it is released, i.e. once every duplicate of
+    /// its descriptor is closed as well. [`crate::BPFRedirectManager::close_socket`] removes its redirects beforehand
+    pub fn close(mut self) -> Result<(), crate::Error> {
+        self.teardown().map_err(|error| self.context(error, SocketOperation::Close))
+    }
+
+    /// Unmap the rings and close the descriptor, keeping the first failure, only once
+    fn teardown(&mut self) -> Result<(), crate::Error> {
+        // rings are mappings of the socket, they go first
+        let mut result = Ok(());
+        for unmapped in [self.rx_ring.unmap(), self.tx_ring.unmap(), self.completion_ring.unmap(), self.fill_ring.unmap()] {
+            result = result.and(unmapped);
+        }
+
+        // close socket
+        if self.fd >= 0 {
+            if unsafe { libc::close(self.fd) } < 0 {
+                result = result.and(Err(crate::Error::SocketCloseFailure { error: std::io::Error::last_os_error() }));
+            }
+            self.fd = -1;
+        }
+        result
+    }
+
+    /// Wrap `error` with the interface and queue of this socket
+    pub(crate) fn context(&self, error: crate::Error, operation: SocketOperation) -> crate::Error {
+        error.on_socket(self.if_index, self.if_queue, operation)
+    }
+
+    /// Map the RX, TX, completion and fill rings of the socket `fd`, which were set up with `ring_sizes`
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn map_rings(fd: RawFd, ring_sizes: RingSizes) -> Result<(XDPRing<'a, libc::xdp_desc>, XDPRing<'a, libc::xdp_desc>, XDPRing<'a, u64>, XDPRing<'a, u64>), crate::Error> {
+        // get rings umem offsets
+        let umem_offsets = utils::getsockopt::<libc::xdp_mmap_offsets>(fd, libc::SOL_XDP, libc::XDP_MMAP_OFFSETS)?;
+
+        Ok((
+            XDPRing::new(ring_sizes.rx, fd, &umem_offsets.rx, libc::XDP_PGOFF_RX_RING)?,
+            XDPRing::new(ring_sizes.tx, fd, &umem_offsets.tx, libc::XDP_PGOFF_TX_RING)?,
+            XDPRing::new(ring_sizes.completion, fd, &umem_offsets.cr, libc::XDP_UMEM_PGOFF_COMPLETION_RING as _)?,
+            XDPRing::new(ring_sizes.fill, fd, &umem_offsets.fr, libc::XDP_UMEM_PGOFF_FILL_RING as _)?,
+        ))
+    }
+
+    /// The sizes of the rings of this socket
+    pub fn ring_sizes(&self) -> RingSizes {
+        RingSizes {
+            rx: self.rx_ring.num_elements(),
+            tx: self.tx_ring.num_elements(),
+            fill: self.fill_ring.num_elements(),
+            completion: self.completion_ring.num_elements(),
+        }
+    }
+
     /// Gets the statistics associated with this AF_XDP socket
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
     pub fn get_statistics(&self) -> Result<libc::xdp_statistics_v1, crate::Error> {
-        utils::getsockopt(self.fd, libc::SOL_XDP, libc::XDP_STATISTICS)
+        utils::getsockopt(self.fd, libc::SOL_XDP, libc::XDP_STATISTICS).map_err(|error| self.context(error, SocketOperation::QueryOptions))
     }
 
     /// Gets the options associated with this AF_XDP socket
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
     pub fn get_options(&self) -> Result<libc::xdp_options, crate::Error> {
-        utils::getsockopt(self.fd, libc::SOL_XDP, libc::XDP_OPTIONS)
+        utils::getsockopt(self.fd, libc::SOL_XDP, libc::XDP_OPTIONS).map_err(|error| self.context(error, SocketOperation::QueryOptions))
+    }
+
+    /// Whether the socket got the zero-copy path, rather than silently falling back to copy mode
+    pub fn is_zero_copy(&self) -> Result<bool, crate::Error> {
+        Ok(self.get_options()?.flags & libc::XDP_OPTIONS_ZEROCOPY != 0)
+    }
+
+    /// The NAPI context the socket receives from, `None` until it received a frame
+    ///
+    /// See [`crate::napi::placement`] to check it against the one of the bound queue
+    pub fn napi_id(&self) -> Result<Option<u32>, crate::Error> {
+        let napi_id: u32 = utils::getsockopt(self.fd, libc::SOL_SOCKET, libc::SO_INCOMING_NAPI_ID).map_err(|error| self.context(error, SocketOperation::QueryOptions))?;
+        Ok((napi_id != 0).then_some(napi_id))
+    }
+
+    /// How much memory the rings and the umem of this socket take
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        MemoryFootprint {
+            rx_ring: page_aligned(self.rx_ring.mmap_size()),
+            tx_ring: page_aligned(self.tx_ring.mmap_size()),
+            fill_ring: page_aligned(self.fill_ring.mmap_size()),
+            completion_ring: page_aligned(self.completion_ring.mmap_size()),
+            umem: self.umem.memory_size(),
+        }
+    }
+
+    /// Gathers the mode and statistics of this socket
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
+    pub fn status(&self) -> Result<SocketStatus, crate::Error> {
+        let statistics = self.get_statistics()?;
+        Ok(SocketStatus {
+            if_index: self.if_index,
+            if_queue: self.if_queue,
+            zero_copy: self.is_zero_copy()?,
+            rx_dropped: statistics.rx_dropped,
+            rx_invalid_descs: statistics.rx_invalid_descs,
+            tx_invalid_descs: statistics.tx_invalid_descs,
+            rx_packets: self.traffic.rx_packets,
+            rx_bytes: self.traffic.rx_bytes,
+            tx_packets: self.traffic.tx_packets,
+            tx_bytes: self.traffic.tx_bytes,
+            tx_wakeups: self.traffic.tx_wakeups,
+            tx_wakeups_skipped: self.traffic.tx_wakeups_skipped,
+        })
     }
 
     /// Poll this socket for new packets
     /// 
+    /// Fails with [`crate::Error::SocketError`], [`crate::Error::SocketClosed`] or [`crate::Error::SocketInvalid`] when the
+    /// socket is dead, e.g. because its interface was unregistered, and with [`crate::Error::PollFailure`] when poll itself
+    /// failed, all of them wrapped in a [`crate::Error::SocketOperationFailure`] naming the socket. Blocks until packets arrive, even across signals, see [`Self::poll_for_reception_timeout`] to get control back.
+    /// 
     /// _You should not use this function unless in development, and leverage some sort of reactor instead_
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
     pub fn poll_for_reception(&self) -> Result<(), crate::Error> {
-        let mut poll_fd = libc::pollfd {
-            fd: self.as_raw_fd(),
-            events: libc::POLLIN,
-            revents: 0,
-        };
-        unsafe { libc::poll(&mut poll_fd as *mut _ as *mut _, 1, -1) };
-        if (poll_fd.revents & libc::POLLIN) == 0 {
-            Err(crate::Error::PollFailure)
-        } else {
-            Ok(())
-        }
+        utils::poll_for_reception(self.fd).map_err(|error| self.context(error, SocketOperation::Poll))
+    }
+
+    /// Poll this socket for new packets for up to `timeout` (forever if `None`), returning whether there are any
+    ///
+    /// `false` means the timeout expired or a signal interrupted the wait, leaving room for periodic work between packets.
+    /// Fails like [`Self::poll_for_reception`] does
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
+    pub fn poll_for_reception_timeout(&self, timeout: Option<std::time::Duration>) -> Result<bool, crate::Error> {
+        utils::poll_for_reception_timeout(self.fd, timeout).map_err(|error| self.context(error, SocketOperation::Poll))
+    }
+
+    /// Wait for frames on the RX ring following `strategy`, returning whether there are any
+    pub fn wait_for_reception(&self, strategy: &WaitStrategy) -> Result<bool, crate::Error> {
+        strategy.wait(self.fd, || self.rx_ring.can_consume()).map_err(|error| self.context(error, SocketOperation::Poll))
     }
 
     /// Wake this socket up for transmission
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
     pub fn wake_for_transmission(&self) -> Result<(), crate::Error> {
-        let ret = unsafe { libc::sendto(self.fd, std::ptr::null(), 0,  libc::MSG_DONTWAIT, std::ptr::null(), 0) };
-        if ret < 0 {
-            Err(crate::Error::SocketSendFailure { error: std::io::Error::last_os_error() })
-        } else {
-            Ok(())
+        utils::wake_for_transmission(self.fd).map_err(|error| self.context(error, SocketOperation::Wakeup))
+    }
+
+    /// Wake this socket up for transmission only if the driver asked for it, returning whether a syscall was made
+    ///
+    /// This is what [`Self::send`] does, call it once after a burst of frames produced on the TX ring by hand
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
+    pub fn wake_for_transmission_if_needed(&mut self) -> Result<bool, crate::Error> {
+        let needs_wakeup = ! self.bind_flags.need_wakeup || self.tx_ring.needs_wakeup();
+        if needs_wakeup {
+            self.wake_for_transmission()?;
+            if let Some(hooks) = &self.hooks {
+                hooks.on_wakeup_syscall(self);
+            }
+        }
+        self.traffic.record_tx_wakeup(needs_wakeup);
+        Ok(needs_wakeup)
+    }
+
+    /// Copy `frame` into a chunk obtained from `allocator` and enqueue it for transmission
+    /// 
+    /// Returns `false` if the TX ring is full, no chunk could be allocated or [`Self::tx_rate_limiter`] did not admit the frame.
+    /// With a [`Self::tx_queue`], frames finding the TX ring full wait there instead, unless its overflow policy drops them.
+    /// The chunk goes back to `allocator` once it appears on the completion ring
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd, len = frame.len())))]
+    pub fn send(&mut self, allocator: &(impl UmemAllocator + ?Sized), frame: &[u8]) -> Result<bool, crate::Error> {
+        // check size
+        if frame.len() > self.umem.chunk_size() {
+            return Err(crate::Error::FrameTooLarge { length: frame.len(), chunk_size: self.umem.chunk_size() });
+        }
+
+        self.send_with(allocator, 0, |chunk| {
+            chunk[..frame.len()].copy_from_slice(frame);
+            Ok(frame.len())
+        })
+    }
+
+    /// Write a frame directly into a chunk obtained from `allocator` and enqueue it for transmission
+    /// 
+    /// `writer` receives the chunk past its first `headroom` bytes and returns the length of the frame it wrote, a length
+    /// past the end of the chunk failing with [`crate::Error::FrameTooLarge`]. Returns `false` like [`Self::send`] does
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
+    pub fn send_with(&mut self, allocator: &(impl UmemAllocator + ?Sized), headroom: usize, writer: impl FnOnce(&mut [u8]) -> Result<usize, crate::Error>) -> Result<bool, crate::Error> {
+        let mut batch = self.tx_batch();
+        let sent = batch.send_with(allocator, headroom, writer)?;
+        batch.commit()?;
+        Ok(sent)
+    }
+
+    /// Enqueue a single packet made of `segments`, each in its own chunk, for transmission on a socket created with [`Self::with_multi_buffer`]
+    ///
+    /// Segments are sent in order, e.g. a header chunk followed by the untouched payload chunk of a received frame.
+    /// Returns `false`, leaving the segments to the caller, if the TX ring cannot take them all or [`Self::tx_rate_limiter`]
+    /// did not admit the packet. Otherwise every chunk appears on the completion ring once transmitted.
+    /// Drivers limit the number of segments, the kernel itself accepts up to 17 in copy mode. Fails with
+    /// [`crate::Error::InvalidConfiguration`] without segments
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd, segments = segments.len())))]
+    pub fn send_multi_buffer(&mut self, segments: &[Frame]) -> Result<bool, crate::Error> {
+        let mut batch = self.tx_batch();
+        let sent = batch.push_multi_buffer(segments)?;
+        batch.commit()?;
+        Ok(sent)
+    }
+
+    /// How many descriptors are free and queued on the TX ring, and how many completions wait to be reaped
+    pub fn tx_backlog(&self) -> TxBacklog {
+        TxBacklog {
+            tx_free: self.tx_ring.num_producible(),
+            tx_queued: self.tx_ring.num_consumable(),
+            completions: self.completion_ring.num_consumable(),
+        }
+    }
+
+    /// Whether the TX ring is congested according to [`Self::tx_watermarks`], or full if there are none
+    ///
+    /// Check it before taking more traffic from upstream rather than finding out on every send attempt
+    pub fn is_tx_congested(&mut self) -> bool {
+        self.update_tx_watermarks();
+        match self.tx_watermarks.as_ref() {
+            Some(watermarks) => watermarks.is_congested(),
+            None => self.tx_ring.num_producible() == 0,
+        }
+    }
+
+    /// Let [`Self::tx_watermarks`] know how much room is left on the TX ring
+    pub(crate) fn update_tx_watermarks(&mut self) {
+        let tx_free = self.tx_ring.num_producible();
+        if let Some(watermarks) = self.tx_watermarks.as_mut() {
+            watermarks.update(tx_free);
+        }
+    }
+
+    /// Start a burst of frames, published together with at most one wakeup, see [`TxBatch`]
+    ///
+    /// Prefer it to repeated [`Self::send`]s, which wake the socket up for every frame
+    pub fn tx_batch(&mut self) -> TxBatch<'_, 'a> {
+        TxBatch::new(self)
+    }
+
+    /// Consume every frame waiting in the RX ring, handing each one to `handler`
+    /// 
+    /// Chunks are given back to the fill ring, or to `allocator` when the fill ring is full. Returns the number of frames received
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
+    pub fn recv(&mut self, allocator: &(impl UmemAllocator + ?Sized), handler: impl FnMut(&[u8])) -> usize {
+        self.recv_at_most(allocator, usize::MAX, handler)
+    }
+
+    /// Like [`Self::recv`], stopping after `max` frames
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
+    pub fn recv_at_most(&mut self, allocator: &(impl UmemAllocator + ?Sized), max: usize, mut handler: impl FnMut(&[u8])) -> usize {
+        let mut count = 0;
+        while count < max && self.rx_ring.can_consume() {
+            // process frame
+            let rx_index = self.rx_ring.get_consumer_index() as usize;
+            let rx_offset = self.rx_ring.get_nth_descriptor(rx_index).addr;
+            let frame = self.rx_ring.get_nth_slice(rx_index, &self.umem);
+            self.rx_ring.prefetch_nth_frame(rx_index + 1, &self.umem);
+            self.traffic.record_rx(frame.len());
+            handler(&frame);
+
+            // give back chunk
+            if self.fill_ring.can_produce() {
+                self.fill_ring.produce_umem_offset(rx_offset);
+            } else {
+                allocator.release_offset(rx_offset);
+            }
+
+            // advance rx index
+            self.rx_ring.advance_consumer_index();
+            count += 1;
+        }
+        count
+    }
+
+    /// Allocate up to `max` chunks from `allocator` and publish them to the fill ring at once, returning how many were added
+    ///
+    /// Stops early when the fill ring is full or `allocator` runs out of chunks
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
+    pub fn refill_fill_ring(&mut self, allocator: &(impl UmemAllocator + ?Sized), max: usize) -> usize {
+        let limit = max.min(self.fill_ring.num_producible() as usize);
+        let producer_index = self.fill_ring.get_producer_index() as usize;
+        let mut count = 0;
+        while count < limit {
+            let Some(chunk_index) = allocator.try_allocate() else {
+                if let Some(hooks) = &self.hooks {
+                    hooks.on_alloc_fail(self);
+                }
+                break;
+            };
+            let index = (producer_index + count) & (self.fill_ring.num_elements() - 1);
+            self.fill_ring.set_nth_umem_offset(index, self.umem.chunk_start_offset_for_index(chunk_index));
+            count += 1;
+        }
+        if self.fill_order == FillOrder::Ascending {
+            self.fill_ring.sort_umem_offsets(producer_index, count);
+        }
+        self.fill_ring.advance_producer_index_by(count as u32);
+        count
+    }
+
+    /// Release every chunk found in the completion ring back to `allocator`, returning how many were released
+    ///
+    /// The ring is drained in a single batch, call it after sending or periodically to keep `allocator` supplied
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
+    pub fn reap_completions(&mut self, allocator: &(impl UmemAllocator + ?Sized)) -> usize {
+        let count = self.completion_ring.num_consumable();
+        let consumer_index = self.completion_ring.get_consumer_index() as usize;
+        for n in 0..count as usize {
+            let index = (consumer_index + n) & (self.completion_ring.num_elements() - 1);
+            self.completion_ring.prefetch_nth_descriptor(index + COMPLETION_PREFETCH_DISTANCE);
+            allocator.release_offset(self.completion_ring.get_nth_umem_offset(index));
+        }
+        self.completion_ring.advance_consumer_index_by(count);
+        self.flush_tx_queue_after_completions(count as usize);
+        count as usize
+    }
+
+    /// Move the chunks found in the completion ring straight to the fill ring, releasing to `allocator` those which do
+    /// not fit, returning how many were moved
+    ///
+    /// When the same socket receives and transmits, as echo and forwarding workloads do, this saves the allocator a release
+    /// and an allocation per frame. Use it in place of [`Self::reap_completions`]. Chunks still referenced elsewhere, e.g.
+    /// on the TX ring of another socket through a [`crate::RefCountingAllocator`], only lose a reference, see
+    /// [`UmemAllocator::try_recycle`]
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip_all, fields(fd = self.fd)))]
+    pub fn recycle_completions(&mut self, allocator: &(impl UmemAllocator + ?Sized)) -> usize {
+        let count = self.completion_ring.num_consumable() as usize;
+        let room = self.fill_ring.num_producible() as usize;
+        let consumer_index = self.completion_ring.get_consumer_index() as usize;
+        let producer_index = self.fill_ring.get_producer_index() as usize;
+        let mut recycled = 0;
+        for n in 0..count {
+            let offset = self.completion_ring.get_nth_umem_offset((consumer_index + n) & (self.completion_ring.num_elements() - 1));
+            if recycled == room {
+                allocator.release_offset(offset);
+                continue;
+            }
+            let chunk_index = self.umem.chunk_index_for_offset(offset);
+            if allocator.try_recycle(chunk_index) {
+                let chunk_start = self.umem.chunk_start_offset_for_index(chunk_index);
+                self.fill_ring.set_nth_umem_offset((producer_index + recycled) & (self.fill_ring.num_elements() - 1), chunk_start);
+                recycled += 1;
+            }
+        }
+        if self.fill_order == FillOrder::Ascending {
+            self.fill_ring.sort_umem_offsets(producer_index, recycled);
         }
+        self.fill_ring.advance_producer_index_by(recycled as u32);
+        self.completion_ring.advance_consumer_index_by(count as u32);
+        self.flush_tx_queue_after_completions(count);
+        recycled
+    }
+
+    /// Give the room `count` completions made to queued frames first, a failed wakeup is retried by the next transmission
+    fn flush_tx_queue_after_completions(&mut self, count: usize) {
+        if count > 0 && self.tx_queue.as_ref().is_some_and(|queue| ! queue.is_empty()) {
+            let _ = self.flush_tx_queue();
+        }
+    }
+
+    /// Move the frames of [`Self::tx_queue`] onto the TX ring while there is room, returning how many were moved
+    pub fn flush_tx_queue(&mut self) -> Result<usize, crate::Error> {
+        let mut batch = self.tx_batch();
+        let count = batch.flush_queue();
+        batch.commit()?;
+        Ok(count)
+    }
+
+    /// Drop the frames of [`Self::tx_queue`], giving their chunks back to `allocator`, returning how many were dropped
+    pub fn clear_tx_queue(&mut self, allocator: &(impl UmemAllocator + ?Sized)) -> usize {
+        let Some(queue) = self.tx_queue.as_mut() else {
+            return 0;
+        };
+        let mut count = 0;
+        while let Some(frame) = queue.dequeue() {
+            allocator.release_offset(frame.addr);
+            count += 1;
+        }
+        count
     }
 
     pub fn debug_print_status(&self) {
         println!("stats for AF_XDP sock {}", self.fd);
-        let stats = self.get_statistics().unwrap();
+        let stats = self.status().unwrap();
+        println!("  zero-copy                       = {}", stats.zero_copy);
         println!("  rx dropped (other reason)       = {}", stats.rx_dropped);
         println!("  rx dropped (invalid descriptor) = {}", stats.rx_invalid_descs);
         println!("  tx dropped (invalid descriptor) = {}", stats.tx_invalid_descs);
+        println!("  rx packets / bytes              = {} / {}", stats.rx_packets, stats.rx_bytes);
+        println!("  tx packets / bytes              = {} / {}", stats.tx_packets, stats.tx_bytes);
+        println!("  tx wakeups performed / skipped  = {} / {}", stats.tx_wakeups, stats.tx_wakeups_skipped);
         fn debug_ring<D>(name: &str, ring: &XDPRing<D>) {
             print!("{name} ring (");
             print!("consumer idx = {:10}", ring.get_consumer_index());
@@ -151,7 +790,118 @@ impl<'a> AsRawFd for XDPSocket<'a> {
 }
 impl<'a> Drop for XDPSocket<'a> {
     fn drop(&mut self) {
-        // close socket
-        unsafe { libc::close(self.fd) };
+        let _ = self.teardown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{FillOrder, MemoryFootprint, RingSizes};
+    use crate::{testing::MockXDP, DefaultAllocator, RefCountingAllocator, Umem, UmemAllocator, UmemAllocatorFactory};
+
+    #[test]
+    fn test_close() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+        socket.refill_fill_ring(&DefaultAllocator::for_umem(umem), 4);
+        socket.close().unwrap();
+
+        // the mock keeps its own mapping of the rings
+        assert!(mock.inject(b"after close"));
+    }
+
+    #[test]
+    fn test_memory_footprint() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let (socket, _mock) = MockXDP::new(umem, 16).unwrap();
+        let footprint = socket.memory_footprint();
+        assert_eq!(footprint, MemoryFootprint { rx_ring: 4096, tx_ring: 4096, fill_ring: 4096, completion_ring: 4096, umem: 64 * 2048 });
+        assert_eq!(footprint.total(), 4 * 4096 + 64 * 2048);
+
+        // rings of 2048 descriptors spill over their last page
+        let estimate = MemoryFootprint::estimate(RingSizes::uniform(2048), 0);
+        assert_eq!((estimate.rx_ring, estimate.fill_ring), (9 * 4096, 5 * 4096));
+        assert_eq!(estimate.rings(), 28 * 4096);
+    }
+
+    #[test]
+    fn test_ascending_fill_order() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let allocator = DefaultAllocator::for_umem(umem.clone());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+        socket.fill_order = FillOrder::Ascending;
+
+        // recycle chunks backwards
+        let chunks: Vec<_> = std::iter::from_fn(|| allocator.try_allocate()).collect();
+        for &chunk in chunks.iter().rev() {
+            allocator.release(chunk);
+        }
+        let offsets = |socket: &super::XDPSocket, range: std::ops::Range<usize>| range.map(|index| socket.fill_ring.get_nth_umem_offset(index)).collect::<Vec<_>>();
+
+        assert_eq!(socket.refill_fill_ring(&allocator, 12), 12);
+        assert!(offsets(&socket, 0..12).is_sorted());
+
+        // a batch wrapping around is sorted in two runs
+        for _ in 0..12 {
+            assert!(mock.inject(b"frame"));
+        }
+        assert_eq!(socket.refill_fill_ring(&allocator, 8), 8);
+        assert!(offsets(&socket, 12..16).is_sorted());
+        assert!(offsets(&socket, 0..4).is_sorted());
+    }
+
+    #[test]
+    fn test_recycle_completions() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let allocator = DefaultAllocator::for_umem(umem.clone());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 4).unwrap();
+
+        // the fill ring holds 3 chunks, leaving room for 2 of the 3 transmitted ones, the last goes back to the allocator
+        assert_eq!(socket.refill_fill_ring(&allocator, 1), 1);
+        for _ in 0..3 {
+            assert!(socket.send(&allocator, b"echo").unwrap());
+        }
+        assert_eq!(mock.transmit(|_| {}), 3);
+        assert_eq!(socket.recycle_completions(&allocator), 2);
+        assert_eq!(mock.fill_ring_len(), 3);
+
+        // received frames land in recycled chunks, no chunk got lost
+        for _ in 0..3 {
+            assert!(mock.inject(b"frame"));
+        }
+        assert_eq!(socket.recv(&allocator, |_| {}), 3);
+        let mut allocated = 0;
+        while allocator.try_allocate().is_some() {
+            allocated += 1;
+        }
+        assert_eq!(allocated, 64 - 3);
+    }
+
+    #[test]
+    fn test_recycle_shared_completions() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let allocator = RefCountingAllocator::<DefaultAllocator>::for_umem(umem.clone());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 4).unwrap();
+
+        // a chunk another socket still transmits stays out of the fill ring
+        let index = allocator.try_allocate().unwrap();
+        allocator.retain(index, 1);
+        let frame = crate::Frame { addr: umem.chunk_start_offset_for_index(index), len: 4 };
+        let mut batch = socket.tx_batch();
+        assert!(batch.push(frame));
+        batch.commit().unwrap();
+        assert_eq!(mock.transmit(|_| {}), 1);
+        assert_eq!(socket.recycle_completions(&allocator), 0);
+        assert_eq!((mock.fill_ring_len(), allocator.references(index)), (0, 1));
+
+        // the last one recycles it
+        let mut batch = socket.tx_batch();
+        assert!(batch.push(frame));
+        batch.commit().unwrap();
+        assert_eq!(mock.transmit(|_| {}), 1);
+        assert_eq!(socket.recycle_completions(&allocator), 1);
+        assert_eq!((mock.fill_ring_len(), allocator.references(index)), (1, 1));
     }
 }
@@ -0,0 +1,201 @@
+use std::{os::fd::{AsRawFd, FromRawFd, OwnedFd}, sync::Arc};
+
+use crate::XDPSocket;
+
+/// Interrupts the polls of an [`XDPSocketSet`] from any thread, see [`XDPSocketSet::waker`]
+#[derive(Debug, Clone)]
+pub struct Waker {
+    eventfd: Arc<OwnedFd>,
+}
+impl Waker {
+    /// Make the current or next [`XDPSocketSet::poll`] return right away, reporting that it was woken
+    ///
+    /// Wakeups coalesce: several of them before a poll are reported once
+    pub fn wake(&self) -> Result<(), crate::Error> {
+        let value = 1_u64;
+        if unsafe { libc::write(self.eventfd.as_raw_fd(), (&value as *const u64).cast(), 8) } < 0 {
+            return Err(crate::Error::EventFdFailure { error: std::io::Error::last_os_error() });
+        }
+        Ok(())
+    }
+}
+
+/// Sockets polled together, along with an eventfd through which other threads interrupt polls
+///
+/// A [`Waker`] lets a control plane thread deliver commands (configuration reloads, shutdowns) to the thread
+/// polling the set without waiting for the next packet. Sockets are referred to by the index returned when inserting
+pub struct XDPSocketSet<'a> {
+    sockets: Vec<XDPSocket<'a>>,
+    eventfd: Arc<OwnedFd>,
+    poll_fds: Vec<libc::pollfd>,
+    next_start: usize,
+}
+impl<'a> XDPSocketSet<'a> {
+    /// Create an empty set
+    pub fn new() -> Result<Self, crate::Error> {
+        let eventfd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if eventfd < 0 {
+            return Err(crate::Error::EventFdFailure { error: std::io::Error::last_os_error() });
+        }
+        let eventfd = unsafe { OwnedFd::from_raw_fd(eventfd) };
+        let poll_fds = vec![libc::pollfd { fd: eventfd.as_raw_fd(), events: libc::POLLIN, revents: 0 }];
+        Ok(Self { sockets: Vec::new(), eventfd: Arc::new(eventfd), poll_fds, next_start: 0 })
+    }
+
+    /// Add `socket` to the set, returning its index
+    pub fn insert(&mut self, socket: XDPSocket<'a>) -> usize {
+        self.poll_fds.push(libc::pollfd { fd: socket.as_raw_fd(), events: libc::POLLIN, revents: 0 });
+        self.sockets.push(socket);
+        self.sockets.len() - 1
+    }
+
+    /// A handle to interrupt polls from other threads
+    pub fn waker(&self) -> Waker {
+        Waker { eventfd: self.eventfd.clone() }
+    }
+
+    /// The number of sockets in the set
+    pub fn len(&self) -> usize {
+        self.sockets.len()
+    }
+
+    /// Whether the set has no sockets
+    pub fn is_empty(&self) -> bool {
+        self.sockets.is_empty()
+    }
+
+    /// The socket at `index`
+    pub fn socket(&mut self, index: usize) -> &mut XDPSocket<'a> {
+        &mut self.sockets[index]
+    }
+
+    /// Every socket of the set, in index order
+    pub fn sockets(&mut self) -> &mut [XDPSocket<'a>] {
+        &mut self.sockets
+    }
+
+    /// Wait up to `timeout` (forever if `None`) for any socket to become readable or a [`Waker`] to be used, returning whether it was woken
+    ///
+    /// Readable sockets are then listed by [`Self::ready`]. Signals interrupt the wait like a timeout does.
+    /// Fails like [`XDPSocket::poll_for_reception`] if any socket is dead
+    pub fn poll(&mut self, timeout: Option<std::time::Duration>) -> Result<bool, crate::Error> {
+        for poll_fd in self.poll_fds.iter_mut() {
+            poll_fd.revents = 0;
+        }
+        if unsafe { libc::poll(self.poll_fds.as_mut_ptr(), self.poll_fds.len() as _, crate::utils::poll_timeout_ms(timeout)) } < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                return Err(crate::Error::PollFailure { error });
+            }
+        }
+        for (socket, poll_fd) in self.sockets.iter().zip(&self.poll_fds[1..]) {
+            crate::utils::check_poll_events(poll_fd.fd, poll_fd.revents).map_err(|error| socket.context(error, crate::SocketOperation::Poll))?;
+        }
+
+        // consume wakeups
+        if self.poll_fds[0].revents & libc::POLLIN == 0 {
+            return Ok(false);
+        }
+        let mut value = 0_u64;
+        unsafe { libc::read(self.eventfd.as_raw_fd(), (&mut value as *mut u64).cast(), 8) };
+        Ok(true)
+    }
+
+    /// Hand every socket with frames on its RX ring to `recv` along with `budget`, returning the sum of what `recv` returned
+    ///
+    /// `recv` receives up to the budget, e.g. with [`XDPSocket::recv_at_most`], and returns how many frames it received.
+    /// Each call starts from the socket after the one the previous call started from, so that a busy queue does not
+    /// starve the others when called in a loop
+    pub fn drain_fair(&mut self, budget: usize, mut recv: impl FnMut(usize, &mut XDPSocket<'a>, usize) -> usize) -> usize {
+        let mut received = 0;
+        for index in self.next_round() {
+            let socket = &mut self.sockets[index];
+            if socket.rx_ring.can_consume() {
+                received += recv(index, socket, budget);
+            }
+        }
+        received
+    }
+
+    /// Every index, starting from the one after the start of the previous round
+    pub(crate) fn next_round(&mut self) -> impl Iterator<Item = usize> + use<> {
+        let len = self.sockets.len();
+        let start = if len == 0 { 0 } else { self.next_start % len };
+        self.next_start = start + 1;
+        (start..len).chain(0..start)
+    }
+
+    /// The indices of the sockets found readable by the last [`Self::poll`]
+    pub fn ready(&self) -> impl Iterator<Item = usize> + '_ {
+        self.poll_fds[1..].iter()
+            .enumerate()
+            .filter(|(_, poll_fd)| poll_fd.revents & libc::POLLIN != 0)
+            .map(|(index, _)| index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::XDPSocketSet;
+    use crate::{testing::MockXDP, DefaultAllocator, Umem, UmemAllocatorFactory};
+
+    #[test]
+    fn test_waker_interrupts_poll() {
+        let mut set = XDPSocketSet::new().unwrap();
+        assert!(! set.poll(Some(Duration::from_millis(1))).unwrap());
+
+        // from another thread, several wakeups are seen once
+        let waker = set.waker();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            waker.wake().unwrap();
+            waker.wake().unwrap();
+        });
+        assert!(set.poll(None).unwrap());
+        assert!(! set.poll(Some(Duration::ZERO)).unwrap());
+
+        // sockets are polled along with the eventfd, mock ones are always readable
+        let umem = Arc::new(Umem::new_2k(16).unwrap());
+        let (socket, _mock) = MockXDP::new(umem, 8).unwrap();
+        assert_eq!(set.insert(socket), 0);
+        assert!(! set.poll(None).unwrap());
+        assert_eq!(set.ready().collect::<Vec<_>>(), [0]);
+    }
+
+    #[test]
+    fn test_drain_fair() {
+        let mut set = XDPSocketSet::new().unwrap();
+        let mut mocks = Vec::new();
+        let mut allocators = Vec::new();
+        for _ in 0..3 {
+            let umem = Arc::new(Umem::new_2k(64).unwrap());
+            let (mut socket, mock) = MockXDP::new(umem.clone(), 32).unwrap();
+            let allocator = DefaultAllocator::for_umem(umem);
+            socket.refill_fill_ring(&allocator, usize::MAX);
+            set.insert(socket);
+            mocks.push(mock);
+            allocators.push(allocator);
+        }
+
+        // a busy first socket gets the same budget as the others
+        for _ in 0..20 {
+            assert!(mocks[0].inject(b"busy"));
+        }
+        assert!(mocks[2].inject(b"quiet"));
+        let mut order = Vec::new();
+        let drain = |set: &mut XDPSocketSet, order: &mut Vec<usize>| set.drain_fair(4, |index, socket, budget| {
+            socket.recv_at_most(&allocators[index], budget, |_| order.push(index))
+        });
+        assert_eq!(drain(&mut set, &mut order), 5);
+        assert_eq!(order, [0, 0, 0, 0, 2]);
+
+        // the starting socket rotates
+        order.clear();
+        assert!(mocks[0].inject(b"busy"));
+        assert!(mocks[1].inject(b"quiet"));
+        assert_eq!(drain(&mut set, &mut order), 5);
+        assert_eq!(order, [1, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,146 @@
+//! Periodic dumps of socket statuses as CSV or JSON lines, for scripts and spreadsheets where there is no Prometheus
+
+use std::{fs::File, io::{BufWriter, Write}, path::Path, time::{Duration, Instant, SystemTime}};
+
+use crate::{SocketStatus, XDPSocket};
+
+/// How a [`StatsDumper`] writes statuses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    /// A header, then a row per socket and dump, led by a timestamp column
+    Csv,
+    /// A JSON object per dump on its own line, holding the timestamp and the status of every socket
+    JsonLines,
+}
+
+/// Writes the status of sockets every interval, see [`SocketStatus::to_csv_row`] and [`SocketStatus::to_json`]
+///
+/// Timestamps are seconds since the Unix epoch, with millisecond precision. The first dump is due as soon as the dumper
+/// is created, late checks write a single dump and skip the intervals they missed. Every dump is flushed
+pub struct StatsDumper<W: Write> {
+    writer: W,
+    format: StatsFormat,
+    interval: Duration,
+    next_due: Instant,
+    header_written: bool,
+}
+impl StatsDumper<std::io::Stdout> {
+    /// Dump to the standard output every `interval`
+    pub fn stdout(format: StatsFormat, interval: Duration) -> Self {
+        Self::new(std::io::stdout(), format, interval)
+    }
+}
+impl StatsDumper<BufWriter<File>> {
+    /// Dump to the file at `path` every `interval`, replacing its contents
+    pub fn create(path: impl AsRef<Path>, format: StatsFormat, interval: Duration) -> Result<Self, crate::Error> {
+        let file = File::create(path).map_err(|error| crate::Error::StatsIOFailure { error })?;
+        Ok(Self::new(BufWriter::new(file), format, interval))
+    }
+}
+impl<W: Write> StatsDumper<W> {
+    /// Dump to `writer` every `interval`
+    pub fn new(writer: W, format: StatsFormat, interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "Interval must be positive");
+        Self { writer, format, interval, next_due: Instant::now(), header_written: false }
+    }
+
+    pub const fn format(&self) -> StatsFormat {
+        self.format
+    }
+
+    pub const fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// When the next dump should be written
+    pub const fn next_due(&self) -> Instant {
+        self.next_due
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Dump the status of `sockets` if a dump is due at `now`, returning whether one was written
+    pub fn dump_if_due<'s, 'a: 's>(&mut self, sockets: impl IntoIterator<Item = &'s XDPSocket<'a>>, now: Instant) -> Result<bool, crate::Error> {
+        if now < self.next_due {
+            return Ok(false);
+        }
+        let statuses = sockets.into_iter().map(XDPSocket::status).collect::<Result<Vec<_>, _>>()?;
+        self.dump(&statuses)?;
+
+        // schedule the next one, skipping what was missed
+        crate::utils::advance_schedule(&mut self.next_due, self.interval, now);
+        Ok(true)
+    }
+
+    /// Dump `statuses` right away, regardless of the interval
+    pub fn dump(&mut self, statuses: &[SocketStatus]) -> Result<(), crate::Error> {
+        self.dump_at(statuses, SystemTime::now()).map_err(|error| crate::Error::StatsIOFailure { error })
+    }
+
+    fn dump_at(&mut self, statuses: &[SocketStatus], time: SystemTime) -> std::io::Result<()> {
+        let timestamp = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+        match self.format {
+            StatsFormat::Csv => {
+                if !self.header_written {
+                    writeln!(self.writer, "timestamp,{}", SocketStatus::CSV_HEADER)?;
+                    self.header_written = true;
+                }
+                for status in statuses {
+                    writeln!(self.writer, "{timestamp:.3},{}", status.to_csv_row())?;
+                }
+            },
+            StatsFormat::JsonLines => {
+                let sockets = statuses.iter().map(SocketStatus::to_json).collect::<Vec<_>>().join(",");
+                writeln!(self.writer, "{{\"timestamp\":{timestamp:.3},\"sockets\":[{sockets}]}}")?;
+            },
+        }
+        self.writer.flush()
+    }
+}
+impl<W: Write> std::fmt::Debug for StatsDumper<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StatsDumper")
+            .field("format", &self.format)
+            .field("interval", &self.interval)
+            .field("next_due", &self.next_due)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{StatsDumper, StatsFormat};
+    use crate::SocketStatus;
+
+    #[test]
+    fn test_dump_formats() {
+        let status = SocketStatus {
+            if_index: 3, if_queue: 1, zero_copy: true, rx_dropped: 2, rx_invalid_descs: 0, tx_invalid_descs: 0,
+            rx_packets: 10, rx_bytes: 640, tx_packets: 5, tx_bytes: 320, tx_wakeups: 1, tx_wakeups_skipped: 4,
+        };
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+
+        // the header once, then a row per socket
+        let mut csv = StatsDumper::new(Vec::new(), StatsFormat::Csv, Duration::from_secs(1));
+        csv.dump_at(&[status, status], time).unwrap();
+        csv.dump_at(&[], time).unwrap();
+        let csv = String::from_utf8(csv.into_inner()).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], format!("timestamp,{}", SocketStatus::CSV_HEADER));
+        assert_eq!(lines[1], "1700000000.250,3,1,true,2,0,0,10,640,5,320,1,4");
+        assert_eq!(lines[0].split(',').count(), lines[1].split(',').count());
+
+        // a line per dump
+        let mut json = StatsDumper::new(Vec::new(), StatsFormat::JsonLines, Duration::from_secs(1));
+        json.dump_at(&[status], time).unwrap();
+        json.dump_at(&[], time).unwrap();
+        let json = String::from_utf8(json.into_inner()).unwrap();
+        assert_eq!(json, format!("{{\"timestamp\":1700000000.250,\"sockets\":[{}]}}\n{{\"timestamp\":1700000000.250,\"sockets\":[]}}\n", status.to_json()));
+        assert!(status.to_json().starts_with("{\"if_index\":3,\"if_queue\":1,\"zero_copy\":true,"));
+    }
+}
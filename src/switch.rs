@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use crate::packet::{EthernetFrame, MacAddress};
+
+/// A port of a [`LearningSwitch`], i.e. an index in `0..num_ports`
+pub type PortId = usize;
+
+/// What to do with frames whose destination is not in the table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloodPolicy {
+    /// Flood unknown unicast, multicast and broadcast frames
+    FloodAll,
+    /// Flood only multicast and broadcast frames, drop unknown unicast
+    FloodMulticastOnly,
+    /// Never flood
+    Drop,
+}
+
+/// The configuration of a [`LearningSwitch`]
+#[derive(Debug, Clone)]
+pub struct SwitchConfig {
+    /// How long an address is remembered after it was last seen
+    pub aging_time: std::time::Duration,
+    /// The maximum number of addresses remembered, new addresses are not learned when full
+    pub max_entries: usize,
+    pub flood_policy: FloodPolicy,
+}
+impl Default for SwitchConfig {
+    fn default() -> Self {
+        Self {
+            aging_time: std::time::Duration::from_secs(300),
+            max_entries: 8192,
+            flood_policy: FloodPolicy::FloodAll,
+        }
+    }
+}
+
+/// Counters kept for every port
+#[derive(Debug, Clone, Default)]
+pub struct PortStats {
+    pub rx_frames: u64,
+    pub rx_bytes: u64,
+    pub tx_frames: u64,
+    pub tx_bytes: u64,
+    /// Frames received on this port which were flooded
+    pub flooded: u64,
+    /// Frames received on this port which were dropped
+    pub dropped: u64,
+    /// Addresses learned on this port
+    pub learned: u64,
+}
+
+/// Where a frame has to go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forwarding {
+    /// To a single port
+    Unicast(PortId),
+    /// To every port except the ingress one
+    Flood,
+    /// Nowhere
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TableEntry {
+    port: PortId,
+    last_seen: std::time::Instant,
+}
+
+/// A MAC-learning bridge, deciding where frames go without touching sockets
+///
+/// Feed every received frame to [`Self::process`], then transmit it to the ports given by [`Self::egress_ports`]
+pub struct LearningSwitch {
+    config: SwitchConfig,
+    table: HashMap<MacAddress, TableEntry>,
+    stats: Vec<PortStats>,
+}
+impl LearningSwitch {
+    /// Create a switch with `num_ports` ports
+    pub fn new(num_ports: usize, config: SwitchConfig) -> Self {
+        Self {
+            config,
+            table: HashMap::new(),
+            stats: vec![PortStats::default(); num_ports],
+        }
+    }
+
+    /// The number of ports
+    pub fn num_ports(&self) -> usize {
+        self.stats.len()
+    }
+
+    /// Learn the source of `frame`, received on `ingress`, and decide where it goes
+    pub fn process(&mut self, ingress: PortId, frame: &[u8]) -> Forwarding {
+        self.process_at(ingress, frame, std::time::Instant::now())
+    }
+
+    /// The ports a frame received on `ingress` goes to, given the decision taken by [`Self::process`]
+    pub fn egress_ports(&self, ingress: PortId, forwarding: Forwarding) -> impl Iterator<Item = PortId> {
+        let (range, single) = match forwarding {
+            Forwarding::Unicast(port) => (0..0, Some(port)),
+            Forwarding::Flood => (0..self.num_ports(), None),
+            Forwarding::Drop => (0..0, None),
+        };
+        range.filter(move |port| *port != ingress).chain(single)
+    }
+
+    /// Account for a frame of `len` bytes transmitted on `port`
+    pub fn record_tx(&mut self, port: PortId, len: usize) {
+        self.stats[port].tx_frames += 1;
+        self.stats[port].tx_bytes += len as u64;
+    }
+
+    /// Remove the addresses not seen for longer than the aging time, returning how many were removed
+    pub fn age(&mut self) -> usize {
+        self.age_at(std::time::Instant::now())
+    }
+
+    /// Remove all the addresses learned on `port`, e.g. when it goes down
+    pub fn flush_port(&mut self, port: PortId) {
+        self.table.retain(|_, entry| entry.port != port);
+    }
+
+    /// The port where `address` was last seen
+    pub fn lookup(&self, address: &MacAddress) -> Option<PortId> {
+        self.table.get(address).map(|entry| entry.port)
+    }
+
+    /// The learned addresses and their ports
+    pub fn table(&self) -> impl Iterator<Item = (&MacAddress, PortId)> {
+        self.table.iter().map(|(address, entry)| (address, entry.port))
+    }
+
+    /// The counters of `port`
+    pub fn stats(&self, port: PortId) -> &PortStats {
+        &self.stats[port]
+    }
+
+    fn process_at(&mut self, ingress: PortId, frame: &[u8], now: std::time::Instant) -> Forwarding {
+        // account
+        let stats = &mut self.stats[ingress];
+        stats.rx_frames += 1;
+        stats.rx_bytes += frame.len() as u64;
+
+        // parse
+        let Some(ethernet) = EthernetFrame::new_checked(frame) else {
+            stats.dropped += 1;
+            return Forwarding::Drop;
+        };
+        let source = ethernet.source();
+        let destination = ethernet.destination();
+
+        // learn, group addresses are never valid sources
+        if ! source.is_multicast() {
+            let table_full = self.table.len() >= self.config.max_entries;
+            match self.table.get_mut(&source) {
+                Some(entry) => *entry = TableEntry { port: ingress, last_seen: now },
+                None if ! table_full => {
+                    self.table.insert(source, TableEntry { port: ingress, last_seen: now });
+                    stats.learned += 1;
+                },
+                None => {},
+            }
+        }
+
+        // decide
+        let known_port = match self.table.get(&destination) {
+            Some(entry) if now.saturating_duration_since(entry.last_seen) <= self.config.aging_time => Some(entry.port),
+            _ => None,
+        };
+        let forwarding = match (known_port, self.config.flood_policy) {
+            (Some(port), _) if port == ingress => Forwarding::Drop,
+            (Some(port), _) if ! destination.is_multicast() => Forwarding::Unicast(port),
+            (_, FloodPolicy::FloodAll) => Forwarding::Flood,
+            (_, FloodPolicy::FloodMulticastOnly) if destination.is_multicast() => Forwarding::Flood,
+            _ => Forwarding::Drop,
+        };
+        match forwarding {
+            Forwarding::Flood => stats.flooded += 1,
+            Forwarding::Drop => stats.dropped += 1,
+            Forwarding::Unicast(_) => {},
+        }
+        forwarding
+    }
+
+    fn age_at(&mut self, now: std::time::Instant) -> usize {
+        let before = self.table.len();
+        let aging_time = self.config.aging_time;
+        self.table.retain(|_, entry| now.saturating_duration_since(entry.last_seen) <= aging_time);
+        before - self.table.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FloodPolicy, Forwarding, LearningSwitch, SwitchConfig};
+
+    fn frame(destination: u8, source: u8) -> [u8; 60] {
+        let mut frame = [0_u8; 60];
+        frame[0..6].copy_from_slice(&[0x54, 0, 0, 0, 0, destination]);
+        frame[6..12].copy_from_slice(&[0x54, 0, 0, 0, 0, source]);
+        frame
+    }
+
+    #[test]
+    fn test_learning_and_aging() {
+        let t0 = std::time::Instant::now();
+        let mut switch = LearningSwitch::new(3, SwitchConfig {
+            aging_time: std::time::Duration::from_secs(10),
+            max_entries: 2,
+            flood_policy: FloodPolicy::FloodMulticastOnly,
+        });
+
+        // unknown unicast is dropped by policy, but the source is learned
+        assert_eq!(switch.process_at(0, &frame(0x20, 0x10), t0), Forwarding::Drop);
+        assert_eq!(switch.process_at(1, &frame(0x10, 0x20), t0), Forwarding::Unicast(0));
+        assert_eq!(switch.egress_ports(1, Forwarding::Unicast(0)).collect::<Vec<_>>(), [0]);
+
+        // broadcast is flooded to every other port
+        let mut broadcast = frame(0, 0x20);
+        broadcast[0..6].fill(0xFF);
+        assert_eq!(switch.process_at(1, &broadcast, t0), Forwarding::Flood);
+        assert_eq!(switch.egress_ports(1, Forwarding::Flood).collect::<Vec<_>>(), [0, 2]);
+
+        // table is full
+        switch.process_at(2, &frame(0x10, 0x30), t0);
+        assert_eq!(switch.lookup(&[0x54, 0, 0, 0, 0, 0x30].into()), None);
+
+        // aging
+        assert_eq!(switch.age_at(t0 + std::time::Duration::from_secs(11)), 2);
+        assert_eq!(switch.stats(0).learned, 1);
+        assert_eq!(switch.stats(1).flooded, 1);
+    }
+}
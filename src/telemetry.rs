@@ -0,0 +1,94 @@
+//! Metrics of sockets through the `metrics` crate facade, behind the `metrics` feature
+//!
+//! Whatever recorder the application installed gets them, Prometheus or otherwise. Every metric is labelled with the
+//! `interface` index and `queue` of its socket
+
+use metrics::{counter, gauge, Counter, Gauge};
+
+use crate::{RingKind, XDPSocket, XdpHooks};
+
+/// [`XdpHooks`] counting events into metrics, which also publishes the counters of a socket on demand
+///
+/// Handles are registered once per socket, so the hooks only bump them on the hot path. Install one per socket:
+///
+/// ```ignore
+/// let hooks = Arc::new(MetricsHooks::for_socket(&socket));
+/// socket.hooks = Some(hooks.clone());
+/// // periodically
+/// hooks.publish(&socket);
+/// ```
+pub struct MetricsHooks {
+    rx_drops: Counter,
+    alloc_failures: Counter,
+    ring_full: [Counter; 4],
+    wakeups: Counter,
+    rx_packets: Counter,
+    rx_bytes: Counter,
+    tx_packets: Counter,
+    tx_bytes: Counter,
+    tx_free: Gauge,
+    tx_queued: Gauge,
+    completions: Gauge,
+}
+impl MetricsHooks {
+    /// Register the metrics of queue `if_queue` of the interface with index `if_index`
+    pub fn new(if_index: u32, if_queue: u32) -> Self {
+        let interface = if_index.to_string();
+        let queue = if_queue.to_string();
+        let ring_full = |ring: &'static str| counter!("xdrippi_socket_ring_full_total", "interface" => interface.clone(), "queue" => queue.clone(), "ring" => ring);
+        Self {
+            rx_drops: counter!("xdrippi_socket_rx_drops_total", "interface" => interface.clone(), "queue" => queue.clone()),
+            alloc_failures: counter!("xdrippi_socket_alloc_failures_total", "interface" => interface.clone(), "queue" => queue.clone()),
+            ring_full: [ring_full("rx"), ring_full("tx"), ring_full("fill"), ring_full("completion")],
+            wakeups: counter!("xdrippi_socket_tx_wakeups_total", "interface" => interface.clone(), "queue" => queue.clone()),
+            rx_packets: counter!("xdrippi_socket_rx_packets_total", "interface" => interface.clone(), "queue" => queue.clone()),
+            rx_bytes: counter!("xdrippi_socket_rx_bytes_total", "interface" => interface.clone(), "queue" => queue.clone()),
+            tx_packets: counter!("xdrippi_socket_tx_packets_total", "interface" => interface.clone(), "queue" => queue.clone()),
+            tx_bytes: counter!("xdrippi_socket_tx_bytes_total", "interface" => interface.clone(), "queue" => queue.clone()),
+            tx_free: gauge!("xdrippi_socket_tx_free", "interface" => interface.clone(), "queue" => queue.clone()),
+            tx_queued: gauge!("xdrippi_socket_tx_queued", "interface" => interface.clone(), "queue" => queue.clone()),
+            completions: gauge!("xdrippi_socket_completions_pending", "interface" => interface, "queue" => queue),
+        }
+    }
+
+    /// Register the metrics of `socket`
+    pub fn for_socket(socket: &XDPSocket) -> Self {
+        Self::new(socket.if_index, socket.if_queue)
+    }
+
+    /// Publish the traffic counters and the TX backlog of `socket`, which the hooks do not see, e.g. once per second
+    pub fn publish(&self, socket: &XDPSocket) {
+        let traffic = &socket.traffic;
+        self.rx_packets.absolute(traffic.rx_packets);
+        self.rx_bytes.absolute(traffic.rx_bytes);
+        self.tx_packets.absolute(traffic.tx_packets);
+        self.tx_bytes.absolute(traffic.tx_bytes);
+        let backlog = socket.tx_backlog();
+        self.tx_free.set(backlog.tx_free as f64);
+        self.tx_queued.set(backlog.tx_queued as f64);
+        self.completions.set(backlog.completions as f64);
+    }
+}
+impl XdpHooks for MetricsHooks {
+    fn on_rx_drop(&self, _: &XDPSocket, _: usize) {
+        self.rx_drops.increment(1);
+    }
+
+    fn on_alloc_fail(&self, _: &XDPSocket) {
+        self.alloc_failures.increment(1);
+    }
+
+    fn on_ring_full(&self, _: &XDPSocket, ring: RingKind) {
+        let index = match ring {
+            RingKind::Rx => 0,
+            RingKind::Tx => 1,
+            RingKind::Fill => 2,
+            RingKind::Completion => 3,
+        };
+        self.ring_full[index].increment(1);
+    }
+
+    fn on_wakeup_syscall(&self, _: &XDPSocket) {
+        self.wakeups.increment(1);
+    }
+}
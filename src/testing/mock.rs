@@ -0,0 +1,271 @@
+use std::{os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd}, sync::Arc};
+
+use crate::{BindFlags, Umem, XDPRing, XDPSocket};
+
+/// Counters kept by a [`MockXDP`]
+#[derive(Debug, Clone, Default)]
+pub struct MockStats {
+    /// Frames placed on the RX ring
+    pub rx_delivered: u64,
+    /// Frames dropped because the fill ring was empty, the RX ring full or the frame too large
+    pub rx_dropped: u64,
+    /// Frames taken from the TX ring
+    pub tx_frames: u64,
+}
+
+/// The kernel side of an [`XDPSocket`] emulated in userspace, for testing without root, NICs or AF_XDP support
+///
+/// Rings live in a memfd mapped twice, once by the socket and once by the mock, exactly like the kernel shares them.
+/// The socket file descriptor is one end of a socket pair: waking for transmission always succeeds and polling
+/// never blocks, getting statistics or options fails. Rings only ask for wakeups when told to, see [`Self::set_tx_needs_wakeup`].
+pub struct MockXDP<'a> {
+    umem: Arc<Umem>,
+    headroom: usize,
+    _memory: OwnedFd,
+    _peer: OwnedFd,
+    rx_ring: XDPRing<'a, libc::xdp_desc>,
+    tx_ring: XDPRing<'a, libc::xdp_desc>,
+    completion_ring: XDPRing<'a, u64>,
+    fill_ring: XDPRing<'a, u64>,
+    stats: MockStats,
+}
+impl<'a> MockXDP<'a> {
+    /// Like the kernel, frames are delivered this far into their chunk
+    pub const DEFAULT_HEADROOM: usize = 256;
+
+    // ring layout within the memfd
+    const PRODUCER_OFFSET: u64 = 0;
+    const CONSUMER_OFFSET: u64 = 64;
+    const FLAGS_OFFSET: u64 = 96;
+    const DESCRIPTORS_OFFSET: u64 = 128;
+
+    /// Create a socket over `umem` with rings of `rings_size` elements, along with the mock driving it
+    pub fn new(umem: Arc<Umem>, rings_size: usize) -> Result<(XDPSocket<'a>, Self), crate::Error> {
+        assert!(rings_size.is_power_of_two(), "rings_size must be a power of two");
+
+        // lay out the rings on page boundaries
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let ring_size = |descriptor_size: usize| (Self::DESCRIPTORS_OFFSET as usize + descriptor_size * rings_size).next_multiple_of(page_size);
+        let desc_ring_size = ring_size(std::mem::size_of::<libc::xdp_desc>());
+        let umem_ring_size = ring_size(std::mem::size_of::<u64>());
+        let rx_offset = 0;
+        let tx_offset = rx_offset + desc_ring_size;
+        let completion_offset = tx_offset + desc_ring_size;
+        let fill_offset = completion_offset + umem_ring_size;
+        let memory_size = fill_offset + umem_ring_size;
+
+        // memory
+        let memory = unsafe { libc::memfd_create(c"xdrippi-mock".as_ptr(), libc::MFD_CLOEXEC) };
+        if memory < 0 {
+            return Err(crate::Error::MemoryAllocationFailure);
+        }
+        let memory = unsafe { OwnedFd::from_raw_fd(memory) };
+        if unsafe { libc::ftruncate(memory.as_raw_fd(), memory_size as _) } < 0 {
+            return Err(crate::Error::MemoryAllocationFailure);
+        }
+
+        // map every ring twice
+        let offsets = libc::xdp_ring_offset {
+            producer: Self::PRODUCER_OFFSET,
+            consumer: Self::CONSUMER_OFFSET,
+            desc: Self::DESCRIPTORS_OFFSET,
+            flags: Self::FLAGS_OFFSET,
+        };
+        let rings = || -> Result<_, crate::Error> {
+            Ok((
+                XDPRing::new(rings_size, memory.as_raw_fd(), &offsets, rx_offset as _)?,
+                XDPRing::new(rings_size, memory.as_raw_fd(), &offsets, tx_offset as _)?,
+                XDPRing::new(rings_size, memory.as_raw_fd(), &offsets, completion_offset as _)?,
+                XDPRing::new(rings_size, memory.as_raw_fd(), &offsets, fill_offset as _)?,
+            ))
+        };
+        let (rx_ring, tx_ring, completion_ring, fill_ring) = rings()?;
+        let (mock_rx_ring, mock_tx_ring, mock_completion_ring, mock_fill_ring) = rings()?;
+
+        // a socket pair stands in for the AF_XDP socket, a pending byte makes it always readable
+        let mut fds = [0; 2];
+        if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0, fds.as_mut_ptr()) } < 0 {
+            return Err(crate::Error::SocketCreationFailure);
+        }
+        let socket = unsafe { OwnedFd::from_raw_fd(fds[0]) };
+        let peer = unsafe { OwnedFd::from_raw_fd(fds[1]) };
+        if unsafe { libc::send(peer.as_raw_fd(), [0_u8].as_ptr().cast(), 1, libc::MSG_DONTWAIT) } < 0 {
+            return Err(crate::Error::SocketSendFailure { error: std::io::Error::last_os_error() });
+        }
+
+        let socket = XDPSocket {
+            if_index: 0,
+            if_queue: 0,
+            umem: umem.clone(),
+            fd: socket.into_raw_fd(),
+            bind_flags: BindFlags::default(),
+            rx_ring,
+            tx_ring,
+            completion_ring,
+            fill_ring,
+            fill_order: Default::default(),
+            tx_rate_limiter: None,
+            tx_watermarks: None,
+            tx_queue: None,
+            hooks: None,
+            traffic: Default::default(),
+        };
+        let mock = Self {
+            umem,
+            headroom: Self::DEFAULT_HEADROOM,
+            _memory: memory,
+            _peer: peer,
+            rx_ring: mock_rx_ring,
+            tx_ring: mock_tx_ring,
+            completion_ring: mock_completion_ring,
+            fill_ring: mock_fill_ring,
+            stats: MockStats::default(),
+        };
+        Ok((socket, mock))
+    }
+
+    /// Deliver frames `headroom` bytes into their chunk instead of [`Self::DEFAULT_HEADROOM`]
+    pub fn with_headroom(mut self, headroom: usize) -> Self {
+        assert!(headroom < self.umem.chunk_size(), "Headroom must be smaller than the chunk size");
+        self.headroom = headroom;
+        self
+    }
+
+    /// Receive `frame` from the wire: take a chunk from the fill ring, copy the frame into it and place it on the RX ring
+    ///
+    /// Returns `false`, dropping the frame, if that was not possible
+    pub fn inject(&mut self, frame: &[u8]) -> bool {
+        if ! self.fill_ring.can_consume() || ! self.rx_ring.can_produce() || self.headroom + frame.len() > self.umem.chunk_size() {
+            self.stats.rx_dropped += 1;
+            return false;
+        }
+
+        // take a chunk, the offset is masked to its start as the kernel does in aligned mode
+        let fill_offset = self.fill_ring.get_nth_umem_offset(self.fill_ring.get_consumer_index() as _);
+        self.fill_ring.advance_consumer_index();
+        let chunk_start = self.umem.chunk_start_offset_for_index(self.umem.chunk_index_for_offset(fill_offset));
+
+        // deliver
+        let rx_index = self.rx_ring.get_producer_index() as usize;
+        self.rx_ring.get_nth_descriptor_mut(rx_index).options = 0;
+        self.rx_ring.get_nth_slice_mut(rx_index, &self.umem, Some(chunk_start + self.headroom as u64), Some(frame.len())).copy_from_slice(frame);
+        self.rx_ring.advance_producer_index();
+        self.stats.rx_delivered += 1;
+        true
+    }
+
+    /// Transmit to the wire: hand every frame on the TX ring to `handler` and complete it, returning how many were transmitted
+    ///
+    /// Stops early when the completion ring is full
+    pub fn transmit(&mut self, mut handler: impl FnMut(&[u8])) -> usize {
+        let mut count = 0;
+        while self.tx_ring.can_consume() && self.completion_ring.can_produce() {
+            let tx_index = self.tx_ring.get_consumer_index() as usize;
+            handler(&self.tx_ring.get_nth_slice(tx_index, &self.umem));
+            self.completion_ring.produce_umem_offset(self.tx_ring.get_nth_descriptor(tx_index).addr);
+            self.tx_ring.advance_consumer_index();
+            count += 1;
+        }
+        self.stats.tx_frames += count as u64;
+        count
+    }
+
+    /// Complete the chunk at `offset` as if a socket sharing the umem transmitted it, returning `false` if the completion
+    /// ring is full
+    pub fn complete_shared(&mut self, offset: u64) -> bool {
+        if ! self.completion_ring.can_produce() {
+            return false;
+        }
+        self.completion_ring.produce_umem_offset(offset);
+        true
+    }
+
+    /// Collect every transmitted frame, see [`Self::transmit`]
+    pub fn transmitted_frames(&mut self) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        self.transmit(|frame| frames.push(frame.to_vec()));
+        frames
+    }
+
+    /// Ask the socket to wake up for transmission, or tell it that the driver is already running
+    pub fn set_tx_needs_wakeup(&mut self, needs_wakeup: bool) {
+        self.tx_ring.set_needs_wakeup(needs_wakeup);
+    }
+
+    /// The number of chunks waiting in the fill ring
+    pub fn fill_ring_len(&self) -> usize {
+        self.fill_ring.get_producer_index().wrapping_sub(self.fill_ring.get_consumer_index()) as usize & (self.fill_ring.num_elements() - 1)
+    }
+
+    pub fn stats(&self) -> &MockStats {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::MockXDP;
+    use crate::{DefaultAllocator, Umem, UmemAllocatorFactory};
+
+    #[test]
+    fn test_mock_round_trip() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let allocator = DefaultAllocator::for_umem(umem.clone());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+
+        // nothing to receive into yet
+        assert!(! mock.inject(b"dropped"));
+        assert_eq!(socket.refill_fill_ring(&allocator, 4), 4);
+        assert_eq!(mock.fill_ring_len(), 4);
+
+        // rx
+        assert!(mock.inject(b"hello"));
+        assert!(mock.inject(b"world"));
+        socket.poll_for_reception().unwrap();
+        let mut received = Vec::new();
+        assert_eq!(socket.recv(&allocator, |frame| received.push(frame.to_vec())), 2);
+        assert_eq!(received, [b"hello", b"world"]);
+        assert_eq!(mock.fill_ring_len(), 4);
+
+        // tx
+        assert!(socket.send(&allocator, b"outgoing").unwrap());
+        assert_eq!(mock.transmitted_frames(), [b"outgoing"]);
+        assert_eq!(socket.reap_completions(&allocator), 1);
+        assert_eq!((mock.stats().rx_delivered, mock.stats().rx_dropped, mock.stats().tx_frames), (2, 1, 1));
+        assert_eq!(socket.traffic, crate::TrafficCounters { rx_packets: 2, rx_bytes: 10, tx_packets: 1, tx_bytes: 8, tx_wakeups: 0, tx_wakeups_skipped: 1 });
+
+        // wakeups only when asked for
+        mock.set_tx_needs_wakeup(true);
+        assert!(socket.send(&allocator, b"kick").unwrap());
+        assert_eq!((socket.traffic.tx_wakeups, socket.traffic.tx_wakeups_skipped), (1, 1));
+        assert_eq!(mock.transmitted_frames(), [b"kick"]);
+        assert_eq!(socket.reap_completions(&allocator), 1);
+
+        // or always without need_wakeup
+        mock.set_tx_needs_wakeup(false);
+        socket.bind_flags.need_wakeup = false;
+        assert!(socket.send(&allocator, b"kick").unwrap());
+        assert_eq!((socket.traffic.tx_wakeups, socket.traffic.tx_wakeups_skipped), (2, 1));
+        mock.transmit(|_| {});
+        assert_eq!(socket.reap_completions(&allocator), 1);
+        socket.bind_flags.need_wakeup = true;
+
+        // completions are reaped in batches, across ring wraparounds
+        for _ in 0..10 {
+            for _ in 0..5 {
+                assert!(socket.send(&allocator, b"batch").unwrap());
+            }
+            mock.transmit(|_| {});
+            assert_eq!(socket.reap_completions(&allocator), 5);
+        }
+        assert_eq!(socket.reap_completions(&allocator), 0);
+
+        // a dead socket is told apart from one without data
+        drop(mock);
+        let error = socket.poll_for_reception().unwrap_err();
+        assert!(matches!(error, crate::Error::SocketOperationFailure { operation: crate::SocketOperation::Poll, .. }));
+        assert!(matches!(error.root_cause(), crate::Error::SocketClosed));
+    }
+}
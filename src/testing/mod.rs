@@ -0,0 +1,152 @@
+//! Disposable veth + network namespace topologies for tests and examples
+//!
+//! [`TestNetwork`] sets up the same topology as the `test-net` Makefile target: for every port a namespace holding
+//! an `eth0` interface, whose veth peer lives in the root namespace ready to have sockets bound to it.
+//! Everything is torn down on drop. Requires `CAP_NET_ADMIN` and the `ip` and `ethtool` utilities.
+//!
+//! [`MockXDP`] instead emulates the kernel side of a socket entirely in userspace, requiring no privileges at all.
+
+use std::{net::Ipv4Addr, os::fd::AsRawFd};
+
+use crate::packet::MacAddress;
+
+mod mock; pub use mock::{MockStats, MockXDP};
+
+/// One port of a [`TestNetwork`]
+#[derive(Debug, Clone)]
+pub struct TestPort {
+    /// The veth interface in the root namespace
+    pub host_interface: String,
+    pub host_address: Ipv4Addr,
+    pub host_mac: MacAddress,
+    /// The namespace holding the `eth0` peer
+    pub namespace: String,
+    pub namespace_address: Ipv4Addr,
+    pub namespace_mac: MacAddress,
+}
+impl TestPort {
+    /// The interface index of [`Self::host_interface`]
+    pub fn if_index(&self) -> libc::c_uint {
+        crate::utils::interface_name_to_index(&self.host_interface).expect("Test interface vanished")
+    }
+
+    /// Run `program` within the namespace of this port, e.g. `port.exec("ping", ["-c1", "10.42.0.1"])`
+    pub fn exec<I: AsRef<std::ffi::OsStr>>(&self, program: &str, args: impl IntoIterator<Item = I>) -> Result<std::process::Output, crate::Error> {
+        let mut command = std::process::Command::new("ip");
+        command.args(["netns", "exec", &self.namespace, program]).args(args);
+        run(&mut command)
+    }
+
+    /// Run `f` on a thread moved into the namespace of this port, sockets created there stay in the namespace
+    pub fn in_namespace<R: Send>(&self, f: impl FnOnce() -> R + Send) -> Result<R, crate::Error> {
+        let command = format!("setns {}", self.namespace);
+        let namespace = std::fs::File::open(format!("/run/netns/{}", self.namespace))
+            .map_err(|error| crate::Error::CommandFailure { command: command.clone(), reason: error.to_string() })?;
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                if unsafe { libc::setns(namespace.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+                    return Err(crate::Error::CommandFailure { command, reason: std::io::Error::last_os_error().to_string() });
+                }
+                Ok(f())
+            }).join().expect("Namespace thread panicked")
+        })
+    }
+}
+
+/// A set of veth pairs whose peers live in their own namespaces, deleted on drop
+pub struct TestNetwork {
+    ports: Vec<TestPort>,
+}
+impl TestNetwork {
+    /// The maximum number of ports, bound by the addressing scheme
+    pub const MAX_PORTS: usize = 15;
+
+    /// Create `num_ports` ports named `{prefix}1`, `{prefix}2`, ... for both interfaces and namespaces
+    ///
+    /// Port `i` gets `10.42.{subnet}.i` and `54:00:00:00:00:0i` on the host side, `10.42.{subnet}.i0` and `54:00:00:00:00:i0` in its namespace,
+    /// with static neighbours and routes so that the two sides can talk without ARP; networks living at the same time need distinct subnets
+    pub fn new(prefix: &str, subnet: u8, num_ports: usize) -> Result<Self, crate::Error> {
+        assert!((1..=Self::MAX_PORTS).contains(&num_ports), "Unsupported number of ports");
+        assert!(prefix.len() + 2 < libc::IF_NAMESIZE, "Prefix too long for an interface name");
+
+        // ports are registered as soon as they exist, so that failures clean up after themselves
+        let mut this = Self { ports: Vec::with_capacity(num_ports) };
+        for i in 1..=num_ports as u8 {
+            let port = TestPort {
+                host_interface: format!("{prefix}{i}"),
+                host_address: Ipv4Addr::new(10, 42, subnet, i),
+                host_mac: MacAddress([0x54, 0, 0, 0, 0, i]),
+                namespace: format!("{prefix}{i}"),
+                namespace_address: Ipv4Addr::new(10, 42, subnet, i * 10),
+                namespace_mac: MacAddress([0x54, 0, 0, 0, 0, i << 4]),
+            };
+            teardown(&port);
+            this.ports.push(port.clone());
+            setup(&port)?;
+        }
+        Ok(this)
+    }
+
+    pub fn ports(&self) -> &[TestPort] {
+        &self.ports
+    }
+
+    pub fn port(&self, index: usize) -> &TestPort {
+        &self.ports[index]
+    }
+}
+impl Drop for TestNetwork {
+    fn drop(&mut self) {
+        for port in &self.ports {
+            teardown(port);
+        }
+    }
+}
+
+fn setup(port: &TestPort) -> Result<(), crate::Error> {
+    let ns = port.namespace.as_str();
+    let host = port.host_interface.as_str();
+    let ns_address = port.namespace_address.to_string();
+    let host_address = port.host_address.to_string();
+
+    // namespace side
+    ip(&["netns", "add", ns])?;
+    ip(&["-n", ns, "link", "set", "dev", "lo", "up"])?;
+    ip(&["link", "add", host, "type", "veth", "peer", "name", "eth0", "netns", ns])?;
+    ip(&["-n", ns, "link", "set", "dev", "eth0", "address", &port.namespace_mac.to_string()])?;
+    ip(&["-n", ns, "addr", "add", &format!("{ns_address}/24"), "dev", "eth0"])?;
+    ip(&["-n", ns, "link", "set", "dev", "eth0", "up"])?;
+    ip(&["-n", ns, "neigh", "add", &host_address, "lladdr", &port.host_mac.to_string(), "nud", "permanent", "dev", "eth0"])?;
+
+    // host side
+    ip(&["link", "set", "dev", host, "address", &port.host_mac.to_string()])?;
+    ip(&["link", "set", "dev", host, "up"])?;
+    ip(&["addr", "add", &format!("{host_address}/32"), "dev", host, "noprefixroute"])?;
+    ip(&["route", "add", "to", &format!("{ns_address}/32"), "dev", host, "src", &host_address])?;
+
+    // checksums must be computed before frames reach the sockets
+    run(std::process::Command::new("ethtool").args(["-K", host, "tx", "off"]))?;
+    port.exec("ethtool", ["-K", "eth0", "tx", "off"])?;
+    Ok(())
+}
+
+fn teardown(port: &TestPort) {
+    // deleting the namespace deletes the veth pair, the host side is removed in case the namespace never got it
+    let _ = ip(&["netns", "del", &port.namespace]);
+    let _ = ip(&["link", "del", &port.host_interface]);
+}
+
+fn ip(args: &[&str]) -> Result<std::process::Output, crate::Error> {
+    run(std::process::Command::new("ip").args(args))
+}
+
+fn run(command: &mut std::process::Command) -> Result<std::process::Output, crate::Error> {
+    let description = format!("{command:?}");
+    let output = command.output()
+        .map_err(|error| crate::Error::CommandFailure { command: description.clone(), reason: error.to_string() })?;
+    if ! output.status.success() {
+        let reason = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(crate::Error::CommandFailure { command: description, reason });
+    }
+    Ok(output)
+}
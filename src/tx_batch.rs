@@ -0,0 +1,315 @@
+use crate::{pacing::LAUNCH_TIME_METADATA_LEN, Frame, RingKind, UmemAllocator, XDPSocket};
+
+/// `XDP_TXMD_FLAGS_LAUNCH_TIME`, see linux/if_xdp.h
+const XDP_TXMD_FLAGS_LAUNCH_TIME: u64 = 1 << 2;
+
+/// A burst of frames being enqueued on the TX ring of a socket, see [`XDPSocket::tx_batch`]
+///
+/// Descriptors are written past the producer index and published all at once when the batch is committed,
+/// followed by a single wakeup if the driver asked for one. Dropping the batch commits it too, ignoring errors
+pub struct TxBatch<'s, 'a> {
+    socket: &'s mut XDPSocket<'a>,
+    pending: u32,
+}
+impl<'s, 'a> TxBatch<'s, 'a> {
+    pub(crate) fn new(socket: &'s mut XDPSocket<'a>) -> Self {
+        Self { socket, pending: 0 }
+    }
+
+    /// The number of descriptors written so far
+    pub const fn len(&self) -> usize {
+        self.pending as usize
+    }
+
+    /// Whether nothing was written so far
+    pub const fn is_empty(&self) -> bool {
+        self.pending == 0
+    }
+
+    /// The number of descriptors that can still be written before the TX ring is full
+    pub fn remaining(&self) -> usize {
+        (self.socket.tx_ring.num_producible() - self.pending) as usize
+    }
+
+    /// The socket this batch is for
+    pub const fn socket(&self) -> &XDPSocket<'a> {
+        self.socket
+    }
+
+    /// Tell the hooks of the socket that the TX ring is full
+    pub(crate) fn report_ring_full(&self) {
+        if let Some(hooks) = &self.socket.hooks {
+            hooks.on_ring_full(self.socket, RingKind::Tx);
+        }
+    }
+
+    /// Write the next descriptor
+    fn write(&mut self, descriptor: libc::xdp_desc) {
+        let ring = &mut self.socket.tx_ring;
+        let index = (ring.get_producer_index() + self.pending) as usize & (ring.num_elements() - 1);
+        *ring.get_nth_descriptor_mut(index) = descriptor;
+        self.pending += 1;
+    }
+
+    /// Enqueue `frame`, already written into its chunk, returning `false` if the TX ring is full
+    ///
+    /// The chunk appears on the completion ring once transmitted
+    pub fn push(&mut self, frame: Frame) -> bool {
+        if self.remaining() == 0 {
+            self.report_ring_full();
+            return false;
+        }
+        self.write(libc::xdp_desc { addr: frame.addr, len: frame.len, options: 0 });
+        self.socket.traffic.record_tx(frame.len as usize);
+        true
+    }
+
+    /// Enqueue `frame` like [`Self::push`], asking the NIC to transmit it at `launch_time_ns`
+    ///
+    /// The request goes in the TX metadata in front of the frame, so the umem needs a TX metadata length of at least
+    /// [`LAUNCH_TIME_METADATA_LEN`], failing with [`crate::Error::InvalidConfiguration`] otherwise, and the frame as much
+    /// headroom in its chunk, failing with [`crate::Error::BufferTooSmall`] otherwise. The launch time is in nanoseconds
+    /// of the clock the NIC schedules against, usually `CLOCK_TAI` through a synchronized PHC. Kernels or NICs without
+    /// launch time support ignore the request, see [`crate::pacing::launch_time_supported`]
+    pub fn push_with_launch_time(&mut self, frame: Frame, launch_time_ns: u64) -> Result<bool, crate::Error> {
+        let umem = &self.socket.umem;
+        let metadata_len = umem.tx_metadata_len() as u64;
+        if metadata_len < LAUNCH_TIME_METADATA_LEN as u64 {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("the umem has {metadata_len} bytes of TX metadata, launch times need {LAUNCH_TIME_METADATA_LEN}") });
+        }
+        let index = umem.chunk_index_for_offset(frame.addr);
+        let headroom = frame.addr - umem.chunk_start_offset_for_index(index);
+        if headroom < metadata_len {
+            return Err(crate::Error::BufferTooSmall { required: metadata_len as usize, available: headroom as usize });
+        }
+        if self.remaining() == 0 {
+            self.report_ring_full();
+            return Ok(false);
+        }
+
+        // write struct xsk_tx_metadata
+        let start = (headroom - metadata_len) as usize;
+        let mut chunk = umem.chunk(index);
+        chunk[start..start + 8].copy_from_slice(&XDP_TXMD_FLAGS_LAUNCH_TIME.to_ne_bytes());
+        chunk[start + 8..start + 16].fill(0);
+        chunk[start + 16..start + 24].copy_from_slice(&launch_time_ns.to_ne_bytes());
+        drop(chunk);
+
+        self.write(libc::xdp_desc { addr: frame.addr, len: frame.len, options: libc::XDP_TX_METADATA });
+        self.socket.traffic.record_tx(frame.len as usize);
+        Ok(true)
+    }
+
+    /// Copy `frame` into a chunk obtained from `allocator` and enqueue it, see [`XDPSocket::send`]
+    pub fn send(&mut self, allocator: &(impl UmemAllocator + ?Sized), frame: &[u8]) -> Result<bool, crate::Error> {
+        // check size
+        if frame.len() > self.socket.umem.chunk_size() {
+            return Err(crate::Error::FrameTooLarge { length: frame.len(), chunk_size: self.socket.umem.chunk_size() });
+        }
+
+        self.write_and_enqueue(allocator, 0, Some(frame.len()), |chunk| {
+            chunk[..frame.len()].copy_from_slice(frame);
+            Ok(frame.len())
+        })
+    }
+
+    /// Write a frame directly into a chunk obtained from `allocator` and enqueue it, see [`XDPSocket::send_with`]
+    pub fn send_with(&mut self, allocator: &(impl UmemAllocator + ?Sized), headroom: usize, writer: impl FnOnce(&mut [u8]) -> Result<usize, crate::Error>) -> Result<bool, crate::Error> {
+        self.write_and_enqueue(allocator, headroom, None, writer)
+    }
+
+    /// Like [`Self::send_with`], pacing the frame as `max_len` bytes long until written, the rest of the chunk by default
+    fn write_and_enqueue(&mut self, allocator: &(impl UmemAllocator + ?Sized), headroom: usize, max_len: Option<usize>, writer: impl FnOnce(&mut [u8]) -> Result<usize, crate::Error>) -> Result<bool, crate::Error> {
+        // queued frames go first
+        self.flush_queue();
+        let umem = &self.socket.umem;
+        if headroom >= umem.chunk_size() {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("headroom of {headroom} bytes leaves no room in chunks of {} bytes", umem.chunk_size()) });
+        }
+
+        // check space
+        let ring_full = self.remaining() == 0;
+        if ring_full {
+            self.report_ring_full();
+            if self.socket.tx_queue.is_none() {
+                return Ok(false);
+            }
+        }
+
+        // check pacing before the writer runs
+        let max_len = max_len.unwrap_or(umem.chunk_size() - headroom);
+        if let Some(limiter) = self.socket.tx_rate_limiter.as_mut() && ! limiter.can_admit(max_len) {
+            return Ok(false);
+        }
+        let Some(chunk_index) = allocator.try_allocate() else {
+            if let Some(hooks) = &self.socket.hooks {
+                hooks.on_alloc_fail(self.socket);
+            }
+            return Ok(false);
+        };
+
+        // write frame
+        let mut chunk = umem.chunk(chunk_index);
+        let frame_len = match writer(&mut chunk[headroom..]) {
+            Ok(frame_len) if frame_len <= umem.chunk_size() - headroom => frame_len,
+            Ok(frame_len) => {
+                drop(chunk);
+                allocator.release(chunk_index);
+                return Err(crate::Error::FrameTooLarge { length: frame_len, chunk_size: umem.chunk_size() - headroom });
+            },
+            Err(error) => {
+                drop(chunk);
+                allocator.release(chunk_index);
+                return Err(error);
+            },
+        };
+        let addr = chunk.offset() + headroom as u64;
+        drop(chunk);
+
+        // account for it
+        if let Some(limiter) = self.socket.tx_rate_limiter.as_mut() && ! limiter.try_admit(frame_len) {
+            allocator.release(chunk_index);
+            return Ok(false);
+        }
+
+        // wait for room
+        if ring_full && let Some(queue) = self.socket.tx_queue.as_mut() {
+            let frame = Frame { addr, len: frame_len as _ };
+            let dropped = queue.enqueue(frame);
+            if let Some(dropped) = dropped {
+                allocator.release_offset(dropped.addr);
+            }
+            return Ok(dropped != Some(frame));
+        }
+
+        self.write(libc::xdp_desc { addr, len: frame_len as _, options: 0 });
+        self.socket.traffic.record_tx(frame_len);
+        Ok(true)
+    }
+
+    /// Move the frames of [`XDPSocket::tx_queue`] onto the TX ring while there is room, returning how many were moved
+    pub fn flush_queue(&mut self) -> usize {
+        let Some(mut queue) = self.socket.tx_queue.take() else {
+            return 0;
+        };
+        let mut count = 0;
+        while self.remaining() > 0 && let Some(frame) = queue.dequeue() {
+            self.write(libc::xdp_desc { addr: frame.addr, len: frame.len, options: 0 });
+            self.socket.traffic.record_tx(frame.len as usize);
+            count += 1;
+        }
+        self.socket.tx_queue = Some(queue);
+        count
+    }
+
+    /// Enqueue a single packet made of `segments`, see [`XDPSocket::send_multi_buffer`]
+    pub fn push_multi_buffer(&mut self, segments: &[Frame]) -> Result<bool, crate::Error> {
+        if segments.is_empty() {
+            return Err(crate::Error::InvalidConfiguration { reason: "a packet needs at least one segment".to_string() });
+        }
+
+        // check segments
+        if let Some(segment) = segments.iter().find(|segment| ! self.socket.umem.contains_frame(segment.addr, segment.len as _)) {
+            return Err(crate::Error::CorruptDescriptor { addr: segment.addr, len: segment.len });
+        }
+        let packet_len = segments.iter().map(|segment| segment.len as usize).sum();
+
+        // check space and pacing
+        if self.remaining() < segments.len() {
+            self.report_ring_full();
+            return Ok(false);
+        }
+        if let Some(limiter) = self.socket.tx_rate_limiter.as_mut() && ! limiter.try_admit(packet_len) {
+            return Ok(false);
+        }
+
+        // chain descriptors
+        for (n, segment) in segments.iter().enumerate() {
+            let options = if n + 1 < segments.len() { libc::XDP_PKT_CONTD } else { 0 };
+            self.write(libc::xdp_desc { addr: segment.addr, len: segment.len, options });
+        }
+        self.socket.traffic.record_tx(packet_len);
+        Ok(true)
+    }
+
+    /// Publish the written descriptors and wake the socket up if needed, returning how many were published
+    pub fn commit(mut self) -> Result<usize, crate::Error> {
+        self.publish()
+    }
+
+    fn publish(&mut self) -> Result<usize, crate::Error> {
+        let count = std::mem::take(&mut self.pending);
+        if count > 0 {
+            self.socket.tx_ring.advance_producer_index_by(count);
+            self.socket.update_tx_watermarks();
+            self.socket.wake_for_transmission_if_needed()?;
+        }
+        Ok(count as usize)
+    }
+}
+impl Drop for TxBatch<'_, '_> {
+    fn drop(&mut self) {
+        let _ = self.publish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{testing::MockXDP, DefaultAllocator, Umem, UmemAllocator, UmemAllocatorFactory};
+
+    #[test]
+    fn test_tx_batch_wakes_once() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let allocator = DefaultAllocator::for_umem(umem.clone());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+        mock.set_tx_needs_wakeup(true);
+
+        // nothing is visible until committed
+        let mut batch = socket.tx_batch();
+        assert_eq!(batch.remaining(), 15);
+        for frame in [b"one", b"two", b"six"] {
+            assert!(batch.send(&allocator, frame).unwrap());
+        }
+        assert_eq!((batch.len(), batch.remaining()), (3, 12));
+        assert!(mock.transmitted_frames().is_empty());
+        assert_eq!(batch.commit().unwrap(), 3);
+        assert_eq!(mock.transmitted_frames(), [b"one", b"two", b"six"]);
+        assert_eq!((socket.traffic.tx_packets, socket.traffic.tx_wakeups), (3, 1));
+
+        // empty batches do not wake, dropped ones are committed
+        socket.tx_batch().commit().unwrap();
+        assert_eq!(socket.traffic.tx_wakeups, 1);
+        assert_eq!(socket.reap_completions(&allocator), 3);
+        {
+            let mut batch = socket.tx_batch();
+            while batch.send(&allocator, b"fill").unwrap() {}
+            assert_eq!(batch.len(), 15);
+        }
+        assert_eq!(mock.transmit(|frame| assert_eq!(frame, b"fill")), 15);
+        assert_eq!(socket.traffic.tx_wakeups, 2);
+    }
+
+    #[test]
+    fn test_send_with_bounds() {
+        let umem = Arc::new(Umem::new_2k(8).unwrap());
+        let allocator = DefaultAllocator::for_umem(umem.clone());
+        let (mut socket, _mock) = MockXDP::new(umem.clone(), 4).unwrap();
+
+        // no room left, or written past the chunk, the chunk goes back
+        let available = allocator.num_available();
+        assert!(matches!(socket.send_with(&allocator, 2048, |_| Ok(0)), Err(crate::Error::InvalidConfiguration { .. })));
+        assert!(matches!(socket.send_with(&allocator, 48, |_| Ok(2048)), Err(crate::Error::FrameTooLarge { length: 2048, chunk_size: 2000 })));
+        assert_eq!(allocator.num_available(), available);
+
+        // the writer does not run for frames the rate limiter holds back
+        socket.tx_rate_limiter = Some(crate::TxRateLimiter::new().with_pps(1.0, 1.0));
+        assert!(socket.send_with(&allocator, 0, |_| Ok(64)).unwrap());
+        assert!(! socket.send_with(&allocator, 0, |_| panic!("written")).unwrap());
+        socket.tx_rate_limiter = None;
+
+        // packets need segments
+        assert!(matches!(socket.send_multi_buffer(&[]), Err(crate::Error::InvalidConfiguration { .. })));
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+
+use crate::Frame;
+
+/// Which frame goes when a [`TxQueue`] is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TxOverflowPolicy {
+    /// Refuse the new frame, like a full TX ring does
+    #[default]
+    DropNewest,
+    /// Drop the frame which waited the longest to make room, favouring fresh traffic
+    DropOldest,
+}
+
+/// Frames written into their chunks, waiting for room on the TX ring, see [`crate::XDPSocket::tx_queue`]
+///
+/// Installed on a socket, it absorbs the bursts [`crate::XDPSocket::send`] and [`crate::XDPSocket::send_with`] would
+/// otherwise refuse for a full TX ring. Queued frames move to the ring before any new frame, and whenever
+/// [`crate::XDPSocket::reap_completions`] made room
+#[derive(Debug, Clone)]
+pub struct TxQueue {
+    frames: VecDeque<Frame>,
+    depth: usize,
+    policy: TxOverflowPolicy,
+    dropped: u64,
+}
+impl TxQueue {
+    /// Hold up to `depth` frames, applying `policy` beyond
+    pub fn new(depth: usize, policy: TxOverflowPolicy) -> Self {
+        assert!(depth > 0, "Depth must allow at least one frame");
+        Self { frames: VecDeque::with_capacity(depth), depth, policy, dropped: 0 }
+    }
+
+    /// The number of queued frames
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Whether no frame is queued
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub const fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// How many frames were dropped by the overflow policy
+    pub const fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Queue `frame`, returning the frame dropped to respect the depth, whose chunk must be released
+    pub(crate) fn enqueue(&mut self, frame: Frame) -> Option<Frame> {
+        if self.frames.len() < self.depth {
+            self.frames.push_back(frame);
+            return None;
+        }
+        self.dropped += 1;
+        match self.policy {
+            TxOverflowPolicy::DropNewest => Some(frame),
+            TxOverflowPolicy::DropOldest => {
+                let oldest = self.frames.pop_front();
+                self.frames.push_back(frame);
+                oldest
+            },
+        }
+    }
+
+    /// Take the frame which waited the longest
+    pub(crate) fn dequeue(&mut self) -> Option<Frame> {
+        self.frames.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{TxOverflowPolicy, TxQueue};
+    use crate::{testing::MockXDP, DefaultAllocator, Frame, Umem, UmemAllocator, UmemAllocatorFactory};
+
+    #[test]
+    fn test_overflow_policies() {
+        let frame = |addr| Frame { addr, len: 64 };
+        let mut newest = TxQueue::new(2, TxOverflowPolicy::DropNewest);
+        let mut oldest = TxQueue::new(2, TxOverflowPolicy::DropOldest);
+        for queue in [&mut newest, &mut oldest] {
+            assert_eq!(queue.enqueue(frame(0)), None);
+            assert_eq!(queue.enqueue(frame(1)), None);
+        }
+        assert_eq!(newest.enqueue(frame(2)), Some(frame(2)));
+        assert_eq!(oldest.enqueue(frame(2)), Some(frame(0)));
+        assert_eq!((newest.dropped(), oldest.dropped()), (1, 1));
+        assert_eq!(oldest.dequeue(), Some(frame(1)));
+    }
+
+    #[test]
+    fn test_socket_tx_queue() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let allocator = DefaultAllocator::for_umem(umem.clone());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 4).unwrap();
+        socket.tx_queue = Some(TxQueue::new(2, TxOverflowPolicy::DropNewest));
+
+        // the ring takes 3 frames, the queue 2 more
+        for frame in [b"one", b"two", b"six", b"ten", b"hex"] {
+            assert!(socket.send(&allocator, frame).unwrap());
+        }
+        assert!(! socket.send(&allocator, b"max").unwrap());
+        assert_eq!(socket.tx_queue.as_ref().unwrap().len(), 2);
+        assert_eq!(mock.transmitted_frames(), [b"one", b"two", b"six"]);
+
+        // completions make room for queued frames, which keep their order
+        assert_eq!(socket.reap_completions(&allocator), 3);
+        assert!(socket.send(&allocator, b"new").unwrap());
+        assert_eq!(mock.transmitted_frames(), [b"ten", b"hex", b"new"]);
+        assert!(socket.tx_queue.as_ref().unwrap().is_empty());
+
+        // queued chunks can be given back
+        socket.reap_completions(&allocator);
+        for _ in 0..4 {
+            socket.send(&allocator, b"drop").unwrap();
+        }
+        assert_eq!(socket.clear_tx_queue(&allocator), 1);
+        socket.reap_completions(&allocator);
+        mock.transmit(|_| {});
+        socket.reap_completions(&allocator);
+        let mut allocated = 0;
+        while allocator.try_allocate().is_some() {
+            allocated += 1;
+        }
+        assert_eq!(allocated, 64);
+    }
+}
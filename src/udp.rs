@@ -0,0 +1,282 @@
+use std::{collections::{HashMap, VecDeque}, net::{IpAddr, SocketAddr}, os::fd::AsRawFd};
+
+use crate::{
+    config::SocketConfig,
+    packet::{self, ip_protocol, FrameBuilder, MacAddress, NetworkHeader, TransportHeader},
+    BPFRedirectManager, Frame, ProgramSource, RedirectFilter, XdpDevice,
+};
+
+/// How many frames are taken from the RX ring at once
+const RX_BATCH: usize = 32;
+
+/// How many peers an [`XdpUdpSocket`] learns the MAC address of, so that spoofed sources cannot grow it without bound
+const MAX_LEARNED_NEIGHBORS: usize = 1024;
+
+/// A UDP endpoint over AF_XDP, for applications which just want datagrams in and out, fast
+///
+/// The redirect program steals the frames of the bound port from the kernel stack, whose headers are stripped on
+/// reception, and transmitted datagrams get their headers built in place. There is no ARP nor NDP: the MAC address of a
+/// peer is learned from the datagrams it sends, up to [`MAX_LEARNED_NEIGHBORS`] peers, and other destinations go through
+/// [`Self::with_gateway`] or [`Self::add_neighbor`], which learning never overrides. Fragments, IP options and checksums
+/// of received datagrams are not looked at
+pub struct XdpUdpSocket<'a> {
+    device: XdpDevice<'a>,
+    local_address: SocketAddr,
+    local_mac: MacAddress,
+    neighbors: HashMap<IpAddr, MacAddress>,
+    learned_neighbors: HashMap<IpAddr, MacAddress>,
+    gateway: Option<MacAddress>,
+    /// Received frames yet to be looked at
+    pending: VecDeque<Frame>,
+    /// Frames dropped for not being datagrams for this socket
+    dropped: u64,
+    _bpf_manager: Option<BPFRedirectManager>,
+}
+impl<'a> XdpUdpSocket<'a> {
+    /// Bind to `address` on queue 0 of the interface named `if_name`
+    ///
+    /// Datagrams arriving on other queues are not seen: use a single queue, or steer the port to queue 0
+    /// (`ethtool -N <if> flow-type udp4 dst-port <port> action 0`)
+    pub fn bind(if_name: &str, address: SocketAddr) -> Result<Self, crate::Error> {
+        Self::bind_with(&SocketConfig::new(if_name, 0), address)
+    }
+
+    /// Bind to `address` with the socket described by `config`
+    ///
+    /// `address` must name the IP of the interface, which is used as the source of transmitted datagrams. Frames to the
+    /// port, sent from or to it, are redirected to the socket regardless of [`SocketConfig::redirect`]
+    pub fn bind_with(config: &SocketConfig, address: SocketAddr) -> Result<Self, crate::Error> {
+        if address.ip().is_unspecified() || address.port() == 0 {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("cannot bind to {address}, an address and port are needed") });
+        }
+        let if_index = config.interface_index()?;
+        let local_mac = std::fs::read_to_string(format!("/sys/class/net/{}/address", config.interface))
+            .map_err(|error| crate::Error::InvalidConfiguration { reason: format!("cannot read the address of {} ({error})", config.interface) })?
+            .parse()?;
+
+        // open the socket, then steal the port
+        let device = XdpDevice::open(&SocketConfig { redirect: false, ..config.clone() })?;
+        let mut bpf_manager = BPFRedirectManager::attach_with(if_index, &[ProgramSource::Embedded, ProgramSource::BundledSource])?;
+        bpf_manager.set_filter(RedirectFilter { ip_protocol: Some(ip_protocol::UDP), port: Some(address.port()), ..RedirectFilter::default() })?;
+        let mut socket = Self::new(device, address, local_mac);
+        bpf_manager.add_redirect(config.queue_id, socket.device.socket().as_raw_fd())?;
+        socket._bpf_manager = Some(bpf_manager);
+        Ok(socket)
+    }
+
+    /// Exchange datagrams of `local_address` through `device`, which the frames for it must already be redirected to
+    pub fn new(device: XdpDevice<'a>, local_address: SocketAddr, local_mac: MacAddress) -> Self {
+        Self {
+            device,
+            local_address,
+            local_mac,
+            neighbors: HashMap::new(),
+            learned_neighbors: HashMap::new(),
+            gateway: None,
+            pending: VecDeque::with_capacity(RX_BATCH),
+            dropped: 0,
+            _bpf_manager: None,
+        }
+    }
+
+    /// Send datagrams to peers without a known MAC address to `gateway`, e.g. the default router
+    pub fn with_gateway(mut self, gateway: MacAddress) -> Self {
+        self.gateway = Some(gateway);
+        self
+    }
+
+    /// Send the datagrams to `address` to `mac`
+    pub fn add_neighbor(&mut self, address: IpAddr, mac: MacAddress) {
+        self.neighbors.insert(address, mac);
+    }
+
+    pub const fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+
+    /// How many received frames were dropped for not being datagrams to [`Self::local_address`]
+    pub const fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The underlying device, e.g. to poll its socket or gather its status
+    pub fn device(&mut self) -> &mut XdpDevice<'a> {
+        &mut self.device
+    }
+
+    /// Wait for a datagram following the wait strategy of the device, then receive it like [`Self::try_recv_from`]
+    ///
+    /// Returns `None` if the wait timed out
+    pub fn recv_from(&mut self, buffer: &mut [u8]) -> Result<Option<(usize, SocketAddr)>, crate::Error> {
+        loop {
+            if let Some(received) = self.try_recv_from(buffer) {
+                return Ok(Some(received));
+            }
+            let mut frames = [Frame::default(); RX_BATCH];
+            let count = self.device.rx_burst_wait(&mut frames)?;
+            if count == 0 {
+                return Ok(None);
+            }
+            self.pending.extend(&frames[..count]);
+        }
+    }
+
+    /// Copy the payload of the next datagram into `buffer`, returning its length and sender, without waiting
+    ///
+    /// Like UDP sockets do, the payload is truncated if `buffer` is too small
+    pub fn try_recv_from(&mut self, buffer: &mut [u8]) -> Option<(usize, SocketAddr)> {
+        loop {
+            if self.pending.is_empty() {
+                let mut frames = [Frame::default(); RX_BATCH];
+                let count = self.device.rx_burst(&mut frames);
+                if count == 0 {
+                    return None;
+                }
+                self.pending.extend(&frames[..count]);
+            }
+            let frame = self.pending.pop_front()?;
+            let received = self.accept(frame, buffer);
+            self.device.free(frame);
+            match received {
+                Some(received) => return Some(received),
+                None => self.dropped += 1,
+            }
+        }
+    }
+
+    /// Copy the payload of `frame` if it is a datagram for this socket, learning the MAC address of its sender
+    fn accept(&mut self, frame: Frame, buffer: &mut [u8]) -> Option<(usize, SocketAddr)> {
+        let data = self.device.data(&frame);
+        let parsed = packet::parse(&data)?;
+        let (source, destination) = match parsed.network? {
+            NetworkHeader::Ipv4(ipv4) => (IpAddr::V4(ipv4.source()), IpAddr::V4(ipv4.destination())),
+            NetworkHeader::Ipv6(ipv6) => (IpAddr::V6(ipv6.source()), IpAddr::V6(ipv6.destination())),
+        };
+        let Some(TransportHeader::Udp(udp)) = parsed.transport else {
+            return None;
+        };
+        if destination != self.local_address.ip() || udp.destination_port() != self.local_address.port() {
+            return None;
+        }
+
+        let payload = udp.payload();
+        let len = payload.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&payload[..len]);
+        let (mac, source_port) = (parsed.ethernet.source(), udp.source_port());
+        drop(data);
+        self.learn(source, mac);
+        Some((len, SocketAddr::new(source, source_port)))
+    }
+
+    /// Remember that `address` is at `mac`, unless it was added with [`Self::add_neighbor`] or too many peers are known
+    fn learn(&mut self, address: IpAddr, mac: MacAddress) {
+        if self.neighbors.contains_key(&address) {
+            return;
+        }
+        if let Some(known) = self.learned_neighbors.get_mut(&address) {
+            *known = mac;
+        } else if self.learned_neighbors.len() < MAX_LEARNED_NEIGHBORS {
+            self.learned_neighbors.insert(address, mac);
+        }
+    }
+
+    /// Enqueue `payload` for transmission to `destination`, returning whether there was room on the TX ring
+    ///
+    /// Fails with [`crate::Error::UnknownNeighbor`] if the MAC address of `destination` is unknown and there is no gateway
+    pub fn send_to(&mut self, payload: &[u8], destination: SocketAddr) -> Result<bool, crate::Error> {
+        let mac = self.neighbors.get(&destination.ip()).or_else(|| self.learned_neighbors.get(&destination.ip())).copied().or(self.gateway)
+            .ok_or(crate::Error::UnknownNeighbor { address: destination.ip() })?;
+        let builder = FrameBuilder::ethernet(self.local_mac, mac);
+        let builder = match (self.local_address.ip(), destination.ip()) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => builder.ipv4(source, destination),
+            (IpAddr::V6(source), IpAddr::V6(destination)) => builder.ipv6(source, destination),
+            _ => return Err(crate::Error::InvalidConfiguration { reason: format!("cannot send from {} to {destination}", self.local_address) }),
+        }.udp(self.local_address.port(), destination.port());
+
+        // build in place
+        let Some(mut frame) = self.device.alloc() else {
+            return Ok(false);
+        };
+        let chunk_size = self.device.socket().umem.chunk_size();
+        let written = builder.write_with_payload(&mut self.device.data_mut(&mut frame, chunk_size), payload);
+        let len = match written {
+            Ok(len) => len,
+            Err(error) => {
+                self.device.free(frame);
+                return Err(error);
+            },
+        };
+        frame.len = len as u32;
+
+        // enqueue
+        if self.device.tx_burst(&[frame])? == 0 {
+            self.device.free(frame);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+impl Drop for XdpUdpSocket<'_> {
+    fn drop(&mut self) {
+        for frame in self.pending.drain(..) {
+            self.device.free(frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use super::XdpUdpSocket;
+    use crate::{packet::{self, FrameBuilder, MacAddress, TransportHeader}, testing::MockXDP, DefaultAllocator, Umem, UmemAllocatorFactory, XdpDevice};
+
+    #[test]
+    fn test_udp_socket_echo() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let (socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+        let device = XdpDevice::new(socket, Arc::new(DefaultAllocator::for_umem(umem)));
+        let local_mac: MacAddress = "02:00:00:00:00:01".parse().unwrap();
+        let peer_mac: MacAddress = "02:00:00:00:00:02".parse().unwrap();
+        let local: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let peer: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        let mut socket = XdpUdpSocket::new(device, local, local_mac);
+        assert!("02:00:00:00:00".parse::<MacAddress>().is_err());
+
+        // unknown peers are unreachable without a gateway
+        assert!(matches!(socket.send_to(b"hello", peer), Err(crate::Error::UnknownNeighbor { .. })));
+
+        // frames to other ports are dropped, the peer is learned
+        let mut frame = [0_u8; 128];
+        let to_peer = |port| FrameBuilder::ethernet(peer_mac, local_mac).ipv4("10.0.0.2".parse().unwrap(), "10.0.0.1".parse().unwrap()).udp(5000, port);
+        let len = to_peer(4001).write_with_payload(&mut frame, b"other").unwrap();
+        assert!(mock.inject(&frame[..len]));
+        let len = to_peer(4000).write_with_payload(&mut frame, b"ping").unwrap();
+        assert!(mock.inject(&frame[..len]));
+        let mut buffer = [0_u8; 16];
+        assert_eq!(socket.recv_from(&mut buffer).unwrap(), Some((4, peer)));
+        assert_eq!(&buffer[..4], b"ping");
+        assert_eq!(socket.dropped(), 1);
+        assert_eq!(socket.try_recv_from(&mut buffer), None);
+
+        // replies go back where the datagram came from
+        assert!(socket.send_to(b"pong", peer).unwrap());
+        let transmitted = mock.transmitted_frames();
+        let parsed = packet::parse(&transmitted[0]).unwrap();
+        assert_eq!(parsed.ethernet.destination(), peer_mac);
+        let Some(TransportHeader::Udp(udp)) = parsed.transport else { panic!("not udp") };
+        assert_eq!((udp.source_port(), udp.destination_port(), udp.payload()), (4000, 5000, &b"pong"[..]));
+
+        // configured neighbors win over learned ones
+        let other_mac: MacAddress = "02:00:00:00:00:03".parse().unwrap();
+        socket.add_neighbor(peer.ip(), other_mac);
+        assert!(mock.inject(&frame[..len]));
+        assert_eq!(socket.recv_from(&mut buffer).unwrap(), Some((4, peer)));
+        assert!(socket.send_to(b"pong", peer).unwrap());
+        assert_eq!(packet::parse(&mock.transmitted_frames()[0]).unwrap().ethernet.destination(), other_mac);
+
+        // address families cannot be mixed
+        let mut socket = socket.with_gateway(peer_mac);
+        assert!(socket.send_to(b"pong", "[2001:db8::2]:5000".parse().unwrap()).is_err());
+    }
+}
@@ -1,35 +1,98 @@
+use std::{cell::UnsafeCell, marker::PhantomData, ptr::NonNull, os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd}, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+
 /// The Umem is a memory area accessible to both the kernel and userspace to perform their AF_XDP tasks, i.e. where xdp_desc descriptors can point to
+///
+/// Its bytes change behind shared references, written by the kernel or through guards, so they are modeled as
+/// [`UnsafeCell`]s. Safe code reaches them only through [`ChunkGuard`]s and [`FrameGuard`]s, which borrow whole chunks
+/// exclusively; raw access goes through [`Self::frame_ptr`] and leaves aliasing to the caller
 pub struct Umem {
     // metadata
     chunk_size: usize,
     num_chunks: usize,
 
     // memory allocation
-    allocation: std::ptr::NonNull<libc::c_void>,
+    memory: NonNull<[UnsafeCell<u8>]>,
+    memfd: Option<OwnedFd>,
+    borrowed_chunks: Box<[AtomicU64]>,
+
+    // registration
+    registration_flags: u32,
+    tx_metadata_len: u32,
 }
 impl Umem {
     // constants
-    const CHUNK_SIZE_2K: usize = 2048;
-    const CHUNK_SIZE_4K: usize = 4096;
+    pub(crate) const CHUNK_SIZE_2K: usize = 2048;
+    pub(crate) const CHUNK_SIZE_4K: usize = 4096;
 
     // constructors
 
     /// Create a new umem containing `num_chunks` chunks of size 2048 bytes
     pub fn new_2k(num_chunks: usize) -> Result<Self, crate::Error> {
-        Self::new(Self::CHUNK_SIZE_2K, num_chunks)
+        Self::new(Self::CHUNK_SIZE_2K, num_chunks, None)
     }
 
     /// Create a new umem containing `num_chunks` chunks of size 4096 bytes
     pub fn new_4k(num_chunks: usize) -> Result<Self, crate::Error> {
-        Self::new(Self::CHUNK_SIZE_4K, num_chunks)
+        Self::new(Self::CHUNK_SIZE_4K, num_chunks, None)
     }
 
-    fn new(chunk_size: usize, num_chunks: usize) -> Result<Self, crate::Error> {
-        // check chunk size
+    /// Like [`Self::new_2k`], backed by a memfd which other processes can map, see [`crate::handoff`]
+    pub fn new_2k_shareable(num_chunks: usize) -> Result<Self, crate::Error> {
+        Self::new(Self::CHUNK_SIZE_2K, num_chunks, Some(Self::create_memfd(Self::CHUNK_SIZE_2K * num_chunks)?))
+    }
+
+    /// Like [`Self::new_4k`], backed by a memfd which other processes can map, see [`crate::handoff`]
+    pub fn new_4k_shareable(num_chunks: usize) -> Result<Self, crate::Error> {
+        Self::new(Self::CHUNK_SIZE_4K, num_chunks, Some(Self::create_memfd(Self::CHUNK_SIZE_4K * num_chunks)?))
+    }
+
+    /// Map the umem backed by `memfd`, as obtained from [`Self::memfd`] in another process, keeping its contents
+    ///
+    /// The chunk size usually comes from that process too, unsupported ones are refused
+    pub fn from_memfd(memfd: OwnedFd, chunk_size: usize) -> Result<Self, crate::Error> {
+        Self::check_chunk_size(chunk_size)?;
+        let mut stat = std::mem::MaybeUninit::<libc::stat>::zeroed();
+        if unsafe { libc::fstat(memfd.as_raw_fd(), stat.as_mut_ptr()) } < 0 {
+            return Err(crate::Error::MemoryMapFailure);
+        }
+        let memory_size = unsafe { stat.assume_init() }.st_size as usize;
+        if ! memory_size.is_multiple_of(chunk_size) {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("memfd size {memory_size} is not a multiple of the chunk size {chunk_size}") });
+        }
+        Self::map(chunk_size, memory_size / chunk_size, Some(memfd))
+    }
+
+    fn create_memfd(memory_size: usize) -> Result<OwnedFd, crate::Error> {
+        let memfd = unsafe { libc::memfd_create(c"xdrippi-umem".as_ptr(), libc::MFD_CLOEXEC) };
+        if memfd < 0 {
+            return Err(crate::Error::MemoryAllocationFailure);
+        }
+        let memfd = unsafe { OwnedFd::from_raw_fd(memfd) };
+        if unsafe { libc::ftruncate(memfd.as_raw_fd(), memory_size as _) } < 0 {
+            return Err(crate::Error::MemoryAllocationFailure);
+        }
+        Ok(memfd)
+    }
+
+    fn new(chunk_size: usize, num_chunks: usize, memfd: Option<OwnedFd>) -> Result<Self, crate::Error> {
+        let umem = Self::map(chunk_size, num_chunks, memfd)?;
+
+        // zero out memory
+        unsafe { std::ptr::write_bytes(umem.memory_ptr(), 0, umem.memory_size()) };
+
+        Ok(umem)
+    }
+
+    fn check_chunk_size(chunk_size: usize) -> Result<(), crate::Error> {
         match chunk_size {
-            Self::CHUNK_SIZE_2K | Self::CHUNK_SIZE_4K => {},
-            other => panic!("Chunk size {other} is not supported"),
-        };
+            Self::CHUNK_SIZE_2K | Self::CHUNK_SIZE_4K => Ok(()),
+            other => Err(crate::Error::InvalidConfiguration { reason: format!("chunk size {other} is not supported") }),
+        }
+    }
+
+    fn map(chunk_size: usize, num_chunks: usize, memfd: Option<OwnedFd>) -> Result<Self, crate::Error> {
+        // check chunk size
+        Self::check_chunk_size(chunk_size)?;
 
         // page size
         let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
@@ -40,8 +103,11 @@ impl Umem {
                 std::ptr::null_mut(),
                 chunk_size * num_chunks,
                 libc::PROT_READ | libc::PROT_WRITE,
-                libc::MAP_SHARED | libc::MAP_ANONYMOUS,
-                0,
+                match memfd {
+                    Some(_) => libc::MAP_SHARED,
+                    None => libc::MAP_SHARED | libc::MAP_ANONYMOUS,
+                },
+                memfd.as_ref().map_or(0, |memfd| memfd.as_raw_fd()),
                 0
             )
         };
@@ -52,19 +118,76 @@ impl Umem {
         // check aligned
         assert_eq!(allocation as usize & (page_size - 1), 0);
 
-        // zero out memory
-        unsafe { libc::memset(allocation, 0, chunk_size * num_chunks); }
-
         // create object
         Ok(Self {
             // metadata
             chunk_size,
             num_chunks,
             // memory allocation
-            allocation: unsafe { std::ptr::NonNull::new_unchecked(allocation) },
+            memory: NonNull::slice_from_raw_parts(unsafe { NonNull::new_unchecked(allocation.cast()) }, chunk_size * num_chunks),
+            memfd,
+            borrowed_chunks: (0..num_chunks.div_ceil(64)).map(|_| AtomicU64::new(0)).collect(),
+            // registration
+            registration_flags: 0,
+            tx_metadata_len: 0,
         })
     }
 
+    // registration
+
+    /// Reserve `tx_metadata_len` bytes in front of every TX frame for metadata requests, a multiple of 8 (kernel 6.8+)
+    pub fn with_tx_metadata_len(mut self, tx_metadata_len: u32) -> Self {
+        assert!(tx_metadata_len.is_multiple_of(8), "TX metadata length must be a multiple of 8");
+        self.tx_metadata_len = tx_metadata_len;
+        self
+    }
+
+    /// Have the kernel compute checksums requested through TX metadata in software, for copy mode and drivers lacking
+    /// hardware checksum offload, instead of silently sending the frame unchanged (kernel 6.11+)
+    ///
+    /// Only useful along with [`Self::with_tx_metadata_len`]
+    pub fn with_tx_sw_csum(mut self) -> Self {
+        self.registration_flags |= libc::XDP_UMEM_TX_SW_CSUM;
+        self
+    }
+
+    /// How many bytes in front of every TX frame are reserved for metadata
+    pub const fn tx_metadata_len(&self) -> u32 {
+        self.tx_metadata_len
+    }
+
+    /// The `XDP_UMEM_*` flags this umem is registered with
+    pub const fn registration_flags(&self) -> u32 {
+        self.registration_flags
+    }
+
+    /// Register this umem with the AF_XDP socket `fd`
+    ///
+    /// The extended registration is used, falling back to the original one on kernels which predate it unless
+    /// flags or TX metadata were requested
+    pub(crate) fn register(&self, fd: RawFd) -> Result<(), crate::Error> {
+        let registration = libc::xdp_umem_reg {
+            addr: self.memory_ptr() as usize as _,
+            len: self.memory_size() as _,
+            chunk_size: self.chunk_size as _,
+            headroom: 0,
+            flags: self.registration_flags,
+            tx_metadata_len: self.tx_metadata_len,
+        };
+        match crate::utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_UMEM_REG, &registration) {
+            Err(crate::Error::SocketSetOptionFailure { error, .. })
+                if error.raw_os_error() == Some(libc::EINVAL) && self.registration_flags == 0 && self.tx_metadata_len == 0 => {
+                crate::utils::setsockopt(fd, libc::SOL_XDP, libc::XDP_UMEM_REG, &libc::xdp_umem_reg_v1 {
+                    addr: registration.addr,
+                    len: registration.len,
+                    chunk_size: registration.chunk_size,
+                    headroom: registration.headroom,
+                })
+            },
+            result => result,
+        }
+    }
+
     // metadata
 
     /// How big in bytes an individual chunk is
@@ -72,6 +195,36 @@ impl Umem {
         self.chunk_size
     }
 
+    /// Fault in every page of the umem, and with `warm_chunks` pull the first cache line of every chunk into the cache
+    ///
+    /// Zeroing at creation makes the memory resident, but mappings from [`Self::from_memfd`] or pages reclaimed since then
+    /// would otherwise be faulted in by the first frames, along with cache misses on every chunk header. Page tables are
+    /// populated with `MADV_POPULATE_WRITE` where available (Linux 5.14), pages are read one by one otherwise. Contents
+    /// are left untouched, so it is safe while traffic flows
+    pub fn prefault(&self, warm_chunks: bool) -> Result<(), crate::Error> {
+        let base = self.memory_ptr();
+
+        // populate page tables
+        if unsafe { libc::madvise(base.cast(), self.memory_size(), libc::MADV_POPULATE_WRITE) } < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::EINVAL) {
+                return Err(crate::Error::MemoryAllocationFailure);
+            }
+            let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+            for offset in (0..self.memory_size()).step_by(page_size) {
+                unsafe { std::ptr::read_volatile(base.add(offset)) };
+            }
+        }
+
+        // warm caches
+        if warm_chunks {
+            for offset in (0..self.memory_size()).step_by(self.chunk_size) {
+                unsafe { std::ptr::read_volatile(base.add(offset)) };
+            }
+        }
+        Ok(())
+    }
+
     /// How big is the umem allocated memory area
     pub const fn memory_size(&self) -> usize {
         self.chunk_size * self.num_chunks
@@ -92,20 +245,323 @@ impl Umem {
         offset as usize / self.chunk_size
     }
 
+    /// Whether `len` bytes starting at `offset` lie within the umem and within a single chunk
+    pub const fn contains_frame(&self, offset: u64, len: usize) -> bool {
+        offset < self.memory_size() as u64
+            && (offset as usize % self.chunk_size) + len <= self.chunk_size
+    }
+
     // memory
 
-    /// Obtain a pointer to the umem allocation
+    /// The memfd backing this umem, if created shareable
+    pub fn memfd(&self) -> Option<BorrowedFd<'_>> {
+        self.memfd.as_ref().map(|memfd| memfd.as_fd())
+    }
+
+    /// Borrow the bytes of the chunk at `index`, e.g. to stage a TX payload
+    /// 
+    /// Panics if the index is out of range or the chunk is already borrowed, see [`Self::try_chunk`]
+    pub fn chunk(&self, index: usize) -> ChunkGuard<'_> {
+        self.try_chunk(index).expect("Chunk is out of range or already borrowed")
+    }
+
+    /// Borrow the bytes of the chunk at `index`, unless out of range or already borrowed
     /// 
-    /// # Safety
-    /// This function is __unsafe__ because it's always possible to cast a *const pointer into a *mut pointer
-    pub const unsafe fn memory_ptr(&self) -> *const u8 {
-        self.allocation.as_ptr().cast()
+    /// Only one guard per chunk exists at a time, [`FrameGuard`]s included. The kernel is not aware of guards: only
+    /// borrow chunks obtained from an allocator and not handed to a ring
+    pub fn try_chunk(&self, index: usize) -> Option<ChunkGuard<'_>> {
+        if index >= self.num_chunks {
+            return None;
+        }
+        let bit = 1 << (index % 64);
+        if self.borrowed_chunks[index / 64].fetch_or(bit, Ordering::Acquire) & bit != 0 {
+            return None;
+        }
+        Some(ChunkGuard { umem: self, index })
+    }
+
+    /// Borrow the `len` bytes of the frame at `offset`, e.g. as pointed to by a descriptor, along with the rest of its chunk
+    ///
+    /// Fails if the frame does not lie within a single chunk, or if its chunk is already borrowed
+    pub fn try_frame(&self, offset: u64, len: usize) -> Result<FrameGuard<'_>, crate::Error> {
+        if ! self.contains_frame(offset, len) {
+            return Err(crate::Error::CorruptDescriptor { addr: offset, len: len as _ });
+        }
+        let index = self.chunk_index_for_offset(offset);
+        let chunk = self.try_chunk(index).ok_or(crate::Error::ChunkAlreadyBorrowed { index })?;
+        let start = (offset - chunk.offset()) as usize;
+        Ok(FrameGuard { chunk, frame: start..start + len })
+    }
+
+    /// Obtain a pointer to the umem allocation, valid for reads and writes of [`Self::memory_size`] bytes while the umem lives
+    ///
+    /// Dereferencing it is up to the caller, who must not create references aliasing a [`ChunkGuard`] or the frames
+    /// owned by the kernel
+    pub const fn memory_ptr(&self) -> *mut u8 {
+        self.memory.as_ptr().cast()
+    }
+
+    /// Obtain a pointer to the `len` bytes of the frame at `offset`
+    ///
+    /// Panics if the frame does not lie within a single chunk, so that the pointer is always in bounds. Dereferencing it
+    /// is up to the caller, like for [`Self::memory_ptr`]
+    pub fn frame_ptr(&self, offset: u64, len: usize) -> NonNull<[u8]> {
+        assert!(self.contains_frame(offset, len), "Frame lies outside of its chunk");
+        let cells = &self.cells()[offset as usize..offset as usize + len];
+        NonNull::slice_from_raw_parts(NonNull::from(cells).cast::<u8>(), len)
+    }
+
+    /// The bytes of the umem, mutable behind a shared reference
+    fn cells(&self) -> &[UnsafeCell<u8>] {
+        unsafe { self.memory.as_ref() }
     }
 }
 impl Drop for Umem {
     fn drop(&mut self) {
-        unsafe { libc::munmap(self.allocation.as_ptr(), self.memory_size()) };
+        unsafe { libc::munmap(self.memory_ptr().cast(), self.memory_size()) };
     }
 }
+// the mapping is owned, and its bytes are only reached through guards which borrow chunks exclusively, through the
+// kernel, or through raw pointers whose users take care of aliasing
 unsafe impl Send for Umem {}
 unsafe impl Sync for Umem {}
+
+/// Exclusive access to the bytes of one chunk of a [`Umem`], released on drop, see [`Umem::chunk`]
+pub struct ChunkGuard<'a> {
+    umem: &'a Umem,
+    index: usize,
+}
+impl ChunkGuard<'_> {
+    /// The index of the chunk
+    pub const fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The offset of the chunk within the umem, as placed in descriptors
+    pub const fn offset(&self) -> u64 {
+        self.umem.chunk_start_offset_for_index(self.index)
+    }
+}
+impl std::ops::Deref for ChunkGuard<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { self.umem.frame_ptr(self.offset(), self.umem.chunk_size()).as_ref() }
+    }
+}
+impl std::ops::DerefMut for ChunkGuard<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { self.umem.frame_ptr(self.offset(), self.umem.chunk_size()).as_mut() }
+    }
+}
+impl Drop for ChunkGuard<'_> {
+    fn drop(&mut self) {
+        self.umem.borrowed_chunks[self.index / 64].fetch_and(! (1 << (self.index % 64)), Ordering::Release);
+    }
+}
+
+/// Exclusive access to the bytes of a frame within a chunk of a [`Umem`], released on drop, see [`Umem::try_frame`]
+///
+/// It dereferences to the frame, its chunk stays borrowed as a whole
+pub struct FrameGuard<'a> {
+    chunk: ChunkGuard<'a>,
+    frame: std::ops::Range<usize>,
+}
+impl<'a> FrameGuard<'a> {
+    /// The range of the frame within its chunk
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.frame.clone()
+    }
+
+    /// The whole chunk along with the range of the frame within it, e.g. to use the headroom in front of the frame
+    pub fn into_chunk(self) -> (ChunkGuard<'a>, std::ops::Range<usize>) {
+        (self.chunk, self.frame)
+    }
+}
+impl std::ops::Deref for FrameGuard<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.chunk[self.frame.clone()]
+    }
+}
+impl std::ops::DerefMut for FrameGuard<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.chunk[self.frame.clone()]
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// A chunk size known at compile time, see [`TypedUmem`]
+///
+/// Only [`Chunk2K`] and [`Chunk4K`] implement it, the sizes the kernel accepts and whose offset conversions are shifts
+pub trait ChunkSize: sealed::Sealed {
+    /// The size in bytes
+    const SIZE: usize;
+    /// The base 2 logarithm of [`Self::SIZE`]
+    const SHIFT: u32 = Self::SIZE.trailing_zeros();
+}
+
+/// Chunks of 2048 bytes
+#[derive(Debug, Clone, Copy)]
+pub struct Chunk2K;
+impl sealed::Sealed for Chunk2K {}
+impl ChunkSize for Chunk2K {
+    const SIZE: usize = Umem::CHUNK_SIZE_2K;
+}
+
+/// Chunks of 4096 bytes
+#[derive(Debug, Clone, Copy)]
+pub struct Chunk4K;
+impl sealed::Sealed for Chunk4K {}
+impl ChunkSize for Chunk4K {
+    const SIZE: usize = Umem::CHUNK_SIZE_4K;
+}
+
+/// A shared [`Umem`] whose chunk size is part of its type
+///
+/// Offset and index conversions compile to shifts, and code taking e.g. a `TypedUmem<Chunk2K>` cannot be handed a
+/// umem with 4K chunks. Sockets and allocators created through [`Self::socket`] and [`Self::allocator`] share it, so
+/// they cannot be mixed up with those of another umem. It dereferences to the underlying [`Umem`]
+pub struct TypedUmem<C: ChunkSize> {
+    umem: Arc<Umem>,
+    _chunk_size: PhantomData<C>,
+}
+impl<C: ChunkSize> TypedUmem<C> {
+    /// Create a new umem containing `num_chunks` chunks of size `C::SIZE`
+    pub fn new(num_chunks: usize) -> Result<Self, crate::Error> {
+        Ok(Self { umem: Arc::new(Umem::new(C::SIZE, num_chunks, None)?), _chunk_size: PhantomData })
+    }
+
+    /// Type an existing umem, unless its chunk size is not `C::SIZE`
+    pub fn from_umem(umem: Arc<Umem>) -> Option<Self> {
+        (umem.chunk_size() == C::SIZE).then_some(Self { umem, _chunk_size: PhantomData })
+    }
+
+    /// The umem, e.g. to create sockets and allocators
+    pub const fn umem(&self) -> &Arc<Umem> {
+        &self.umem
+    }
+
+    /// Create an allocator holding every chunk of this umem
+    pub fn allocator<A: crate::UmemAllocatorFactory>(&self) -> A {
+        A::for_umem(self.umem.clone())
+    }
+
+    /// Create a socket using this umem, see [`crate::XDPSocket::with_ring_sizes`]
+    pub fn socket<'a>(&self, interface_index: libc::c_uint, queue_id: libc::c_uint, ring_sizes: crate::RingSizes) -> Result<crate::XDPSocket<'a>, crate::Error> {
+        crate::XDPSocket::with_ring_sizes(interface_index, queue_id, self.umem.clone(), ring_sizes)
+    }
+
+    /// How big in bytes an individual chunk is
+    pub const fn chunk_size(&self) -> usize {
+        C::SIZE
+    }
+
+    /// Given a chunk index, return the offset from the start of allocated area where that chunk starts
+    pub const fn chunk_start_offset_for_index(&self, index: usize) -> u64 {
+        (index as u64) << C::SHIFT
+    }
+
+    /// Given an offset, return the chunk index associated with it
+    pub const fn chunk_index_for_offset(&self, offset: u64) -> usize {
+        (offset >> C::SHIFT) as usize
+    }
+}
+impl<C: ChunkSize> Clone for TypedUmem<C> {
+    fn clone(&self) -> Self {
+        Self { umem: self.umem.clone(), _chunk_size: PhantomData }
+    }
+}
+impl<C: ChunkSize> std::ops::Deref for TypedUmem<C> {
+    type Target = Umem;
+
+    fn deref(&self) -> &Umem {
+        &self.umem
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Chunk2K, Chunk4K, TypedUmem, Umem};
+
+    #[test]
+    fn test_typed_umem() {
+        let umem = TypedUmem::<Chunk4K>::new(16).unwrap();
+        assert_eq!(umem.chunk_size(), 4096);
+        assert_eq!(umem.memory_size(), 16 * 4096);
+        for offset in [0, 4095, 4096, 5000, 15 * 4096 + 1] {
+            assert_eq!(umem.chunk_index_for_offset(offset), umem.umem().chunk_index_for_offset(offset));
+        }
+        assert_eq!(umem.chunk_start_offset_for_index(3), umem.umem().chunk_start_offset_for_index(3));
+        assert!(TypedUmem::<Chunk2K>::from_umem(umem.umem().clone()).is_none());
+        assert!(TypedUmem::<Chunk2K>::from_umem(Arc::new(Umem::new_2k(16).unwrap())).is_some());
+
+        // allocators hand out its chunks
+        let allocator = umem.allocator::<crate::DefaultAllocator>();
+        assert!(std::ptr::eq(crate::UmemAllocator::umem_reference(&allocator), &**umem.umem()));
+        let index = crate::UmemAllocator::try_allocate(&allocator).unwrap();
+        assert!(index < 16);
+    }
+
+    #[test]
+    fn test_chunk_guard() {
+        let umem = Umem::new_2k(128).unwrap();
+        let mut chunk = umem.chunk(100);
+        assert_eq!((chunk.len(), chunk.offset()), (2048, 100 * 2048));
+        chunk[..5].copy_from_slice(b"hello");
+
+        // one guard per chunk
+        assert!(umem.try_chunk(100).is_none());
+        assert!(umem.try_chunk(101).is_some());
+        assert!(umem.try_chunk(128).is_none());
+        drop(chunk);
+        assert_eq!(&umem.chunk(100)[..5], b"hello");
+    }
+
+    #[test]
+    fn test_frame_ptr() {
+        let umem = Umem::new_2k(4).unwrap();
+        umem.chunk(1)[16..21].copy_from_slice(b"hello");
+
+        // in bounds, sharing the bytes of guards
+        let frame = umem.frame_ptr(2048 + 16, 5);
+        assert_eq!((frame.len(), frame.cast::<u8>().as_ptr()), (5, umem.memory_ptr().wrapping_add(2048 + 16)));
+        assert_eq!(unsafe { frame.as_ref() }, b"hello");
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| umem.frame_ptr(2048 + 16, 2048))).is_err());
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| umem.frame_ptr(4 * 2048, 0))).is_err());
+    }
+
+    #[test]
+    fn test_prefault() {
+        // contents survive, shared mappings included
+        let umem = Umem::new_2k_shareable(64).unwrap();
+        umem.chunk(7)[..5].copy_from_slice(b"hello");
+        umem.prefault(true).unwrap();
+        let mapped = Umem::from_memfd(umem.memfd().unwrap().try_clone_to_owned().unwrap(), 2048).unwrap();
+        assert!(matches!(Umem::from_memfd(umem.memfd().unwrap().try_clone_to_owned().unwrap(), 1000), Err(crate::Error::InvalidConfiguration { .. })));
+        mapped.prefault(false).unwrap();
+        assert_eq!(&mapped.chunk(7)[..5], b"hello");
+    }
+
+    #[test]
+    #[ignore = "requires root"]
+    fn test_register() {
+        let register = |umem: &Umem| {
+            let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW, 0) };
+            assert!(fd >= 0);
+            let result = umem.register(fd);
+            unsafe { libc::close(fd) };
+            result
+        };
+        register(&Umem::new_2k(64).unwrap()).unwrap();
+        let umem = Umem::new_2k(64).unwrap().with_tx_metadata_len(16).with_tx_sw_csum();
+        assert_eq!(umem.registration_flags(), libc::XDP_UMEM_TX_SW_CSUM);
+        register(&umem).unwrap();
+    }
+}
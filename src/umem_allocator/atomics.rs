@@ -2,18 +2,68 @@ use std::sync::{atomic::{AtomicU64, AtomicUsize}, Arc};
 
 use crate::Umem;
 
-use super::UmemAllocator;
+use super::{UmemAllocator, UmemAllocatorFactory};
 
+/// Counters of an [`AtomicBitSetAllocator`], see [`AtomicBitSetAllocator::stats`]
+///
+/// Allocations scanning many words mean a fragmented bitset, many retries threads fighting over the same words: either
+/// way a queue-based allocator will likely do better
+///
+/// Allocations and scanned words are only counted with the `allocator-stats` feature, keeping the fast path free of
+/// shared writes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BitSetStats {
+    pub allocations: u64,
+    pub failed_allocations: u64,
+    /// Failed compare-and-swaps
+    pub cas_retries: u64,
+    /// Words looked at by all allocations, failed ones included
+    pub words_scanned: u64,
+}
+impl BitSetStats {
+    /// How many words an allocation looked at on average
+    pub fn words_scanned_per_allocation(&self) -> f64 {
+        self.words_scanned as f64 / (self.allocations + self.failed_allocations).max(1) as f64
+    }
+
+    /// How many compare-and-swaps an allocation retried on average
+    pub fn cas_retries_per_allocation(&self) -> f64 {
+        self.cas_retries as f64 / (self.allocations + self.failed_allocations).max(1) as f64
+    }
+}
+
+/// An allocator marking chunks in a bitset, scanned from a hint to the first word with free chunks
 pub struct AtomicBitSetAllocator {
     umem: Arc<Umem>,
     storage: Box<[AtomicU64]>,
     // Hint for next word that might have free slots
     next_word_hint: AtomicUsize,
+    // failed compare and swaps
+    cas_retries: AtomicU64,
+    allocations: AtomicU64,
+    failed_allocations: AtomicU64,
+    words_scanned: AtomicU64,
 }
-impl UmemAllocator for AtomicBitSetAllocator {
-    fn for_umem(umem: Arc<Umem>) -> Self
-    where
-        Self: Sized {
+impl AtomicBitSetAllocator {
+    /// Take a snapshot of the counters
+    pub fn stats(&self) -> BitSetStats {
+        BitSetStats {
+            allocations: self.allocations.load(std::sync::atomic::Ordering::Relaxed),
+            failed_allocations: self.failed_allocations.load(std::sync::atomic::Ordering::Relaxed),
+            cas_retries: self.cas_retries.load(std::sync::atomic::Ordering::Relaxed),
+            words_scanned: self.words_scanned.load(std::sync::atomic::Ordering::Relaxed),
+        }
+    }
+
+    /// Zero the counters, e.g. to look at a window of time
+    pub fn reset_stats(&self) {
+        for counter in [&self.cas_retries, &self.allocations, &self.failed_allocations, &self.words_scanned] {
+            counter.store(0, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+}
+impl UmemAllocatorFactory for AtomicBitSetAllocator {
+    fn for_umem(umem: Arc<Umem>) -> Self {
         assert_eq!(umem.num_chunks() % 64, 0, "Umem number of chunks ({}) is not divisible by 64", umem.num_chunks());
         
         let storage = (0..umem.num_chunks() / 64)
@@ -24,13 +74,19 @@ impl UmemAllocator for AtomicBitSetAllocator {
             umem,
             storage,
             next_word_hint: AtomicUsize::new(0),
+            cas_retries: AtomicU64::new(0),
+            allocations: AtomicU64::new(0),
+            failed_allocations: AtomicU64::new(0),
+            words_scanned: AtomicU64::new(0),
         }
     }
-
+}
+impl UmemAllocator for AtomicBitSetAllocator {
     fn umem_reference(&self) -> &Umem {
         &self.umem
     }
 
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip(self), ret))]
     fn try_allocate(&self) -> Option<usize> {
         for offset in 0..self.storage.len() {
             // get word index
@@ -71,10 +127,16 @@ impl UmemAllocator for AtomicBitSetAllocator {
                             self.next_word_hint.fetch_min(word_index, std::sync::atomic::Ordering::Relaxed);
                         }
 
-                        // return
+                        // account and return
+                        #[cfg(feature = "allocator-stats")]
+                        {
+                            self.allocations.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                            self.words_scanned.fetch_add(offset as u64 + 1, std::sync::atomic::Ordering::Relaxed);
+                        }
                         return Some(word_index * 64 + bit_index as usize);
                     },
                     Err(new_word) => {
+                        self.cas_retries.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
                         word = new_word;
                     }
                 }
@@ -85,9 +147,13 @@ impl UmemAllocator for AtomicBitSetAllocator {
                 }
             }
         }
+        self.failed_allocations.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        #[cfg(feature = "allocator-stats")]
+        self.words_scanned.fetch_add(self.storage.len() as u64, std::sync::atomic::Ordering::Relaxed);
         None
     }
 
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip(self), ret))]
     fn try_release(&self, index: usize) -> bool {
         // get the indexes
         let word_index = index / 64;
@@ -129,6 +195,10 @@ impl UmemAllocator for AtomicBitSetAllocator {
         )
     }
 
+    fn contention_count(&self) -> Option<u64> {
+        Some(self.cas_retries.load(std::sync::atomic::Ordering::Relaxed))
+    }
+
 }
 impl std::fmt::Debug for AtomicBitSetAllocator {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -148,11 +218,39 @@ impl std::fmt::Debug for AtomicBitSetAllocator {
 
 #[cfg(test)]
 mod tests {
-    use crate::umem_allocator::tests::crunch_allocator;
+    use std::sync::Arc;
+
+    use crate::{umem_allocator::tests::crunch_allocator, Umem, UmemAllocator, UmemAllocatorFactory};
     use super::AtomicBitSetAllocator;
 
     #[test]
     fn test_atomics_allocator() {
         crunch_allocator::<AtomicBitSetAllocator>();
     }
+
+    #[test]
+    fn test_stats() {
+        let allocator = AtomicBitSetAllocator::for_umem(Arc::new(Umem::new_2k(128).unwrap()));
+
+        // the first word serves the first 64 allocations, the next ones look at it again before moving on
+        for _ in 0..128 {
+            allocator.try_allocate().unwrap();
+        }
+        let stats = allocator.stats();
+        assert_eq!((stats.failed_allocations, stats.cas_retries), (0, 0));
+        #[cfg(feature = "allocator-stats")]
+        assert_eq!((stats.allocations, stats.words_scanned), (128, 64 + 64 * 2));
+
+        // a full bitset is scanned whole
+        assert_eq!(allocator.try_allocate(), None);
+        assert_eq!(allocator.stats().failed_allocations, 1);
+        #[cfg(feature = "allocator-stats")]
+        {
+            assert_eq!(allocator.stats().words_scanned, 194);
+            assert_eq!(allocator.stats().words_scanned_per_allocation(), 194.0 / 129.0);
+        }
+
+        allocator.reset_stats();
+        assert_eq!(allocator.stats(), Default::default());
+    }
 }
@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use crate::Umem;
+
+use super::{UmemAllocator, UmemAllocatorFactory};
+
+/// Like [`super::ConcurrentQueueAllocator`], but keeping chunk indices as `u32`
+///
+/// Halves the queue storage, which matters for umems with millions of chunks
+pub struct CompactQueueAllocator {
+    umem: Arc<Umem>,
+    available_chunks: crossbeam::queue::ArrayQueue<u32>,
+}
+impl UmemAllocatorFactory for CompactQueueAllocator {
+    fn for_umem(umem: Arc<Umem>) -> Self {
+        let num_chunks = u32::try_from(umem.num_chunks())
+            .unwrap_or_else(|_| panic!("Umem number of chunks ({}) does not fit in u32", umem.num_chunks()));
+
+        // make the chunk list
+        let available_chunks = crossbeam::queue::ArrayQueue::new(umem.num_chunks());
+        for i in 0..num_chunks {
+            available_chunks.push(i).unwrap();
+        }
+        Self {
+            umem,
+            available_chunks
+        }
+    }
+}
+impl UmemAllocator for CompactQueueAllocator {
+    fn umem_reference(&self) -> &Umem {
+        &self.umem
+    }
+
+    fn try_allocate(&self) -> Option<usize> {
+        self.try_allocate_u32().map(|index| index as usize)
+    }
+
+    fn try_release(&self, index: usize) -> bool {
+        u32::try_from(index).is_ok_and(|index| self.try_release_u32(index))
+    }
+
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip(self), ret))]
+    fn try_allocate_u32(&self) -> Option<u32> {
+        self.available_chunks.pop()
+    }
+
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip(self), ret))]
+    fn try_release_u32(&self, index: u32) -> bool {
+        // check
+        if index as usize >= self.umem.num_chunks() {
+            return false;
+        }
+        // try to push
+        self.available_chunks.push(index).is_ok()
+    }
+
+    fn num_available(&self) -> Option<usize> {
+        Some(self.available_chunks.len())
+    }
+
+    fn num_allocated(&self) -> Option<usize> {
+        Some(self.available_chunks.capacity() - self.available_chunks.len())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{umem_allocator::tests::crunch_allocator, Umem, UmemAllocator, UmemAllocatorFactory};
+    use super::CompactQueueAllocator;
+
+    #[test]
+    fn test_compact_allocator() {
+        crunch_allocator::<CompactQueueAllocator>();
+    }
+
+    #[test]
+    fn test_compact_allocator_counts() {
+        let allocator = CompactQueueAllocator::for_umem(Arc::new(Umem::new_2k(16).unwrap()));
+        let index = allocator.try_allocate_u32().unwrap();
+        assert_eq!(allocator.num_allocated(), Some(1));
+        assert_eq!(allocator.num_available(), Some(15));
+        assert!(allocator.try_release_u32(index));
+        assert!(! allocator.try_release(usize::MAX));
+        assert_eq!(allocator.num_available(), Some(16));
+    }
+}
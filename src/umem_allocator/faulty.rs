@@ -0,0 +1,164 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::Umem;
+
+use super::UmemAllocator;
+
+/// Wraps an allocator making [`UmemAllocator::try_allocate`] fail on purpose, to exercise backpressure and drop paths
+///
+/// Failures are injected with a given probability, drawn from a seeded generator so that runs are repeatable,
+/// and/or for every allocation after the first N. Releases always reach the wrapped allocator
+pub struct FaultyAllocator<A> {
+    inner: A,
+    // probability of failure scaled to u64::MAX, 0 never fails
+    failure_threshold: u64,
+    random_state: AtomicU64,
+    // remaining allocations before failing for good
+    remaining_allocations: Option<AtomicU64>,
+    injected_failures: AtomicU64,
+}
+impl<A: UmemAllocator> FaultyAllocator<A> {
+    /// Wrap `inner`, failing nothing until configured
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            failure_threshold: 0,
+            random_state: AtomicU64::new(0x9E37_79B9_7F4A_7C15),
+            remaining_allocations: None,
+            injected_failures: AtomicU64::new(0),
+        }
+    }
+
+    /// Fail each allocation with `probability` (between 0 and 1), seeding the generator with `seed`
+    pub fn fail_with_probability(mut self, probability: f64, seed: u64) -> Self {
+        assert!((0.0..=1.0).contains(&probability), "probability ({probability}) is not between 0 and 1");
+        self.failure_threshold = (probability * u64::MAX as f64) as u64;
+        // xorshift gets stuck on zero
+        self.random_state = AtomicU64::new(if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed });
+        self
+    }
+
+    /// Fail every allocation after the first `allocations`
+    pub fn fail_after(mut self, allocations: u64) -> Self {
+        self.remaining_allocations = Some(AtomicU64::new(allocations));
+        self
+    }
+
+    /// The wrapped allocator
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// How many allocations were failed on purpose
+    pub fn injected_failures(&self) -> u64 {
+        self.injected_failures.load(Ordering::Relaxed)
+    }
+
+    fn should_fail(&self) -> bool {
+        // probability
+        if self.failure_threshold > 0 && self.next_random() <= self.failure_threshold {
+            return true;
+        }
+
+        // allocation budget
+        self.remaining_allocations.as_ref().is_some_and(|remaining| {
+            remaining.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| remaining.checked_sub(1)).is_err()
+        })
+    }
+
+    /// xorshift64*
+    fn next_random(&self) -> u64 {
+        let xorshift = |mut state: u64| {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            state
+        };
+        let previous = self.random_state.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |state| Some(xorshift(state))).unwrap();
+        xorshift(previous).wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+impl<A: UmemAllocator> UmemAllocator for FaultyAllocator<A> {
+    fn umem_reference(&self) -> &Umem {
+        self.inner.umem_reference()
+    }
+
+    fn try_allocate(&self) -> Option<usize> {
+        if self.should_fail() {
+            self.injected_failures.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        self.inner.try_allocate()
+    }
+
+    fn try_release(&self, index: usize) -> bool {
+        self.inner.try_release(index)
+    }
+
+    fn try_recycle(&self, index: usize) -> bool {
+        self.inner.try_recycle(index)
+    }
+
+    fn num_available(&self) -> Option<usize> {
+        self.inner.num_available()
+    }
+
+    fn num_allocated(&self) -> Option<usize> {
+        self.inner.num_allocated()
+    }
+
+    fn contention_count(&self) -> Option<u64> {
+        self.inner.contention_count()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{Umem, UmemAllocator, UmemAllocatorFactory, DefaultAllocator};
+    use super::FaultyAllocator;
+
+    fn allocator() -> DefaultAllocator {
+        DefaultAllocator::for_umem(Arc::new(Umem::new_2k(64).unwrap()))
+    }
+
+    #[test]
+    fn test_fail_after() {
+        let allocator = FaultyAllocator::new(allocator()).fail_after(3);
+        let indexes: Vec<_> = (0..3).map(|_| allocator.try_allocate().unwrap()).collect();
+        assert!(allocator.try_allocate().is_none());
+        assert!(allocator.try_allocate().is_none());
+        assert_eq!(allocator.injected_failures(), 2);
+
+        // releases still go through
+        for index in indexes {
+            assert!(allocator.try_release(index));
+        }
+        assert!(allocator.try_allocate().is_none());
+    }
+
+    #[test]
+    fn test_fail_with_probability() {
+        let pattern = |seed| {
+            let allocator = FaultyAllocator::new(allocator()).fail_with_probability(0.5, seed);
+            (0..1000).map(|_| match allocator.try_allocate() {
+                Some(index) => { allocator.release(index); true },
+                None => false,
+            }).collect::<Vec<_>>()
+        };
+
+        // repeatable
+        let first = pattern(42);
+        assert_eq!(first, pattern(42));
+        let successes = first.iter().filter(|success| **success).count();
+        assert!((400..600).contains(&successes), "{successes} successes out of 1000");
+
+        // extremes
+        let never = FaultyAllocator::new(allocator()).fail_with_probability(0.0, 1);
+        assert!(never.try_allocate().is_some());
+        let always = FaultyAllocator::new(allocator()).fail_with_probability(1.0, 1);
+        assert!((0..100).all(|_| always.try_allocate().is_none()));
+    }
+}
@@ -0,0 +1,208 @@
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex};
+
+use crate::Umem;
+
+use super::{UmemAllocator, UmemAllocatorFactory};
+
+/// Wraps an allocator recording how long allocations and releases take, to compare allocators under real workloads
+///
+/// Latencies go to histograms behind a lock, which is taken after the measurement and therefore does not show up in it
+pub struct InstrumentedAllocator<A> {
+    inner: A,
+    allocations: AtomicU64,
+    failed_allocations: AtomicU64,
+    releases: AtomicU64,
+    failed_releases: AtomicU64,
+    allocation_latency: Mutex<hdrhistogram::Histogram<u64>>,
+    release_latency: Mutex<hdrhistogram::Histogram<u64>>,
+}
+impl<A: UmemAllocator> InstrumentedAllocator<A> {
+    /// Wrap `inner`
+    pub fn new(inner: A) -> Self {
+        // 1ns up to 1s
+        let histogram = || Mutex::new(hdrhistogram::Histogram::new_with_bounds(1, 1_000_000_000, 3).unwrap());
+        Self {
+            inner,
+            allocations: AtomicU64::new(0),
+            failed_allocations: AtomicU64::new(0),
+            releases: AtomicU64::new(0),
+            failed_releases: AtomicU64::new(0),
+            allocation_latency: histogram(),
+            release_latency: histogram(),
+        }
+    }
+
+    /// The wrapped allocator
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Take a snapshot of what was recorded so far
+    pub fn stats(&self) -> AllocatorStats {
+        AllocatorStats {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            failed_allocations: self.failed_allocations.load(Ordering::Relaxed),
+            releases: self.releases.load(Ordering::Relaxed),
+            failed_releases: self.failed_releases.load(Ordering::Relaxed),
+            contention: self.inner.contention_count(),
+            allocation_latency: self.allocation_latency.lock().unwrap().clone(),
+            release_latency: self.release_latency.lock().unwrap().clone(),
+        }
+    }
+
+    /// Forget what was recorded so far, the contention count of the wrapped allocator is not affected
+    pub fn reset(&self) {
+        for counter in [ &self.allocations, &self.failed_allocations, &self.releases, &self.failed_releases ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.allocation_latency.lock().unwrap().reset();
+        self.release_latency.lock().unwrap().reset();
+    }
+
+    fn record_allocation<T>(&self, operation: impl FnOnce() -> Option<T>) -> Option<T> {
+        let t0 = std::time::Instant::now();
+        let result = operation();
+        let elapsed_ns = t0.elapsed().as_nanos() as u64;
+        self.allocation_latency.lock().unwrap().saturating_record(elapsed_ns.max(1));
+        match result {
+            Some(..) => self.allocations.fetch_add(1, Ordering::Relaxed),
+            None => self.failed_allocations.fetch_add(1, Ordering::Relaxed),
+        };
+        result
+    }
+
+    fn record_release(&self, operation: impl FnOnce() -> bool) -> bool {
+        let t0 = std::time::Instant::now();
+        let result = operation();
+        let elapsed_ns = t0.elapsed().as_nanos() as u64;
+        self.release_latency.lock().unwrap().saturating_record(elapsed_ns.max(1));
+        if result {
+            self.releases.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failed_releases.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+}
+impl<A: UmemAllocatorFactory> UmemAllocatorFactory for InstrumentedAllocator<A> {
+    fn for_umem(umem: Arc<Umem>) -> Self {
+        Self::new(A::for_umem(umem))
+    }
+}
+impl<A: UmemAllocator> UmemAllocator for InstrumentedAllocator<A> {
+    fn umem_reference(&self) -> &Umem {
+        self.inner.umem_reference()
+    }
+
+    fn try_allocate(&self) -> Option<usize> {
+        self.record_allocation(|| self.inner.try_allocate())
+    }
+
+    fn try_release(&self, index: usize) -> bool {
+        self.record_release(|| self.inner.try_release(index))
+    }
+
+    fn try_allocate_u32(&self) -> Option<u32> {
+        self.record_allocation(|| self.inner.try_allocate_u32())
+    }
+
+    fn try_release_u32(&self, index: u32) -> bool {
+        self.record_release(|| self.inner.try_release_u32(index))
+    }
+
+    fn try_recycle(&self, index: usize) -> bool {
+        self.inner.try_recycle(index)
+    }
+
+    fn num_available(&self) -> Option<usize> {
+        self.inner.num_available()
+    }
+
+    fn num_allocated(&self) -> Option<usize> {
+        self.inner.num_allocated()
+    }
+
+    fn contention_count(&self) -> Option<u64> {
+        self.inner.contention_count()
+    }
+
+}
+
+/// What an [`InstrumentedAllocator`] recorded, latencies are in nanoseconds
+#[derive(Debug, Clone)]
+pub struct AllocatorStats {
+    pub allocations: u64,
+    pub failed_allocations: u64,
+    pub releases: u64,
+    pub failed_releases: u64,
+    /// See [`UmemAllocator::contention_count`]
+    pub contention: Option<u64>,
+    pub allocation_latency: hdrhistogram::Histogram<u64>,
+    pub release_latency: hdrhistogram::Histogram<u64>,
+}
+impl AllocatorStats {
+    /// Write these statistics in the Prometheus text exposition format, labelled with `allocator`
+    pub fn write_prometheus(&self, writer: &mut impl std::fmt::Write, allocator: &str) -> std::fmt::Result {
+        // counters
+        writeln!(writer, "# TYPE xdrippi_allocator_allocations_total counter")?;
+        writeln!(writer, "xdrippi_allocator_allocations_total{{allocator=\"{allocator}\",result=\"ok\"}} {}", self.allocations)?;
+        writeln!(writer, "xdrippi_allocator_allocations_total{{allocator=\"{allocator}\",result=\"failed\"}} {}", self.failed_allocations)?;
+        writeln!(writer, "# TYPE xdrippi_allocator_releases_total counter")?;
+        writeln!(writer, "xdrippi_allocator_releases_total{{allocator=\"{allocator}\",result=\"ok\"}} {}", self.releases)?;
+        writeln!(writer, "xdrippi_allocator_releases_total{{allocator=\"{allocator}\",result=\"failed\"}} {}", self.failed_releases)?;
+        if let Some(contention) = self.contention {
+            writeln!(writer, "# TYPE xdrippi_allocator_contention_total counter")?;
+            writeln!(writer, "xdrippi_allocator_contention_total{{allocator=\"{allocator}\"}} {contention}")?;
+        }
+
+        // latencies
+        for (name, histogram) in [ ("allocation", &self.allocation_latency), ("release", &self.release_latency) ] {
+            writeln!(writer, "# TYPE xdrippi_allocator_{name}_seconds summary")?;
+            for quantile in [ 0.5, 0.9, 0.99, 0.999 ] {
+                let seconds = histogram.value_at_quantile(quantile) as f64 / 1e9;
+                writeln!(writer, "xdrippi_allocator_{name}_seconds{{allocator=\"{allocator}\",quantile=\"{quantile}\"}} {seconds}")?;
+            }
+            let sum = histogram.mean() * histogram.len() as f64 / 1e9;
+            writeln!(writer, "xdrippi_allocator_{name}_seconds_sum{{allocator=\"{allocator}\"}} {sum}")?;
+            writeln!(writer, "xdrippi_allocator_{name}_seconds_count{{allocator=\"{allocator}\"}} {}", histogram.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{umem_allocator::atomics::AtomicBitSetAllocator, Umem, UmemAllocator, UmemAllocatorFactory};
+    use super::InstrumentedAllocator;
+
+    #[test]
+    fn test_instrumented_allocator() {
+        let allocator = InstrumentedAllocator::<AtomicBitSetAllocator>::for_umem(Arc::new(Umem::new_2k(64).unwrap()));
+        let indexes: Vec<_> = std::iter::from_fn(|| allocator.try_allocate()).collect();
+        assert_eq!(indexes.len(), 64);
+        for index in indexes {
+            allocator.release(index);
+        }
+        assert!(! allocator.try_release(1000));
+
+        // counters and histograms
+        let stats = allocator.stats();
+        assert_eq!((stats.allocations, stats.failed_allocations), (64, 1));
+        assert_eq!((stats.releases, stats.failed_releases), (64, 1));
+        assert_eq!(stats.contention, Some(0));
+        assert_eq!(stats.allocation_latency.len(), 65);
+        assert_eq!(stats.release_latency.len(), 65);
+
+        // export
+        let mut exported = String::new();
+        stats.write_prometheus(&mut exported, "bitset").unwrap();
+        assert!(exported.contains("xdrippi_allocator_allocations_total{allocator=\"bitset\",result=\"ok\"} 64\n"));
+        assert!(exported.contains("xdrippi_allocator_contention_total{allocator=\"bitset\"} 0\n"));
+        assert!(exported.contains("xdrippi_allocator_release_seconds_count{allocator=\"bitset\"} 65\n"));
+
+        allocator.reset();
+        assert_eq!(allocator.stats().allocations, 0);
+    }
+}
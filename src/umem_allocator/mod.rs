@@ -2,18 +2,23 @@ use std::sync::Arc;
 
 use crate::Umem;
 
-mod atomics;
+mod atomics; pub use atomics::{AtomicBitSetAllocator, BitSetStats};
+mod compact; pub use compact::CompactQueueAllocator;
+mod faulty; pub use faulty::FaultyAllocator;
+mod instrumented; pub use instrumented::{AllocatorStats, InstrumentedAllocator};
 mod queue; pub use queue::ConcurrentQueueAllocator;
+mod refcount; pub use refcount::RefCountingAllocator;
 
 pub type DefaultAllocator = ConcurrentQueueAllocator;
 
-/// A Umem allocator
-pub trait UmemAllocator {
+/// Creates [`UmemAllocator`]s, kept apart so that `dyn UmemAllocator` can be chosen at runtime
+pub trait UmemAllocatorFactory: UmemAllocator + Sized {
     /// Create an allocator prepopulated with all the chunks in the provided umem
-    fn for_umem(umem: Arc<Umem>) -> Self
-    where
-        Self: Sized;
+    fn for_umem(umem: Arc<Umem>) -> Self;
+}
 
+/// A Umem allocator
+pub trait UmemAllocator {
     /// Get the umem utilized by this allocator
     fn umem_reference(&self) -> &Umem;
     
@@ -23,6 +28,23 @@ pub trait UmemAllocator {
     /// Try to release a chunk back to the allocator, provided by its index
     fn try_release(&self, index: usize) -> bool;
 
+    /// Try to allocate a chunk, returning its index as a `u32`
+    ///
+    /// Chunks whose index does not fit are given back, and `None` returned
+    fn try_allocate_u32(&self) -> Option<u32> {
+        let index = self.try_allocate()?;
+        let index_u32 = u32::try_from(index).ok();
+        if index_u32.is_none() {
+            self.try_release(index);
+        }
+        index_u32
+    }
+
+    /// Try to release a chunk back to the allocator, provided by its index as a `u32`
+    fn try_release_u32(&self, index: u32) -> bool {
+        self.try_release(index as usize)
+    }
+
     /// Release a chunk back to the allocator, provided by its index
     /// 
     /// Panics on failure
@@ -47,6 +69,15 @@ pub trait UmemAllocator {
         }
     }
 
+    /// Take the allocated chunk at `index` back for its holder to reuse right away, e.g. from the completion ring to the
+    /// fill ring, returning whether it can be reused
+    ///
+    /// Allocators sharing chunks, like [`RefCountingAllocator`], drop a reference instead while others remain: the chunk
+    /// must not be touched then
+    fn try_recycle(&self, _index: usize) -> bool {
+        true
+    }
+
     /// Estimates the available free slots
     fn num_available(&self) -> Option<usize> {
         None
@@ -57,6 +88,11 @@ pub trait UmemAllocator {
         None
     }
 
+    /// Counts how many times an operation had to be retried because of other threads, e.g. failed compare-and-swaps
+    fn contention_count(&self) -> Option<u64> {
+        None
+    }
+
 }
 
 #[cfg(test)]
@@ -65,9 +101,26 @@ mod tests {
 
     use crate::Umem;
 
-    use super::UmemAllocator;
+    use super::{CompactQueueAllocator, ConcurrentQueueAllocator, UmemAllocator, UmemAllocatorFactory};
+
+    #[test]
+    fn test_dyn_allocator() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let allocators: [Arc<dyn UmemAllocator + Send + Sync>; 3] = [
+            Arc::new(ConcurrentQueueAllocator::for_umem(umem.clone())),
+            Arc::new(CompactQueueAllocator::for_umem(umem.clone())),
+            Arc::new(super::atomics::AtomicBitSetAllocator::for_umem(umem.clone())),
+        ];
+        for allocator in allocators {
+            let index = allocator.try_allocate().unwrap();
+            assert!(allocator.try_release(index));
+            let index = allocator.try_allocate_u32().unwrap();
+            assert!(allocator.try_release_u32(index));
+            assert!(! allocator.try_release_u32(64));
+        }
+    }
 
-    pub(crate) fn benchmark_allocator<A: UmemAllocator + Send + Sync>() {
+    pub(crate) fn benchmark_allocator<A: UmemAllocatorFactory + Send + Sync>() {
         for n_slots in [ 1024, 2048, 4096, 8192 ] {
             for n_threads in [ 1, 2, 4, 8, 16, 32 ] {
                 benchmark_allocator_run::<A>(n_slots, n_threads);
@@ -75,7 +128,7 @@ mod tests {
         }
     }
 
-    pub(crate) fn benchmark_allocator_run<A: UmemAllocator + Send + Sync>(
+    pub(crate) fn benchmark_allocator_run<A: UmemAllocatorFactory + Send + Sync>(
         n_slots: usize,
         n_threads: usize,
     ) {
@@ -138,7 +191,7 @@ mod tests {
         });
     }
 
-    pub(crate) fn crunch_allocator<A: UmemAllocator + Send + Sync>() {
+    pub(crate) fn crunch_allocator<A: UmemAllocatorFactory + Send + Sync>() {
         for n_slots in [ 1024, 2048, 4096, 8192 ] {
             for n_threads in [ 1, 2, 4, 8, 16, 32 ] {
                 crunch_allocator_run::<A>(n_slots, n_threads);
@@ -146,7 +199,7 @@ mod tests {
         }
     }
 
-    pub(crate) fn crunch_allocator_run<A: UmemAllocator + Send + Sync>(
+    pub(crate) fn crunch_allocator_run<A: UmemAllocatorFactory + Send + Sync>(
         n_slots: usize,
         n_threads: usize,
     ) {
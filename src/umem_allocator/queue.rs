@@ -2,17 +2,15 @@ use std::sync::Arc;
 
 use crate::Umem;
 
-use super::UmemAllocator;
+use super::{UmemAllocator, UmemAllocatorFactory};
 
 /// A very simple umem allocator adding available chunks to an internal list
 pub struct ConcurrentQueueAllocator {
     umem: Arc<Umem>,
     available_chunks: crossbeam::queue::ArrayQueue<usize>,
 }
-impl UmemAllocator for ConcurrentQueueAllocator {
-    fn for_umem(umem: Arc<Umem>) -> Self
-    where
-        Self: Sized {
+impl UmemAllocatorFactory for ConcurrentQueueAllocator {
+    fn for_umem(umem: Arc<Umem>) -> Self {
         // make the chunk list
         let available_chunks = crossbeam::queue::ArrayQueue::new(umem.num_chunks());
         for i in 0..umem.num_chunks() {
@@ -23,15 +21,18 @@ impl UmemAllocator for ConcurrentQueueAllocator {
             available_chunks
         }
     }
-
+}
+impl UmemAllocator for ConcurrentQueueAllocator {
     fn umem_reference(&self) -> &Umem {
         &self.umem
     }
 
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip(self), ret))]
     fn try_allocate(&self) -> Option<usize> {
         self.available_chunks.pop()
     }
 
+    #[cfg_attr(feature = "instrumentation", tracing::instrument(level = "trace", skip(self), ret))]
     fn try_release(&self, index: usize) -> bool {
         // check
         if index >= self.umem.num_chunks() {
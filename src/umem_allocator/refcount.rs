@@ -0,0 +1,131 @@
+use std::sync::{atomic::{AtomicU32, Ordering}, Arc};
+
+use crate::Umem;
+
+use super::{UmemAllocator, UmemAllocatorFactory};
+
+/// Wraps an allocator counting references to chunks, so that a chunk can be enqueued for transmission several times
+///
+/// Allocated chunks start with one reference, [`Self::retain`] adds more and every release drops one, the chunk
+/// going back to the wrapped allocator with the last. This is what lets [`crate::fanout::send_shared`] put the same
+/// chunk on the TX rings of several sockets sharing a umem, each completion releasing it once
+pub struct RefCountingAllocator<A> {
+    inner: A,
+    references: Box<[AtomicU32]>,
+}
+impl<A: UmemAllocator> RefCountingAllocator<A> {
+    /// Wrap `inner`
+    pub fn new(inner: A) -> Self {
+        let num_chunks = inner.umem_reference().num_chunks();
+        Self { inner, references: (0..num_chunks).map(|_| AtomicU32::new(0)).collect() }
+    }
+
+    /// The wrapped allocator
+    pub const fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Add `count` references to the allocated chunk at `index`
+    pub fn retain(&self, index: usize, count: u32) {
+        // a free chunk must stay free, the allocator may hand it out meanwhile
+        let retained = self.references[index].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |references| {
+            if references > 0 { references.checked_add(count) } else { None }
+        });
+        assert!(retained.is_ok(), "Chunk {index} is not allocated or has too many references");
+    }
+
+    /// Add `count` references to the allocated chunk at `offset` in the umem area
+    pub fn retain_offset(&self, offset: u64, count: u32) {
+        self.retain(self.umem_reference().chunk_index_for_offset(offset), count);
+    }
+
+    /// The references to the chunk at `index`, 0 if it is free
+    pub fn references(&self, index: usize) -> u32 {
+        self.references[index].load(Ordering::Relaxed)
+    }
+}
+impl<A: UmemAllocatorFactory> UmemAllocatorFactory for RefCountingAllocator<A> {
+    fn for_umem(umem: Arc<Umem>) -> Self {
+        Self::new(A::for_umem(umem))
+    }
+}
+impl<A: UmemAllocator> UmemAllocator for RefCountingAllocator<A> {
+    fn umem_reference(&self) -> &Umem {
+        self.inner.umem_reference()
+    }
+
+    fn try_allocate(&self) -> Option<usize> {
+        let index = self.inner.try_allocate()?;
+        self.references[index].store(1, Ordering::Relaxed);
+        Some(index)
+    }
+
+    fn try_release(&self, index: usize) -> bool {
+        let Some(references) = self.references.get(index) else {
+            return false;
+        };
+        match references.fetch_update(Ordering::AcqRel, Ordering::Relaxed, |references| references.checked_sub(1)) {
+            Ok(1) => self.inner.try_release(index),
+            Ok(_) => true,
+            Err(_) => false,
+        }
+    }
+
+    fn try_recycle(&self, index: usize) -> bool {
+        let Some(references) = self.references.get(index) else {
+            return false;
+        };
+        // the last reference goes to the caller
+        match references.fetch_update(Ordering::AcqRel, Ordering::Relaxed, |references| (references > 1).then(|| references - 1)) {
+            Ok(_) => false,
+            Err(references) => references == 1,
+        }
+    }
+
+    fn num_available(&self) -> Option<usize> {
+        self.inner.num_available()
+    }
+
+    fn num_allocated(&self) -> Option<usize> {
+        self.inner.num_allocated()
+    }
+
+    fn contention_count(&self) -> Option<u64> {
+        self.inner.contention_count()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{DefaultAllocator, Umem, UmemAllocator, UmemAllocatorFactory};
+    use super::RefCountingAllocator;
+
+    #[test]
+    fn test_reference_counting() {
+        let allocator = RefCountingAllocator::<DefaultAllocator>::for_umem(Arc::new(Umem::new_2k(1).unwrap()));
+        let index = allocator.try_allocate().unwrap();
+        allocator.retain(index, 2);
+        assert_eq!(allocator.references(index), 3);
+
+        // the chunk is only free after the last release
+        assert!(allocator.try_release(index));
+        assert!(allocator.try_release(index));
+        assert!(allocator.try_allocate().is_none());
+        assert!(allocator.try_release(index));
+        assert!(! allocator.try_release(index));
+
+        // free chunks cannot be retained
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| allocator.retain(index, 1))).is_err());
+        assert_eq!(allocator.references(index), 0);
+        assert_eq!(allocator.try_allocate(), Some(index));
+
+        // only the last reference is recycled, and kept
+        allocator.retain(index, 1);
+        assert!(! allocator.try_recycle(index));
+        assert!(allocator.try_recycle(index));
+        assert_eq!(allocator.references(index), 1);
+    }
+}
@@ -30,22 +30,304 @@ pub(crate) fn setsockopt<T: Sized>(socket: impl AsRawFd, level: libc::c_int, nam
     }
 }
 
-pub fn interface_index_to_name(interface_index: libc::c_uint) -> Option<String> {
+/// Turn the `revents` reported by poll for `socket` into an error when the socket is no longer usable
+///
+/// POLLNVAL becomes [`crate::Error::SocketInvalid`], POLLERR a [`crate::Error::SocketError`] carrying SO_ERROR and
+/// POLLHUP [`crate::Error::SocketClosed`]
+pub(crate) fn check_poll_events(socket: impl AsRawFd, revents: libc::c_short) -> Result<(), crate::Error> {
+    if revents & libc::POLLNVAL != 0 {
+        Err(crate::Error::SocketInvalid)
+    } else if revents & libc::POLLERR != 0 {
+        let error = getsockopt::<libc::c_int>(socket.as_raw_fd(), libc::SOL_SOCKET, libc::SO_ERROR)?;
+        Err(crate::Error::SocketError { error: std::io::Error::from_raw_os_error(error) })
+    } else if revents & libc::POLLHUP != 0 {
+        Err(crate::Error::SocketClosed)
+    } else {
+        Ok(())
+    }
+}
+
+/// Wait for `socket` to become readable, see [`crate::XDPSocket::poll_for_reception`]
+pub(crate) fn poll_for_reception(socket: impl AsRawFd) -> Result<(), crate::Error> {
+    while ! poll_for_reception_timeout(socket.as_raw_fd(), None)? {}
+    Ok(())
+}
+
+/// Wait up to `timeout` (forever if `None`) for `socket` to become readable, see [`crate::XDPSocket::poll_for_reception_timeout`]
+pub(crate) fn poll_for_reception_timeout(socket: impl AsRawFd, timeout: Option<std::time::Duration>) -> Result<bool, crate::Error> {
+    let mut poll_fd = libc::pollfd {
+        fd: socket.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    if unsafe { libc::poll(&mut poll_fd as *mut _ as *mut _, 1, poll_timeout_ms(timeout)) } < 0 {
+        let error = std::io::Error::last_os_error();
+        if error.kind() == std::io::ErrorKind::Interrupted {
+            return Ok(false);
+        }
+        return Err(crate::Error::PollFailure { error });
+    }
+    check_poll_events(socket, poll_fd.revents)?;
+    Ok(poll_fd.revents & libc::POLLIN != 0)
+}
+
+/// Convert `timeout` (forever if `None`) to what poll expects, rounding up so that short timeouts do not turn into busy loops
+pub(crate) fn poll_timeout_ms(timeout: Option<std::time::Duration>) -> libc::c_int {
+    timeout.map(|timeout| timeout.as_nanos().div_ceil(1_000_000).min(libc::c_int::MAX as u128) as libc::c_int).unwrap_or(-1)
+}
+
+/// Hint the CPU to bring the cache line at `pointer` in, with the `prefetch` feature on x86_64 and aarch64
+///
+/// Prefetches never fault, `pointer` may be dangling
+#[inline(always)]
+pub(crate) fn prefetch<T>(pointer: *const T) {
+    #[cfg(all(feature = "prefetch", target_arch = "x86_64"))]
+    unsafe { std::arch::x86_64::_mm_prefetch::<{ std::arch::x86_64::_MM_HINT_T0 }>(pointer.cast()) };
+    #[cfg(all(feature = "prefetch", target_arch = "aarch64"))]
+    unsafe { std::arch::asm!("prfm pldl1keep, [{0}]", in(reg) pointer, options(nostack, readonly, preserves_flags)) };
+    let _ = pointer;
+}
+
+/// Kick the kernel into transmitting what is on the TX ring of `socket`
+pub(crate) fn wake_for_transmission(socket: impl AsRawFd) -> Result<(), crate::Error> {
+    let ret = unsafe { libc::sendto(socket.as_raw_fd(), std::ptr::null(), 0,  libc::MSG_DONTWAIT, std::ptr::null(), 0) };
+    if ret < 0 {
+        Err(crate::Error::SocketSendFailure { error: std::io::Error::last_os_error() })
+    } else {
+        Ok(())
+    }
+}
+
+/// Sleep until shortly before `instant`, then spin to hit it precisely
+pub(crate) fn wait_until(instant: std::time::Instant) {
+    const SPIN_THRESHOLD: std::time::Duration = std::time::Duration::from_micros(200);
+    loop {
+        let now = std::time::Instant::now();
+        if now >= instant {
+            return;
+        }
+        let remaining = instant - now;
+        if remaining > SPIN_THRESHOLD {
+            std::thread::sleep(remaining - SPIN_THRESHOLD);
+        } else {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// Move `next_due` one `interval` ahead, then past `now` skipping the whole intervals missed, returning how many were
+pub(crate) fn advance_schedule(next_due: &mut std::time::Instant, interval: std::time::Duration, now: std::time::Instant) -> u64 {
+    *next_due += interval;
+    if *next_due > now {
+        return 0;
+    }
+    let missed = (now - *next_due).as_nanos() / interval.as_nanos() + 1;
+    let skipped = missed * interval.as_nanos();
+    *next_due += std::time::Duration::new((skipped / 1_000_000_000) as u64, (skipped % 1_000_000_000) as u32);
+    missed as u64
+}
+
+/// Copy `frame`, a range of `source`, into `destination` keeping its offset within a cache line, returning where it landed
+///
+/// Meant for umem chunks: the frame keeps its position (moving back by whole cache lines if it would not fit), so both sides
+/// stay equally aligned and the copy is widened to whole cache lines within both buffers, which compiles to full width vector
+/// moves without unaligned heads or byte tails. Bytes around the frame in `destination` may be overwritten.
+/// Plain stores are used rather than non-temporal ones, as the destination is read back by the kernel right away.
+pub fn copy_frame(destination: &mut [u8], source: &[u8], frame: std::ops::Range<usize>) -> std::ops::Range<usize> {
+    const CACHE_LINE: usize = 64;
+    assert!(frame.len() <= destination.len(), "Frame exceeds the destination");
+
+    // move back by whole lines if needed, or give up on alignment if even that does not fit
+    let shift = frame.end.saturating_sub(destination.len()).next_multiple_of(CACHE_LINE);
+    let Some(start) = frame.start.checked_sub(shift) else {
+        destination[..frame.len()].copy_from_slice(&source[frame.clone()]);
+        return 0..frame.len();
+    };
+
+    // copy whole lines
+    let lines_start = frame.start & ! (CACHE_LINE - 1);
+    let lines_end = frame.end.next_multiple_of(CACHE_LINE).min(source.len()).min(destination.len() + shift);
+    let mut source_lines = source[lines_start..lines_end].chunks_exact(CACHE_LINE);
+    let mut destination_lines = destination[lines_start - shift..lines_end - shift].chunks_exact_mut(CACHE_LINE);
+    for (destination_line, source_line) in (&mut destination_lines).zip(&mut source_lines) {
+        destination_line.copy_from_slice(source_line);
+    }
+    destination_lines.into_remainder().copy_from_slice(source_lines.remainder());
+    start..start + frame.len()
+}
+
+/// Check that `interface_name` could name an interface, so that it can be used in ioctls and sysfs paths safely
+pub(crate) fn validate_interface_name(interface_name: &str) -> Result<(), crate::Error> {
+    let reason = if interface_name.is_empty() {
+        "empty"
+    } else if interface_name.len() >= libc::IF_NAMESIZE {
+        "too long"
+    } else if interface_name == "." || interface_name == ".." {
+        "reserved"
+    } else if interface_name.chars().any(|c| c == '/' || c == ':' || c == '\0' || c.is_whitespace()) {
+        "contains a forbidden character"
+    } else {
+        return Ok(());
+    };
+    Err(crate::Error::InvalidInterfaceName { name: interface_name.to_string(), reason })
+}
+
+/// The name of the interface with index `interface_index`
+pub fn interface_index_to_name(interface_index: libc::c_uint) -> Result<String, crate::Error> {
     let mut buffer = [0_u8; libc::IF_NAMESIZE];
     let result = unsafe { libc::if_indextoname(interface_index, buffer.as_mut_ptr() as *mut _) };
     if result.is_null() {
-        return None;
+        return Err(crate::Error::InterfaceNotFound { interface: format!("index {interface_index}") });
     }
     let if_name = std::ffi::CStr::from_bytes_until_nul(&buffer)
-        .expect("ifname is not a null-terminated string")
-        .to_str().expect("ifname is not an UTF8 string")
-        .to_string();
-    Some(if_name)
+        .map_err(|_| crate::Error::InvalidInterfaceName { name: String::from_utf8_lossy(&buffer).into_owned(), reason: "not terminated" })?;
+    if_name.to_str()
+        .map(str::to_string)
+        .map_err(|_| crate::Error::InvalidInterfaceName { name: if_name.to_string_lossy().into_owned(), reason: "not UTF-8" })
+}
+
+/// The index of the interface named `interface_name`
+pub fn interface_name_to_index(interface_name: impl AsRef<str>) -> Result<libc::c_uint, crate::Error> {
+    let interface_name = interface_name.as_ref();
+    validate_interface_name(interface_name)?;
+    let c_name = std::ffi::CString::new(interface_name).expect("validated names have no NUL");
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(crate::Error::InterfaceNotFound { interface: interface_name.to_string() }),
+        index => Ok(index),
+    }
+}
+
+/// Interface names and indexes looked up once, then remembered
+///
+/// Interfaces can be renamed, and indexes reused once an interface is deleted: forget entries on
+/// [`crate::LinkEvent::Removed`] with [`Self::forget_index`], or start over with [`Self::clear`]
+#[derive(Debug, Clone, Default)]
+pub struct InterfaceCache {
+    indexes: std::collections::HashMap<String, libc::c_uint>,
+    names: std::collections::HashMap<libc::c_uint, String>,
 }
+impl InterfaceCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The index of the interface named `interface_name`, see [`interface_name_to_index`]
+    pub fn index(&mut self, interface_name: &str) -> Result<libc::c_uint, crate::Error> {
+        if let Some(&index) = self.indexes.get(interface_name) {
+            return Ok(index);
+        }
+        let index = interface_name_to_index(interface_name)?;
+        self.insert(interface_name.to_string(), index);
+        Ok(index)
+    }
+
+    /// The name of the interface with index `interface_index`, see [`interface_index_to_name`]
+    pub fn name(&mut self, interface_index: libc::c_uint) -> Result<&str, crate::Error> {
+        if ! self.names.contains_key(&interface_index) {
+            let name = interface_index_to_name(interface_index)?;
+            self.insert(name, interface_index);
+        }
+        Ok(&self.names[&interface_index])
+    }
+
+    /// Forget the interface with index `interface_index`
+    pub fn forget_index(&mut self, interface_index: libc::c_uint) {
+        if let Some(name) = self.names.remove(&interface_index) {
+            self.indexes.remove(&name);
+        }
+    }
+
+    /// Forget every interface
+    pub fn clear(&mut self) {
+        self.indexes.clear();
+        self.names.clear();
+    }
 
-pub fn interface_name_to_index(interface_name: impl AsRef<str>) -> Option<libc::c_uint> {
-    // TODO - path injection
-    std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", interface_name.as_ref()))
-        .ok()
-        .map(|ifindex_str| ifindex_str.trim().parse().expect("ifindex was not a number!"))
+    /// Remember that `name` has index `index`, dropping what either was mapped to
+    fn insert(&mut self, name: String, index: libc::c_uint) {
+        if let Some(stale_index) = self.indexes.remove(&name) {
+            self.names.remove(&stale_index);
+        }
+        if let Some(stale_name) = self.names.remove(&index) {
+            self.indexes.remove(&stale_name);
+        }
+        self.indexes.insert(name.clone(), index);
+        self.names.insert(index, name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::fd::{AsRawFd, FromRawFd, OwnedFd}, time::Duration};
+
+    use super::{advance_schedule, copy_frame, interface_index_to_name, interface_name_to_index, poll_for_reception_timeout, poll_timeout_ms, InterfaceCache};
+
+    #[test]
+    fn test_advance_schedule() {
+        let start = std::time::Instant::now();
+        let interval = Duration::from_millis(10);
+        let mut next_due = start;
+        assert_eq!((advance_schedule(&mut next_due, interval, start), next_due), (0, start + interval));
+        assert_eq!((advance_schedule(&mut next_due, interval, start + interval * 3), next_due), (2, start + interval * 4));
+
+        // more intervals missed than fit in an u32
+        let interval = Duration::from_nanos(1);
+        let mut next_due = start;
+        let now = start + Duration::from_secs(10);
+        assert_eq!((advance_schedule(&mut next_due, interval, now), next_due), (10_000_000_000, now + interval));
+    }
+
+    #[test]
+    fn test_poll_timeout() {
+        assert_eq!(poll_timeout_ms(None), -1);
+        assert_eq!(poll_timeout_ms(Some(Duration::ZERO)), 0);
+        assert_eq!(poll_timeout_ms(Some(Duration::from_micros(10))), 1);
+        assert_eq!(poll_timeout_ms(Some(Duration::from_secs(u64::MAX))), libc::c_int::MAX);
+
+        // times out on a quiet socket, then sees data
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0, fds.as_mut_ptr()) }, 0);
+        let [socket, peer] = fds.map(|fd| unsafe { OwnedFd::from_raw_fd(fd) });
+        assert!(! poll_for_reception_timeout(socket.as_raw_fd(), Some(Duration::from_millis(1))).unwrap());
+        assert_eq!(unsafe { libc::send(peer.as_raw_fd(), [0_u8].as_ptr().cast(), 1, 0) }, 1);
+        assert!(poll_for_reception_timeout(socket.as_raw_fd(), None).unwrap());
+    }
+
+    #[test]
+    fn test_interface_lookup() {
+        // the loopback exists in every namespace
+        let index = interface_name_to_index("lo").unwrap();
+        assert_eq!(interface_index_to_name(index).unwrap(), "lo");
+        let mut cache = InterfaceCache::new();
+        assert_eq!(cache.index("lo").unwrap(), index);
+        assert_eq!(cache.name(index).unwrap(), "lo");
+        cache.forget_index(index);
+        assert_eq!(cache.name(index).unwrap(), "lo");
+
+        // hostile or missing names
+        for name in ["", "../../etc/passwd", "a name", "averyveryverylongname", "bad\0"] {
+            assert!(matches!(interface_name_to_index(name), Err(crate::Error::InvalidInterfaceName { .. })), "{name:?}");
+        }
+        assert!(matches!(interface_name_to_index("missing0"), Err(crate::Error::InterfaceNotFound { .. })));
+        assert!(matches!(interface_index_to_name(u32::MAX), Err(crate::Error::InterfaceNotFound { .. })));
+    }
+
+    #[test]
+    fn test_copy_frame() {
+        let source = (0..4096).map(|i| (i * 7 + 3) as u8).collect::<Vec<_>>();
+        for destination_size in [2048, 4096] {
+            for start in [0, 1, 63, 64, 256, 1000, 2000, 2047] {
+                for len in [0, 1, 14, 60, 64, 65, 1500, 2048] {
+                    if start + len > source.len() || len > destination_size {
+                        continue;
+                    }
+                    let mut destination = vec![0_u8; destination_size];
+                    let landed = copy_frame(&mut destination, &source, start..start + len);
+                    assert_eq!(landed.len(), len);
+                    assert_eq!(&destination[landed.clone()], &source[start..start + len], "{destination_size} {start} {len}");
+                    assert!(landed.start % 64 == start % 64 || landed.start == 0);
+                }
+            }
+        }
+    }
 }
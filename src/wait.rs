@@ -0,0 +1,81 @@
+use std::os::fd::AsRawFd;
+
+/// How to wait for frames: spin on the RX ring for a while, then poll the socket
+///
+/// Spinning trades CPU for latency, [`Self::blocking`] suits development machines and [`Self::busy`] dedicated cores
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(deny_unknown_fields))]
+pub struct WaitStrategy {
+    /// How many times the RX ring is checked before polling
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub spin_iterations: u32,
+    /// How long a poll lasts in milliseconds, forever if `None`
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub poll_timeout_ms: Option<u32>,
+}
+impl WaitStrategy {
+    /// Poll until frames arrive, without spinning
+    pub const fn blocking() -> Self {
+        Self { spin_iterations: 0, poll_timeout_ms: None }
+    }
+
+    /// Spin `spin_iterations` times, then poll for up to `poll_timeout_ms` (forever if `None`)
+    pub const fn hybrid(spin_iterations: u32, poll_timeout_ms: Option<u32>) -> Self {
+        Self { spin_iterations, poll_timeout_ms }
+    }
+
+    /// Spin `spin_iterations` times, then check the socket without sleeping
+    pub const fn busy(spin_iterations: u32) -> Self {
+        Self { spin_iterations, poll_timeout_ms: Some(0) }
+    }
+
+    /// Wait for `ready` to hold, spinning on it then polling `socket` for input, returning whether it holds
+    ///
+    /// `false` means that the poll timed out, or that the socket became readable without `ready` holding
+    pub fn wait(&self, socket: impl AsRawFd, mut ready: impl FnMut() -> bool) -> Result<bool, crate::Error> {
+        // spin
+        for _ in 0..self.spin_iterations {
+            if ready() {
+                return Ok(true);
+            }
+            std::hint::spin_loop();
+        }
+        if ready() {
+            return Ok(true);
+        }
+
+        // poll
+        let timeout = self.poll_timeout_ms.map(|ms| std::time::Duration::from_millis(ms as u64));
+        crate::utils::poll_for_reception_timeout(socket, timeout)?;
+        Ok(ready())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::WaitStrategy;
+    use crate::{testing::MockXDP, DefaultAllocator, Umem, UmemAllocatorFactory};
+
+    #[test]
+    fn test_wait_strategy() {
+        let umem = Arc::new(Umem::new_2k(64).unwrap());
+        let allocator = DefaultAllocator::for_umem(umem.clone());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 16).unwrap();
+        socket.refill_fill_ring(&allocator, usize::MAX);
+
+        // the mock socket is always readable, so polls return right away
+        for strategy in [WaitStrategy::blocking(), WaitStrategy::busy(100), WaitStrategy::hybrid(10, Some(1))] {
+            assert!(! socket.wait_for_reception(&strategy).unwrap());
+            assert!(mock.inject(b"frame"));
+            assert!(socket.wait_for_reception(&strategy).unwrap());
+            assert_eq!(socket.recv(&allocator, |_| {}), 1);
+        }
+
+        // spinning stops as soon as the condition holds
+        let mut checks = 0;
+        assert!(WaitStrategy::busy(100).wait(socket.fd, || { checks += 1; checks == 5 }).unwrap());
+        assert_eq!(checks, 5);
+    }
+}
@@ -0,0 +1,293 @@
+//! Detection of stalled datapaths, the classic symptom of a wedged driver or of a lost wakeup: frames wait on the
+//! rings of a socket, yet the kernel stops moving them
+
+use std::time::{Duration, Instant};
+
+use crate::XDPSocket;
+
+/// Called with the socket and the stall found on it, e.g. to log it or to tear the socket down
+type StallFn = Box<dyn FnMut(&mut XDPSocket, &Stall) + Send>;
+
+/// The direction of a socket which stopped moving
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallDirection {
+    /// Frames were queued for transmission but no completion arrived
+    Tx,
+    /// Chunks were on the fill ring but no frame arrived
+    Rx,
+}
+
+/// A stall found by a [`Watchdog`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stall {
+    pub direction: StallDirection,
+    /// How long the kernel made no progress for
+    pub stalled_for: Duration,
+    /// Kicks already attempted during this stall, without effect
+    pub kicks: u32,
+}
+
+/// Counters kept by a [`Watchdog`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WatchdogStats {
+    /// Stalls found on each direction, each counted once however long it lasts
+    pub tx_stalls: u64,
+    pub rx_stalls: u64,
+    /// Wakeups forced on stalled sockets
+    pub kicks: u64,
+    /// Stalls which ended, the kernel moving frames again
+    pub recoveries: u64,
+}
+
+/// How far the kernel got on a ring, and since when it did not get any further while it had work
+#[derive(Debug, Clone, Copy)]
+struct Progress {
+    position: u32,
+    pending: bool,
+    since: Instant,
+    deadline: Instant,
+    kicks: u32,
+    stalled: bool,
+}
+impl Progress {
+    fn new(now: Instant, timeout: Duration) -> Self {
+        Self { position: 0, pending: false, since: now, deadline: now + timeout, kicks: 0, stalled: false }
+    }
+
+    /// Start over from `position`, returning whether a stall ended
+    fn reset(&mut self, position: u32, pending: bool, now: Instant, timeout: Duration) -> bool {
+        let recovered = self.stalled;
+        *self = Self { position, pending, ..Self::new(now, timeout) };
+        recovered
+    }
+}
+
+/// Watches a socket for stalls, reporting them to a callback and optionally kicking the kernel with a wakeup
+///
+/// The TX side stalls when frames were handed to the kernel, the completion ring has room, and no completion arrived for
+/// the timeout. Frames are counted as completed as completions arrive, those of [`crate::SharedXDPSocket`]s included:
+/// their traffic can hide a stall of the socket, never cause one. The RX side stalls when the fill ring holds chunks, the RX ring has room, and no frame arrived for the
+/// timeout: an idle link looks the same, so it is only watched with [`Self::with_rx`], for links which always carry
+/// traffic, e.g. because of [`crate::heartbeat`]s. A stall is reported, then kicked if asked to, once per timeout for as
+/// long as it lasts. Call [`Self::check`] more often than the timeout, e.g. between polls
+pub struct Watchdog {
+    timeout: Duration,
+    watch_rx: bool,
+    kick: bool,
+    on_stall: Option<StallFn>,
+    tx: Progress,
+    rx: Progress,
+    /// Frames of the socket handed to the kernel and not completed yet
+    tx_outstanding: u32,
+    /// The TX and completion ring producer positions at the last check
+    tx_positions: Option<(u32, u32)>,
+    stats: WatchdogStats,
+}
+impl Watchdog {
+    /// Report stalls of the TX side lasting `timeout`
+    pub fn new(timeout: Duration) -> Self {
+        assert!(!timeout.is_zero(), "Timeout must be positive");
+        let now = Instant::now();
+        Self {
+            timeout,
+            watch_rx: false,
+            kick: false,
+            on_stall: None,
+            tx: Progress::new(now, timeout),
+            rx: Progress::new(now, timeout),
+            tx_outstanding: 0,
+            tx_positions: None,
+            stats: WatchdogStats::default(),
+        }
+    }
+
+    /// Watch the RX side as well
+    pub fn with_rx(mut self) -> Self {
+        self.watch_rx = true;
+        self
+    }
+
+    /// Attempt a recovery on stalls: a TX wakeup for the TX side, a poll for the RX side, like after a lost wakeup
+    pub fn with_kick(mut self) -> Self {
+        self.kick = true;
+        self
+    }
+
+    /// Let `on_stall` know about every stall, before any kick
+    pub fn with_callback(mut self, on_stall: impl FnMut(&mut XDPSocket, &Stall) + Send + 'static) -> Self {
+        self.on_stall = Some(Box::new(on_stall));
+        self
+    }
+
+    pub const fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub const fn stats(&self) -> &WatchdogStats {
+        &self.stats
+    }
+
+    /// Whether a stall is ongoing on `direction`
+    pub const fn is_stalled(&self, direction: StallDirection) -> bool {
+        match direction {
+            StallDirection::Tx => self.tx.stalled,
+            StallDirection::Rx => self.rx.stalled,
+        }
+    }
+
+    /// Look at the rings of `socket` at `now`, returning the stalls reported by this check
+    pub fn check(&mut self, socket: &mut XDPSocket, now: Instant) -> Result<Vec<Stall>, crate::Error> {
+        let mut stalls = Vec::new();
+
+        // transmitted frames come back as completions, at first only those the kernel did not take are known of
+        let completions = &socket.completion_ring;
+        let (submitted, completed) = (socket.tx_ring.producer_position(), completions.producer_position());
+        let (last_submitted, last_completed) = self.tx_positions.replace((submitted, completed))
+            .unwrap_or((socket.tx_ring.consumer_position(), completed));
+        self.tx_outstanding = self.tx_outstanding
+            .saturating_add(submitted.wrapping_sub(last_submitted))
+            .saturating_sub(completed.wrapping_sub(last_completed));
+        let tx_pending = self.tx_outstanding > 0 && (completions.num_consumable() as usize) < completions.num_elements();
+        if let Some(stall) = self.observe(StallDirection::Tx, completions.producer_position(), tx_pending, now) {
+            stalls.push(stall);
+        }
+
+        // chunks on the fill ring come back as frames
+        if self.watch_rx {
+            let rx_pending = socket.fill_ring.num_consumable() > 0
+                && (socket.rx_ring.num_consumable() as usize) < socket.rx_ring.num_elements();
+            if let Some(stall) = self.observe(StallDirection::Rx, socket.rx_ring.producer_position(), rx_pending, now) {
+                stalls.push(stall);
+            }
+        }
+
+        // report then kick
+        for stall in &stalls {
+            if let Some(on_stall) = &mut self.on_stall {
+                on_stall(socket, stall);
+            }
+            if self.kick {
+                match stall.direction {
+                    StallDirection::Tx => socket.wake_for_transmission()?,
+                    StallDirection::Rx => {
+                        socket.poll_for_reception_timeout(Some(Duration::ZERO))?;
+                    },
+                }
+                self.progress(stall.direction).kicks += 1;
+                self.stats.kicks += 1;
+            }
+        }
+        Ok(stalls)
+    }
+
+    /// Track `position` on `direction`, returning a stall when it is due for a report
+    fn observe(&mut self, direction: StallDirection, position: u32, pending: bool, now: Instant) -> Option<Stall> {
+        let timeout = self.timeout;
+        let progress = self.progress(direction);
+        if !pending || !progress.pending || position != progress.position {
+            if progress.reset(position, pending, now, timeout) {
+                self.stats.recoveries += 1;
+            }
+            return None;
+        }
+        if now < progress.deadline {
+            return None;
+        }
+
+        // report once per timeout
+        let first = !progress.stalled;
+        progress.stalled = true;
+        progress.deadline = now + timeout;
+        let stall = Stall { direction, stalled_for: now - progress.since, kicks: progress.kicks };
+        if first {
+            match direction {
+                StallDirection::Tx => self.stats.tx_stalls += 1,
+                StallDirection::Rx => self.stats.rx_stalls += 1,
+            }
+        }
+        Some(stall)
+    }
+
+    fn progress(&mut self, direction: StallDirection) -> &mut Progress {
+        match direction {
+            StallDirection::Tx => &mut self.tx,
+            StallDirection::Rx => &mut self.rx,
+        }
+    }
+}
+impl std::fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("timeout", &self.timeout)
+            .field("watch_rx", &self.watch_rx)
+            .field("kick", &self.kick)
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{Arc, Mutex}, time::Duration};
+
+    use super::{Stall, StallDirection, Watchdog, WatchdogStats};
+    use crate::{testing::MockXDP, DefaultAllocator, Umem, UmemAllocatorFactory};
+
+    #[test]
+    fn test_watchdog_stalls() {
+        let umem = Arc::new(Umem::new_2k(32).unwrap());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 8).unwrap();
+        let allocator = DefaultAllocator::for_umem(umem);
+        let timeout = Duration::from_millis(100);
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let mut watchdog = Watchdog::new(timeout).with_rx().with_kick().with_callback({
+            let reported = reported.clone();
+            move |_, stall: &Stall| reported.lock().unwrap().push(*stall)
+        });
+        let start = std::time::Instant::now();
+
+        // idle is fine
+        assert!(watchdog.check(&mut socket, start + timeout * 2).unwrap().is_empty());
+
+        // frames never completed
+        assert!(socket.send(&allocator, b"frame").unwrap());
+        assert!(watchdog.check(&mut socket, start + timeout * 3).unwrap().is_empty());
+        let stalls = watchdog.check(&mut socket, start + timeout * 4).unwrap();
+        assert_eq!(stalls, [Stall { direction: StallDirection::Tx, stalled_for: timeout, kicks: 0 }]);
+        assert!(watchdog.check(&mut socket, start + timeout * 4 + timeout / 2).unwrap().is_empty());
+        assert_eq!(watchdog.check(&mut socket, start + timeout * 5).unwrap()[0].kicks, 1);
+        assert!(watchdog.is_stalled(StallDirection::Tx));
+
+        // completions resume
+        mock.transmit(|_| {});
+        assert!(watchdog.check(&mut socket, start + timeout * 6).unwrap().is_empty());
+        assert!(!watchdog.is_stalled(StallDirection::Tx));
+
+        // chunks on the fill ring, no frames
+        socket.refill_fill_ring(&allocator, 4);
+        assert!(watchdog.check(&mut socket, start + timeout * 7).unwrap().is_empty());
+        assert_eq!(watchdog.check(&mut socket, start + timeout * 8).unwrap()[0].direction, StallDirection::Rx);
+        assert!(mock.inject(b"frame"));
+        assert!(watchdog.check(&mut socket, start + timeout * 9).unwrap().is_empty());
+
+        assert_eq!(reported.lock().unwrap().len(), 3);
+        assert_eq!(*watchdog.stats(), WatchdogStats { tx_stalls: 1, rx_stalls: 1, kicks: 3, recoveries: 2 });
+    }
+
+    #[test]
+    fn test_watchdog_shared_completions() {
+        let umem = Arc::new(Umem::new_2k(32).unwrap());
+        let (mut socket, mut mock) = MockXDP::new(umem.clone(), 8).unwrap();
+        let timeout = Duration::from_millis(100);
+        let mut watchdog = Watchdog::new(timeout);
+        let start = std::time::Instant::now();
+
+        // completions of another socket are no stall of this one
+        assert!(watchdog.check(&mut socket, start).unwrap().is_empty());
+        assert!(mock.complete_shared(umem.chunk_start_offset_for_index(3)));
+        for n in 1..4 {
+            assert!(watchdog.check(&mut socket, start + timeout * n).unwrap().is_empty());
+        }
+        assert_eq!(watchdog.stats().tx_stalls, 0);
+    }
+}
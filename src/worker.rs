@@ -0,0 +1,390 @@
+//! An event loop running a [`Pipeline`] over the frames received on a set of ports
+
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use crate::{heartbeat::Heartbeat, pipeline::{FrameCtx, Pipeline, Verdict}, DefaultAllocator, UmemAllocator, Waker, XDPSocket, XDPSocketSet};
+
+/// Counters kept by a [`Worker`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkerStats {
+    pub received: u64,
+    /// Frames transmitted on another port
+    pub forwarded: u64,
+    /// Frames transmitted back on the port they came from
+    pub transmitted: u64,
+    /// Frames dropped by the pipeline, or sent to a port that does not exist
+    pub dropped: u64,
+    pub consumed: u64,
+    /// Frames dropped because the TX ring was full or no chunk was available
+    pub dropped_no_space: u64,
+}
+
+/// Drives a [`Pipeline`] over ports, each a socket along with the allocator of its umem
+///
+/// Ports are drained fairly a budget at a time, like [`XDPSocketSet::drain_fair`] does. Frames transmitted on a port sharing
+/// the allocator of the ingress one are handed over without copying, otherwise they are copied into a chunk of the egress umem
+pub struct Worker<'a, A: UmemAllocator + ?Sized = DefaultAllocator> {
+    ports: XDPSocketSet<'a>,
+    allocators: Vec<Arc<A>>,
+    pipeline: Pipeline,
+    budget: usize,
+    stats: WorkerStats,
+    pending_wakeups: Vec<bool>,
+    tick_interval: Duration,
+    last_tick: Instant,
+    heartbeats: Vec<Heartbeat>,
+}
+impl<'a, A: UmemAllocator + ?Sized> Worker<'a, A> {
+    /// Frames received from each port per round, unless changed with [`Self::with_budget`]
+    pub const DEFAULT_BUDGET: usize = 64;
+
+    /// How often stages are ticked, unless changed with [`Self::with_tick_interval`]
+    pub const DEFAULT_TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// A worker without ports running `pipeline`
+    pub fn new(pipeline: Pipeline) -> Result<Self, crate::Error> {
+        Ok(Self {
+            ports: XDPSocketSet::new()?,
+            allocators: Vec::new(),
+            pipeline,
+            budget: Self::DEFAULT_BUDGET,
+            stats: WorkerStats::default(),
+            pending_wakeups: Vec::new(),
+            tick_interval: Self::DEFAULT_TICK_INTERVAL,
+            last_tick: Instant::now(),
+            heartbeats: Vec::new(),
+        })
+    }
+
+    /// Receive up to `budget` frames from each port per round
+    pub fn with_budget(mut self, budget: usize) -> Self {
+        assert!(budget > 0, "Budget must be positive");
+        self.budget = budget;
+        self
+    }
+
+    /// Call [`crate::pipeline::Stage::tick`] at most every `interval`, at the end of a round
+    ///
+    /// Ticks happen while frames flow as well as when idle, as long as [`Self::run_once`] is given a timeout no longer than `interval`
+    pub fn with_tick_interval(mut self, interval: Duration) -> Self {
+        self.tick_interval = interval;
+        self
+    }
+
+    /// Add `socket`, whose chunks come from `allocator`, as a port filling its fill ring, returning the port number
+    pub fn add_port(&mut self, mut socket: XDPSocket<'a>, allocator: Arc<A>) -> usize {
+        assert!(std::ptr::eq(allocator.umem_reference(), socket.umem.as_ref()), "allocator does not belong to the umem of the socket");
+        socket.refill_fill_ring(allocator.as_ref(), usize::MAX);
+        self.allocators.push(allocator);
+        self.pending_wakeups.push(false);
+        self.ports.insert(socket)
+    }
+
+    /// The socket of `port`
+    pub fn port(&mut self, port: usize) -> &mut XDPSocket<'a> {
+        self.ports.socket(port)
+    }
+
+    /// The pipeline, e.g. to reconfigure its stages between rounds
+    pub fn pipeline(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+
+    /// Transmit `heartbeat` on its port at the end of the rounds it is due, returning its index
+    ///
+    /// [`Self::run_once`] waits no longer than until the next heartbeat is due. Fails with
+    /// [`crate::Error::InvalidConfiguration`] if the port does not exist, and with [`crate::Error::FrameTooLarge`] if the
+    /// template does not fit in a chunk of its umem
+    pub fn add_heartbeat(&mut self, heartbeat: Heartbeat) -> Result<usize, crate::Error> {
+        if heartbeat.port() >= self.allocators.len() {
+            return Err(crate::Error::InvalidConfiguration { reason: format!("heartbeat port {} does not exist", heartbeat.port()) });
+        }
+        let chunk_size = self.ports.socket(heartbeat.port()).umem.chunk_size();
+        if heartbeat.template().len() > chunk_size {
+            return Err(crate::Error::FrameTooLarge { length: heartbeat.template().len(), chunk_size });
+        }
+        self.heartbeats.push(heartbeat);
+        Ok(self.heartbeats.len() - 1)
+    }
+
+    /// The heartbeat at `index`, e.g. to change its interval or look at its counters
+    pub fn heartbeat(&mut self, index: usize) -> &mut Heartbeat {
+        &mut self.heartbeats[index]
+    }
+
+    /// Stop transmitting the heartbeat at `index`, shifting the following ones down
+    pub fn remove_heartbeat(&mut self, index: usize) -> Heartbeat {
+        self.heartbeats.remove(index)
+    }
+
+    /// A handle to interrupt [`Self::run_once`] from other threads
+    pub fn waker(&self) -> Waker {
+        self.ports.waker()
+    }
+
+    pub const fn stats(&self) -> &WorkerStats {
+        &self.stats
+    }
+
+    /// Wait up to `timeout` (forever if `None`) for frames then run a round, returning whether a [`Waker`] was used
+    pub fn run_once(&mut self, timeout: Option<Duration>) -> Result<bool, crate::Error> {
+        let timeout = match self.heartbeats.iter().map(Heartbeat::next_due).min() {
+            Some(next_due) => {
+                let until_due = next_due.saturating_duration_since(Instant::now());
+                Some(timeout.map_or(until_due, |timeout| timeout.min(until_due)))
+            },
+            None => timeout,
+        };
+        let woken = self.ports.poll(timeout)?;
+        self.run_round()?;
+        Ok(woken)
+    }
+
+    /// Run rounds until `stop` returns `true`, checking it at least every `check_interval` and whenever woken
+    pub fn run(&mut self, check_interval: Duration, mut stop: impl FnMut() -> bool) -> Result<(), crate::Error> {
+        while ! stop() {
+            self.run_once(Some(check_interval))?;
+        }
+        Ok(())
+    }
+
+    /// Without waiting, process up to a budget of frames from each port, then service every ring, returning how many frames were received
+    ///
+    /// Call it in a loop to busy poll
+    pub fn run_round(&mut self) -> Result<usize, crate::Error> {
+        let mut received = 0;
+        for port in self.ports.next_round() {
+            let mut count = 0;
+            while count < self.budget && self.ports.socket(port).rx_ring.can_consume() {
+                self.process_frame(port);
+                count += 1;
+            }
+            received += count;
+        }
+
+        // one wakeup per port and round
+        for (port, pending) in self.pending_wakeups.iter_mut().enumerate() {
+            if std::mem::take(pending) {
+                self.ports.socket(port).wake_for_transmission_if_needed()?;
+            }
+        }
+
+        // recycle
+        for (socket, allocator) in self.ports.sockets().iter_mut().zip(&self.allocators) {
+            socket.reap_completions(allocator.as_ref());
+            socket.refill_fill_ring(allocator.as_ref(), usize::MAX);
+        }
+
+        // housekeeping
+        let now = Instant::now();
+        for heartbeat in self.heartbeats.iter_mut() {
+            let port = heartbeat.port();
+            heartbeat.transmit_if_due(self.ports.socket(port), self.allocators[port].as_ref(), now)?;
+        }
+        if now.saturating_duration_since(self.last_tick) >= self.tick_interval {
+            self.pipeline.tick(now);
+            self.last_tick = now;
+        }
+        Ok(received)
+    }
+
+    /// Run the pipeline over the next frame of `ingress` and act on its verdict
+    fn process_frame(&mut self, ingress: usize) {
+        let sockets = self.ports.sockets();
+
+        // run the pipeline
+        let rx_socket = &mut sockets[ingress];
+        let rx_ring_index = rx_socket.rx_ring.get_consumer_index() as usize;
+        let rx_descriptor = *rx_socket.rx_ring.get_nth_descriptor(rx_ring_index);
+        let mut data = rx_socket.rx_ring.get_nth_slice_mut(rx_ring_index, &rx_socket.umem, None, None);
+        let verdict = self.pipeline.process(&mut FrameCtx { ingress, data: &mut data });
+        drop(data);
+        rx_socket.traffic.record_rx(rx_descriptor.len as usize);
+        self.stats.received += 1;
+
+        // act
+        let egress = match verdict {
+            Verdict::Transmit => Some(ingress),
+            Verdict::Forward(egress) if egress < sockets.len() => Some(egress),
+            Verdict::Consume => {
+                self.stats.consumed += 1;
+                None
+            },
+            Verdict::Continue | Verdict::Drop | Verdict::Forward(_) => {
+                self.stats.dropped += 1;
+                None
+            },
+        };
+        let recycle_rx_chunk = match egress {
+            Some(egress) if self.transmit(ingress, rx_ring_index, egress) => {
+                if egress == ingress { self.stats.transmitted += 1 } else { self.stats.forwarded += 1 }
+                self.pending_wakeups[egress] = true;
+                egress != ingress && ! Arc::ptr_eq(&self.allocators[ingress], &self.allocators[egress])
+            },
+            Some(_) => {
+                self.stats.dropped_no_space += 1;
+                true
+            },
+            None => true,
+        };
+
+        // give back the rx chunk
+        let rx_socket = self.ports.socket(ingress);
+        rx_socket.rx_ring.advance_consumer_index();
+        if recycle_rx_chunk {
+            if rx_socket.fill_ring.can_produce() {
+                rx_socket.fill_ring.produce_umem_offset(rx_descriptor.addr);
+            } else {
+                self.allocators[ingress].release_offset(rx_descriptor.addr);
+            }
+        }
+    }
+
+    /// Put the frame at `rx_ring_index` of the RX ring of `ingress` on the TX ring of `egress`, returning whether there was room
+    fn transmit(&mut self, ingress: usize, rx_ring_index: usize, egress: usize) -> bool {
+        let sockets = self.ports.sockets();
+        let rx_descriptor = *sockets[ingress].rx_ring.get_nth_descriptor(rx_ring_index);
+        let descriptor = if ingress == egress || Arc::ptr_eq(&self.allocators[ingress], &self.allocators[egress]) {
+            // hand over the chunk itself
+            if ! sockets[egress].tx_ring.can_produce() {
+                return false;
+            }
+            libc::xdp_desc { addr: rx_descriptor.addr, len: rx_descriptor.len, options: 0 }
+        } else {
+            // copy into a chunk of the egress umem
+            let Ok([rx_socket, tx_socket]) = sockets.get_disjoint_mut([ingress, egress]) else { unreachable!() };
+            if ! tx_socket.tx_ring.can_produce() {
+                return false;
+            }
+            let Some(chunk_index) = self.allocators[egress].try_allocate() else {
+                return false;
+            };
+            let (rx_chunk, rx_frame) = rx_socket.rx_ring.get_nth_chunk_mut(rx_ring_index, &rx_socket.umem);
+            let mut tx_chunk = tx_socket.umem.chunk(chunk_index);
+            let tx_frame = crate::utils::copy_frame(&mut tx_chunk, &rx_chunk, rx_frame);
+            libc::xdp_desc { addr: tx_chunk.offset() + tx_frame.start as u64, len: tx_frame.len() as _, options: 0 }
+        };
+
+        let tx_socket = &mut sockets[egress];
+        let tx_ring_index = tx_socket.tx_ring.get_producer_index() as usize;
+        *tx_socket.tx_ring.get_nth_descriptor_mut(tx_ring_index) = descriptor;
+        tx_socket.tx_ring.advance_producer_index();
+        tx_socket.traffic.record_tx(descriptor.len as usize);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::{Duration, Instant}};
+
+    use super::Worker;
+    use crate::{
+        flow::FlowTable,
+        heartbeat::Heartbeat,
+        packet::{FiveTuple, FrameBuilder, MacAddress},
+        pipeline::{FrameCtx, Pipeline, Stage, Verdict},
+        testing::MockXDP,
+        DefaultAllocator, Umem, UmemAllocatorFactory,
+    };
+
+    #[test]
+    fn test_worker_runs_pipeline() {
+        let pipeline = Pipeline::new()
+            .with_stage(|ctx: &mut FrameCtx| if ctx.data[0] == b'x' { Verdict::Drop } else { Verdict::Continue })
+            .with_stage(|ctx: &mut FrameCtx| if ctx.data[0] == b'c' { Verdict::Consume } else { Verdict::Continue })
+            .with_stage(|ctx: &mut FrameCtx| { ctx.data[1] = b'!'; Verdict::Continue })
+            .with_stage(|ctx: &mut FrameCtx| match ctx.ingress {
+                0 => Verdict::Forward(1),
+                1 => Verdict::Transmit,
+                _ => Verdict::Forward(ctx.ingress + 1),
+            });
+        let mut worker = Worker::<DefaultAllocator>::new(pipeline).unwrap();
+
+        // two ports over separate umems, a third sharing the umem of the second
+        let umem_a = Arc::new(Umem::new_2k(32).unwrap());
+        let umem_b = Arc::new(Umem::new_4k(32).unwrap());
+        let allocator_b = Arc::new(DefaultAllocator::for_umem(umem_b.clone()));
+        let (a, mut mock_a) = MockXDP::new(umem_a.clone(), 8).unwrap();
+        let (b, mut mock_b) = MockXDP::new(umem_b.clone(), 8).unwrap();
+        let (c, mut mock_c) = MockXDP::new(umem_b.clone(), 8).unwrap();
+        assert_eq!(worker.add_port(a, Arc::new(DefaultAllocator::for_umem(umem_a))), 0);
+        assert_eq!(worker.add_port(b, allocator_b.clone()), 1);
+        assert_eq!(worker.add_port(c, allocator_b), 2);
+
+        assert!(mock_a.inject(b"a-to-b"));
+        assert!(mock_a.inject(b"xdropped"));
+        assert!(mock_a.inject(b"consumed"));
+        assert!(mock_b.inject(b"hairpin"));
+        assert!(mock_c.inject(b"nowhere"));
+        assert!(! worker.run_once(None).unwrap());
+        assert_eq!(mock_b.transmitted_frames(), [b"a!to-b".to_vec(), b"h!irpin".to_vec()]);
+        assert!(mock_a.transmitted_frames().is_empty());
+        assert!(mock_c.transmitted_frames().is_empty());
+        let stats = worker.stats();
+        assert_eq!((stats.received, stats.forwarded, stats.transmitted, stats.dropped, stats.consumed), (5, 1, 1, 2, 1));
+
+        // chunks flow back to the fill rings
+        worker.run_round().unwrap();
+        assert_eq!((mock_a.fill_ring_len(), mock_b.fill_ring_len()), (7, 7));
+    }
+
+    /// Counts frames per flow, reporting flows as they expire
+    struct FlowCounter {
+        table: FlowTable<u32>,
+        expired: Arc<AtomicUsize>,
+    }
+    impl Stage for FlowCounter {
+        fn process(&mut self, ctx: &mut FrameCtx) -> Verdict {
+            if let Some(flow) = FiveTuple::from_frame(ctx.data) {
+                *self.table.get_or_insert_with(flow, || 0) += 1;
+            }
+            Verdict::Consume
+        }
+
+        fn tick(&mut self, now: Instant) {
+            self.table.expire(now, |_, frames| { self.expired.fetch_add(frames as usize, Ordering::Relaxed); });
+        }
+    }
+
+    #[test]
+    fn test_worker_ticks_stages() {
+        let expired = Arc::new(AtomicUsize::new(0));
+        let counter = FlowCounter { table: FlowTable::new(16, Duration::ZERO), expired: expired.clone() };
+        let mut worker = Worker::<DefaultAllocator>::new(Pipeline::new().with_stage(counter)).unwrap().with_tick_interval(Duration::ZERO);
+        let umem = Arc::new(Umem::new_2k(16).unwrap());
+        let (socket, mut mock) = MockXDP::new(umem.clone(), 8).unwrap();
+        worker.add_port(socket, Arc::new(DefaultAllocator::for_umem(umem)));
+
+        let mut frame = [0_u8; 128];
+        let builder = FrameBuilder::ethernet(MacAddress([6; 6]), MacAddress([8; 6]));
+        for source_port in [1000, 1000, 2000] {
+            let len = builder.clone().ipv4("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()).udp(source_port, 53).write_with_payload(&mut frame, b"flow").unwrap();
+            assert!(mock.inject(&frame[..len]));
+        }
+        assert_eq!(worker.run_round().unwrap(), 3);
+
+        // the next round finds both flows idle
+        std::thread::sleep(Duration::from_millis(1));
+        assert_eq!(worker.run_round().unwrap(), 0);
+        assert_eq!(expired.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_worker_sends_heartbeats() {
+        let mut worker = Worker::<DefaultAllocator>::new(Pipeline::new()).unwrap();
+        let umem = Arc::new(Umem::new_2k(16).unwrap());
+        let (socket, mut mock) = MockXDP::new(umem.clone(), 8).unwrap();
+        worker.add_port(socket, Arc::new(DefaultAllocator::for_umem(umem)));
+        assert!(matches!(worker.add_heartbeat(Heartbeat::new(1, *b"keepalive", Duration::from_secs(1))), Err(crate::Error::InvalidConfiguration { .. })));
+        assert!(matches!(worker.add_heartbeat(Heartbeat::new(0, vec![0; 4096], Duration::from_secs(1))), Err(crate::Error::FrameTooLarge { .. })));
+        assert_eq!(worker.add_heartbeat(Heartbeat::new(0, *b"keepalive", Duration::from_secs(3600))).unwrap(), 0);
+
+        // due at once, so waiting forever returns
+        worker.run_once(None).unwrap();
+        worker.run_round().unwrap();
+        assert_eq!(mock.transmitted_frames(), [b"keepalive".to_vec()]);
+        assert_eq!(worker.heartbeat(0).stats().sent, 1);
+        assert_eq!(worker.remove_heartbeat(0).port(), 0);
+    }
+}
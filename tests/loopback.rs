@@ -0,0 +1,86 @@
+#![cfg(feature = "testing")]
+
+use std::{net::UdpSocket, os::fd::AsRawFd, sync::Arc};
+
+use xdrippi::{packet::{parse, FrameBuilder, NetworkHeader, TransportHeader}, testing::{TestNetwork, TestPort}, BPFRedirectManager, DefaultAllocator, Umem, UmemAllocatorFactory, XDPSocket};
+
+struct Endpoint<'a> {
+    sock: XDPSocket<'a>,
+    allocator: DefaultAllocator,
+    _bpf_manager: BPFRedirectManager,
+}
+impl Endpoint<'_> {
+    fn bind(port: &TestPort) -> Self {
+        let umem = Arc::new(Umem::new_2k(512).unwrap());
+        let mut sock = XDPSocket::new(port.if_index(), 0, umem.clone(), 256).unwrap();
+        let mut bpf_manager = BPFRedirectManager::attach(port.if_index());
+        bpf_manager.add_redirect(0, sock.as_raw_fd()).unwrap();
+        let allocator = DefaultAllocator::for_umem(umem.clone());
+        sock.refill_fill_ring(&allocator, usize::MAX);
+        Self { sock, allocator, _bpf_manager: bpf_manager }
+    }
+}
+
+#[test]
+#[ignore = "requires root"]
+fn test_rx_from_udp_socket() {
+    let network = TestNetwork::new("xdrrx", 2, 1).unwrap();
+    let port = network.port(0);
+    let mut endpoint = Endpoint::bind(port);
+    assert!(! endpoint.sock.status().unwrap().zero_copy, "veth has no zero-copy support");
+
+    // send from the namespace
+    let udp = port.in_namespace(|| UdpSocket::bind((port.namespace_address, 0)).unwrap()).unwrap();
+    let payloads = (0..16).map(|i| format!("xdrippi rx {i}").into_bytes()).collect::<Vec<_>>();
+    for payload in &payloads {
+        udp.send_to(payload, (port.host_address, 9000)).unwrap();
+    }
+
+    // receive intact
+    let mut received = Vec::new();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while received.len() < payloads.len() && std::time::Instant::now() < deadline {
+        endpoint.sock.recv(&endpoint.allocator, |frame| {
+            let Some(parsed) = parse(frame) else { return };
+            if let (Some(NetworkHeader::Ipv4(ipv4)), Some(TransportHeader::Udp(datagram))) = (parsed.network, parsed.transport)
+                && ipv4.verify_checksum()
+                && datagram.destination_port() == 9000 {
+                received.push(datagram.payload().to_vec());
+            }
+        });
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(received, payloads);
+}
+
+#[test]
+#[ignore = "requires root"]
+fn test_tx_to_udp_socket() {
+    let network = TestNetwork::new("xdrtx", 3, 1).unwrap();
+    let port = network.port(0);
+    let mut endpoint = Endpoint::bind(port);
+
+    // receive in the namespace
+    let udp = port.in_namespace(|| UdpSocket::bind((port.namespace_address, 0)).unwrap()).unwrap();
+    udp.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
+    let udp_port = udp.local_addr().unwrap().port();
+
+    // send from the socket
+    let builder = FrameBuilder::ethernet(port.host_mac, port.namespace_mac)
+        .ipv4(port.host_address, port.namespace_address)
+        .udp(9000, udp_port);
+    let payloads = (0..16).map(|i| format!("xdrippi tx {i}").into_bytes()).collect::<Vec<_>>();
+    for payload in &payloads {
+        let mut frame = [0_u8; 128];
+        let len = builder.write_with_payload(&mut frame, payload).unwrap();
+        assert!(endpoint.sock.send(&endpoint.allocator, &frame[..len]).unwrap());
+    }
+
+    // arrive intact
+    for payload in &payloads {
+        let mut buffer = [0_u8; 128];
+        let (len, source) = udp.recv_from(&mut buffer).unwrap();
+        assert_eq!(source, (port.host_address, 9000).into());
+        assert_eq!(&buffer[..len], payload);
+    }
+}
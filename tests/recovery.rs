@@ -0,0 +1,33 @@
+#![cfg(feature = "testing")]
+
+use xdrippi::{config::SocketConfig, testing::TestNetwork, DefaultAllocator, RecoveringSocket};
+
+#[test]
+#[ignore = "requires root"]
+fn test_rebind_after_link_flap() {
+    let network = TestNetwork::new("xdrrb", 4, 1).unwrap();
+    let port = network.port(0);
+    let mut recovering = RecoveringSocket::<DefaultAllocator>::new(SocketConfig::new(&port.host_interface, 0)).unwrap();
+    assert!(recovering.socket().is_some());
+
+    let set_link = |state: &str| {
+        let status = std::process::Command::new("ip").args(["link", "set", &port.host_interface, state]).status().unwrap();
+        assert!(status.success());
+    };
+
+    // down tears the socket down
+    set_link("down");
+    while recovering.link_state().is_some_and(|state| state.up) {
+        recovering.check_link(Some(std::time::Duration::from_secs(5))).unwrap();
+    }
+    assert!(recovering.socket().is_none());
+
+    // up binds it again
+    set_link("up");
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while ! recovering.check_link(Some(std::time::Duration::from_millis(100))).unwrap() {
+        assert!(std::time::Instant::now() < deadline, "socket was not re-created");
+    }
+    assert!(recovering.socket().is_some());
+    assert_eq!(recovering.rebinds(), 1);
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "testing")]
+
+use std::{net::UdpSocket, os::fd::AsRawFd, sync::Arc};
+
+use xdrippi::{testing::TestNetwork, BPFRedirectManager, DefaultAllocator, SharedXDPSocket, Umem, UmemAllocatorFactory, XDPSocket};
+
+#[test]
+#[ignore = "requires root"]
+fn test_rx_on_secondary_socket() {
+    let network = TestNetwork::new("xdrsh", 5, 1).unwrap();
+    let port = network.port(0);
+
+    // the owner carries the fill ring, the secondary socket receives
+    let umem = Arc::new(Umem::new_2k(512).unwrap());
+    let allocator = DefaultAllocator::for_umem(umem.clone());
+    let mut owner = XDPSocket::new(port.if_index(), 0, umem.clone(), 256).unwrap();
+    let mut secondary = SharedXDPSocket::new(&owner, 256, 256).unwrap();
+    let mut bpf_manager = BPFRedirectManager::attach(port.if_index());
+    bpf_manager.add_redirect(0, secondary.as_raw_fd()).unwrap();
+    owner.refill_fill_ring(&allocator, usize::MAX);
+
+    // send from the namespace
+    let udp = port.in_namespace(|| UdpSocket::bind((port.namespace_address, 0)).unwrap()).unwrap();
+    for _ in 0..8 {
+        udp.send_to(b"xdrippi shared umem", (port.host_address, 9000)).unwrap();
+    }
+
+    // receive
+    let mut received = 0;
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while received < 8 && std::time::Instant::now() < deadline {
+        secondary.recv(&mut owner, &allocator, |frame| {
+            if frame.windows(19).any(|window| window == b"xdrippi shared umem") {
+                received += 1;
+            }
+        });
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(received, 8);
+    assert_eq!(owner.recv(&allocator, |_| {}), 0);
+}
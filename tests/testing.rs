@@ -0,0 +1,32 @@
+#![cfg(feature = "testing")]
+
+use std::{os::fd::AsRawFd, sync::Arc};
+
+use xdrippi::{packet::{parse, NetworkHeader}, testing::TestNetwork, BPFRedirectManager, DefaultAllocator, Umem, UmemAllocatorFactory, XDPSocket};
+
+#[test]
+#[ignore = "requires root"]
+fn test_receive_from_namespace() {
+    let network = TestNetwork::new("xdrt", 1, 1).unwrap();
+    let port = network.port(0);
+
+    // socket
+    let umem = Arc::new(Umem::new_2k(256).unwrap());
+    let mut sock = XDPSocket::new(port.if_index(), 0, umem.clone(), 128).unwrap();
+    let mut bpf_manager = BPFRedirectManager::attach(port.if_index());
+    bpf_manager.add_redirect(0, sock.as_raw_fd()).unwrap();
+    let allocator = DefaultAllocator::for_umem(umem.clone());
+    sock.refill_fill_ring(&allocator, usize::MAX);
+
+    // the pings go unanswered, the socket takes them
+    let _ = port.exec("ping", ["-c2", "-W1", &port.host_address.to_string()]);
+    let mut pings = 0;
+    sock.recv(&allocator, |frame| {
+        if let Some(NetworkHeader::Ipv4(ipv4)) = parse(frame).and_then(|parsed| parsed.network)
+            && ipv4.source() == port.namespace_address
+            && ipv4.destination() == port.host_address {
+            pings += 1;
+        }
+    });
+    assert!(pings > 0);
+}